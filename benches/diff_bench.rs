@@ -1,6 +1,7 @@
 use bencher::{benchmark_group, benchmark_main, Bencher};
 use criterion_bencher_compat as bencher;
 use jujutsu_lib::diff;
+use jujutsu_lib::files::{self, DiffOptions};
 
 fn unchanged_lines(count: usize) -> (String, String) {
     let mut lines = vec![];
@@ -30,6 +31,16 @@ fn reversed_lines(count: usize) -> (String, String) {
     (left_lines.join(""), right_lines.join(""))
 }
 
+fn one_changed_line(count: usize) -> (String, String) {
+    let mut lines = vec![];
+    for i in 0..count {
+        lines.push(format!("left line {}\n", i));
+    }
+    let left = lines.join("");
+    lines[count / 2] = "changed line\n".to_string();
+    (left, lines.join(""))
+}
+
 fn bench_diff_1k_unchanged_lines(b: &mut Bencher) {
     let (left, right) = unchanged_lines(1000);
     b.iter(|| diff::diff(left.as_bytes(), right.as_bytes()));
@@ -60,6 +71,29 @@ fn bench_diff_10k_lines_reversed(b: &mut Bencher) {
     b.iter(|| diff::diff(left.as_bytes(), right.as_bytes()));
 }
 
+fn bench_diff_100k_lines_one_changed(b: &mut Bencher) {
+    let (left, right) = one_changed_line(100000);
+    let options = DiffOptions::default();
+    b.iter(|| files::diff_with_options(left.as_bytes(), right.as_bytes(), &options).count());
+}
+
+fn bench_diff_100k_lines_one_changed_with_line_hashes(b: &mut Bencher) {
+    let (left, right) = one_changed_line(100000);
+    let left_hashes = files::line_hashes(left.as_bytes());
+    let right_hashes = files::line_hashes(right.as_bytes());
+    let options = DiffOptions::default();
+    b.iter(|| {
+        files::diff_with_line_hashes(
+            left.as_bytes(),
+            right.as_bytes(),
+            &left_hashes,
+            &right_hashes,
+            &options,
+        )
+        .count()
+    });
+}
+
 fn bench_diff_git_git_read_tree_c(b: &mut Bencher) {
     b.iter(|| {
         diff::diff(
@@ -213,6 +247,8 @@ benchmark_group!(
     bench_diff_10k_modified_lines,
     bench_diff_1k_lines_reversed,
     bench_diff_10k_lines_reversed,
+    bench_diff_100k_lines_one_changed,
+    bench_diff_100k_lines_one_changed_with_line_hashes,
     bench_diff_git_git_read_tree_c,
 );
 benchmark_main!(benches);
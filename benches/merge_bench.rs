@@ -0,0 +1,139 @@
+use bencher::{benchmark_group, benchmark_main, Bencher};
+use criterion_bencher_compat as bencher;
+use std::sync::Arc;
+
+use jujutsu_lib::commit::Commit;
+use jujutsu_lib::commit_builder::CommitBuilder;
+use jujutsu_lib::repo::ReadonlyRepo;
+use jujutsu_lib::repo_path::RepoPath;
+use jujutsu_lib::rewrite::rebase_commit;
+use jujutsu_lib::settings::UserSettings;
+use jujutsu_lib::tree::{self, Tree};
+use testutils::TestRepo;
+
+/// Trees where both sides changed different lines of one large file, so
+/// merging them has to merge the file's contents.
+fn large_file_trees(test_repo: &TestRepo, count: usize) -> (Tree, Tree, Tree) {
+    let path = RepoPath::from_internal_string("file");
+    let mut lines = vec![];
+    for i in 0..count {
+        lines.push(format!("line {}\n", i));
+    }
+    let base = lines.join("");
+    lines[0] = "changed on side 1\n".to_string();
+    let side1 = lines.join("");
+    lines[0] = "line 0\n".to_string();
+    lines[count - 1] = "changed on side 2\n".to_string();
+    let side2 = lines.join("");
+    let repo = &test_repo.repo;
+    (
+        testutils::create_tree(repo, &[(&path, &side1)]),
+        testutils::create_tree(repo, &[(&path, &base)]),
+        testutils::create_tree(repo, &[(&path, &side2)]),
+    )
+}
+
+fn merge_10k_lines_cached(b: &mut Bencher) {
+    let test_repo = TestRepo::init(false);
+    let (side1, base, side2) = large_file_trees(&test_repo, 10000);
+    b.iter(|| tree::merge_trees(&side1, &base, &side2).unwrap());
+}
+
+fn merge_10k_lines_uncached(b: &mut Bencher) {
+    let test_repo = TestRepo::init(false);
+    let (side1, base, side2) = large_file_trees(&test_repo, 10000);
+    let store = test_repo.repo.store();
+    b.iter(|| {
+        store.clear_merge_cache();
+        tree::merge_trees(&side1, &base, &side2).unwrap()
+    });
+}
+
+/// A stack of `stack_len` commits that each change a different line of one
+/// large file, and a sibling of the stack's base that changes the last line,
+/// to rebase the stack onto.
+fn large_file_stack(
+    settings: &UserSettings,
+    test_repo: &TestRepo,
+    stack_len: usize,
+    line_count: usize,
+) -> (Arc<ReadonlyRepo>, Vec<Commit>, Commit) {
+    let path = RepoPath::from_internal_string("file");
+    let mut lines = vec![];
+    for i in 0..line_count {
+        lines.push(format!("line {}\n", i));
+    }
+    let base_lines = lines.clone();
+    let repo = &test_repo.repo;
+    let mut tx = repo.start_transaction(settings, "test");
+    let base_tree = testutils::create_tree(repo, &[(&path, &lines.join(""))]);
+    let base = CommitBuilder::for_new_commit(
+        settings,
+        vec![repo.store().root_commit_id().clone()],
+        base_tree.id().clone(),
+    )
+    .write_to_repo(tx.mut_repo());
+    let mut stack = vec![];
+    let mut parent = base.clone();
+    for i in 0..stack_len {
+        lines[i * 2] = format!("changed in commit {}\n", i);
+        let tree = testutils::create_tree(repo, &[(&path, &lines.join(""))]);
+        let commit =
+            CommitBuilder::for_new_commit(settings, vec![parent.id().clone()], tree.id().clone())
+                .write_to_repo(tx.mut_repo());
+        stack.push(commit.clone());
+        parent = commit;
+    }
+    let mut new_base_lines = base_lines;
+    new_base_lines[line_count - 1] = "changed in new base\n".to_string();
+    let new_base_tree = testutils::create_tree(repo, &[(&path, &new_base_lines.join(""))]);
+    let new_base = CommitBuilder::for_new_commit(
+        settings,
+        vec![base.id().clone()],
+        new_base_tree.id().clone(),
+    )
+    .write_to_repo(tx.mut_repo());
+    (tx.commit(), stack, new_base)
+}
+
+/// Rebases each commit in `stack` onto the rewritten previous one, starting
+/// with `new_base`, in a transaction that is then dropped.
+fn rebase_stack(
+    settings: &UserSettings,
+    repo: &Arc<ReadonlyRepo>,
+    stack: &[Commit],
+    new_base: &Commit,
+) {
+    let mut tx = repo.start_transaction(settings, "rebase stack");
+    let mut new_parent = new_base.clone();
+    for commit in stack {
+        new_parent = rebase_commit(settings, tx.mut_repo(), commit, &[new_parent]);
+    }
+}
+
+fn rebase_50_commits_cached(b: &mut Bencher) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(false);
+    let (repo, stack, new_base) = large_file_stack(&settings, &test_repo, 50, 10000);
+    b.iter(|| rebase_stack(&settings, &repo, &stack, &new_base));
+}
+
+fn rebase_50_commits_uncached(b: &mut Bencher) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(false);
+    let (repo, stack, new_base) = large_file_stack(&settings, &test_repo, 50, 10000);
+    let store = repo.store();
+    b.iter(|| {
+        store.clear_merge_cache();
+        rebase_stack(&settings, &repo, &stack, &new_base)
+    });
+}
+
+benchmark_group!(
+    benches,
+    merge_10k_lines_cached,
+    merge_10k_lines_uncached,
+    rebase_50_commits_cached,
+    rebase_50_commits_uncached
+);
+benchmark_main!(benches);
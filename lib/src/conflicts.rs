@@ -13,21 +13,251 @@
 // limitations under the License.
 
 use std::io::{Cursor, Write};
+use std::str::FromStr;
+use std::sync::Arc;
 
 use itertools::Itertools;
+use thiserror::Error;
 
 use crate::backend::{BackendResult, Conflict, ConflictId, ConflictPart, TreeValue};
 use crate::diff::{find_line_ranges, Diff, DiffHunk};
 use crate::files;
-use crate::files::{MergeHunk, MergeResult};
+use crate::files::{
+    ConflictStats, DiffOptions, MergeHunk, MergeHunkRef, MergeResult, MergeResultRef,
+    SimplifiedHunk,
+};
 use crate::repo_path::RepoPath;
+use crate::settings::UserSettings;
 use crate::store::Store;
 
-const CONFLICT_START_LINE: &[u8] = b"<<<<<<<\n";
-const CONFLICT_END_LINE: &[u8] = b">>>>>>>\n";
-const CONFLICT_DIFF_LINE: &[u8] = b"%%%%%%%\n";
-const CONFLICT_MINUS_LINE: &[u8] = b"-------\n";
-const CONFLICT_PLUS_LINE: &[u8] = b"+++++++\n";
+/// The length of conflict markers, unless the content has lines that could
+/// be mistaken for markers of that length.
+const MIN_CONFLICT_MARKER_LEN: usize = 7;
+/// How much longer the markers get each time they need to be longer.
+const CONFLICT_MARKER_LEN_INCREMENT: usize = 4;
+
+/// The kinds of lines in materialized conflicts. Each marker is a line with
+/// a repeated character, and the start and end markers (and the base marker
+/// in the Git style) may be followed by a label, which other tools may put
+/// anything in.
+///
+/// A part of a conflict that doesn't end with a newline gets one anyway, so
+/// the next marker starts on its own line. That's recorded by a `NoNewline`
+/// marker after it, and the newline is removed again when parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConflictMarker {
+    Start,
+    End,
+    Diff,
+    Minus,
+    Plus,
+    GitBase,
+    GitSeparator,
+    NoNewline,
+}
+
+impl ConflictMarker {
+    const ALL: [ConflictMarker; 8] = [
+        ConflictMarker::Start,
+        ConflictMarker::End,
+        ConflictMarker::Diff,
+        ConflictMarker::Minus,
+        ConflictMarker::Plus,
+        ConflictMarker::GitBase,
+        ConflictMarker::GitSeparator,
+        ConflictMarker::NoNewline,
+    ];
+
+    fn char(self) -> u8 {
+        match self {
+            ConflictMarker::Start => b'<',
+            ConflictMarker::End => b'>',
+            ConflictMarker::Diff => b'%',
+            ConflictMarker::Minus => b'-',
+            ConflictMarker::Plus => b'+',
+            ConflictMarker::GitBase => b'|',
+            ConflictMarker::GitSeparator => b'=',
+            ConflictMarker::NoNewline => b'\\',
+        }
+    }
+
+    fn from_char(c: u8) -> Option<Self> {
+        ConflictMarker::ALL
+            .into_iter()
+            .find(|marker| marker.char() == c)
+    }
+}
+
+/// A parsed marker line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MarkerLine {
+    marker: ConflictMarker,
+    len: usize,
+}
+
+fn write_conflict_marker(
+    output: &mut dyn Write,
+    marker: ConflictMarker,
+    len: usize,
+    label: Option<&str>,
+) -> std::io::Result<()> {
+    output.write_all(&vec![marker.char(); len])?;
+    if let Some(label) = label {
+        output.write_all(b" ")?;
+        output.write_all(label.as_bytes())?;
+    }
+    output.write_all(b"\n")
+}
+
+/// Writes `content`, which is a part of a conflict or a line of one, and a
+/// `NoNewline` marker if it doesn't end with a newline.
+fn write_conflict_content(
+    output: &mut dyn Write,
+    content: &[u8],
+    marker_len: usize,
+) -> std::io::Result<()> {
+    output.write_all(content)?;
+    if content.is_empty() || content.ends_with(b"\n") {
+        return Ok(());
+    }
+    output.write_all(b"\n")?;
+    write_conflict_marker(
+        output,
+        ConflictMarker::NoNewline,
+        marker_len,
+        Some("No newline at end of file"),
+    )
+}
+
+/// Removes the newline that `write_conflict_content()` added to `part`.
+fn remove_added_newline(part: &mut Vec<u8>) {
+    if part.ends_with(b"\n") {
+        part.pop();
+    }
+}
+
+/// Parses a line consisting of at least `MIN_CONFLICT_MARKER_LEN` marker
+/// characters, optionally followed by whitespace and a label. The line may end
+/// in "\n" or "\r\n", or not at all if it's the last line.
+fn parse_conflict_marker(line: &[u8]) -> Option<MarkerLine> {
+    let marker = ConflictMarker::from_char(*line.first()?)?;
+    let len = line.iter().take_while(|b| **b == marker.char()).count();
+    if len < MIN_CONFLICT_MARKER_LEN {
+        return None;
+    }
+    let rest = &line[len..];
+    let rest = rest.strip_suffix(b"\n").unwrap_or(rest);
+    let rest = rest.strip_suffix(b"\r").unwrap_or(rest);
+    match rest.first() {
+        None | Some(b' ' | b'\t') => Some(MarkerLine { marker, len }),
+        _ => None,
+    }
+}
+
+/// Whether `line` is the given marker, of length `len`. A label after the
+/// marker characters is ignored.
+fn is_conflict_marker(line: &[u8], marker: ConflictMarker, len: usize) -> bool {
+    parse_conflict_marker(line) == Some(MarkerLine { marker, len })
+}
+
+/// Picks a length for the conflict markers such that no line in `contents`
+/// could be mistaken for a marker. Starts at `MIN_CONFLICT_MARKER_LEN` and
+/// grows by `CONFLICT_MARKER_LEN_INCREMENT` as needed.
+fn choose_conflict_marker_len(contents: &[&[u8]]) -> usize {
+    let longest_marker_like_line = contents
+        .iter()
+        .flat_map(|content| content.split_inclusive(|b| *b == b'\n'))
+        .filter_map(|line| {
+            let marker = ConflictMarker::from_char(*line.first()?)?;
+            let len = line.iter().take_while(|b| **b == marker.char()).count();
+            // Lines in a diff get a "-" or "+" prefix, which makes a line of
+            // those characters one longer.
+            match marker {
+                ConflictMarker::Minus | ConflictMarker::Plus => Some(len + 1),
+                _ => Some(len),
+            }
+        })
+        .max()
+        .unwrap_or(0);
+    let mut len = MIN_CONFLICT_MARKER_LEN;
+    while longest_marker_like_line >= len {
+        len += CONFLICT_MARKER_LEN_INCREMENT;
+    }
+    len
+}
+
+/// How conflicts are written to files with markers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictMarkerStyle {
+    /// Shows a diff from a removed part to the most similar added part,
+    /// followed by the remaining parts in full.
+    Diff,
+    /// Shows every removed and added part in full.
+    Snapshot,
+    /// Uses Git's "diff3" style markers. Only used for conflict hunks with
+    /// exactly two sides; other hunks are written in the `Diff` style.
+    Git,
+}
+
+impl Default for ConflictMarkerStyle {
+    fn default() -> Self {
+        ConflictMarkerStyle::Diff
+    }
+}
+
+impl FromStr for ConflictMarkerStyle {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "diff" => Ok(ConflictMarkerStyle::Diff),
+            "snapshot" => Ok(ConflictMarkerStyle::Snapshot),
+            "git" => Ok(ConflictMarkerStyle::Git),
+            _ => Err("must be one of diff, snapshot, or git"),
+        }
+    }
+}
+
+impl ConflictMarkerStyle {
+    pub fn from_settings(settings: &UserSettings) -> Result<Self, config::ConfigError> {
+        Ok(settings
+            .get_parsed("ui.conflict-marker-style")?
+            .unwrap_or_default())
+    }
+}
+
+/// Labels for the removed and added parts of a conflict, in the same order as
+/// the parts, to write on the conflict markers. Parts with a `None` label, or
+/// without an entry, are shown without one. The labels are ignored when the
+/// conflict is parsed again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConflictLabels {
+    pub removes: Vec<Option<String>>,
+    pub adds: Vec<Option<String>>,
+}
+
+impl ConflictLabels {
+    fn remove(&self, index: usize) -> Option<&str> {
+        self.removes.get(index)?.as_deref()
+    }
+
+    fn add(&self, index: usize) -> Option<&str> {
+        self.adds.get(index)?.as_deref()
+    }
+}
+
+/// Computes the labels of a conflict at a path. Used where conflicts are
+/// written without the caller knowing which ones there are, such as on
+/// checkout.
+pub type ConflictLabeler = Arc<dyn Fn(&RepoPath, &Conflict) -> ConflictLabels + Send + Sync>;
+
+/// A part of a conflict hunk to write, with the label of the conflict part it
+/// came from.
+#[derive(Clone, Copy)]
+struct LabeledPart<'a> {
+    content: &'a [u8],
+    label: Option<&'a str>,
+}
 
 fn describe_conflict_part(part: &ConflictPart) -> String {
     match &part.value {
@@ -102,23 +332,27 @@ fn get_file_contents(store: &Store, path: &RepoPath, part: &ConflictPart) -> Vec
     }
 }
 
-fn write_diff_hunks(hunks: &[DiffHunk], file: &mut dyn Write) -> std::io::Result<()> {
+fn write_diff_hunks(
+    hunks: &[DiffHunk],
+    marker_len: usize,
+    file: &mut dyn Write,
+) -> std::io::Result<()> {
     for hunk in hunks {
         match hunk {
             DiffHunk::Matching(content) => {
                 for line in content.split_inclusive(|b| *b == b'\n') {
                     file.write_all(b" ")?;
-                    file.write_all(line)?;
+                    write_conflict_content(file, line, marker_len)?;
                 }
             }
             DiffHunk::Different(content) => {
                 for line in content[0].split_inclusive(|b| *b == b'\n') {
                     file.write_all(b"-")?;
-                    file.write_all(line)?;
+                    write_conflict_content(file, line, marker_len)?;
                 }
                 for line in content[1].split_inclusive(|b| *b == b'\n') {
                     file.write_all(b"+")?;
-                    file.write_all(line)?;
+                    write_conflict_content(file, line, marker_len)?;
                 }
             }
         }
@@ -131,6 +365,51 @@ pub fn materialize_conflict(
     path: &RepoPath,
     conflict: &Conflict,
     output: &mut dyn Write,
+) -> std::io::Result<()> {
+    materialize_conflict_with_options(
+        store,
+        path,
+        conflict,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::default(),
+        output,
+    )
+}
+
+/// Like `materialize_conflict()`, but writes the markers in `marker_style`, and
+/// uses `diff_options` when deciding which added part to show as a diff
+/// against a removed part.
+pub fn materialize_conflict_with_options(
+    store: &Store,
+    path: &RepoPath,
+    conflict: &Conflict,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    materialize_conflict_with_labels(
+        store,
+        path,
+        conflict,
+        diff_options,
+        marker_style,
+        &ConflictLabels::default(),
+        output,
+    )
+}
+
+/// Like `materialize_conflict_with_options()`, but writes `labels` on the
+/// conflict markers, similar to the branch names Git writes. A part of a
+/// conflict hunk that stands for several identical parts gets the label of the
+/// first of them.
+pub fn materialize_conflict_with_labels(
+    store: &Store,
+    path: &RepoPath,
+    conflict: &Conflict,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+    labels: &ConflictLabels,
+    output: &mut dyn Write,
 ) -> std::io::Result<()> {
     let file_adds = file_parts(&conflict.adds);
     let file_removes = file_parts(&conflict.removes);
@@ -152,69 +431,365 @@ pub fn materialize_conflict(
     let removed_slices = removed_content.iter().map(Vec::as_slice).collect_vec();
     let added_slices = added_content.iter().map(Vec::as_slice).collect_vec();
 
-    let merge_result = files::merge(&removed_slices, &added_slices);
+    let marker_len = choose_conflict_marker_len(
+        &removed_slices
+            .iter()
+            .chain(&added_slices)
+            .copied()
+            .collect_vec(),
+    );
+    // The merge result borrows from the inputs, and the resolved parts are
+    // written piece by piece, so the merged content is never copied as a whole
+    let merge_result = files::merge_borrowed(&removed_slices, &added_slices);
     match merge_result {
-        MergeResult::Resolved(content) => {
-            output.write_all(&content)?;
+        MergeResultRef::Resolved(pieces) => {
+            for piece in pieces {
+                output.write_all(piece)?;
+            }
         }
-        MergeResult::Conflict(hunks) => {
+        MergeResultRef::Conflict(hunks) => {
             for hunk in hunks {
-                match hunk {
-                    MergeHunk::Resolved(content) => {
-                        output.write_all(&content)?;
-                    }
-                    MergeHunk::Conflict {
-                        mut removes,
-                        mut adds,
-                    } => {
-                        output.write_all(CONFLICT_START_LINE)?;
-                        while !removes.is_empty() && !adds.is_empty() {
-                            let left = &removes[0];
-                            let mut diffs = vec![];
-                            for right in &adds {
-                                diffs.push(
-                                    Diff::for_tokenizer(&[left, right], &find_line_ranges)
-                                        .hunks()
-                                        .collect_vec(),
-                                );
-                            }
-                            let min_diff_index = diffs
-                                .iter()
-                                .position_min_by_key(|diff| diff_size(diff))
-                                .unwrap();
-                            output.write_all(CONFLICT_DIFF_LINE)?;
-                            write_diff_hunks(&diffs[min_diff_index], output)?;
-                            removes.remove(0);
-                            adds.remove(min_diff_index);
-                        }
-
-                        for slice in removes {
-                            output.write_all(CONFLICT_MINUS_LINE)?;
-                            output.write_all(&slice)?;
+                let hunk = match hunk {
+                    MergeHunkRef::Resolved(pieces) => {
+                        for piece in pieces {
+                            output.write_all(piece)?;
                         }
-                        for slice in adds {
-                            output.write_all(CONFLICT_PLUS_LINE)?;
-                            output.write_all(&slice)?;
-                        }
-                        output.write_all(CONFLICT_END_LINE)?;
+                        continue;
                     }
-                }
+                    hunk => hunk.into_owned(),
+                };
+                write_conflict_hunk(hunk, marker_style, diff_options, labels, marker_len, output)?;
             }
         }
     }
     Ok(())
 }
 
-fn diff_size(hunks: &[DiffHunk]) -> usize {
+/// A line of resolved content next to a conflict hunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextLine<'a> {
+    /// The 1-based number of the line in the materialized file.
+    pub line_number: usize,
+    /// The line, including its newline, if any.
+    pub content: &'a [u8],
+}
+
+/// A conflict hunk of a merge result, with the resolved lines around it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictHunkContext<'a> {
+    pub hunk: &'a MergeHunk,
+    /// The 1-based number of the first line of the hunk (its first conflict
+    /// marker) in the materialized file.
+    pub line_number: usize,
+    /// Resolved lines just before the hunk. Fewer than requested at the start
+    /// of the file or next to another conflict hunk.
+    pub before: Vec<ContextLine<'a>>,
+    /// Resolved lines just after the hunk. Fewer than requested at the end of
+    /// the file or next to another conflict hunk.
+    pub after: Vec<ContextLine<'a>>,
+}
+
+/// Returns each conflict hunk of `merge_result` with up to `context_lines`
+/// resolved lines before and after it, numbered as they would be in the file
+/// written by `materialize_conflict_with_options()` with the same options.
+pub fn conflict_hunks_with_context<'a>(
+    merge_result: &'a MergeResult,
+    context_lines: usize,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+) -> Vec<ConflictHunkContext<'a>> {
+    let hunks = match merge_result {
+        MergeResult::Resolved(_) => return vec![],
+        MergeResult::Conflict { hunks, .. } => hunks,
+    };
+    let contents = hunks
+        .iter()
+        .flat_map(|hunk| match hunk {
+            MergeHunk::Resolved(content) => vec![content.as_slice()],
+            MergeHunk::Conflict { removes, adds } => {
+                removes.iter().chain(adds).map(Vec::as_slice).collect_vec()
+            }
+        })
+        .collect_vec();
+    let marker_len = choose_conflict_marker_len(&contents);
+    // The line number each hunk starts at in the materialized file
+    let mut start_line_numbers = Vec::with_capacity(hunks.len());
+    let mut line_number = 1;
+    for hunk in hunks {
+        start_line_numbers.push(line_number);
+        line_number += match hunk {
+            MergeHunk::Resolved(content) => content.iter().filter(|b| **b == b'\n').count(),
+            hunk => {
+                let mut buf = vec![];
+                write_conflict_hunk(
+                    hunk.clone(),
+                    marker_style,
+                    diff_options,
+                    &ConflictLabels::default(),
+                    marker_len,
+                    &mut buf,
+                )
+                .unwrap();
+                buf.iter().filter(|b| **b == b'\n').count()
+            }
+        };
+    }
+    // The lines of a resolved hunk, or `None` for a conflict hunk
+    let resolved_lines = |index: usize| match &hunks[index] {
+        MergeHunk::Resolved(content) => Some(
+            content
+                .split_inclusive(|b| *b == b'\n')
+                .enumerate()
+                .map(|(i, line)| ContextLine {
+                    line_number: start_line_numbers[index] + i,
+                    content: line,
+                })
+                .collect_vec(),
+        ),
+        MergeHunk::Conflict { .. } => None,
+    };
+
+    let mut result = vec![];
+    for (index, hunk) in hunks.iter().enumerate() {
+        if matches!(hunk, MergeHunk::Resolved(_)) {
+            continue;
+        }
+        let mut before = vec![];
+        for before_index in (0..index).rev() {
+            let lines = match resolved_lines(before_index) {
+                Some(lines) if before.len() < context_lines => lines,
+                _ => break,
+            };
+            let num_lines = lines.len().min(context_lines - before.len());
+            before.splice(0..0, lines[lines.len() - num_lines..].iter().copied());
+        }
+        let mut after = vec![];
+        for after_index in index + 1..hunks.len() {
+            let lines = match resolved_lines(after_index) {
+                Some(lines) if after.len() < context_lines => lines,
+                _ => break,
+            };
+            let num_lines = lines.len().min(context_lines - after.len());
+            after.extend_from_slice(&lines[..num_lines]);
+        }
+        result.push(ConflictHunkContext {
+            hunk,
+            line_number: start_line_numbers[index],
+            before,
+            after,
+        });
+    }
+    result
+}
+
+/// Writes a conflict hunk with markers of the given style.
+fn write_conflict_hunk(
+    hunk: MergeHunk,
+    marker_style: ConflictMarkerStyle,
+    diff_options: &DiffOptions,
+    labels: &ConflictLabels,
+    marker_len: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    // Conflict hunks are shown with as few parts as possible.
+    // `update_conflict_from_content()` expands them again.
+    let (removes, adds) = match hunk.simplify() {
+        SimplifiedHunk::Resolved(content) => return output.write_all(&content),
+        SimplifiedHunk::Conflict { removes, adds } => (removes, adds),
+    };
+    let removes = removes
+        .iter()
+        .map(|part| LabeledPart {
+            content: &part.content,
+            label: labels.remove(part.indices[0]),
+        })
+        .collect_vec();
+    let adds = adds
+        .iter()
+        .map(|part| LabeledPart {
+            content: &part.content,
+            label: labels.add(part.indices[0]),
+        })
+        .collect_vec();
+    match marker_style {
+        ConflictMarkerStyle::Git if removes.len() == 1 && adds.len() == 2 => {
+            write_git_conflict_hunk(removes[0], adds[0], adds[1], marker_len, output)
+        }
+        ConflictMarkerStyle::Snapshot => {
+            write_snapshot_conflict_hunk(removes, adds, marker_len, output)
+        }
+        ConflictMarkerStyle::Diff | ConflictMarkerStyle::Git => {
+            write_diff_conflict_hunk(removes, adds, diff_options, marker_len, output)
+        }
+    }
+}
+
+fn write_diff_conflict_hunk(
+    mut removes: Vec<LabeledPart>,
+    mut adds: Vec<LabeledPart>,
+    diff_options: &DiffOptions,
+    marker_len: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    write_conflict_marker(output, ConflictMarker::Start, marker_len, None)?;
+    while !removes.is_empty() && !adds.is_empty() {
+        let left = removes[0];
+        let mut diffs = vec![];
+        for right in &adds {
+            diffs.push(
+                Diff::for_tokenizer(&[left.content, right.content], &find_line_ranges)
+                    .hunks()
+                    .collect_vec(),
+            );
+        }
+        let min_diff_index = diffs
+            .iter()
+            .position_min_by_key(|diff| diff_size(diff, diff_options))
+            .unwrap();
+        let label = match (left.label, adds[min_diff_index].label) {
+            (Some(from), Some(to)) => Some(format!("diff from {from} to {to}")),
+            _ => None,
+        };
+        write_conflict_marker(output, ConflictMarker::Diff, marker_len, label.as_deref())?;
+        write_diff_hunks(&diffs[min_diff_index], marker_len, output)?;
+        removes.remove(0);
+        adds.remove(min_diff_index);
+    }
+
+    for part in removes {
+        write_conflict_marker(output, ConflictMarker::Minus, marker_len, part.label)?;
+        write_conflict_content(output, part.content, marker_len)?;
+    }
+    for part in adds {
+        write_conflict_marker(output, ConflictMarker::Plus, marker_len, part.label)?;
+        write_conflict_content(output, part.content, marker_len)?;
+    }
+    write_conflict_marker(output, ConflictMarker::End, marker_len, None)
+}
+
+fn write_snapshot_conflict_hunk(
+    removes: Vec<LabeledPart>,
+    adds: Vec<LabeledPart>,
+    marker_len: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    write_conflict_marker(output, ConflictMarker::Start, marker_len, None)?;
+    // Alternate between the adds and the removes, so a 3-way conflict reads as
+    // side, base, side.
+    let mut removes = removes.into_iter();
+    for add in adds {
+        write_conflict_marker(output, ConflictMarker::Plus, marker_len, add.label)?;
+        write_conflict_content(output, add.content, marker_len)?;
+        if let Some(remove) = removes.next() {
+            write_conflict_marker(output, ConflictMarker::Minus, marker_len, remove.label)?;
+            write_conflict_content(output, remove.content, marker_len)?;
+        }
+    }
+    for remove in removes {
+        write_conflict_marker(output, ConflictMarker::Minus, marker_len, remove.label)?;
+        write_conflict_content(output, remove.content, marker_len)?;
+    }
+    write_conflict_marker(output, ConflictMarker::End, marker_len, None)
+}
+
+fn write_git_conflict_hunk(
+    base: LabeledPart,
+    side1: LabeledPart,
+    side2: LabeledPart,
+    marker_len: usize,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    write_conflict_marker(
+        output,
+        ConflictMarker::Start,
+        marker_len,
+        Some(side1.label.unwrap_or("Side #1")),
+    )?;
+    write_conflict_content(output, side1.content, marker_len)?;
+    write_conflict_marker(
+        output,
+        ConflictMarker::GitBase,
+        marker_len,
+        Some(base.label.unwrap_or("Base")),
+    )?;
+    write_conflict_content(output, base.content, marker_len)?;
+    write_conflict_marker(output, ConflictMarker::GitSeparator, marker_len, None)?;
+    write_conflict_content(output, side2.content, marker_len)?;
+    write_conflict_marker(
+        output,
+        ConflictMarker::End,
+        marker_len,
+        Some(side2.label.unwrap_or("Side #2")),
+    )
+}
+
+fn diff_size(hunks: &[DiffHunk], diff_options: &DiffOptions) -> usize {
     hunks
         .iter()
         .map(|hunk| match hunk {
             DiffHunk::Matching(_) => 0,
+            DiffHunk::Different(slices)
+                if diff_options.ignore_whitespace
+                    && files::equal_ignoring_whitespace(slices[0], slices[1]) =>
+            {
+                0
+            }
             DiffHunk::Different(slices) => slices.iter().map(|slice| slice.len()).sum(),
         })
         .sum()
 }
 
+/// Merges the file contents of a conflict the same way as when materializing
+/// it, so the hunks line up with the materialized conflict markers. Returns
+/// `None` unless all parts of the conflict are regular files.
+pub fn merge_conflict_contents(
+    store: &Store,
+    path: &RepoPath,
+    conflict: &Conflict,
+) -> Option<MergeResult> {
+    let file_adds = file_parts(&conflict.adds);
+    let file_removes = file_parts(&conflict.removes);
+    if file_adds.len() != conflict.adds.len() || file_removes.len() != conflict.removes.len() {
+        return None;
+    }
+    let added_content = file_adds
+        .iter()
+        .map(|part| get_file_contents(store, path, part))
+        .collect_vec();
+    let removed_content = file_removes
+        .iter()
+        .map(|part| get_file_contents(store, path, part))
+        .collect_vec();
+    // Parts of a conflict can be missing, such as when a side deleted the file,
+    // so the removes and adds don't necessarily make up a `MergeInput`
+    let result = files::merge_borrowed(
+        &removed_content.iter().map(Vec::as_slice).collect_vec(),
+        &added_content.iter().map(Vec::as_slice).collect_vec(),
+    );
+    Some(result.into_owned())
+}
+
+/// Returns statistics about a conflict in the tree. The file contents are
+/// merged like by `merge_conflict_contents()`. A conflict whose parts aren't
+/// all regular files, or whose contents merge cleanly, counts as a single
+/// conflict hunk. A conflict with a side that deleted the path always involves
+/// a deletion.
+pub fn stored_conflict_stats(store: &Store, path: &RepoPath, conflict: &Conflict) -> ConflictStats {
+    let mut stats = match merge_conflict_contents(store, path, conflict) {
+        Some(result @ MergeResult::Conflict { .. }) => files::conflict_stats(&result),
+        _ => ConflictStats {
+            num_hunks: 1,
+            num_sides: conflict.adds.len(),
+            num_lines: 0,
+            num_deletion_hunks: 0,
+        },
+    };
+    if conflict.adds.len() <= conflict.removes.len() {
+        stats.num_deletion_hunks = stats.num_deletion_hunks.max(1);
+    }
+    stats
+}
+
 pub fn conflict_to_materialized_value(
     store: &Store,
     path: &RepoPath,
@@ -232,35 +807,64 @@ pub fn conflict_to_materialized_value(
 /// Parses conflict markers from a slice. Returns None if there were no valid
 /// conflict markers. The caller has to provide the expected number of removed
 /// and added inputs to the conflicts. Conflict markers that are otherwise valid
-/// will be considered invalid if they don't have the expected arity.
+/// will be considered invalid if they don't have the expected arity. Markers in
+/// any `ConflictMarkerStyle` are accepted, and of any length of at least
+/// `MIN_CONFLICT_MARKER_LEN`, as long as all markers of a conflict have the
+/// same length.
 // TODO: "parse" is not usually the opposite of "materialize", so maybe we
 // should rename them to "serialize" and "deserialize"?
 pub fn parse_conflict(input: &[u8], num_removes: usize, num_adds: usize) -> Option<Vec<MergeHunk>> {
+    parse_conflict_with(input, |hunk| match &hunk {
+        MergeHunk::Conflict { removes, adds }
+            if removes.len() == num_removes && adds.len() == num_adds =>
+        {
+            Some(hunk)
+        }
+        _ => None,
+    })
+}
+
+/// Like `parse_conflict()`, but each parsed conflict hunk is passed to
+/// `accept_hunk`, which returns the hunk to use, or `None` to treat the
+/// conflict markers as regular content.
+fn parse_conflict_with(
+    input: &[u8],
+    mut accept_hunk: impl FnMut(MergeHunk) -> Option<MergeHunk>,
+) -> Option<Vec<MergeHunk>> {
     if input.is_empty() {
         return None;
     }
     let mut hunks = vec![];
     let mut pos = 0;
     let mut resolved_start = 0;
-    let mut conflict_start = None;
+    // The position where the current conflict started, the length of the start
+    // line, and the parsed start line
+    let mut conflict_start: Option<(usize, usize, MarkerLine)> = None;
     for line in input.split_inclusive(|b| *b == b'\n') {
-        if line == CONFLICT_START_LINE {
-            conflict_start = Some(pos);
-        } else if conflict_start.is_some() && line == CONFLICT_END_LINE {
-            let conflict_body = &input[conflict_start.unwrap() + CONFLICT_START_LINE.len()..pos];
-            let hunk = parse_conflict_hunk(conflict_body);
-            match &hunk {
-                MergeHunk::Conflict { removes, adds }
-                    if removes.len() == num_removes && adds.len() == num_adds =>
-                {
-                    let resolved_slice = &input[resolved_start..conflict_start.unwrap()];
-                    if !resolved_slice.is_empty() {
-                        hunks.push(MergeHunk::Resolved(resolved_slice.to_vec()));
-                    }
-                    hunks.push(hunk);
-                    resolved_start = pos + line.len();
+        let marker_line = parse_conflict_marker(line);
+        // Content that looks like markers is always shorter than the real
+        // markers, so a shorter start marker doesn't start a new conflict
+        if let Some(start) = marker_line.filter(|m| {
+            m.marker == ConflictMarker::Start
+                && conflict_start.map_or(true, |(_, _, current)| m.len >= current.len)
+        }) {
+            conflict_start = Some((pos, line.len(), start));
+        } else if let Some((start_pos, start_line_len, start)) =
+            conflict_start.filter(|(_, _, start)| {
+                marker_line.map_or(false, |m| {
+                    m.marker == ConflictMarker::End && m.len == start.len
+                })
+            })
+        {
+            let conflict_body = &input[start_pos + start_line_len..pos];
+            let hunk = parse_conflict_body(conflict_body, start.len);
+            if let Some(hunk) = accept_hunk(hunk) {
+                let resolved_slice = &input[resolved_start..start_pos];
+                if !resolved_slice.is_empty() {
+                    hunks.push(MergeHunk::Resolved(resolved_slice.to_vec()));
                 }
-                _ => {}
+                hunks.push(hunk);
+                resolved_start = pos + line.len();
             }
             conflict_start = None;
         }
@@ -277,7 +881,106 @@ pub fn parse_conflict(input: &[u8], num_removes: usize, num_adds: usize) -> Opti
     }
 }
 
-fn parse_conflict_hunk(input: &[u8]) -> MergeHunk {
+/// Parses the lines between a start and an end marker, in whichever style
+/// they're in. Returns an empty `MergeHunk::Resolved` if they don't look like a
+/// conflict in any style.
+fn parse_conflict_body(body: &[u8], marker_len: usize) -> MergeHunk {
+    // Conflicts in our own styles start with one of our markers
+    let is_own_style = body
+        .split_inclusive(|b| *b == b'\n')
+        .next()
+        .and_then(parse_conflict_marker)
+        .map_or(false, |m| {
+            matches!(
+                m.marker,
+                ConflictMarker::Diff | ConflictMarker::Minus | ConflictMarker::Plus
+            ) && m.len == marker_len
+        });
+    if is_own_style {
+        parse_conflict_hunk(body, marker_len)
+    } else {
+        parse_git_conflict_hunk(body, marker_len)
+    }
+}
+
+/// What's wrong with the conflict markers in a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum ConflictParseErrorKind {
+    #[error("conflict start marker without an end marker")]
+    UnclosedConflict,
+    #[error("conflict end marker without a start marker")]
+    UnmatchedEndMarker,
+    #[error("conflict start marker inside another conflict")]
+    NestedStartMarker,
+    #[error("conflict markers don't match any conflict style")]
+    InvalidConflict,
+}
+
+/// Malformed conflict markers, found by `check_conflict_markers()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("{kind} at line {line_number}")]
+pub struct ConflictParseError {
+    /// The 1-based number of the offending line. For conflicts that are
+    /// unclosed or invalid, this is the line of the start marker.
+    pub line_number: usize,
+    pub kind: ConflictParseErrorKind,
+}
+
+/// Checks that the conflict markers in `input` make up well-formed conflicts,
+/// the way `parse_conflict()` would find them. Returns the first problem
+/// found, so callers can tell the user why their markers were treated as
+/// regular content.
+pub fn check_conflict_markers(input: &[u8]) -> Result<(), ConflictParseError> {
+    let mut pos = 0;
+    // The line number of the start marker of the current conflict, the
+    // position after it, and the parsed start marker
+    let mut conflict_start: Option<(usize, usize, MarkerLine)> = None;
+    for (index, line) in input.split_inclusive(|b| *b == b'\n').enumerate() {
+        let line_number = index + 1;
+        match (parse_conflict_marker(line), conflict_start) {
+            (Some(m), None) if m.marker == ConflictMarker::Start => {
+                conflict_start = Some((line_number, pos + line.len(), m));
+            }
+            (Some(m), Some((_, _, start)))
+                if m.marker == ConflictMarker::Start && m.len >= start.len =>
+            {
+                return Err(ConflictParseError {
+                    line_number,
+                    kind: ConflictParseErrorKind::NestedStartMarker,
+                });
+            }
+            (Some(m), None) if m.marker == ConflictMarker::End => {
+                return Err(ConflictParseError {
+                    line_number,
+                    kind: ConflictParseErrorKind::UnmatchedEndMarker,
+                });
+            }
+            (Some(m), Some((start_line_number, body_start, start)))
+                if m.marker == ConflictMarker::End && m.len == start.len =>
+            {
+                let body = &input[body_start..pos];
+                if matches!(parse_conflict_body(body, start.len), MergeHunk::Resolved(_)) {
+                    return Err(ConflictParseError {
+                        line_number: start_line_number,
+                        kind: ConflictParseErrorKind::InvalidConflict,
+                    });
+                }
+                conflict_start = None;
+            }
+            _ => {}
+        }
+        pos += line.len();
+    }
+    match conflict_start {
+        Some((line_number, _, _)) => Err(ConflictParseError {
+            line_number,
+            kind: ConflictParseErrorKind::UnclosedConflict,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn parse_conflict_hunk(input: &[u8], marker_len: usize) -> MergeHunk {
     enum State {
         Diff,
         Minus,
@@ -285,30 +988,41 @@ fn parse_conflict_hunk(input: &[u8]) -> MergeHunk {
         Unknown,
     }
     let mut state = State::Unknown;
-    let mut removes = vec![];
-    let mut adds = vec![];
+    let mut removes: Vec<Vec<u8>> = vec![];
+    let mut adds: Vec<Vec<u8>> = vec![];
+    // The prefix of the previous line in a diff
+    let mut diff_prefix = None;
     for line in input.split_inclusive(|b| *b == b'\n') {
-        match line {
-            CONFLICT_DIFF_LINE => {
-                state = State::Diff;
-                removes.push(vec![]);
-                adds.push(vec![]);
-                continue;
-            }
-            CONFLICT_MINUS_LINE => {
-                state = State::Minus;
-                removes.push(vec![]);
-                continue;
+        if is_conflict_marker(line, ConflictMarker::NoNewline, marker_len) {
+            let in_removes = matches!(state, State::Minus)
+                || (matches!(state, State::Diff) && matches!(diff_prefix, Some(b'-' | b' ')));
+            let in_adds = matches!(state, State::Plus)
+                || (matches!(state, State::Diff) && matches!(diff_prefix, Some(b'+' | b' ')));
+            if in_removes {
+                remove_added_newline(removes.last_mut().unwrap());
             }
-            CONFLICT_PLUS_LINE => {
-                state = State::Plus;
-                adds.push(vec![]);
-                continue;
+            if in_adds {
+                remove_added_newline(adds.last_mut().unwrap());
             }
-            _ => {}
-        };
+            continue;
+        } else if is_conflict_marker(line, ConflictMarker::Diff, marker_len) {
+            state = State::Diff;
+            diff_prefix = None;
+            removes.push(vec![]);
+            adds.push(vec![]);
+            continue;
+        } else if is_conflict_marker(line, ConflictMarker::Minus, marker_len) {
+            state = State::Minus;
+            removes.push(vec![]);
+            continue;
+        } else if is_conflict_marker(line, ConflictMarker::Plus, marker_len) {
+            state = State::Plus;
+            adds.push(vec![]);
+            continue;
+        }
         match state {
             State::Diff => {
+                diff_prefix = line.first().copied();
                 if let Some(rest) = line.strip_prefix(b"-") {
                     removes.last_mut().unwrap().extend_from_slice(rest);
                 } else if let Some(rest) = line.strip_prefix(b"+") {
@@ -337,13 +1051,77 @@ fn parse_conflict_hunk(input: &[u8]) -> MergeHunk {
     MergeHunk::Conflict { removes, adds }
 }
 
+fn parse_git_conflict_hunk(input: &[u8], marker_len: usize) -> MergeHunk {
+    enum State {
+        Side1,
+        Base,
+        Side2,
+    }
+    let mut state = State::Side1;
+    let mut side1 = vec![];
+    let mut base = vec![];
+    let mut side2 = vec![];
+    for line in input.split_inclusive(|b| *b == b'\n') {
+        if is_conflict_marker(line, ConflictMarker::NoNewline, marker_len) {
+            remove_added_newline(match state {
+                State::Side1 => &mut side1,
+                State::Base => &mut base,
+                State::Side2 => &mut side2,
+            });
+            continue;
+        }
+        match state {
+            State::Side1
+                if parse_conflict_marker(line).map_or(false, |m| {
+                    m.marker == ConflictMarker::GitBase && m.len == marker_len
+                }) =>
+            {
+                state = State::Base;
+            }
+            State::Side1 => side1.extend_from_slice(line),
+            State::Base if is_conflict_marker(line, ConflictMarker::GitSeparator, marker_len) => {
+                state = State::Side2;
+            }
+            State::Base => base.extend_from_slice(line),
+            State::Side2 => side2.extend_from_slice(line),
+        }
+    }
+    match state {
+        State::Side2 => MergeHunk::Conflict {
+            removes: vec![base],
+            adds: vec![side1, side2],
+        },
+        // Without the base, we can't tell what each side changed
+        State::Side1 | State::Base => MergeHunk::Resolved(vec![]),
+    }
+}
+
 pub fn update_conflict_from_content(
     store: &Store,
     path: &RepoPath,
     conflict_id: &ConflictId,
     content: &[u8],
 ) -> BackendResult<Option<ConflictId>> {
-    let mut conflict = store.read_conflict(path, conflict_id)?;
+    update_conflict_from_content_with_style(
+        store,
+        path,
+        conflict_id,
+        ConflictMarkerStyle::default(),
+        content,
+    )
+}
+
+/// Like `update_conflict_from_content()`, but for content that was
+/// materialized in `marker_style`. The content may use any style; this is only
+/// used to tell if it's unchanged.
+pub fn update_conflict_from_content_with_style(
+    store: &Store,
+    path: &RepoPath,
+    conflict_id: &ConflictId,
+    marker_style: ConflictMarkerStyle,
+    content: &[u8],
+) -> BackendResult<Option<ConflictId>> {
+    let conflict = store.read_conflict(path, conflict_id)?;
 
     // First check if the new content is unchanged compared to the old content. If
     // it is, we don't need parse the content or write any new objects to the
@@ -351,59 +1129,141 @@ pub fn update_conflict_from_content(
     // conflicts (for example) are not converted to regular files in the working
     // copy.
     let mut old_content = Vec::with_capacity(content.len());
-    materialize_conflict(store, path, &conflict, &mut old_content).unwrap();
+    materialize_conflict_with_options(
+        store,
+        path,
+        &conflict,
+        &DiffOptions::default(),
+        marker_style,
+        &mut old_content,
+    )
+    .unwrap();
     if content == old_content {
         return Ok(Some(conflict_id.clone()));
     }
 
+    // The content has the conflict hunks in their simplified form, so we need
+    // the original ones to expand them again
+    let original_hunks = match merge_conflict_contents(store, path, &conflict) {
+        Some(MergeResult::Conflict { hunks, .. }) => hunks
+            .into_iter()
+            .filter(|hunk| matches!(hunk, MergeHunk::Conflict { .. }))
+            .map(|hunk| {
+                let simplified = hunk.simplify();
+                (hunk, simplified)
+            })
+            .collect_vec(),
+        _ => vec![],
+    };
+    let mut remaining_hunks = original_hunks.as_slice();
+    let num_removes = conflict.removes.len();
+    let num_adds = conflict.adds.len();
+    let parsed_hunks = parse_conflict_with(content, |hunk| {
+        let (removes, adds) = match hunk {
+            MergeHunk::Conflict { removes, adds } => (removes, adds),
+            MergeHunk::Resolved(_) => return None,
+        };
+        if removes.len() == num_removes && adds.len() == num_adds {
+            return Some(MergeHunk::Conflict { removes, adds });
+        }
+        // Pair the hunk with the next original hunk that was simplified to the
+        // same parts, or failing that, to the same number of parts, so hunks
+        // the user resolved are skipped.
+        let same_parts = |simplified: &SimplifiedHunk| {
+            simplified
+                .conflict_parts()
+                .map_or(false, |(old_removes, old_adds)| {
+                    old_removes
+                        .iter()
+                        .copied()
+                        .sorted()
+                        .eq(removes.iter().map(Vec::as_slice).sorted())
+                        && old_adds
+                            .iter()
+                            .copied()
+                            .sorted()
+                            .eq(adds.iter().map(Vec::as_slice).sorted())
+                })
+        };
+        let same_shape = |simplified: &SimplifiedHunk| {
+            simplified
+                .conflict_parts()
+                .map_or(false, |(old_removes, old_adds)| {
+                    old_removes.len() == removes.len() && old_adds.len() == adds.len()
+                })
+        };
+        let index = remaining_hunks
+            .iter()
+            .position(|(_, simplified)| same_parts(simplified))
+            .or_else(|| {
+                remaining_hunks
+                    .iter()
+                    .position(|(_, simplified)| same_shape(simplified))
+            })?;
+        let candidates = remaining_hunks;
+        let (original, simplified) = &candidates[index];
+        remaining_hunks = &candidates[index + 1..];
+        simplified.expand(original, removes, adds)
+    });
+
+    match parsed_hunks {
+        Some(hunks) => update_conflict_from_hunks(store, path, conflict, &hunks).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Writes a conflict whose sides have the contents of the sides of `hunks`,
+/// which must have as many removes and adds as `conflict`, and returns its ID.
+/// This is used to record a conflict that was edited outside of jj, e.g. in a
+/// file with conflict markers or in a conflict sidecar file.
+pub fn update_conflict_from_hunks(
+    store: &Store,
+    path: &RepoPath,
+    mut conflict: Conflict,
+    hunks: &[MergeHunk],
+) -> BackendResult<ConflictId> {
     let mut removed_content = vec![vec![]; conflict.removes.len()];
     let mut added_content = vec![vec![]; conflict.adds.len()];
-    if let Some(hunks) = parse_conflict(content, conflict.removes.len(), conflict.adds.len()) {
-        for hunk in hunks {
-            match hunk {
-                MergeHunk::Resolved(slice) => {
-                    for buf in &mut removed_content {
-                        buf.extend_from_slice(&slice);
-                    }
-                    for buf in &mut added_content {
-                        buf.extend_from_slice(&slice);
-                    }
+    for hunk in hunks {
+        match hunk {
+            MergeHunk::Resolved(slice) => {
+                for buf in &mut removed_content {
+                    buf.extend_from_slice(slice);
                 }
-                MergeHunk::Conflict { removes, adds } => {
-                    for (i, buf) in removes.iter().enumerate() {
-                        removed_content[i].extend_from_slice(buf);
-                    }
-                    for (i, buf) in adds.iter().enumerate() {
-                        added_content[i].extend_from_slice(buf);
-                    }
+                for buf in &mut added_content {
+                    buf.extend_from_slice(slice);
                 }
             }
-        }
-        // Now write the new files contents we found by parsing the file
-        // with conflict markers. Update the Conflict object with the new
-        // FileIds.
-        for (i, buf) in removed_content.iter().enumerate() {
-            let file_id = store.write_file(path, &mut Cursor::new(buf))?;
-            if let TreeValue::File { id, executable: _ } = &mut conflict.removes[i].value {
-                *id = file_id;
-            } else {
-                // TODO: This can actually happen. We should check earlier
-                // that the we only attempt to parse the conflicts if it's a
-                // file-only conflict.
-                panic!("Found conflict markers in merge of non-files");
+            MergeHunk::Conflict { removes, adds } => {
+                for (i, buf) in removes.iter().enumerate() {
+                    removed_content[i].extend_from_slice(buf);
+                }
+                for (i, buf) in adds.iter().enumerate() {
+                    added_content[i].extend_from_slice(buf);
+                }
             }
         }
-        for (i, buf) in added_content.iter().enumerate() {
-            let file_id = store.write_file(path, &mut Cursor::new(buf))?;
-            if let TreeValue::File { id, executable: _ } = &mut conflict.adds[i].value {
-                *id = file_id;
-            } else {
-                panic!("Found conflict markers in merge of non-files");
-            }
+    }
+    // Now write the new files contents we found in the hunks. Update the
+    // Conflict object with the new FileIds.
+    for (i, buf) in removed_content.iter().enumerate() {
+        let file_id = store.write_file(path, &mut Cursor::new(buf))?;
+        if let TreeValue::File { id, executable: _ } = &mut conflict.removes[i].value {
+            *id = file_id;
+        } else {
+            // TODO: This can actually happen. We should check earlier
+            // that the we only attempt to parse the conflicts if it's a
+            // file-only conflict.
+            panic!("Found conflict markers in merge of non-files");
+        }
+    }
+    for (i, buf) in added_content.iter().enumerate() {
+        let file_id = store.write_file(path, &mut Cursor::new(buf))?;
+        if let TreeValue::File { id, executable: _ } = &mut conflict.adds[i].value {
+            *id = file_id;
+        } else {
+            panic!("Found conflict markers in merge of non-files");
         }
-        let new_conflict_id = store.write_conflict(path, &conflict)?;
-        Ok(Some(new_conflict_id))
-    } else {
-        Ok(None)
     }
+    store.write_conflict(path, &conflict)
 }
@@ -14,7 +14,7 @@
 
 use std::cmp::{max, min, Ordering};
 use std::collections::{BTreeMap, HashMap};
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::ops::Range;
 use std::slice;
 
@@ -246,26 +246,44 @@ pub(crate) fn unchanged_ranges(
     }
 
     let lcs = find_lcs(&left_index_by_right_index);
+    let left_positions = left_positions.iter().map(|(pos, _, _)| *pos).collect_vec();
+    let right_positions = right_positions.iter().map(|(pos, _, _)| *pos).collect_vec();
+    unchanged_ranges_from_lcs(
+        (left, left_ranges, &left_positions),
+        (right, right_ranges, &right_positions),
+        &lcs,
+        unchanged_ranges,
+    )
+}
 
-    // Produce output ranges, recursing into the modified areas between the elements
-    // in the LCS.
+/// A function like `unchanged_ranges()`.
+type UnchangedRangesFn =
+    fn(&[u8], &[u8], &[Range<usize>], &[Range<usize>]) -> Vec<(Range<usize>, Range<usize>)>;
+
+/// Produces the unchanged ranges given by an LCS of the tokens at
+/// `left_positions` and `right_positions`, calling `recurse` on the modified
+/// areas between the elements in the LCS.
+fn unchanged_ranges_from_lcs(
+    (left, left_ranges, left_positions): (&[u8], &[Range<usize>], &[usize]),
+    (right, right_ranges, right_positions): (&[u8], &[Range<usize>], &[usize]),
+    lcs: &[(usize, usize)],
+    recurse: UnchangedRangesFn,
+) -> Vec<(Range<usize>, Range<usize>)> {
     let mut result = vec![];
     let mut previous_left_position = 0;
     let mut previous_right_position = 0;
-    for (left_index, right_index) in lcs {
-        let left_position = left_positions[left_index].0;
-        let right_position = right_positions[right_index].0;
+    for &(left_index, right_index) in lcs {
+        let left_position = left_positions[left_index];
+        let right_position = right_positions[right_index];
         let skipped_left_positions = previous_left_position..left_position;
         let skipped_right_positions = previous_right_position..right_position;
         if !skipped_left_positions.is_empty() || !skipped_right_positions.is_empty() {
-            for unchanged_nested_range in unchanged_ranges(
+            result.extend(recurse(
                 left,
                 right,
-                &left_ranges[skipped_left_positions.clone()],
-                &right_ranges[skipped_right_positions.clone()],
-            ) {
-                result.push(unchanged_nested_range);
-            }
+                &left_ranges[skipped_left_positions],
+                &right_ranges[skipped_right_positions],
+            ));
         }
         result.push((
             left_ranges[left_position].clone(),
@@ -278,17 +296,91 @@ pub(crate) fn unchanged_ranges(
     let skipped_left_positions = previous_left_position..left_ranges.len();
     let skipped_right_positions = previous_right_position..right_ranges.len();
     if !skipped_left_positions.is_empty() || !skipped_right_positions.is_empty() {
-        for unchanged_nested_range in unchanged_ranges(
+        result.extend(recurse(
             left,
             right,
             &left_ranges[skipped_left_positions],
             &right_ranges[skipped_right_positions],
-        ) {
-            result.push(unchanged_nested_range);
+        ));
+    }
+    result
+}
+
+/// Like `unchanged_ranges()`, but uses patience diff: only the tokens that
+/// occur exactly once in each input are lined up, and the areas between them
+/// are diffed recursively. Unlike `unchanged_ranges()`, tokens that are common
+/// in both inputs (such as closing braces) never anchor the diff, which tends
+/// to keep changed functions and blocks together. Areas without any such
+/// unique tokens fall back to `unchanged_ranges()`.
+pub(crate) fn patience_unchanged_ranges(
+    left: &[u8],
+    right: &[u8],
+    left_ranges: &[Range<usize>],
+    right_ranges: &[Range<usize>],
+) -> Vec<(Range<usize>, Range<usize>)> {
+    if left_ranges.is_empty() || right_ranges.is_empty() {
+        return vec![];
+    }
+
+    // The position of each token in each input, or None if it occurs more than
+    // once in that input
+    let mut token_positions: HashMap<&[u8], (Option<usize>, Option<usize>)> = HashMap::new();
+    let mut left_counts: HashMap<&[u8], usize> = HashMap::new();
+    for (i, range) in left_ranges.iter().enumerate() {
+        let token = &left[range.clone()];
+        let count = left_counts.entry(token).or_default();
+        *count += 1;
+        token_positions.insert(token, (if *count == 1 { Some(i) } else { None }, None));
+    }
+    let mut right_counts: HashMap<&[u8], usize> = HashMap::new();
+    for (i, range) in right_ranges.iter().enumerate() {
+        let token = &right[range.clone()];
+        let count = right_counts.entry(token).or_default();
+        *count += 1;
+        if let Some((_, right_position)) = token_positions.get_mut(token) {
+            *right_position = if *count == 1 { Some(i) } else { None };
         }
     }
+    let mut unique_pairs = token_positions
+        .values()
+        .filter_map(|positions| match positions {
+            (Some(left_position), Some(right_position)) => Some((*left_position, *right_position)),
+            _ => None,
+        })
+        .collect_vec();
+    if unique_pairs.is_empty() {
+        return unchanged_ranges(left, right, left_ranges, right_ranges);
+    }
+
+    // Same as in `unchanged_ranges()`: walk the unique tokens in the right input
+    // in order, and find the LCS of their indexes in the left input.
+    unique_pairs.sort();
+    let left_positions = unique_pairs
+        .iter()
+        .map(|(left_position, _)| *left_position)
+        .collect_vec();
+    let mut by_right_position = unique_pairs
+        .iter()
+        .enumerate()
+        .map(|(left_index, (_, right_position))| (*right_position, left_index))
+        .collect_vec();
+    by_right_position.sort();
+    let right_positions = by_right_position
+        .iter()
+        .map(|(right_position, _)| *right_position)
+        .collect_vec();
+    let left_index_by_right_index = by_right_position
+        .iter()
+        .map(|(_, left_index)| *left_index)
+        .collect_vec();
 
-    result
+    let lcs = find_lcs(&left_index_by_right_index);
+    unchanged_ranges_from_lcs(
+        (left, left_ranges, &left_positions),
+        (right, right_ranges, &right_positions),
+        &lcs,
+        patience_unchanged_ranges,
+    )
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -387,6 +479,23 @@ impl<'input> Diff<'input> {
     pub fn for_tokenizer(
         inputs: &[&'input [u8]],
         tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+    ) -> Self {
+        Diff::for_tokenizer_with(inputs, tokenizer, unchanged_ranges)
+    }
+
+    /// Like `for_tokenizer()`, but uses patience diff to line up the tokens.
+    /// See `patience_diff()`.
+    pub fn for_tokenizer_patience(
+        inputs: &[&'input [u8]],
+        tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+    ) -> Self {
+        Diff::for_tokenizer_with(inputs, tokenizer, patience_unchanged_ranges)
+    }
+
+    fn for_tokenizer_with(
+        inputs: &[&'input [u8]],
+        tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+        find_unchanged_ranges: UnchangedRangesFn,
     ) -> Self {
         assert!(!inputs.is_empty());
         let base_input = inputs[0];
@@ -407,7 +516,7 @@ impl<'input> Diff<'input> {
             offsets: vec![],
         }];
         for (i, other_token_ranges) in other_token_ranges.iter().enumerate() {
-            let unchanged_diff_ranges = unchanged_ranges(
+            let unchanged_diff_ranges = find_unchanged_ranges(
                 base_input,
                 other_inputs[i],
                 &base_token_ranges,
@@ -565,6 +674,27 @@ impl Debug for DiffHunk<'_> {
     }
 }
 
+/// Renders matching text verbatim and differing text inline as
+/// `{-left-}{+right+}`, omitting empty sides. A hunk with more than two inputs
+/// shows each input after the first as an addition.
+impl Display for DiffHunk<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            DiffHunk::Matching(slice) => write!(f, "{}", String::from_utf8_lossy(slice)),
+            DiffHunk::Different(slices) => {
+                for (i, slice) in slices.iter().enumerate() {
+                    if slice.is_empty() {
+                        continue;
+                    }
+                    let sign = if i == 0 { '-' } else { '+' };
+                    write!(f, "{{{sign}{}{sign}}}", String::from_utf8_lossy(slice))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 pub struct DiffHunkIterator<'diff, 'input> {
     diff: &'diff Diff<'input>,
     previous: UnchangedRange,
@@ -626,6 +756,29 @@ pub fn diff<'a>(left: &'a [u8], right: &'a [u8]) -> Vec<DiffHunk<'a>> {
         .collect_vec()
 }
 
+/// Like `diff()`, but lines up the lines using patience diff: only lines that
+/// occur exactly once in each input are used as anchors. That gives more
+/// readable diffs of code with many repeated lines, such as closing braces or
+/// blank lines. The changed ranges are refined at the word level like in
+/// `diff()`. If no line occurs more than once in either input, the result is
+/// the same as from `diff()`.
+pub fn patience_diff<'a>(left: &'a [u8], right: &'a [u8]) -> Vec<DiffHunk<'a>> {
+    if left == right {
+        return vec![DiffHunk::Matching(left)];
+    }
+    if left.is_empty() {
+        return vec![DiffHunk::Different(vec![b"", right])];
+    }
+    if right.is_empty() {
+        return vec![DiffHunk::Different(vec![left, b""])];
+    }
+
+    let mut diff = Diff::for_tokenizer_patience(&[left, right], &find_line_ranges);
+    diff.refine_changed_regions(&find_word_ranges);
+    diff.refine_changed_regions(&find_nonword_ranges);
+    diff.hunks().collect_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -982,6 +1135,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_diff_hunk_display() {
+        let hunks = diff(b"a b c\n", b"a x c d\n");
+        assert_eq!(
+            hunks.iter().map(|hunk| hunk.to_string()).join(""),
+            "a {-b-}{+x+} c{+ d+}\n"
+        );
+        assert_eq!(
+            DiffHunk::Different(vec![b"a", b"", b"c"]).to_string(),
+            "{-a-}{+c+}"
+        );
+    }
+
+    #[test]
+    fn test_patience_diff_same_as_diff_without_repeated_lines() {
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"a\nb\nc\n", b"a\nx\nc\n"),
+            (b"a\nb\nc\n", b"c\nb\na\n"),
+            (b"a\nb\nc\nd\n", b"x\nc\na\ny\n"),
+            (b"one two\nthree\n", b"three four\none\n"),
+            (b"a\n", b""),
+            (b"", b"a\n"),
+        ];
+        for (left, right) in cases {
+            assert_eq!(patience_diff(left, right), diff(left, right));
+        }
+    }
+
+    #[test]
+    fn test_patience_diff_repeated_lines() {
+        // The closing brace occurs once on the left but twice on the right, so
+        // only the `if` line is used as an anchor.
+        let left = b"if a {\n}\n";
+        let right = b"if b {\n}\nif a {\n}\n";
+        assert_eq!(
+            patience_diff(left, right),
+            vec![
+                DiffHunk::Different(vec![b"", b"if b {\n}\n"]),
+                DiffHunk::Matching(b"if a {\n}\n"),
+            ]
+        );
+        assert_ne!(patience_diff(left, right), diff(left, right));
+
+        // Without any unique lines, the diff falls back to the default algorithm.
+        assert_eq!(patience_diff(b"a\na\n", b"a\n"), diff(b"a\na\n", b"a\n"));
+    }
+
     #[test]
     fn test_diff_real_case_write_fmt() {
         // This is from src/ui.rs in commit f44d246e3f88 in this repo. It highlights the
@@ -0,0 +1,199 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A serializable form of the lines returned by `files::diff()`, for tools
+//! that consume diffs over a protocol such as JSON.
+//!
+//! A line looks like this in JSON:
+//!
+//! ```json
+//! {
+//!   "left_line_number": 3,
+//!   "right_line_number": 3,
+//!   "has_left_content": true,
+//!   "has_right_content": true,
+//!   "hunks": [
+//!     {"matching": {"utf8": "fn "}},
+//!     {"different": [{"utf8": "foo"}, {"hex": "ff00"}]}
+//!   ]
+//! }
+//! ```
+//!
+//! Content that is valid UTF-8 is stored as a string. Anything else is
+//! hex-encoded, so every line converts back to the exact bytes it was made
+//! from.
+
+use serde::{Deserialize, Serialize};
+
+use crate::diff::DiffHunk;
+use crate::files::DiffLine;
+
+/// Serializable form of a `DiffLine`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffLineJson {
+    pub left_line_number: u32,
+    pub right_line_number: u32,
+    pub has_left_content: bool,
+    pub has_right_content: bool,
+    pub hunks: Vec<DiffHunkJson>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_block: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+}
+
+/// Serializable form of a `DiffHunk`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffHunkJson {
+    Matching(JsonBytes),
+    Different(Vec<JsonBytes>),
+}
+
+/// Content of a hunk, as a string if it is valid UTF-8 and hex-encoded
+/// otherwise.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonBytes {
+    Utf8(String),
+    Hex(String),
+}
+
+impl JsonBytes {
+    pub fn new(content: &[u8]) -> Self {
+        match std::str::from_utf8(content) {
+            Ok(text) => JsonBytes::Utf8(text.to_string()),
+            Err(_) => JsonBytes::Hex(hex::encode(content)),
+        }
+    }
+
+    /// Returns the bytes this was made from. Fails if a deserialized `Hex`
+    /// value isn't valid hex.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, hex::FromHexError> {
+        match self {
+            JsonBytes::Utf8(text) => Ok(text.as_bytes().to_vec()),
+            JsonBytes::Hex(hex) => hex::decode(hex),
+        }
+    }
+}
+
+impl From<&DiffHunk<'_>> for DiffHunkJson {
+    fn from(hunk: &DiffHunk) -> Self {
+        match hunk {
+            DiffHunk::Matching(content) => DiffHunkJson::Matching(JsonBytes::new(content)),
+            DiffHunk::Different(contents) => DiffHunkJson::Different(
+                contents
+                    .iter()
+                    .map(|content| JsonBytes::new(content))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&DiffLine<'_>> for DiffLineJson {
+    fn from(line: &DiffLine) -> Self {
+        DiffLineJson {
+            left_line_number: line.left_line_number,
+            right_line_number: line.right_line_number,
+            has_left_content: line.has_left_content,
+            has_right_content: line.has_right_content,
+            hunks: line.hunks.iter().map(DiffHunkJson::from).collect(),
+            moved_block: line.moved_block,
+            source_file: line.source_file.map(str::to_string),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_hunk(hunk: &DiffHunkJson) -> Vec<Vec<u8>> {
+        match hunk {
+            DiffHunkJson::Matching(content) => vec![content.to_bytes().unwrap()],
+            DiffHunkJson::Different(contents) => contents
+                .iter()
+                .map(|content| content.to_bytes().unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_non_utf8() {
+        let line = DiffLine {
+            left_line_number: 2,
+            right_line_number: 3,
+            has_left_content: true,
+            has_right_content: true,
+            hunks: vec![
+                DiffHunk::Matching(b"a "),
+                DiffHunk::Different(vec![&b"b\xff\n"[..], b"c\n"]),
+            ],
+            moved_block: Some(1),
+            source_file: Some("src/a.txt"),
+        };
+        let json_line = DiffLineJson::from(&line);
+        assert_eq!(
+            json_line.hunks,
+            vec![
+                DiffHunkJson::Matching(JsonBytes::Utf8("a ".to_string())),
+                DiffHunkJson::Different(vec![
+                    JsonBytes::Hex("62ff0a".to_string()),
+                    JsonBytes::Utf8("c\n".to_string()),
+                ]),
+            ]
+        );
+
+        assert_eq!(json_line.source_file.as_deref(), Some("src/a.txt"));
+
+        let json = serde_json::to_string(&json_line).unwrap();
+        let decoded: DiffLineJson = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, json_line);
+        assert_eq!(
+            decoded.hunks.iter().map(decode_hunk).collect::<Vec<_>>(),
+            vec![
+                vec![b"a ".to_vec()],
+                vec![b"b\xff\n".to_vec(), b"c\n".to_vec()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = DiffLine {
+            left_line_number: 1,
+            right_line_number: 1,
+            has_left_content: true,
+            has_right_content: false,
+            hunks: vec![DiffHunk::Different(vec![&b"foo\n"[..], b""])],
+            moved_block: None,
+            source_file: None,
+        };
+        assert_eq!(
+            serde_json::to_value(DiffLineJson::from(&line)).unwrap(),
+            serde_json::json!({
+                "left_line_number": 1,
+                "right_line_number": 1,
+                "has_left_content": true,
+                "has_right_content": false,
+                "hunks": [{"different": [{"utf8": "foo\n"}, {"utf8": ""}]}],
+            })
+        );
+        assert_eq!(
+            JsonBytes::Hex("not hex".to_string()).to_bytes(),
+            Err(hex::FromHexError::OddLength)
+        );
+    }
+}
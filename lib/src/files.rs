@@ -12,14 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{HashSet, VecDeque};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt::{Debug, Error, Formatter};
 use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use itertools::Itertools;
+use unicode_width::UnicodeWidthChar;
 
 use crate::diff;
 use crate::diff::{Diff, DiffHunk};
+use crate::gitignore::GitIgnoreFile;
+use crate::repo_path::RepoPath;
+use crate::settings::UserSettings;
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct DiffLine<'a> {
@@ -28,13 +35,33 @@ pub struct DiffLine<'a> {
     pub has_left_content: bool,
     pub has_right_content: bool,
     pub hunks: Vec<DiffHunk<'a>>,
+    /// If the line's changes are part of a block of lines that was moved, an
+    /// id shared by the deleted and the inserted copy of the block. Only set
+    /// with `DiffOptions::detect_moves`.
+    pub moved_block: Option<usize>,
+    /// The path of the file the line belongs to. Only set by `diff_files()`.
+    pub source_file: Option<&'a str>,
 }
 
-impl DiffLine<'_> {
+impl<'a> DiffLine<'a> {
+    /// An empty first line.
+    fn new() -> Self {
+        DiffLine {
+            left_line_number: 1,
+            right_line_number: 1,
+            has_left_content: false,
+            has_right_content: false,
+            hunks: vec![],
+            moved_block: None,
+            source_file: None,
+        }
+    }
+
     fn reset_line(&mut self) {
         self.has_left_content = false;
         self.has_right_content = false;
         self.hunks.clear();
+        self.moved_block = None;
     }
 
     pub fn is_unmodified(&self) -> bool {
@@ -42,89 +69,1024 @@ impl DiffLine<'_> {
             .iter()
             .all(|hunk| matches!(hunk, DiffHunk::Matching(_)))
     }
+
+    /// Whether the line was added, so there's no content on the left side.
+    pub fn is_insertion(&self) -> bool {
+        !self.has_left_content && self.has_right_content
+    }
+
+    /// Whether the line was removed, so there's no content on the right side.
+    pub fn is_deletion(&self) -> bool {
+        self.has_left_content && !self.has_right_content
+    }
+
+    /// Whether the line was changed, with content on both sides.
+    pub fn is_modification(&self) -> bool {
+        self.has_left_content && self.has_right_content && !self.is_unmodified()
+    }
+
+    /// Truncates the line's content (not counting the newline) to fit in
+    /// `max_width` columns, ending it with `ELLIPSIS` if anything was cut off.
+    /// Hunks are shortened or emptied but not removed, so whether the line is
+    /// modified doesn't change. Returns whether anything was cut off.
+    pub fn truncate(&mut self, max_width: usize) -> bool {
+        let slices = || {
+            self.hunks.iter().flat_map(|hunk| match hunk {
+                DiffHunk::Matching(data) => vec![*data],
+                DiffHunk::Different(parts) => parts.clone(),
+            })
+        };
+        let content_width: usize = slices()
+            .map(|data| display_width(strip_newline(data)))
+            .sum();
+        if content_width <= max_width {
+            return false;
+        }
+        let has_newline = slices().any(|data| data.ends_with(b"\n"));
+
+        let mut remaining_width = max_width.saturating_sub(1);
+        let mut truncate_slice = |data: &'a [u8]| -> &'a [u8] {
+            let (truncated, _) = truncate_to_width(strip_newline(data), remaining_width);
+            remaining_width -= display_width(truncated);
+            truncated
+        };
+        let mut hunks = self
+            .hunks
+            .iter()
+            .map(|hunk| match hunk {
+                DiffHunk::Matching(data) => DiffHunk::Matching(truncate_slice(*data)),
+                DiffHunk::Different(parts) => {
+                    DiffHunk::Different(parts.iter().map(|data| truncate_slice(*data)).collect())
+                }
+            })
+            .collect_vec();
+        hunks.push(DiffHunk::Matching(ELLIPSIS));
+        if has_newline {
+            hunks.push(DiffHunk::Matching(b"\n"));
+        }
+        self.hunks = hunks;
+        true
+    }
+}
+
+/// Appended to lines that were truncated by `DiffLine::truncate()`.
+pub const ELLIPSIS: &[u8] = "\u{2026}".as_bytes();
+
+fn strip_newline(data: &[u8]) -> &[u8] {
+    data.strip_suffix(b"\n").unwrap_or(data)
+}
+
+/// Returns the length of the UTF-8 sequence at the start of `data`. Invalid
+/// UTF-8 is treated as sequences of one byte.
+fn utf8_sequence_len(data: &[u8]) -> usize {
+    let expected_len = match data[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let num_continuation_bytes = data[1..]
+        .iter()
+        .take(expected_len - 1)
+        .take_while(|b| *b & 0xc0 == 0x80)
+        .count();
+    if num_continuation_bytes == expected_len - 1 {
+        expected_len
+    } else {
+        1
+    }
+}
+
+/// The length of the UTF-8 sequence at the start of `data`, and the number of
+/// columns it takes up on a terminal. Wide characters, such as CJK characters,
+/// take up two columns, and combining characters none. Control characters, and
+/// bytes that aren't part of a valid sequence, take up one column.
+fn char_len_and_width(data: &[u8]) -> (usize, usize) {
+    let len = utf8_sequence_len(data);
+    let width = std::str::from_utf8(&data[..len])
+        .ok()
+        .and_then(|text| text.chars().next())
+        .map_or(1, |c| c.width().unwrap_or(1));
+    (len, width)
+}
+
+/// The number of columns needed to display `data` on a terminal.
+pub fn display_width(data: &[u8]) -> usize {
+    let mut pos = 0;
+    let mut width = 0;
+    while pos < data.len() {
+        let (len, char_width) = char_len_and_width(&data[pos..]);
+        pos += len;
+        width += char_width;
+    }
+    width
+}
+
+/// Expands tabs in `data` to spaces up to the next multiple of `tab_width`,
+/// for display purposes. `column` is the column `data` starts at. Returns the
+/// expanded text and the column after it (a newline resets the column to 0).
+/// Tabs are left alone if `tab_width` is 0.
+pub fn expand_tabs(data: &[u8], column: usize, tab_width: usize) -> (Cow<[u8]>, usize) {
+    let mut column = column;
+    if tab_width == 0 || !data.contains(&b'\t') {
+        for line in data.split_inclusive(|b| *b == b'\n') {
+            if line.ends_with(b"\n") {
+                column = 0;
+            } else {
+                column += display_width(line);
+            }
+        }
+        return (Cow::Borrowed(data), column);
+    }
+    let mut expanded = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] {
+            b'\t' => {
+                let num_spaces = tab_width - column % tab_width;
+                expanded.resize(expanded.len() + num_spaces, b' ');
+                column += num_spaces;
+                pos += 1;
+            }
+            b'\n' => {
+                expanded.push(b'\n');
+                column = 0;
+                pos += 1;
+            }
+            _ => {
+                let (len, width) = char_len_and_width(&data[pos..]);
+                expanded.extend_from_slice(&data[pos..pos + len]);
+                column += width;
+                pos += len;
+            }
+        }
+    }
+    (Cow::Owned(expanded), column)
+}
+
+/// How `escape_control_chars()` shows control characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlCharStyle {
+    /// Caret notation, such as `^[` for ESC and `^@` for NUL.
+    Caret,
+    /// Unicode control pictures, such as `␛` for ESC and `␀` for NUL.
+    Picture,
+    /// Control characters are left as they are, for callers that handle them
+    /// themselves.
+    Verbatim,
+}
+
+impl Default for ControlCharStyle {
+    fn default() -> Self {
+        ControlCharStyle::Caret
+    }
+}
+
+impl FromStr for ControlCharStyle {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "caret" => Ok(ControlCharStyle::Caret),
+            "picture" => Ok(ControlCharStyle::Picture),
+            "verbatim" => Ok(ControlCharStyle::Verbatim),
+            _ => Err("must be one of caret, picture, or verbatim"),
+        }
+    }
+}
+
+/// Which algorithm `diff_with_options()` uses to line up the two inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    /// The default algorithm; see `diff::diff()`.
+    Minimal,
+    /// Patience diff, which only uses lines that occur once in each input as
+    /// anchors; see `diff::patience_diff()`.
+    Patience,
+}
+
+impl Default for DiffAlgorithm {
+    fn default() -> Self {
+        DiffAlgorithm::Minimal
+    }
+}
+
+impl FromStr for DiffAlgorithm {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(DiffAlgorithm::Minimal),
+            "patience" => Ok(DiffAlgorithm::Patience),
+            _ => Err("must be one of minimal or patience"),
+        }
+    }
+}
+
+/// Replaces the control characters in `data` as `style` says, for display
+/// purposes, so they can't affect the terminal or hide other content. Tabs,
+/// newlines, and a carriage return before a newline are left alone, since
+/// they're part of the layout.
+pub fn escape_control_chars(data: &[u8], style: ControlCharStyle) -> Cow<[u8]> {
+    let is_escaped = |pos: usize| match data[pos] {
+        b'\t' | b'\n' => false,
+        b'\r' => data.get(pos + 1) != Some(&b'\n'),
+        b => b < 0x20 || b == 0x7f,
+    };
+    if style == ControlCharStyle::Verbatim || !(0..data.len()).any(is_escaped) {
+        return Cow::Borrowed(data);
+    }
+    let mut escaped = Vec::with_capacity(data.len() + 8);
+    for (pos, b) in data.iter().enumerate() {
+        if !is_escaped(pos) {
+            escaped.push(*b);
+        } else if style == ControlCharStyle::Caret {
+            // DEL becomes `^?`
+            escaped.extend_from_slice(&[b'^', b ^ 0x40]);
+        } else {
+            let code = if *b == 0x7f {
+                0x2421
+            } else {
+                0x2400 + u32::from(*b)
+            };
+            let picture = char::from_u32(code).unwrap();
+            escaped.extend_from_slice(picture.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Returns the longest prefix of `data` that fits in `max_width` columns, as
+/// measured by `display_width()`, and whether anything was cut off. A
+/// multibyte UTF-8 sequence is never split.
+pub fn truncate_to_width(data: &[u8], max_width: usize) -> (&[u8], bool) {
+    let mut pos = 0;
+    let mut width = 0;
+    while pos < data.len() {
+        let (len, char_width) = char_len_and_width(&data[pos..]);
+        if width + char_width > max_width {
+            return (&data[..pos], true);
+        }
+        pos += len;
+        width += char_width;
+    }
+    (data, false)
+}
+
+/// The UTF-8 byte order mark.
+pub const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Splits `data` into its leading UTF-8 byte order mark (or an empty slice if
+/// there is none) and the content after it.
+pub fn split_bom(data: &[u8]) -> (&[u8], &[u8]) {
+    if data.starts_with(UTF8_BOM) {
+        data.split_at(UTF8_BOM.len())
+    } else {
+        (&[], data)
+    }
+}
+
+/// How the byte order mark differs between the two sides of a diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BomChange {
+    Added,
+    Removed,
+}
+
+/// Options for `diff_with_options()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Number of unmodified lines to include before and after each modified
+    /// line. Unmodified lines further away are skipped. `usize::MAX` (the
+    /// default) includes all lines.
+    pub context_lines: usize,
+    /// Treat hunks that differ only in spaces, tabs, and carriage returns as
+    /// unmodified.
+    pub ignore_whitespace: bool,
+    /// Refine changed lines to find matching words within them. If false,
+    /// changes are reported for whole lines.
+    pub intra_line: bool,
+    /// Truncate lines whose content is wider than this many columns. See
+    /// `DiffLine::truncate()`.
+    pub max_line_width: Option<usize>,
+    /// Distance between tab stops when rendering lines. Only affects display;
+    /// the diffed content is never modified. See `expand_tabs()`.
+    pub tab_width: usize,
+    /// Unchanged runs shorter than this many bytes between two changes are
+    /// reported as changed, so the changes are combined into one. This gives
+    /// fewer, larger changes instead of many small fragments. 0 (the default)
+    /// leaves the changes as they are.
+    pub min_matching_len: usize,
+    /// How control characters are shown when rendering lines. Like
+    /// `tab_width`, this only affects display. See `escape_control_chars()`.
+    pub control_chars: ControlCharStyle,
+    /// Find blocks of lines that were deleted in one place and inserted
+    /// unchanged in another, and tag their lines with a shared
+    /// `DiffLine::moved_block` id, like Git's `--color-moved`.
+    pub detect_moves: bool,
+    /// The algorithm used to line up the inputs.
+    pub algorithm: DiffAlgorithm,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            context_lines: usize::MAX,
+            ignore_whitespace: false,
+            intra_line: true,
+            max_line_width: None,
+            tab_width: 8,
+            min_matching_len: 0,
+            control_chars: ControlCharStyle::default(),
+            detect_moves: false,
+            algorithm: DiffAlgorithm::default(),
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Creates options from the `diff.*` settings. Unlike with
+    /// `DiffOptions::default()`, only 3 lines of context are included unless
+    /// configured otherwise.
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let config = settings.config();
+        let defaults = DiffOptions::default();
+        DiffOptions {
+            context_lines: config
+                .get_int("diff.context-lines")
+                .ok()
+                .and_then(|n| usize::try_from(n).ok())
+                .unwrap_or(3),
+            ignore_whitespace: config
+                .get_bool("diff.ignore-whitespace")
+                .unwrap_or(defaults.ignore_whitespace),
+            intra_line: config
+                .get_bool("diff.intra-line")
+                .unwrap_or(defaults.intra_line),
+            max_line_width: defaults.max_line_width,
+            tab_width: config
+                .get_int("ui.tab-width")
+                .ok()
+                .and_then(|n| usize::try_from(n).ok())
+                .unwrap_or(defaults.tab_width),
+            min_matching_len: config
+                .get_int("diff.min-matching-len")
+                .ok()
+                .and_then(|n| usize::try_from(n).ok())
+                .unwrap_or(defaults.min_matching_len),
+            control_chars: config
+                .get_string("diff.control-chars")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.control_chars),
+            detect_moves: config
+                .get_bool("diff.detect-moves")
+                .unwrap_or(defaults.detect_moves),
+            algorithm: config
+                .get_string("diff.algorithm")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.algorithm),
+        }
+    }
+
+    pub fn set_context_lines(mut self, context_lines: usize) -> Self {
+        self.context_lines = context_lines;
+        self
+    }
+
+    pub fn set_ignore_whitespace(mut self, ignore_whitespace: bool) -> Self {
+        self.ignore_whitespace = ignore_whitespace;
+        self
+    }
+
+    pub fn set_intra_line(mut self, intra_line: bool) -> Self {
+        self.intra_line = intra_line;
+        self
+    }
+
+    pub fn set_max_line_width(mut self, max_line_width: Option<usize>) -> Self {
+        self.max_line_width = max_line_width;
+        self
+    }
+
+    pub fn set_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width;
+        self
+    }
+
+    pub fn set_min_matching_len(mut self, min_matching_len: usize) -> Self {
+        self.min_matching_len = min_matching_len;
+        self
+    }
+
+    pub fn set_control_chars(mut self, control_chars: ControlCharStyle) -> Self {
+        self.control_chars = control_chars;
+        self
+    }
+
+    pub fn set_detect_moves(mut self, detect_moves: bool) -> Self {
+        self.detect_moves = detect_moves;
+        self
+    }
+
+    pub fn set_algorithm(mut self, algorithm: DiffAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
 }
 
 pub fn diff<'a>(left: &'a [u8], right: &'a [u8]) -> DiffLineIterator<'a> {
-    let diff_hunks = diff::diff(left, right);
-    DiffLineIterator::new(diff_hunks)
+    diff_with_options(left, right, &DiffOptions::default())
+}
+
+/// Like `diff()`, but only diffs lines `left_lines` of `left` against lines
+/// `right_lines` of `right`. Line numbers start at 1 like those of `DiffLine`,
+/// and the lines are numbered as in the whole inputs. Parts of the ranges past
+/// the last line are ignored.
+pub fn diff_range<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    left_lines: Range<u32>,
+    right_lines: Range<u32>,
+) -> DiffLineIterator<'a> {
+    let mut lines = diff(
+        line_range_slice(left, &left_lines),
+        line_range_slice(right, &right_lines),
+    );
+    lines.current_line.left_line_number = left_lines.start.max(1);
+    lines.current_line.right_line_number = right_lines.start.max(1);
+    lines
+}
+
+/// Returns the content of lines `lines` of `text`, counting from 1.
+fn line_range_slice<'a>(text: &'a [u8], lines: &Range<u32>) -> &'a [u8] {
+    let line_ranges = diff::find_line_ranges(text);
+    let start = lines.start.saturating_sub(1) as usize;
+    let end = (lines.end.saturating_sub(1) as usize).min(line_ranges.len());
+    if start >= end {
+        return &[];
+    }
+    &text[line_ranges[start].start..line_ranges[end - 1].end]
+}
+
+/// Diffs `left` and `right` line by line. A byte order mark at the start of
+/// either side is not part of the diffed content; see
+/// `DiffLineIterator::bom_change()`.
+///
+/// Lines that the inputs have in common at the start and at the end are
+/// reported as matching without running the diff algorithm on them, so only
+/// the lines in between are tokenized and diffed.
+pub fn diff_with_options<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    options: &DiffOptions,
+) -> DiffLineIterator<'a> {
+    let (left, right, bom_change) = split_boms(left, right);
+    let diff_hunks = line_diff_hunks(left, right, options);
+    DiffLineIterator::new(
+        diff_hunks,
+        bom_change,
+        options.context_lines,
+        options.max_line_width,
+        options.detect_moves,
+    )
+}
+
+/// Like `diff()`, but calls `f` with each line instead of returning an
+/// iterator. The same `DiffLine` is passed for every line, so unlike
+/// `DiffLineIterator`, lines aren't cloned or queued. A byte order mark at the
+/// start of either side is not part of the diffed content.
+pub fn for_each_diff_line<'a>(left: &'a [u8], right: &'a [u8], mut f: impl FnMut(&DiffLine<'a>)) {
+    let (left, right, _) = split_boms(left, right);
+    let diff_hunks = line_diff_hunks(left, right, &DiffOptions::default());
+    let mut line = DiffLine::new();
+    for hunk in &diff_hunks {
+        push_hunk_lines(&mut line, hunk, None, &mut f);
+    }
+    if !line.hunks.is_empty() {
+        f(&line);
+    }
+}
+
+/// Whether `diff()` would report any modified lines between `left` and
+/// `right`, for callers that skip rendering when nothing changed. Unlike
+/// checking the lines of `diff()`, no lines are built, and it returns at the
+/// first difference.
+pub fn has_changes(left: &[u8], right: &[u8]) -> bool {
+    has_changes_with_options(left, right, &DiffOptions::default())
+}
+
+/// Like `has_changes()`, but for `diff_with_options()`. A change to the byte
+/// order mark counts as a change.
+pub fn has_changes_with_options(left: &[u8], right: &[u8], options: &DiffOptions) -> bool {
+    if left == right {
+        return false;
+    }
+    // Every byte that differs is part of a `Different` hunk unless whitespace
+    // changes are ignored, so there's no need to diff
+    if !options.ignore_whitespace {
+        return true;
+    }
+    let (left, right, bom_change) = split_boms(left, right);
+    bom_change.is_some()
+        || line_diff_hunks(left, right, options)
+            .iter()
+            .any(|hunk| matches!(hunk, DiffHunk::Different(_)))
+}
+
+/// Computes the diff hunks of `left` and `right` for `diff_with_options()`,
+/// without the byte order marks.
+fn line_diff_hunks<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    options: &DiffOptions,
+) -> Vec<DiffHunk<'a>> {
+    let left_ranges = diff::find_line_ranges(left);
+    let right_ranges = diff::find_line_ranges(right);
+    let (prefix, suffix) = count_common_lines(
+        (left, &left_ranges),
+        (right, &right_ranges),
+        usize::MAX,
+        usize::MAX,
+    );
+    let diff_hunks = diff_between_common_affixes(
+        left,
+        right,
+        common_lines_len(&left_ranges, left.len(), prefix, suffix),
+        options.intra_line,
+        options.algorithm,
+    );
+    postprocess_hunks(left, right, diff_hunks, options)
+}
+
+/// Hashes each line of `text`, as split by `diff::find_line_ranges()`. A byte
+/// order mark at the start isn't part of the first line. The hashes don't
+/// depend on the process or the Rust release, so they can be stored.
+///
+/// Callers that diff the same content repeatedly can keep the hashes and pass
+/// them to `diff_with_line_hashes()`.
+pub fn line_hashes(text: &[u8]) -> Vec<u64> {
+    let (_, text) = split_bom(text);
+    diff::find_line_ranges(text)
+        .into_iter()
+        .map(|range| hash_line(&text[range]))
+        .collect()
+}
+
+/// The 64-bit FNV-1a hash of `line`.
+fn hash_line(line: &[u8]) -> u64 {
+    line.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns the number of lines at the start and at the end of two inputs whose
+/// hashes are equal. The two counts never overlap, so at most
+/// `min(left.len(), right.len())` lines are counted in total.
+pub fn common_line_hash_affixes(left_hashes: &[u64], right_hashes: &[u64]) -> (usize, usize) {
+    let prefix = left_hashes
+        .iter()
+        .zip(right_hashes)
+        .take_while(|(left, right)| left == right)
+        .count();
+    let suffix = left_hashes[prefix..]
+        .iter()
+        .rev()
+        .zip(right_hashes[prefix..].iter().rev())
+        .take_while(|(left, right)| left == right)
+        .count();
+    (prefix, suffix)
+}
+
+/// Like `diff_with_options()`, but uses hashes from `line_hashes()` to find the
+/// lines that `left` and `right` have in common at the start and at the end.
+/// Only the lines in between are split into lines and diffed. The lines whose
+/// hashes match are still compared byte by byte, in one go, so a hash collision
+/// can't change the diff. If the hashes don't match the number of lines, or the
+/// lines don't match the hashes, they're ignored.
+pub fn diff_with_line_hashes<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    left_hashes: &[u64],
+    right_hashes: &[u64],
+    options: &DiffOptions,
+) -> DiffLineIterator<'a> {
+    let (left, right, bom_change) = split_boms(left, right);
+    let diff_hunks = hashed_line_diff_hunks(left, right, left_hashes, right_hashes, options)
+        .unwrap_or_else(|| line_diff_hunks(left, right, options));
+    DiffLineIterator::new(
+        diff_hunks,
+        bom_change,
+        options.context_lines,
+        options.max_line_width,
+        options.detect_moves,
+    )
+}
+
+/// Like `line_diff_hunks()`, but finds the common lines at the start and at the
+/// end by their hashes. Returns `None` if the hashes can't be for the inputs,
+/// or if lines with equal hashes aren't equal.
+fn hashed_line_diff_hunks<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    left_hashes: &[u64],
+    right_hashes: &[u64],
+    options: &DiffOptions,
+) -> Option<Vec<DiffHunk<'a>>> {
+    if count_lines(left) != left_hashes.len() || count_lines(right) != right_hashes.len() {
+        return None;
+    }
+    let (prefix, suffix) = common_line_hash_affixes(left_hashes, right_hashes);
+    let left_common_len = (
+        leading_lines_len(left, prefix),
+        trailing_lines_len(left, suffix),
+    );
+    let right_common_len = (
+        leading_lines_len(right, prefix),
+        trailing_lines_len(right, suffix),
+    );
+    // Lines of different lengths can't be equal, even if their hashes are
+    if left_common_len != right_common_len {
+        return None;
+    }
+    let (prefix_len, suffix_len) = left_common_len;
+    if left[..prefix_len] != right[..prefix_len]
+        || left[left.len() - suffix_len..] != right[right.len() - suffix_len..]
+    {
+        return None;
+    }
+    let diff_hunks = diff_between_common_affixes(
+        left,
+        right,
+        left_common_len,
+        options.intra_line,
+        options.algorithm,
+    );
+    Some(postprocess_hunks(left, right, diff_hunks, options))
+}
+
+/// The number of lines that `diff::find_line_ranges()` splits `text` into.
+fn count_lines(text: &[u8]) -> usize {
+    let newlines = text.iter().filter(|byte| **byte == b'\n').count();
+    newlines + usize::from(!text.is_empty() && !text.ends_with(b"\n"))
+}
+
+/// The length in bytes of the first `count` lines of `text`.
+fn leading_lines_len(text: &[u8], count: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    text.iter()
+        .positions(|byte| *byte == b'\n')
+        .nth(count - 1)
+        .map_or(text.len(), |newline| newline + 1)
+}
+
+/// The length in bytes of the last `count` lines of `text`.
+fn trailing_lines_len(text: &[u8], count: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    // The last line ends at the end, with or without a newline
+    let body = text.strip_suffix(b"\n").unwrap_or(text);
+    let newline = body
+        .iter()
+        .rev()
+        .positions(|byte| *byte == b'\n')
+        .nth(count - 1);
+    match newline {
+        Some(distance) => text.len() - (body.len() - distance),
+        None => text.len(),
+    }
+}
+
+/// Returns the number of equal lines at the start and at the end of `left` and
+/// `right`, which are given with their line ranges, up to `max_prefix` and
+/// `max_suffix` lines. The two counts never overlap.
+fn count_common_lines(
+    (left, left_ranges): (&[u8], &[Range<usize>]),
+    (right, right_ranges): (&[u8], &[Range<usize>]),
+    max_prefix: usize,
+    max_suffix: usize,
+) -> (usize, usize) {
+    let line_eq =
+        |(l, r): &(&Range<usize>, &Range<usize>)| left[l.start..l.end] == right[r.start..r.end];
+    let prefix = left_ranges
+        .iter()
+        .zip(right_ranges)
+        .take(max_prefix)
+        .take_while(line_eq)
+        .count();
+    let suffix = left_ranges[prefix..]
+        .iter()
+        .rev()
+        .zip(right_ranges[prefix..].iter().rev())
+        .take(max_suffix)
+        .take_while(line_eq)
+        .count();
+    (prefix, suffix)
+}
+
+/// The lengths in bytes of the first `prefix` and the last `suffix` lines of a
+/// text of length `len` with the line ranges `ranges`.
+fn common_lines_len(
+    ranges: &[Range<usize>],
+    len: usize,
+    prefix: usize,
+    suffix: usize,
+) -> (usize, usize) {
+    let prefix_len = ranges[..prefix].last().map_or(0, |range| range.end);
+    let suffix_len = ranges[ranges.len() - suffix..]
+        .first()
+        .map_or(0, |range| len - range.start);
+    (prefix_len, suffix_len)
+}
+
+/// Diffs `left` and `right`, which have the same `prefix_len` bytes of whole
+/// lines at the start and `suffix_len` bytes at the end. Only the lines in
+/// between are run through the diff algorithm. The common lines become
+/// `Matching` hunks, merged with adjacent ones.
+fn diff_between_common_affixes<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    (prefix_len, suffix_len): (usize, usize),
+    intra_line: bool,
+    algorithm: DiffAlgorithm,
+) -> Vec<DiffHunk<'a>> {
+    let prefix_end = prefix_len;
+    let left_suffix_start = left.len() - suffix_len;
+    let right_suffix_start = right.len() - suffix_len;
+    let left_middle = &left[prefix_end..left_suffix_start];
+    let right_middle = &right[prefix_end..right_suffix_start];
+
+    let mut diff_hunks = vec![];
+    // Adds `left[start..end]` as matching, merged with a preceding `Matching`
+    // hunk, which always ends at `start`
+    let push_matching = |hunks: &mut Vec<DiffHunk<'a>>, mut start: usize, end: usize| {
+        if start == end {
+            return;
+        }
+        if let Some(DiffHunk::Matching(content)) = hunks.last() {
+            start -= content.len();
+            hunks.pop();
+        }
+        hunks.push(DiffHunk::Matching(&left[start..end]));
+    };
+    push_matching(&mut diff_hunks, 0, prefix_end);
+    if !left_middle.is_empty() || !right_middle.is_empty() {
+        let mut left_pos = prefix_end;
+        for hunk in diff_contents(left_middle, right_middle, intra_line, algorithm) {
+            match hunk {
+                DiffHunk::Matching(content) => {
+                    push_matching(&mut diff_hunks, left_pos, left_pos + content.len());
+                    left_pos += content.len();
+                }
+                DiffHunk::Different(parts) => {
+                    left_pos += parts[0].len();
+                    diff_hunks.push(DiffHunk::Different(parts));
+                }
+            }
+        }
+    }
+    push_matching(&mut diff_hunks, left_suffix_start, left.len());
+    diff_hunks
+}
+
+/// Strips the byte order marks from `left` and `right`, and reports how they
+/// changed.
+fn split_boms<'a>(left: &'a [u8], right: &'a [u8]) -> (&'a [u8], &'a [u8], Option<BomChange>) {
+    let (left_bom, left) = split_bom(left);
+    let (right_bom, right) = split_bom(right);
+    let bom_change = match (left_bom.is_empty(), right_bom.is_empty()) {
+        (true, false) => Some(BomChange::Added),
+        (false, true) => Some(BomChange::Removed),
+        _ => None,
+    };
+    (left, right, bom_change)
+}
+
+/// Runs the diff `algorithm` on `left` and `right`, refining changed lines to
+/// words if `intra_line` is set.
+fn diff_contents<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    intra_line: bool,
+    algorithm: DiffAlgorithm,
+) -> Vec<DiffHunk<'a>> {
+    match (algorithm, intra_line) {
+        (DiffAlgorithm::Minimal, true) => diff::diff(left, right),
+        (DiffAlgorithm::Patience, true) => diff::patience_diff(left, right),
+        (DiffAlgorithm::Minimal, false) => {
+            Diff::for_tokenizer(&[left, right], &diff::find_line_ranges)
+                .hunks()
+                .collect_vec()
+        }
+        (DiffAlgorithm::Patience, false) => {
+            Diff::for_tokenizer_patience(&[left, right], &diff::find_line_ranges)
+                .hunks()
+                .collect_vec()
+        }
+    }
+}
+
+/// Applies the options that post-process the hunks of the diff of `left` and
+/// `right`.
+fn postprocess_hunks<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    mut diff_hunks: Vec<DiffHunk<'a>>,
+    options: &DiffOptions,
+) -> Vec<DiffHunk<'a>> {
+    if options.min_matching_len > 0 {
+        diff_hunks = coalesce_hunks(left, right, diff_hunks, options.min_matching_len);
+    }
+    if options.ignore_whitespace {
+        diff_hunks = diff_hunks
+            .into_iter()
+            .map(|hunk| match hunk {
+                DiffHunk::Different(contents)
+                    if equal_ignoring_whitespace(contents[0], contents[1]) =>
+                {
+                    DiffHunk::Matching(contents[1])
+                }
+                hunk => hunk,
+            })
+            .collect_vec();
+    }
+    diff_hunks
+}
+
+/// Diffs each `(path, left, right)` triple in `files` with
+/// `diff_with_options()`, one file after the other, so callers can print a
+/// header whenever the `source_file` of a line changes. Files that produce no
+/// lines don't show up in the result. Changes to byte order marks aren't
+/// reported.
+pub fn diff_files<'a>(
+    files: &[(&'a str, &'a [u8], &'a [u8])],
+    options: &DiffOptions,
+) -> impl Iterator<Item = DiffLine<'a>> {
+    let options = options.clone();
+    files
+        .to_vec()
+        .into_iter()
+        .flat_map(move |(path, left, right)| {
+            diff_with_options(left, right, &options).map(move |mut line| {
+                line.source_file = Some(path);
+                line
+            })
+        })
+}
+
+/// Combines `Different` hunks that are only separated by a `Matching` hunk
+/// shorter than `min_matching_len` bytes. `hunks` must be the diff of `left`
+/// and `right`.
+fn coalesce_hunks<'a>(
+    left: &'a [u8],
+    right: &'a [u8],
+    hunks: Vec<DiffHunk<'a>>,
+    min_matching_len: usize,
+) -> Vec<DiffHunk<'a>> {
+    let mut coalesced = vec![];
+    let mut left_pos = 0;
+    let mut right_pos = 0;
+    // Where the `Different` hunk at the end of `coalesced` starts, if there is
+    // one
+    let mut different_start: Option<(usize, usize)> = None;
+    for (i, hunk) in hunks.iter().enumerate() {
+        match hunk {
+            DiffHunk::Matching(content) => {
+                left_pos += content.len();
+                right_pos += content.len();
+                let is_short_gap = different_start.is_some()
+                    && content.len() < min_matching_len
+                    && matches!(hunks.get(i + 1), Some(DiffHunk::Different(_)));
+                if !is_short_gap {
+                    coalesced.push(hunk.clone());
+                    different_start = None;
+                }
+            }
+            DiffHunk::Different(parts) => {
+                left_pos += parts[0].len();
+                right_pos += parts[1].len();
+                let (left_start, right_start) = match different_start {
+                    Some(start) => {
+                        coalesced.pop();
+                        start
+                    }
+                    None => (left_pos - parts[0].len(), right_pos - parts[1].len()),
+                };
+                coalesced.push(DiffHunk::Different(vec![
+                    &left[left_start..left_pos],
+                    &right[right_start..right_pos],
+                ]));
+                different_start = Some((left_start, right_start));
+            }
+        }
+    }
+    coalesced
+}
+
+pub(crate) fn equal_ignoring_whitespace(left: &[u8], right: &[u8]) -> bool {
+    let is_not_whitespace = |b: &&u8| !matches!(**b, b' ' | b'\t' | b'\r');
+    left.iter()
+        .filter(is_not_whitespace)
+        .eq(right.iter().filter(is_not_whitespace))
 }
 
 pub struct DiffLineIterator<'a> {
     diff_hunks: Vec<DiffHunk<'a>>,
+    bom_change: Option<BomChange>,
     current_pos: usize,
     current_line: DiffLine<'a>,
     queued_lines: VecDeque<DiffLine<'a>>,
+    context_lines: usize,
+    // Unmodified lines that will be emitted if a modified line follows soon
+    // enough.
+    context_before: VecDeque<DiffLine<'a>>,
+    // Lines that passed the context filter and are ready to be emitted.
+    filtered_lines: VecDeque<DiffLine<'a>>,
+    // Number of unmodified lines emitted since the last modified line, or
+    // `None` if there hasn't been any modified line yet.
+    lines_after_change: Option<usize>,
+    max_line_width: Option<usize>,
+    // The next line, if `peek()` was called since the last `next()`.
+    peeked: Option<Option<DiffLine<'a>>>,
+    // The `DiffLine::moved_block` of the lines of each hunk in `diff_hunks`
+    moved_blocks: Vec<Option<usize>>,
+}
+
+/// Whether a `DiffLine` has any changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffLineKind {
+    Unmodified,
+    Modified,
 }
 
 impl<'a> DiffLineIterator<'a> {
-    fn new(diff_hunks: Vec<DiffHunk<'a>>) -> Self {
-        let current_line = DiffLine {
-            left_line_number: 1,
-            right_line_number: 1,
-            has_left_content: false,
-            has_right_content: false,
-            hunks: vec![],
+    fn new(
+        diff_hunks: Vec<DiffHunk<'a>>,
+        bom_change: Option<BomChange>,
+        context_lines: usize,
+        max_line_width: Option<usize>,
+        detect_moves: bool,
+    ) -> Self {
+        let moved_blocks = if detect_moves {
+            find_moved_blocks(&diff_hunks)
+        } else {
+            vec![None; diff_hunks.len()]
         };
         DiffLineIterator {
             diff_hunks,
+            bom_change,
             current_pos: 0,
-            current_line,
+            current_line: DiffLine::new(),
             queued_lines: VecDeque::new(),
+            context_lines,
+            context_before: VecDeque::new(),
+            filtered_lines: VecDeque::new(),
+            lines_after_change: None,
+            max_line_width,
+            peeked: None,
+            moved_blocks,
         }
     }
-}
 
-impl<'a> Iterator for DiffLineIterator<'a> {
-    type Item = DiffLine<'a>;
+    /// Whether a byte order mark was added or removed. The byte order mark
+    /// itself is never included in the lines.
+    pub fn bom_change(&self) -> Option<BomChange> {
+        self.bom_change
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // TODO: Should we attempt to interpret as utf-8 and otherwise break only at
-        // newlines?
+    /// Returns the next line without consuming it.
+    pub fn peek(&mut self) -> Option<&DiffLine<'a>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.next_output_line());
+        }
+        self.peeked.as_ref().unwrap().as_ref()
+    }
+
+    /// Whether the next line has changes, without consuming it. Useful to
+    /// decide whether to end a group of context lines before emitting them.
+    pub fn peek_next_hunk_kind(&mut self) -> Option<DiffLineKind> {
+        self.peek().map(|line| {
+            if line.is_unmodified() {
+                DiffLineKind::Unmodified
+            } else {
+                DiffLineKind::Modified
+            }
+        })
+    }
+
+    fn next_line(&mut self) -> Option<DiffLine<'a>> {
         while self.current_pos < self.diff_hunks.len() && self.queued_lines.is_empty() {
             let hunk = &self.diff_hunks[self.current_pos];
+            let moved_block = self.moved_blocks[self.current_pos];
             self.current_pos += 1;
-            match hunk {
-                diff::DiffHunk::Matching(text) => {
-                    let lines = text.split_inclusive(|b| *b == b'\n');
-                    for line in lines {
-                        self.current_line.has_left_content = true;
-                        self.current_line.has_right_content = true;
-                        self.current_line.hunks.push(DiffHunk::Matching(line));
-                        if line.ends_with(b"\n") {
-                            self.queued_lines.push_back(self.current_line.clone());
-                            self.current_line.left_line_number += 1;
-                            self.current_line.right_line_number += 1;
-                            self.current_line.reset_line();
-                        }
-                    }
-                }
-                diff::DiffHunk::Different(contents) => {
-                    let left_lines = contents[0].split_inclusive(|b| *b == b'\n');
-                    for left_line in left_lines {
-                        self.current_line.has_left_content = true;
-                        self.current_line
-                            .hunks
-                            .push(DiffHunk::Different(vec![left_line, b""]));
-                        if left_line.ends_with(b"\n") {
-                            self.queued_lines.push_back(self.current_line.clone());
-                            self.current_line.left_line_number += 1;
-                            self.current_line.reset_line();
-                        }
-                    }
-                    let right_lines = contents[1].split_inclusive(|b| *b == b'\n');
-                    for right_line in right_lines {
-                        self.current_line.has_right_content = true;
-                        self.current_line
-                            .hunks
-                            .push(DiffHunk::Different(vec![b"", right_line]));
-                        if right_line.ends_with(b"\n") {
-                            self.queued_lines.push_back(self.current_line.clone());
-                            self.current_line.right_line_number += 1;
-                            self.current_line.reset_line();
-                        }
-                    }
-                }
-            }
+            let queued_lines = &mut self.queued_lines;
+            push_hunk_lines(&mut self.current_line, hunk, moved_block, |line| {
+                queued_lines.push_back(line.clone());
+            });
         }
 
         if let Some(line) = self.queued_lines.pop_front() {
@@ -141,34 +1103,164 @@ impl<'a> Iterator for DiffLineIterator<'a> {
     }
 }
 
-#[derive(PartialEq, Eq, Clone)]
-pub enum MergeHunk {
-    Resolved(Vec<u8>),
-    Conflict {
-        removes: Vec<Vec<u8>>,
-        adds: Vec<Vec<u8>>,
-    },
+/// Adds the content of `hunk` to `line`, calling `emit` with `line` whenever
+/// it's complete. `line` is then reset to become the next line. The lines with
+/// changes from `hunk` get `moved_block` as their `DiffLine::moved_block`.
+fn push_hunk_lines<'a>(
+    line: &mut DiffLine<'a>,
+    hunk: &DiffHunk<'a>,
+    moved_block: Option<usize>,
+    mut emit: impl FnMut(&DiffLine<'a>),
+) {
+    // TODO: Should we attempt to interpret as utf-8 and otherwise break only at
+    // newlines?
+    match hunk {
+        diff::DiffHunk::Matching(text) => {
+            for text_line in text.split_inclusive(|b| *b == b'\n') {
+                line.has_left_content = true;
+                line.has_right_content = true;
+                line.hunks.push(DiffHunk::Matching(text_line));
+                if text_line.ends_with(b"\n") {
+                    emit(line);
+                    line.left_line_number += 1;
+                    line.right_line_number += 1;
+                    line.reset_line();
+                }
+            }
+        }
+        diff::DiffHunk::Different(contents) => {
+            for left_line in contents[0].split_inclusive(|b| *b == b'\n') {
+                line.has_left_content = true;
+                line.hunks.push(DiffHunk::Different(vec![left_line, b""]));
+                line.moved_block = line.moved_block.or(moved_block);
+                if left_line.ends_with(b"\n") {
+                    emit(line);
+                    line.left_line_number += 1;
+                    line.reset_line();
+                }
+            }
+            for right_line in contents[1].split_inclusive(|b| *b == b'\n') {
+                line.has_right_content = true;
+                line.hunks.push(DiffHunk::Different(vec![b"", right_line]));
+                line.moved_block = line.moved_block.or(moved_block);
+                if right_line.ends_with(b"\n") {
+                    emit(line);
+                    line.right_line_number += 1;
+                    line.reset_line();
+                }
+            }
+        }
+    }
 }
 
-impl Debug for MergeHunk {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match self {
-            MergeHunk::Resolved(data) => f
-                .debug_tuple("Resolved")
-                .field(&String::from_utf8_lossy(data))
-                .finish(),
-            MergeHunk::Conflict { removes, adds } => f
-                .debug_struct("Conflict")
-                .field(
-                    "removes",
-                    &removes
-                        .iter()
-                        .map(|part| String::from_utf8_lossy(part))
-                        .collect_vec(),
-                )
-                .field(
-                    "adds",
-                    &adds
+/// Pairs each hunk that only deletes content with a hunk elsewhere that only
+/// inserts the same content, and returns the move id of each hunk. The two
+/// hunks of a pair share an id, and the ids are numbered from 0 in the order
+/// of the deletions. Blocks that are only whitespace aren't considered moved.
+fn find_moved_blocks(diff_hunks: &[DiffHunk]) -> Vec<Option<usize>> {
+    let mut moved_blocks = vec![None; diff_hunks.len()];
+    let mut next_id = 0;
+    for (deleted_index, hunk) in diff_hunks.iter().enumerate() {
+        let deleted = match hunk {
+            DiffHunk::Different(parts)
+                if parts[1].is_empty() && parts[0].iter().any(|b| !b.is_ascii_whitespace()) =>
+            {
+                parts[0]
+            }
+            _ => continue,
+        };
+        let inserted_index = (0..diff_hunks.len()).find(|index| {
+            moved_blocks[*index].is_none()
+                && match &diff_hunks[*index] {
+                    DiffHunk::Different(parts) => parts[0].is_empty() && parts[1] == deleted,
+                    DiffHunk::Matching(_) => false,
+                }
+        });
+        if let Some(inserted_index) = inserted_index {
+            moved_blocks[deleted_index] = Some(next_id);
+            moved_blocks[inserted_index] = Some(next_id);
+            next_id += 1;
+        }
+    }
+    moved_blocks
+}
+
+impl<'a> Iterator for DiffLineIterator<'a> {
+    type Item = DiffLine<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(line) => line,
+            None => self.next_output_line(),
+        }
+    }
+}
+
+impl<'a> DiffLineIterator<'a> {
+    fn next_output_line(&mut self) -> Option<DiffLine<'a>> {
+        let mut line = self.next_filtered_line()?;
+        if let Some(max_line_width) = self.max_line_width {
+            line.truncate(max_line_width);
+        }
+        Some(line)
+    }
+
+    fn next_filtered_line(&mut self) -> Option<DiffLine<'a>> {
+        if self.context_lines == usize::MAX {
+            return self.next_line();
+        }
+        while self.filtered_lines.is_empty() {
+            let line = self.next_line()?;
+            if !line.is_unmodified() {
+                self.filtered_lines.extend(self.context_before.drain(..));
+                self.filtered_lines.push_back(line);
+                self.lines_after_change = Some(0);
+            } else if let Some(num_lines) = self
+                .lines_after_change
+                .filter(|num_lines| *num_lines < self.context_lines)
+            {
+                self.lines_after_change = Some(num_lines + 1);
+                self.filtered_lines.push_back(line);
+            } else {
+                self.context_before.push_back(line);
+                if self.context_before.len() > self.context_lines {
+                    self.context_before.pop_front();
+                }
+            }
+        }
+        self.filtered_lines.pop_front()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MergeHunk {
+    Resolved(Vec<u8>),
+    Conflict {
+        removes: Vec<Vec<u8>>,
+        adds: Vec<Vec<u8>>,
+    },
+}
+
+impl Debug for MergeHunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            MergeHunk::Resolved(data) => f
+                .debug_tuple("Resolved")
+                .field(&String::from_utf8_lossy(data))
+                .finish(),
+            MergeHunk::Conflict { removes, adds } => f
+                .debug_struct("Conflict")
+                .field(
+                    "removes",
+                    &removes
+                        .iter()
+                        .map(|part| String::from_utf8_lossy(part))
+                        .collect_vec(),
+                )
+                .field(
+                    "adds",
+                    &adds
                         .iter()
                         .map(|part| String::from_utf8_lossy(part))
                         .collect_vec(),
@@ -176,269 +1268,4744 @@ impl Debug for MergeHunk {
                 .finish(),
         }
     }
-}
+}
+
+impl MergeHunk {
+    /// The content of a resolved hunk, or `None` for a conflict hunk.
+    pub fn resolved_bytes(&self) -> Option<&[u8]> {
+        match self {
+            MergeHunk::Resolved(data) => Some(data),
+            MergeHunk::Conflict { .. } => None,
+        }
+    }
+
+    /// The removed parts of a conflict hunk, or `None` for a resolved hunk.
+    pub fn conflict_removes(&self) -> Option<&[Vec<u8>]> {
+        match self {
+            MergeHunk::Resolved(_) => None,
+            MergeHunk::Conflict { removes, .. } => Some(removes),
+        }
+    }
+
+    /// The added parts of a conflict hunk, or `None` for a resolved hunk.
+    pub fn conflict_adds(&self) -> Option<&[Vec<u8>]> {
+        match self {
+            MergeHunk::Resolved(_) => None,
+            MergeHunk::Conflict { adds, .. } => Some(adds),
+        }
+    }
+
+    /// Why a conflict hunk couldn't be resolved, for tools that group or
+    /// prioritize conflicts. Like when merging, removes and adds with the same
+    /// content cancel out first. `None` for a resolved hunk.
+    pub fn conflict_kind(&self) -> Option<ConflictKind> {
+        let (removes, adds) = match self {
+            MergeHunk::Resolved(_) => return None,
+            MergeHunk::Conflict { removes, adds } => (removes, adds),
+        };
+        let parts = removes.iter().chain(adds).map(Vec::as_slice).collect_vec();
+        let eq = |left: &[u8], right: &[u8]| left == right;
+        let (removed_indices, added_indices) = cancel_matching_parts(&parts, removes.len(), &eq);
+        let distinct_removes = distinct_parts(&parts, &removed_indices, &eq);
+        let distinct_adds = distinct_parts(&parts, &added_indices, &eq);
+        let kind = if distinct_removes.iter().all(|part| part.is_empty()) {
+            ConflictKind::AddAdd
+        } else if distinct_adds.iter().any(|part| part.is_empty()) {
+            ConflictKind::ModifyDelete
+        } else if let ([base], [left, right]) =
+            (distinct_removes.as_slice(), distinct_adds.as_slice())
+        {
+            if changes_overlap(base, left, right) {
+                ConflictKind::DistinctChanges { num_sides: 2 }
+            } else {
+                ConflictKind::AdjacentChanges
+            }
+        } else {
+            ConflictKind::DistinctChanges {
+                num_sides: distinct_adds.len(),
+            }
+        };
+        Some(kind)
+    }
+
+    /// Splits off the lines at the start and at the end of a conflict hunk
+    /// that are the same in every remove and add, so they become resolved
+    /// hunks around a smaller conflict. The removes and adds of the conflict
+    /// stay in the same order. Resolved hunks and conflicts without such lines
+    /// are returned unchanged.
+    pub fn trim_common_lines(self) -> Vec<MergeHunk> {
+        let (removes, adds) = match self {
+            MergeHunk::Resolved(_) => return vec![self],
+            MergeHunk::Conflict { removes, adds } => (removes, adds),
+        };
+        let parts = removes.iter().chain(&adds).map(Vec::as_slice).collect_vec();
+        let (prefix_len, suffix_len) = common_line_affixes(&parts);
+        if prefix_len == 0 && suffix_len == 0 {
+            return vec![MergeHunk::Conflict { removes, adds }];
+        }
+        let first = parts[0];
+        let trim = |part: &Vec<u8>| part[prefix_len..part.len() - suffix_len].to_vec();
+        let mut hunks = vec![];
+        if prefix_len > 0 {
+            hunks.push(MergeHunk::Resolved(first[..prefix_len].to_vec()));
+        }
+        hunks.push(MergeHunk::Conflict {
+            removes: removes.iter().map(trim).collect_vec(),
+            adds: adds.iter().map(trim).collect_vec(),
+        });
+        if suffix_len > 0 {
+            hunks.push(MergeHunk::Resolved(
+                first[first.len() - suffix_len..].to_vec(),
+            ));
+        }
+        hunks
+    }
+
+    /// For a conflict hunk, groups the indices of `adds` that have identical
+    /// content. Returns `None` for a resolved hunk.
+    pub fn conflict_agreement(&self) -> Option<ConflictAgreement> {
+        match self {
+            MergeHunk::Resolved(_) => None,
+            MergeHunk::Conflict { adds, .. } => {
+                let mut add_groups: Vec<Vec<usize>> = vec![];
+                for (i, add) in adds.iter().enumerate() {
+                    match add_groups.iter_mut().find(|group| adds[group[0]] == *add) {
+                        Some(group) => group.push(i),
+                        None => add_groups.push(vec![i]),
+                    }
+                }
+                Some(ConflictAgreement { add_groups })
+            }
+        }
+    }
+
+    /// Simplifies a conflict hunk so it can be presented with as few parts as
+    /// possible. Each add that's identical to a remove cancels out with it, and
+    /// the remaining identical removes and identical adds are collapsed into
+    /// one part each. The hunk is resolved if no removes and only one distinct
+    /// add are left.
+    ///
+    /// For example, the removes `[a, b]` and adds `[b, x, y]` simplify to the
+    /// remove `a` and the adds `x` and `y`, since the `b`s cancel out. The
+    /// removes `[a, a]` and adds `[x, x, y]` simplify the same way, with the
+    /// remove `a` and the add `x` each standing for two parts.
+    pub fn simplify(&self) -> SimplifiedHunk {
+        let (removes, adds) = match self {
+            MergeHunk::Resolved(data) => return SimplifiedHunk::Resolved(data.clone()),
+            MergeHunk::Conflict { removes, adds } => (removes, adds),
+        };
+        let mut remaining_removes = (0..removes.len()).collect_vec();
+        let mut remaining_adds = vec![];
+        for (add_index, add) in adds.iter().enumerate() {
+            match remaining_removes.iter().position(|i| removes[*i] == *add) {
+                Some(position) => {
+                    remaining_removes.remove(position);
+                }
+                None => remaining_adds.push(add_index),
+            }
+        }
+        let removes = collapse_parts(removes, &remaining_removes);
+        let adds = collapse_parts(adds, &remaining_adds);
+        match (removes.as_slice(), adds.as_slice()) {
+            ([], []) => SimplifiedHunk::Resolved(vec![]),
+            ([], [add]) => SimplifiedHunk::Resolved(add.content.clone()),
+            _ => SimplifiedHunk::Conflict { removes, adds },
+        }
+    }
+}
+
+/// Groups the parts at `indices` by content, in order of first appearance.
+fn collapse_parts(parts: &[Vec<u8>], indices: &[usize]) -> Vec<SimplifiedPart> {
+    let mut collapsed: Vec<SimplifiedPart> = vec![];
+    for index in indices {
+        let content = &parts[*index];
+        match collapsed.iter_mut().find(|part| part.content == *content) {
+            Some(part) => part.indices.push(*index),
+            None => collapsed.push(SimplifiedPart {
+                content: content.clone(),
+                indices: vec![*index],
+            }),
+        }
+    }
+    collapsed
+}
+
+/// A part of a simplified conflict hunk, standing for one or more identical
+/// parts of the original hunk.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SimplifiedPart {
+    pub content: Vec<u8>,
+    /// Indices into the original hunk's removes or adds, increasing.
+    pub indices: Vec<usize>,
+}
+
+impl SimplifiedPart {
+    /// The number of parts of the original hunk this part stands for.
+    pub fn multiplicity(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+/// The result of `MergeHunk::simplify()`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum SimplifiedHunk {
+    Resolved(Vec<u8>),
+    Conflict {
+        removes: Vec<SimplifiedPart>,
+        adds: Vec<SimplifiedPart>,
+    },
+}
+
+impl SimplifiedHunk {
+    /// The contents of the removed and added parts of a conflict hunk, or
+    /// `None` for a resolved hunk.
+    pub fn conflict_parts(&self) -> Option<(Vec<&[u8]>, Vec<&[u8]>)> {
+        match self {
+            SimplifiedHunk::Resolved(_) => None,
+            SimplifiedHunk::Conflict { removes, adds } => Some((
+                removes.iter().map(|part| part.content.as_slice()).collect(),
+                adds.iter().map(|part| part.content.as_slice()).collect(),
+            )),
+        }
+    }
+
+    /// Undoes the simplification of `original` after the parts of this hunk
+    /// were replaced by `removes` and `adds`, for example because the user
+    /// edited them. Each new part replaces all the parts of `original` it
+    /// stands for, and parts that cancelled out are kept as they were. Returns
+    /// `None` if this is a resolved hunk or the number of parts doesn't match.
+    pub fn expand(
+        &self,
+        original: &MergeHunk,
+        removes: Vec<Vec<u8>>,
+        adds: Vec<Vec<u8>>,
+    ) -> Option<MergeHunk> {
+        let (simplified_removes, simplified_adds) = match self {
+            SimplifiedHunk::Resolved(_) => return None,
+            SimplifiedHunk::Conflict { removes, adds } => (removes, adds),
+        };
+        let (original_removes, original_adds) = match original {
+            MergeHunk::Resolved(_) => return None,
+            MergeHunk::Conflict { removes, adds } => (removes, adds),
+        };
+        if removes.len() != simplified_removes.len() || adds.len() != simplified_adds.len() {
+            return None;
+        }
+        let expand_parts = |original_parts: &[Vec<u8>],
+                            simplified_parts: &[SimplifiedPart],
+                            new_parts: Vec<Vec<u8>>| {
+            let mut parts = original_parts.to_vec();
+            for (simplified_part, new_part) in simplified_parts.iter().zip(new_parts) {
+                for index in &simplified_part.indices {
+                    parts[*index] = new_part.clone();
+                }
+            }
+            parts
+        };
+        Some(MergeHunk::Conflict {
+            removes: expand_parts(original_removes, simplified_removes, removes),
+            adds: expand_parts(original_adds, simplified_adds, adds),
+        })
+    }
+}
+
+/// Describes which sides of a conflict hunk agree with each other. Computed by
+/// `MergeHunk::conflict_agreement()`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ConflictAgreement {
+    /// Indices into the hunk's `adds`, grouped by equal content. The groups are
+    /// ordered by their first index, and the indices within a group are
+    /// increasing.
+    pub add_groups: Vec<Vec<usize>>,
+}
+
+impl ConflictAgreement {
+    /// Whether every side added different content.
+    pub fn all_sides_differ(&self) -> bool {
+        self.add_groups.iter().all(|group| group.len() == 1)
+    }
+}
+
+/// Why a conflict hunk couldn't be resolved. See `MergeHunk::conflict_kind()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The base didn't have any content here, and the sides added different
+    /// content.
+    AddAdd,
+    /// Some sides deleted the content that other sides changed.
+    ModifyDelete,
+    /// Two sides changed different lines that are next to each other, so
+    /// applying both changes may well be the resolution.
+    AdjacentChanges,
+    /// This many sides made different changes to the same lines.
+    DistinctChanges { num_sides: usize },
+}
+
+/// Whether `left` and `right` change any of the same lines of `base`, or
+/// insert lines at the same place.
+fn changes_overlap(base: &[u8], left: &[u8], right: &[u8]) -> bool {
+    let changed_ranges = |side: &[u8]| {
+        let mut base_pos = 0;
+        let mut ranges = vec![];
+        for hunk in Diff::for_tokenizer(&[base, side], &diff::find_line_ranges).hunks() {
+            match hunk {
+                DiffHunk::Matching(content) => base_pos += content.len(),
+                DiffHunk::Different(parts) => {
+                    ranges.push(base_pos..base_pos + parts[0].len());
+                    base_pos += parts[0].len();
+                }
+            }
+        }
+        ranges
+    };
+    let left_ranges = changed_ranges(left);
+    let right_ranges = changed_ranges(right);
+    left_ranges.iter().any(|left_range| {
+        right_ranges.iter().any(|right_range| {
+            left_range == right_range
+                || (left_range.start < right_range.end && right_range.start < left_range.end)
+        })
+    })
+}
+
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MergeResult {
+    Resolved(Vec<u8>),
+    Conflict {
+        hunks: Vec<MergeHunk>,
+        /// The number of removes in each conflict hunk
+        num_removes: usize,
+        /// The number of adds in each conflict hunk
+        num_adds: usize,
+    },
+}
+
+impl MergeResult {
+    /// A conflict made of `hunks`, with the number of removes and adds taken
+    /// from its conflict hunks, which all have the same number of them.
+    pub fn conflict(hunks: Vec<MergeHunk>) -> Self {
+        let (num_removes, num_adds) = hunks
+            .iter()
+            .find_map(|hunk| match hunk {
+                MergeHunk::Resolved(_) => None,
+                MergeHunk::Conflict { removes, adds } => Some((removes.len(), adds.len())),
+            })
+            .unwrap_or((0, 0));
+        MergeResult::Conflict {
+            hunks,
+            num_removes,
+            num_adds,
+        }
+    }
+
+    /// Whether the merge had no conflicts.
+    pub fn is_resolved(&self) -> bool {
+        matches!(self, MergeResult::Resolved(_))
+    }
+
+    /// The number of conflict hunks. Zero for a resolved result.
+    pub fn conflict_count(&self) -> usize {
+        match self {
+            MergeResult::Resolved(_) => 0,
+            MergeResult::Conflict { hunks, .. } => hunks
+                .iter()
+                .filter(|hunk| matches!(hunk, MergeHunk::Conflict { .. }))
+                .count(),
+        }
+    }
+
+    /// The merged content, or `None` if the result has conflicts.
+    pub fn resolved_bytes(&self) -> Option<&[u8]> {
+        match self {
+            MergeResult::Resolved(data) => Some(data),
+            MergeResult::Conflict { .. } => None,
+        }
+    }
+
+    /// The conflict hunks with their indexes among all hunks. The indexes stay
+    /// valid when other hunks are resolved with `resolve_hunk()`, so they can
+    /// be used to resolve the hunks in any order. Empty for a resolved result.
+    pub fn conflict_hunks(&self) -> Vec<(usize, &MergeHunk)> {
+        match self {
+            MergeResult::Resolved(_) => vec![],
+            MergeResult::Conflict { hunks, .. } => hunks
+                .iter()
+                .enumerate()
+                .filter(|(_, hunk)| matches!(hunk, MergeHunk::Conflict { .. }))
+                .collect_vec(),
+        }
+    }
+
+    /// Resolves the conflict hunk at `index`, as returned by
+    /// `conflict_hunks()`. The hunk is replaced by a resolved hunk in the same
+    /// place, so the other hunks and their indexes are unchanged. Once the last
+    /// conflict hunk is resolved, the result becomes `MergeResult::Resolved`
+    /// with the concatenated content of all hunks.
+    pub fn resolve_hunk(
+        &mut self,
+        index: usize,
+        resolution: HunkResolution,
+    ) -> Result<(), ResolveHunkError> {
+        let hunks = match self {
+            MergeResult::Resolved(_) => return Err(ResolveHunkError::NoSuchConflict(index)),
+            MergeResult::Conflict { hunks, .. } => hunks,
+        };
+        let (removes, adds) = match hunks.get(index) {
+            Some(MergeHunk::Conflict { removes, adds }) => (removes, adds),
+            _ => return Err(ResolveHunkError::NoSuchConflict(index)),
+        };
+        let content = match resolution {
+            HunkResolution::Remove(i) => removes
+                .get(i)
+                .ok_or(ResolveHunkError::NoSuchRemove(i))?
+                .clone(),
+            HunkResolution::Add(i) => adds.get(i).ok_or(ResolveHunkError::NoSuchAdd(i))?.clone(),
+            HunkResolution::Content(content) => content,
+        };
+        hunks[index] = MergeHunk::Resolved(content);
+        if hunks
+            .iter()
+            .all(|hunk| matches!(hunk, MergeHunk::Resolved(_)))
+        {
+            let content = hunks
+                .iter()
+                .flat_map(|hunk| hunk.resolved_bytes().unwrap())
+                .copied()
+                .collect_vec();
+            *self = MergeResult::Resolved(content);
+        }
+        Ok(())
+    }
+}
+
+/// How `MergeResult::resolve_hunk()` resolves a conflict hunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HunkResolution {
+    /// Use the content of the remove with this index.
+    Remove(usize),
+    /// Use the content of the add (side) with this index.
+    Add(usize),
+    /// Use this content.
+    Content(Vec<u8>),
+}
+
+/// Returned by `MergeResult::resolve_hunk()` if the hunk or the side to use
+/// doesn't exist.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ResolveHunkError {
+    #[error("There's no conflict hunk with index {0}")]
+    NoSuchConflict(usize),
+    #[error("The conflict hunk has no remove with index {0}")]
+    NoSuchRemove(usize),
+    #[error("The conflict hunk has no add with index {0}")]
+    NoSuchAdd(usize),
+}
+
+/// Like `MergeHunk`, but borrows the content from the merge inputs. Resolved
+/// content may come from several inputs, so it's a list of slices to be
+/// concatenated.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum MergeHunkRef<'a> {
+    Resolved(Vec<&'a [u8]>),
+    Conflict {
+        removes: Vec<&'a [u8]>,
+        adds: Vec<&'a [u8]>,
+    },
+}
+
+impl MergeHunkRef<'_> {
+    pub fn into_owned(self) -> MergeHunk {
+        match self {
+            MergeHunkRef::Resolved(pieces) => MergeHunk::Resolved(pieces.concat()),
+            MergeHunkRef::Conflict { removes, adds } => MergeHunk::Conflict {
+                removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+                adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+            },
+        }
+    }
+}
+
+/// Like `MergeResult`, but borrows the content from the merge inputs. Returned
+/// by `merge_borrowed()`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum MergeResultRef<'a> {
+    Resolved(Vec<&'a [u8]>),
+    Conflict(Vec<MergeHunkRef<'a>>),
+}
+
+impl MergeResultRef<'_> {
+    pub fn into_owned(self) -> MergeResult {
+        match self {
+            MergeResultRef::Resolved(pieces) => MergeResult::Resolved(pieces.concat()),
+            MergeResultRef::Conflict(hunks) => MergeResult::conflict(
+                hunks
+                    .into_iter()
+                    .map(MergeHunkRef::into_owned)
+                    .collect_vec(),
+            ),
+        }
+    }
+}
+
+impl Debug for MergeResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            MergeResult::Resolved(data) => f
+                .debug_tuple("Resolved")
+                .field(&String::from_utf8_lossy(data))
+                .finish(),
+            MergeResult::Conflict {
+                hunks,
+                num_removes,
+                num_adds,
+            } => f
+                .debug_struct("Conflict")
+                .field("hunks", hunks)
+                .field("num_removes", num_removes)
+                .field("num_adds", num_adds)
+                .finish(),
+        }
+    }
+}
+
+/// A region where the base and two sides match.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct SyncRegion {
+    base: Range<usize>,
+    left: Range<usize>,
+    right: Range<usize>,
+}
+
+/// Options for `merge_with_options()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MergeOptions {
+    /// Ignore spaces and tabs at the end of lines when comparing the inputs.
+    /// Where the inputs only differ in such whitespace, the content from the
+    /// base is used.
+    pub ignore_trailing_whitespace: bool,
+    /// Resolve conflict hunks where a side only changed whitespace. If all
+    /// parts only differ in whitespace, the first side is taken. Otherwise, if
+    /// one side of a 3-way conflict only changed whitespace, the other side is
+    /// taken, with its whitespace. Runs of whitespace, including line breaks,
+    /// are considered equal, but whitespace between two words isn't ignored.
+    pub resolve_whitespace_only: bool,
+    pub granularity: MergeGranularity,
+    /// How much work goes into making conflict hunks smaller, or resolving
+    /// them.
+    pub conflict_minimization: ConflictMinimization,
+    /// How the sides are compared when deciding whether they made the same
+    /// change.
+    pub same_change_whitespace: SameChangeWhitespace,
+    /// How to treat lines that only differ between CRLF and LF line endings.
+    /// Only `merge_with_options()` can change line endings, since that needs
+    /// a copy of the inputs.
+    pub line_endings: LineEndings,
+    /// The line ending every line in the merge result gets, including the
+    /// parts of conflict hunks. Like `line_endings`, only applied by
+    /// `merge_with_options()`.
+    pub output_line_ending: LineEnding,
+}
+
+impl MergeOptions {
+    pub fn from_settings(settings: &UserSettings) -> Result<Self, config::ConfigError> {
+        let defaults = MergeOptions::default();
+        Ok(MergeOptions {
+            granularity: settings
+                .get_parsed("merge.granularity")?
+                .unwrap_or(defaults.granularity),
+            conflict_minimization: settings
+                .get_parsed("merge.conflict-minimization")?
+                .unwrap_or(defaults.conflict_minimization),
+            same_change_whitespace: settings
+                .get_parsed("merge.same-change-whitespace")?
+                .unwrap_or(defaults.same_change_whitespace),
+            line_endings: settings
+                .get_parsed("merge.line-endings")?
+                .unwrap_or(defaults.line_endings),
+            output_line_ending: settings
+                .get_parsed("merge.output-line-ending")?
+                .unwrap_or(defaults.output_line_ending),
+            resolve_whitespace_only: settings
+                .config()
+                .get_bool("merge.resolve-whitespace-only")
+                .unwrap_or(defaults.resolve_whitespace_only),
+            ..defaults
+        })
+    }
+}
+
+/// The units in which `merge_with_options()` compares the inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MergeGranularity {
+    /// Conflicts are reported for whole lines.
+    Line,
+    /// Conflicts are first found for whole lines. Then each conflicting hunk
+    /// is merged again word by word, and replaced by the result if that
+    /// resolves it.
+    Word,
+}
+
+impl Default for MergeGranularity {
+    fn default() -> Self {
+        MergeGranularity::Line
+    }
+}
+
+impl FromStr for MergeGranularity {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(MergeGranularity::Line),
+            "word" => Ok(MergeGranularity::Word),
+            _ => Err("must be one of line or word"),
+        }
+    }
+}
+
+/// How `merge_with_options()` minimizes conflicts, similar to Git's
+/// `merge.conflictStyle` of `zdiff3` versus `diff3`. In all of them, parts of
+/// a region that didn't change from a base cancel out against that base, so a
+/// region that only one side changed is still resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConflictMinimization {
+    /// Sides that made the same change are resolved. Conflict hunks with
+    /// several removes are merged again with a better base, and lines that all
+    /// parts of a conflict hunk start or end with are moved out of it.
+    Zealous,
+    /// Sides that made the same change are resolved, but conflict hunks are
+    /// kept as the diff of the inputs found them.
+    Minimal,
+    /// Of the regions where the inputs differ, only the ones where a single add
+    /// is left after cancelling out unchanged parts are resolved. The others,
+    /// including where sides made the same change, are conflicts with the full
+    /// content of every part, for tools that do their own minimization.
+    None,
+}
+
+impl Default for ConflictMinimization {
+    fn default() -> Self {
+        ConflictMinimization::Zealous
+    }
+}
+
+impl FromStr for ConflictMinimization {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zealous" => Ok(ConflictMinimization::Zealous),
+            "minimal" => Ok(ConflictMinimization::Minimal),
+            "none" => Ok(ConflictMinimization::None),
+            _ => Err("must be one of zealous, minimal, or none"),
+        }
+    }
+}
+
+/// How `merge_with_options()` compares the content that sides added to a region
+/// when deciding whether they all made the same change, which resolves the
+/// region. The resolved content is always that of the first such side, byte
+/// for byte. Other comparisons of the inputs aren't affected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SameChangeWhitespace {
+    /// The sides must have added exactly the same content.
+    Exact,
+    /// Spaces and tabs at the end of lines are ignored.
+    Trailing,
+    /// All whitespace is ignored.
+    All,
+}
+
+impl SameChangeWhitespace {
+    fn equal(self, left: &[u8], right: &[u8]) -> bool {
+        match self {
+            SameChangeWhitespace::Exact => left == right,
+            SameChangeWhitespace::Trailing => {
+                left == right
+                    || StrippedInput::new(left).content == StrippedInput::new(right).content
+            }
+            SameChangeWhitespace::All => {
+                let non_whitespace = |text: &[u8]| {
+                    text.iter()
+                        .copied()
+                        .filter(|b| !b.is_ascii_whitespace())
+                        .collect_vec()
+                };
+                non_whitespace(left) == non_whitespace(right)
+            }
+        }
+    }
+}
+
+impl Default for SameChangeWhitespace {
+    fn default() -> Self {
+        SameChangeWhitespace::Exact
+    }
+}
+
+impl FromStr for SameChangeWhitespace {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(SameChangeWhitespace::Exact),
+            "trailing" => Ok(SameChangeWhitespace::Trailing),
+            "all" => Ok(SameChangeWhitespace::All),
+            _ => Err("must be one of exact, trailing, or all"),
+        }
+    }
+}
+
+/// How `merge_with_options()` treats lines that only differ in their line
+/// endings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LineEndings {
+    /// Line endings are compared like any other content.
+    Exact,
+    /// Lines that only differ between CRLF and LF are considered equal. Where
+    /// they are merged, they get the line ending of the last input that has
+    /// them.
+    LastSide,
+    /// Like `LastSide`, but such lines get LF line endings.
+    Lf,
+    /// Like `LastSide`, but such lines get CRLF line endings.
+    Crlf,
+}
+
+impl Default for LineEndings {
+    fn default() -> Self {
+        LineEndings::Exact
+    }
+}
+
+impl FromStr for LineEndings {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(LineEndings::Exact),
+            "last-side" => Ok(LineEndings::LastSide),
+            "lf" => Ok(LineEndings::Lf),
+            "crlf" => Ok(LineEndings::Crlf),
+            _ => Err("must be one of exact, last-side, lf, or crlf"),
+        }
+    }
+}
+
+/// The line ending `merge_with_options()` gives the lines of the merge result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// Each line keeps the line ending it has in the input it came from.
+    Preserve,
+    /// Every line ends in LF.
+    Lf,
+    /// Every line ends in CRLF.
+    Crlf,
+    /// Every line ends in CRLF on Windows, and in LF elsewhere.
+    Native,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::Preserve
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(LineEnding::Preserve),
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            "native" => Ok(LineEnding::Native),
+            _ => Err("must be one of preserve, lf, crlf, or native"),
+        }
+    }
+}
+
+impl LineEnding {
+    /// The bytes to end lines with, or `None` if lines keep their line ending.
+    fn bytes(self) -> Option<&'static [u8]> {
+        match self {
+            LineEnding::Preserve => None,
+            LineEnding::Lf => Some(b"\n"),
+            LineEnding::Crlf => Some(b"\r\n"),
+            LineEnding::Native if cfg!(windows) => Some(b"\r\n"),
+            LineEnding::Native => Some(b"\n"),
+        }
+    }
+}
+
+/// Gives every line in `data` the line ending `line_ending`.
+fn normalize_line_endings(data: &[u8], line_ending: &[u8]) -> Vec<u8> {
+    data.split_inclusive(|b| *b == b'\n')
+        .flat_map(|line| with_line_ending(line, line_ending))
+        .collect()
+}
+
+/// How `merge_with_strategy()` resolves conflicting hunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Take the first side.
+    Ours,
+    /// Take the last side.
+    Theirs,
+    /// Take every side, in input order, skipping sides identical to an
+    /// earlier one.
+    Union,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ours" => Ok(MergeStrategy::Ours),
+            "theirs" => Ok(MergeStrategy::Theirs),
+            "union" => Ok(MergeStrategy::Union),
+            _ => Err("must be one of ours, theirs, or union"),
+        }
+    }
+}
+
+impl MergeStrategy {
+    fn resolve_hunk<'a>(&self, adds: &[&'a [u8]]) -> Vec<&'a [u8]> {
+        match self {
+            MergeStrategy::Ours => adds.first().copied().into_iter().collect_vec(),
+            MergeStrategy::Theirs => adds.last().copied().into_iter().collect_vec(),
+            MergeStrategy::Union => adds.iter().copied().unique().collect_vec(),
+        }
+    }
+}
+
+/// How a tree merge resolves conflicts in files matching a
+/// `merge.file-strategies` pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMergeStrategy {
+    /// Merge the contents, resolving conflicting hunks with the strategy.
+    Resolve(MergeStrategy),
+    /// Don't merge the contents at all, so the file is left conflicted unless
+    /// only one side changed it.
+    Binary,
+}
+
+impl FromStr for FileMergeStrategy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(FileMergeStrategy::Binary),
+            _ => s
+                .parse()
+                .map(FileMergeStrategy::Resolve)
+                .map_err(|_| "must be one of ours, theirs, union, or binary"),
+        }
+    }
+}
+
+/// The merge strategies for files matching `.gitignore`-style patterns. When
+/// several patterns match a file, the longest one wins.
+#[derive(Clone, Default)]
+pub struct FileMergeStrategies {
+    // Sorted by decreasing length of the pattern
+    patterns: Vec<(String, Arc<GitIgnoreFile>, FileMergeStrategy)>,
+}
+
+impl Debug for FileMergeStrategies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.debug_map()
+            .entries(
+                self.patterns
+                    .iter()
+                    .map(|(pattern, _, strategy)| (pattern, strategy)),
+            )
+            .finish()
+    }
+}
+
+impl FileMergeStrategies {
+    pub fn new(patterns: impl IntoIterator<Item = (String, FileMergeStrategy)>) -> Self {
+        let mut patterns = patterns
+            .into_iter()
+            .map(|(pattern, strategy)| {
+                let matcher = GitIgnoreFile::empty().chain("", pattern.as_bytes());
+                (pattern, matcher, strategy)
+            })
+            .collect_vec();
+        patterns.sort_by(|(a, _, _), (b, _, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        FileMergeStrategies { patterns }
+    }
+
+    /// Reads the `merge.file-strategies` table, which maps patterns to
+    /// strategies. Entries with an invalid strategy are ignored.
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let table = settings
+            .config()
+            .get_table("merge.file-strategies")
+            .unwrap_or_default();
+        FileMergeStrategies::new(table.into_iter().filter_map(|(pattern, value)| {
+            let strategy = value.into_string().ok()?.parse().ok()?;
+            Some((pattern, strategy))
+        }))
+    }
+
+    /// The strategy for the file at `path`, if any pattern matches it.
+    pub fn strategy_for(&self, path: &RepoPath) -> Option<FileMergeStrategy> {
+        let path = path.to_internal_file_string();
+        self.patterns
+            .iter()
+            .find(|(_, matcher, _)| matcher.matches_file(&path))
+            .map(|(_, _, strategy)| *strategy)
+    }
+}
+
+/// Whether `content` looks like binary data rather than text, i.e. it has a
+/// NUL byte.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.contains(&0)
+}
+
+/// Resolves each conflict hunk in `hunks` according to `strategy` and returns
+/// the resulting content.
+pub fn resolve_hunks_with_strategy(hunks: &[MergeHunk], strategy: MergeStrategy) -> Vec<u8> {
+    hunks
+        .iter()
+        .flat_map(|hunk| match hunk {
+            MergeHunk::Resolved(data) => vec![data.as_slice()],
+            MergeHunk::Conflict { adds, .. } => {
+                strategy.resolve_hunk(&adds.iter().map(Vec::as_slice).collect_vec())
+            }
+        })
+        .collect_vec()
+        .concat()
+}
+
+/// The inputs of `merge()`: the removes (bases) and the adds (sides) of a
+/// chain of changes. There's one more add than there are removes, except in
+/// the degenerate cases created by `addition()` and `deletion()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeInput<'a> {
+    removes: Vec<&'a [u8]>,
+    adds: Vec<&'a [u8]>,
+}
+
+/// Returned by `MergeInput::new()` when the number of removes and adds don't
+/// fit together.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "A merge needs one more add than removes, but got {num_removes} removes and {num_adds} adds"
+)]
+pub struct MergeArityError {
+    pub num_removes: usize,
+    pub num_adds: usize,
+}
+
+impl<'a> MergeInput<'a> {
+    /// Fails unless there's exactly one more add than there are removes.
+    /// Missing content, like a side that deleted a file, must be passed
+    /// explicitly as an empty input.
+    pub fn new(removes: &[&'a [u8]], adds: &[&'a [u8]]) -> Result<Self, MergeArityError> {
+        if adds.len() != removes.len() + 1 {
+            return Err(MergeArityError {
+                num_removes: removes.len(),
+                num_adds: adds.len(),
+            });
+        }
+        Ok(MergeInput {
+            removes: removes.to_vec(),
+            adds: adds.to_vec(),
+        })
+    }
+
+    /// A regular 3-way merge of `left` and `right`, which both started from
+    /// `base`.
+    pub fn three_way(base: &'a [u8], left: &'a [u8], right: &'a [u8]) -> Self {
+        MergeInput {
+            removes: vec![base],
+            adds: vec![left, right],
+        }
+    }
+
+    /// Sides that each added content without a base. The merge resolves if
+    /// they all added the same content.
+    pub fn addition(adds: &[&'a [u8]]) -> Self {
+        MergeInput {
+            removes: vec![],
+            adds: adds.to_vec(),
+        }
+    }
+
+    /// Bases whose content was removed, with nothing added. The merge resolves
+    /// to nothing if they all had the same content.
+    pub fn deletion(removes: &[&'a [u8]]) -> Self {
+        MergeInput {
+            removes: removes.to_vec(),
+            adds: vec![],
+        }
+    }
+
+    pub fn removes(&self) -> &[&'a [u8]] {
+        &self.removes
+    }
+
+    pub fn adds(&self) -> &[&'a [u8]] {
+        &self.adds
+    }
+}
+
+/// Merges a chain of changes: the result is `adds[0] - removes[0] + adds[1] -
+/// removes[1] + ... + adds[n]`. A regular 3-way merge is
+/// `merge(&MergeInput::three_way(base, left, right))`, and merging four
+/// branches that share a base (an octopus merge) has the removes
+/// `[base, base, base]` and the adds `[a, b, c, d]`.
+///
+/// Each region where the inputs differ is merged by first cancelling every add
+/// against an identical remove. The region resolves if nothing is left, if only
+/// adds with the same content are left, if only copies of one remove are left,
+/// or if one distinct remove and one distinct add are left and there's one
+/// more copy of the add than of the remove. The last rule also resolves a
+/// region that all sides removed, since their adds are all empty. Otherwise,
+/// the region is a conflict that lists all the removes and adds in input order.
+///
+/// For example, with the removes `[a, a, a]` and the adds `[a, b, a, b]`, the
+/// two `a` adds cancel two of the removes. That leaves the removes `[a]` and
+/// the adds `[b, b]`: both remaining sides made the same change to the
+/// remaining base, so the result is `b`. With the adds `[a, b, a, c]` instead,
+/// the removes `[a]` and the adds `[b, c]` are left, which is a conflict
+/// between `b` and `c`.
+pub fn merge(input: &MergeInput) -> MergeResult {
+    merge_with_options(input.removes(), input.adds(), &MergeOptions::default())
+}
+
+/// The previous signature of `merge()`, which accepts any number of removes
+/// and adds.
+#[deprecated(note = "use `merge()` with a `MergeInput`")]
+pub fn merge_slices(removes: &[&[u8]], adds: &[&[u8]]) -> MergeResult {
+    merge_with_options(removes, adds, &MergeOptions::default())
+}
+
+/// Like `merge()`, but two parts of a differing region are considered the same
+/// if `eq` returns true for them, instead of if they're byte-for-byte equal.
+/// For example, with an `eq` that ignores whitespace, a side that only
+/// reformatted a region cancels out against the base, so the other side's
+/// change is taken. Where parts that `eq` considers the same are merged, the
+/// content of one of them is used. The inputs are still diffed byte by byte,
+/// so `eq` only decides how each differing region is merged.
+pub fn merge_with_comparator<F>(removes: &[&[u8]], adds: &[&[u8]], eq: F) -> MergeResult
+where
+    F: Fn(&[u8], &[u8]) -> bool,
+{
+    let inputs = removes.iter().chain(adds).copied().collect_vec();
+    let result = merge_inputs_with_comparator(
+        &inputs,
+        removes.len(),
+        ConflictMinimization::default(),
+        &diff::find_line_ranges,
+        |input_index, range| {
+            let input: &[u8] = inputs[input_index];
+            &input[range]
+        },
+        &eq,
+        SameChangeWhitespace::Exact,
+    );
+    match result {
+        MergeResultRef::Conflict(hunks) => trim_conflict_hunks(hunks).into_owned(),
+        result => result.into_owned(),
+    }
+}
+
+/// Whether an input to `merge_labeled()` is a remove (a base) or an add (a
+/// side).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeSide {
+    Remove,
+    Add,
+}
+
+/// A part of a conflict hunk from `merge_labeled()`, with the label of the
+/// input it came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabeledPart<L> {
+    pub label: L,
+    pub content: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LabeledMergeHunk<L> {
+    Resolved(Vec<u8>),
+    Conflict {
+        removes: Vec<LabeledPart<L>>,
+        adds: Vec<LabeledPart<L>>,
+    },
+}
+
+/// The result of `merge_labeled()`. Like `MergeResult`, but each part of a
+/// conflict hunk carries the label of its input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LabeledMergeResult<L> {
+    Resolved(Vec<u8>),
+    Conflict(Vec<LabeledMergeHunk<L>>),
+}
+
+/// Like `merge()`, but each input has a label, such as "base", "ours", or a
+/// branch name, which is kept on the parts of the conflict hunks. The removes
+/// and adds are the inputs with `MergeSide::Remove` and `MergeSide::Add`
+/// respectively, each in the order they're given.
+pub fn merge_labeled<L: Clone>(inputs: &[(L, MergeSide, &[u8])]) -> LabeledMergeResult<L> {
+    let side_inputs = |side: MergeSide| {
+        inputs
+            .iter()
+            .filter(|(_, input_side, _)| *input_side == side)
+            .collect_vec()
+    };
+    let removes = side_inputs(MergeSide::Remove);
+    let adds = side_inputs(MergeSide::Add);
+    // Unlike `merge()`, this doesn't merge again with another base, so the
+    // parts of every conflict hunk are the inputs in order.
+    let result = merge_with_comparator(
+        &removes.iter().map(|(_, _, content)| *content).collect_vec(),
+        &adds.iter().map(|(_, _, content)| *content).collect_vec(),
+        |left, right| left == right,
+    );
+    let label_parts = |parts: Vec<Vec<u8>>, labeled_inputs: &[&(L, MergeSide, &[u8])]| {
+        parts
+            .into_iter()
+            .zip(labeled_inputs)
+            .map(|(content, (label, _, _))| LabeledPart {
+                label: label.clone(),
+                content,
+            })
+            .collect_vec()
+    };
+    match result {
+        MergeResult::Resolved(content) => LabeledMergeResult::Resolved(content),
+        MergeResult::Conflict { hunks, .. } => LabeledMergeResult::Conflict(
+            hunks
+                .into_iter()
+                .map(|hunk| match hunk {
+                    MergeHunk::Resolved(content) => LabeledMergeHunk::Resolved(content),
+                    MergeHunk::Conflict {
+                        removes: remove_parts,
+                        adds: add_parts,
+                    } => LabeledMergeHunk::Conflict {
+                        removes: label_parts(remove_parts, &removes),
+                        adds: label_parts(add_parts, &adds),
+                    },
+                })
+                .collect_vec(),
+        ),
+    }
+}
+
+/// Where a region of the output of `merge_with_provenance()` came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeProvenance {
+    /// The region is the same in all the inputs, so it's attributed to the
+    /// base.
+    Base,
+    /// The region was taken from the adds with these indices, which all have
+    /// the same content there. Other adds have the same content as a remove,
+    /// so they didn't change the region.
+    Adds(Vec<usize>),
+    /// The region is a conflict. Its removes and adds are in the same order as
+    /// the inputs.
+    Conflict,
+}
+
+/// A region of the output of `merge_with_provenance()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeRegion {
+    pub hunk: MergeHunk,
+    pub provenance: MergeProvenance,
+}
+
+/// Like `merge()`, but returns the output as a list of regions, each with the
+/// inputs its content came from, for example to annotate the lines of a merge
+/// result or to color conflict hunks by side. Concatenating the regions gives
+/// the same content as `merge()`, except that conflicts aren't narrowed down
+/// by merging again with another base, or by merging words.
+pub fn merge_with_provenance(input: &MergeInput) -> Vec<MergeRegion> {
+    let inputs = input
+        .removes()
+        .iter()
+        .chain(input.adds())
+        .copied()
+        .collect_vec();
+    merge_input_regions(
+        &inputs,
+        input.removes().len(),
+        ConflictMinimization::default(),
+        &diff::find_line_ranges,
+        |input_index, range| {
+            let input: &[u8] = inputs[input_index];
+            &input[range]
+        },
+        &|left, right| left == right,
+        SameChangeWhitespace::Exact,
+    )
+    .into_iter()
+    .map(|(hunk, provenance)| MergeRegion {
+        hunk: hunk.into_owned(),
+        provenance,
+    })
+    .collect()
+}
+
+/// A byte range of the resolved content of a merge and the kind of input it
+/// came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnotatedRange {
+    pub bytes: Range<usize>,
+    pub origin: MergeSide,
+}
+
+/// The result of `annotate()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnnotatedContent {
+    pub ranges: Vec<AnnotatedRange>,
+}
+
+/// Attributes the resolved content of a merge to its inputs, for blame-style
+/// output. `content` is the base the merge started from, and `hunks` are the
+/// hunks of the merge result. The ranges are offsets into the concatenated
+/// content of the `MergeHunk::Resolved` hunks; conflict hunks don't have a
+/// single content, so they're skipped. Lines that are unchanged from the base
+/// come from `MergeSide::Remove`, and lines that a side changed or added come
+/// from `MergeSide::Add`. Adjacent ranges with the same origin are combined.
+pub fn annotate(content: &[u8], hunks: &[MergeHunk]) -> AnnotatedContent {
+    let resolved = hunks
+        .iter()
+        .filter_map(|hunk| match hunk {
+            MergeHunk::Resolved(content) => Some(content.as_slice()),
+            MergeHunk::Conflict { .. } => None,
+        })
+        .collect_vec()
+        .concat();
+    let mut ranges: Vec<AnnotatedRange> = vec![];
+    let mut pos = 0;
+    for hunk in Diff::for_tokenizer(&[content, &resolved], &diff::find_line_ranges).hunks() {
+        let (len, origin) = match hunk {
+            DiffHunk::Matching(content) => (content.len(), MergeSide::Remove),
+            DiffHunk::Different(parts) => (parts[1].len(), MergeSide::Add),
+        };
+        if len == 0 {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.origin == origin => last.bytes.end = pos + len,
+            _ => ranges.push(AnnotatedRange {
+                bytes: pos..pos + len,
+                origin,
+            }),
+        }
+        pos += len;
+    }
+    AnnotatedContent { ranges }
+}
+
+pub fn merge_with_options(
+    removes: &[&[u8]],
+    adds: &[&[u8]],
+    options: &MergeOptions,
+) -> MergeResult {
+    let inputs = removes.iter().chain(adds).copied().collect_vec();
+    let result = if let Some(unified_inputs) = unify_line_endings(&inputs, options.line_endings) {
+        let unified_slices = unified_inputs.iter().map(Vec::as_slice).collect_vec();
+        let (removes, adds) = unified_slices.split_at(removes.len());
+        merge_borrowed_with_options(removes, adds, options).into_owned()
+    } else {
+        merge_borrowed_with_options(removes, adds, options).into_owned()
+    };
+    match options.output_line_ending.bytes() {
+        None => result,
+        Some(line_ending) => {
+            let normalize = |data: &Vec<u8>| normalize_line_endings(data, line_ending);
+            match result {
+                MergeResult::Resolved(data) => MergeResult::Resolved(normalize(&data)),
+                MergeResult::Conflict {
+                    hunks,
+                    num_removes,
+                    num_adds,
+                } => MergeResult::Conflict {
+                    hunks: hunks
+                        .iter()
+                        .map(|hunk| match hunk {
+                            MergeHunk::Resolved(data) => MergeHunk::Resolved(normalize(data)),
+                            MergeHunk::Conflict { removes, adds } => MergeHunk::Conflict {
+                                removes: removes.iter().map(normalize).collect_vec(),
+                                adds: adds.iter().map(normalize).collect_vec(),
+                            },
+                        })
+                        .collect_vec(),
+                    num_removes,
+                    num_adds,
+                },
+            }
+        }
+    }
+}
+
+/// Rewrites the inputs so that lines that only differ in their line endings
+/// are identical wherever inputs have them at the same place, using the line
+/// ending chosen by `line_endings`. Other lines are left alone, so files with
+/// mixed line endings keep them. Returns `None` if nothing needed rewriting.
+fn unify_line_endings(inputs: &[&[u8]], line_endings: LineEndings) -> Option<Vec<Vec<u8>>> {
+    if line_endings == LineEndings::Exact || !inputs.iter().any(|input| input.contains(&b'\r')) {
+        return None;
+    }
+    let input_lines = inputs
+        .iter()
+        .map(|input| input.split_inclusive(|b| *b == b'\n').collect_vec())
+        .collect_vec();
+    let normalized_inputs = input_lines
+        .iter()
+        .map(|lines| {
+            lines
+                .iter()
+                .flat_map(|line| with_line_ending(line, b"\n"))
+                .collect_vec()
+        })
+        .collect_vec();
+    let diff = Diff::for_tokenizer(
+        &normalized_inputs.iter().map(Vec::as_slice).collect_vec(),
+        &diff::find_line_ranges,
+    );
+    let mut line_positions = vec![0; inputs.len()];
+    let mut unified_inputs = vec![vec![]; inputs.len()];
+    let mut changed = false;
+    for diff_hunk in diff.hunks() {
+        let parts = match diff_hunk {
+            DiffHunk::Matching(content) => vec![content; inputs.len()],
+            DiffHunk::Different(parts) => parts,
+        };
+        let part_lines = parts
+            .iter()
+            .zip(&mut line_positions)
+            .enumerate()
+            .map(|(input_index, (part, position))| {
+                let num_lines = part.split_inclusive(|b| *b == b'\n').count();
+                let lines = &input_lines[input_index][*position..*position + num_lines];
+                *position += num_lines;
+                lines
+            })
+            .collect_vec();
+        for (input_index, lines) in part_lines.iter().enumerate() {
+            // The inputs whose part only differs from this one in line endings
+            let group = (0..inputs.len())
+                .filter(|other_index| parts[*other_index] == parts[input_index])
+                .collect_vec();
+            for (line_index, line) in lines.iter().enumerate() {
+                let output = &mut unified_inputs[input_index];
+                if group
+                    .iter()
+                    .all(|other_index| part_lines[*other_index][line_index] == *line)
+                {
+                    output.extend_from_slice(line);
+                    continue;
+                }
+                changed = true;
+                match line_endings {
+                    LineEndings::Exact => unreachable!(),
+                    LineEndings::LastSide => {
+                        output.extend_from_slice(part_lines[*group.last().unwrap()][line_index]);
+                    }
+                    LineEndings::Lf => output.extend(with_line_ending(line, b"\n")),
+                    LineEndings::Crlf => output.extend(with_line_ending(line, b"\r\n")),
+                }
+            }
+        }
+    }
+    if changed {
+        Some(unified_inputs)
+    } else {
+        None
+    }
+}
+
+/// Replaces the line ending of `line`, if it has one, by `line_ending`.
+fn with_line_ending(line: &[u8], line_ending: &[u8]) -> Vec<u8> {
+    match line
+        .strip_suffix(b"\r\n")
+        .or_else(|| line.strip_suffix(b"\n"))
+    {
+        Some(content) => [content, line_ending].concat(),
+        None => line.to_vec(),
+    }
+}
+
+/// Like `merge()`, but the result borrows from the inputs instead of copying
+/// them.
+pub fn merge_borrowed<'a>(removes: &[&'a [u8]], adds: &[&'a [u8]]) -> MergeResultRef<'a> {
+    merge_borrowed_with_options(removes, adds, &MergeOptions::default())
+}
+
+/// Like `merge_with_options()`, but the result borrows from the inputs instead
+/// of copying them.
+pub fn merge_borrowed_with_options<'a>(
+    removes: &[&'a [u8]],
+    adds: &[&'a [u8]],
+    options: &MergeOptions,
+) -> MergeResultRef<'a> {
+    if !removes
+        .iter()
+        .chain(adds)
+        .any(|input| input.starts_with(UTF8_BOM))
+    {
+        return merge_content(removes, adds, options);
+    }
+    // The byte order mark is merged on its own, so it's kept once if the sides
+    // agree, and doesn't end up in the middle of a conflict hunk otherwise.
+    let (boms, contents): (Vec<&'a [u8]>, Vec<&'a [u8]>) = removes
+        .iter()
+        .chain(adds)
+        .map(|input| split_bom(input))
+        .unzip();
+    let bom_result = merge_inputs(
+        &boms,
+        removes.len(),
+        options.conflict_minimization,
+        &diff::find_line_ranges,
+        |input_index, range| {
+            let bom: &'a [u8] = boms[input_index];
+            &bom[range]
+        },
+    );
+    let (content_removes, content_adds) = contents.split_at(removes.len());
+    let content_result = merge_content(content_removes, content_adds, options);
+    let mut builder = MergeResultBuilder::default();
+    builder.push_result(bom_result);
+    builder.push_result(content_result);
+    builder.build()
+}
+
+fn merge_content<'a>(
+    removes: &[&'a [u8]],
+    adds: &[&'a [u8]],
+    options: &MergeOptions,
+) -> MergeResultRef<'a> {
+    // The first remove is used as the base of the diff, which is how it's usually
+    // done for 3-way conflicts. With more removes, conflicts are merged again
+    // below with a better base.
+    let mut inputs = removes.to_vec();
+    inputs.extend(adds);
+    let result = if options.ignore_trailing_whitespace {
+        let stripped_inputs = inputs
+            .iter()
+            .map(|input| StrippedInput::new(input))
+            .collect_vec();
+        let diff_inputs = stripped_inputs
+            .iter()
+            .map(|input| input.content.as_slice())
+            .collect_vec();
+        merge_inputs_with_comparator(
+            &diff_inputs,
+            removes.len(),
+            options.conflict_minimization,
+            &diff::find_line_ranges,
+            |input_index, range| {
+                let input: &'a [u8] = inputs[input_index];
+                &input[stripped_inputs[input_index].original_range(range)]
+            },
+            &|left, right| left == right,
+            options.same_change_whitespace,
+        )
+    } else {
+        merge_inputs_with_comparator(
+            &inputs,
+            removes.len(),
+            options.conflict_minimization,
+            &diff::find_line_ranges,
+            |input_index, range| {
+                let input: &'a [u8] = inputs[input_index];
+                &input[range]
+            },
+            &|left, right| left == right,
+            options.same_change_whitespace,
+        )
+    };
+    let zealous = options.conflict_minimization == ConflictMinimization::Zealous;
+    let result = match result {
+        MergeResultRef::Conflict(hunks) if zealous && removes.len() > 1 => {
+            remerge_hunks_with_best_base(hunks)
+        }
+        result => result,
+    };
+    let result = match result {
+        MergeResultRef::Conflict(hunks) if zealous => trim_conflict_hunks(hunks),
+        result => result,
+    };
+    let result = match result {
+        MergeResultRef::Conflict(hunks) if options.resolve_whitespace_only => {
+            resolve_whitespace_only_hunks(hunks)
+        }
+        result => result,
+    };
+    match (options.granularity, result) {
+        (MergeGranularity::Word, MergeResultRef::Conflict(hunks)) => {
+            resolve_hunks_by_word(hunks, options.conflict_minimization)
+        }
+        (_, result) => result,
+    }
+}
+
+/// Merges each conflict hunk again after cancelling out its identical removed
+/// and added parts, using the remaining remove that's closest to the remaining
+/// adds as the base. Parts that cancel out can still spoil how the others line
+/// up in the diff, and the first remove isn't necessarily a good base when
+/// there are several. Hunks that don't resolve this way are kept as they were.
+fn remerge_hunks_with_best_base<'a>(hunks: Vec<MergeHunkRef<'a>>) -> MergeResultRef<'a> {
+    let mut builder = MergeResultBuilder::default();
+    for hunk in hunks {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    builder.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => {
+                match remerge_with_best_base(&removes, &adds) {
+                    Some(pieces) => {
+                        for piece in pieces {
+                            builder.push_resolved(piece);
+                        }
+                    }
+                    None => builder.push_conflict(removes, adds),
+                }
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Returns the resolved content if the parts of a conflict hunk merge cleanly
+/// once identical removes and adds are dropped and the best base is picked.
+/// The best base is the remove with the smallest total edit distance to the
+/// adds. On a tie, the earliest of them is used, as before.
+fn remerge_with_best_base<'a>(removes: &[&'a [u8]], adds: &[&'a [u8]]) -> Option<Vec<&'a [u8]>> {
+    let mut removes = removes.to_vec();
+    let mut adds = adds.to_vec();
+    let num_inputs = removes.len() + adds.len();
+    adds.retain(
+        |add| match removes.iter().position(|remove| remove == add) {
+            Some(index) => {
+                removes.remove(index);
+                false
+            }
+            None => true,
+        },
+    );
+    let distances = removes
+        .iter()
+        .map(|remove| {
+            adds.iter()
+                .map(|add| edit_distance(remove, add))
+                .sum::<usize>()
+        })
+        .collect_vec();
+    let best_index = distances.iter().position_min().unwrap_or(0);
+    if best_index == 0 && removes.len() + adds.len() == num_inputs {
+        // Merging again would give the same conflict
+        return None;
+    }
+    if best_index > 0 {
+        removes[..=best_index].rotate_right(1);
+    }
+    let inputs = removes.iter().chain(&adds).copied().collect_vec();
+    let result = merge_inputs(
+        &inputs,
+        removes.len(),
+        ConflictMinimization::Zealous,
+        &diff::find_line_ranges,
+        |input_index, range| {
+            let input: &'a [u8] = inputs[input_index];
+            &input[range]
+        },
+    );
+    match result {
+        MergeResultRef::Resolved(pieces) => Some(pieces),
+        MergeResultRef::Conflict(_) => None,
+    }
+}
+
+/// How similar `left` and `right` are, as the percentage of their bytes that
+/// are in lines they have in common. Two empty inputs are 100% similar.
+pub fn similarity(left: &[u8], right: &[u8]) -> u32 {
+    let total = left.len() + right.len();
+    if total == 0 {
+        return 100;
+    }
+    ((total - edit_distance(left, right)) * 100 / total) as u32
+}
+
+/// The number of bytes in the lines that differ between `left` and `right`.
+fn edit_distance(left: &[u8], right: &[u8]) -> usize {
+    Diff::for_tokenizer(&[left, right], &diff::find_line_ranges)
+        .hunks()
+        .map(|hunk| match hunk {
+            DiffHunk::Matching(_) => 0,
+            DiffHunk::Different(parts) => parts.iter().map(|part| part.len()).sum(),
+        })
+        .sum()
+}
+
+/// Merges the parts of each conflict hunk in `result` again, for example after
+/// some of them were edited, and combines adjacent resolved hunks. A result
+/// without conflicts is returned unchanged.
+pub fn remerge(result: MergeResult) -> MergeResult {
+    let hunks = match result {
+        MergeResult::Resolved(_) => return result,
+        MergeResult::Conflict { hunks, .. } => hunks,
+    };
+    let mut builder = MergeResultBuilder::default();
+    for hunk in &hunks {
+        match hunk {
+            MergeHunk::Resolved(data) => builder.push_resolved(data),
+            MergeHunk::Conflict { removes, adds } => {
+                let removes = removes.iter().map(Vec::as_slice).collect_vec();
+                let adds = adds.iter().map(Vec::as_slice).collect_vec();
+                match merge_borrowed(&removes, &adds) {
+                    MergeResultRef::Resolved(pieces) => {
+                        for piece in pieces {
+                            builder.push_resolved(piece);
+                        }
+                    }
+                    MergeResultRef::Conflict(sub_hunks) => {
+                        for sub_hunk in sub_hunks {
+                            builder.push_hunk(sub_hunk);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    builder.build().into_owned()
+}
+
+/// Splits a conflict hunk in two at byte offset `at` in the content of its
+/// first add, for example when the first part of a conflict has been resolved.
+/// The first add is split exactly at `at`. Every other remove and add is split
+/// at the corresponding position: where `at` is in content that's the same as
+/// in the first add, at the same place in that content; where it's inside a
+/// region that differs from the first add, at the end of that region, so the
+/// region stays in the first half. A region that starts exactly at `at` goes
+/// to the second half. Each half is returned as `MergeHunk::Resolved` if its
+/// removes and adds merge cleanly, and as a `MergeHunk::Conflict` otherwise.
+///
+/// Panics if `hunk` isn't a conflict or if `at` is past the end of the first
+/// add.
+pub fn split_conflict_hunk(hunk: &MergeHunk, at: usize) -> (MergeHunk, MergeHunk) {
+    let (removes, adds) = match hunk {
+        MergeHunk::Resolved(_) => panic!("can't split a resolved hunk"),
+        MergeHunk::Conflict { removes, adds } => (removes, adds),
+    };
+    let first_add = adds[0].as_slice();
+    assert!(at <= first_add.len(), "split offset is out of range");
+    let (removes1, removes2): (Vec<&[u8]>, Vec<&[u8]>) = removes
+        .iter()
+        .map(|part| part.split_at(corresponding_position(first_add, part, at)))
+        .unzip();
+    let (adds1, adds2): (Vec<&[u8]>, Vec<&[u8]>) = adds
+        .iter()
+        .map(|part| part.split_at(corresponding_position(first_add, part, at)))
+        .unzip();
+    let to_hunk = |removes: Vec<&[u8]>, adds: Vec<&[u8]>| match merge_borrowed(&removes, &adds) {
+        MergeResultRef::Resolved(pieces) => MergeHunk::Resolved(pieces.concat()),
+        MergeResultRef::Conflict(_) => MergeHunk::Conflict {
+            removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+            adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+        },
+    };
+    (to_hunk(removes1, adds1), to_hunk(removes2, adds2))
+}
+
+/// Returns the position in `other` that corresponds to `at` in `base`, as
+/// described for `split_conflict_hunk()`.
+fn corresponding_position(base: &[u8], other: &[u8], at: usize) -> usize {
+    let mut base_pos = 0;
+    let mut other_pos = 0;
+    for hunk in diff::diff(base, other) {
+        match hunk {
+            DiffHunk::Matching(content) => {
+                if at <= base_pos + content.len() {
+                    return other_pos + (at - base_pos);
+                }
+                base_pos += content.len();
+                other_pos += content.len();
+            }
+            DiffHunk::Different(parts) => {
+                if at <= base_pos {
+                    return other_pos;
+                }
+                base_pos += parts[0].len();
+                other_pos += parts[1].len();
+                if at < base_pos {
+                    return other_pos;
+                }
+            }
+        }
+    }
+    other_pos
+}
+
+/// Returns the number of conflicts in `result`, which is the number of
+/// `MergeHunk::Conflict` hunks. Two adjacent conflict hunks with no `Resolved`
+/// hunk between them count as two, even though they may look like one block
+/// when materialized. A resolved result has no conflicts.
+pub fn count_conflicts(result: &MergeResult) -> usize {
+    result.conflict_count()
+}
+
+/// How many conflicts a merge has, and how severe they are. See
+/// `conflict_stats()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConflictStats {
+    /// The number of conflict hunks, as counted by `count_conflicts()`.
+    pub num_hunks: usize,
+    /// The largest number of sides of any conflict hunk.
+    pub num_sides: usize,
+    /// The number of conflicted lines. Each conflict hunk counts as many lines
+    /// as its longest part has.
+    pub num_lines: usize,
+    /// The number of conflict hunks where a side deleted the content of the
+    /// base: the side is empty where a base isn't, or the side is missing.
+    pub num_deletion_hunks: usize,
+}
+
+impl ConflictStats {
+    /// Whether any conflict hunk involves a deleted side.
+    pub fn involves_deletion(&self) -> bool {
+        self.num_deletion_hunks > 0
+    }
+}
+
+/// Returns statistics about the conflict hunks in `result`. A resolved result
+/// has no conflicts.
+pub fn conflict_stats(result: &MergeResult) -> ConflictStats {
+    let mut stats = ConflictStats::default();
+    let hunks = match result {
+        MergeResult::Resolved(_) => return stats,
+        MergeResult::Conflict { hunks, .. } => hunks,
+    };
+    for hunk in hunks {
+        if let MergeHunk::Conflict { removes, adds } = hunk {
+            stats.num_hunks += 1;
+            stats.num_sides = stats.num_sides.max(adds.len());
+            stats.num_lines += removes
+                .iter()
+                .chain(adds)
+                .map(|part| diff::find_line_ranges(part).len())
+                .max()
+                .unwrap_or(0);
+            let has_deleted_side = adds.len() <= removes.len()
+                || (adds.iter().any(|add| add.is_empty())
+                    && removes.iter().any(|remove| !remove.is_empty()));
+            if has_deleted_side {
+                stats.num_deletion_hunks += 1;
+            }
+        }
+    }
+    stats
+}
+
+/// Like `merge()`, but resolves each conflicting hunk according to `strategy`
+/// instead of leaving a conflict.
+pub fn merge_with_strategy(removes: &[&[u8]], adds: &[&[u8]], strategy: MergeStrategy) -> Vec<u8> {
+    match merge_borrowed(removes, adds) {
+        MergeResultRef::Resolved(pieces) => pieces.concat(),
+        MergeResultRef::Conflict(hunks) => hunks
+            .iter()
+            .flat_map(|hunk| match hunk {
+                MergeHunkRef::Resolved(pieces) => pieces.clone(),
+                MergeHunkRef::Conflict { adds, .. } => strategy.resolve_hunk(adds),
+            })
+            .collect_vec()
+            .concat(),
+    }
+}
+
+/// Whether `left` and `right` have the same words, separated by any amount of
+/// whitespace.
+fn equal_ignoring_whitespace_runs(left: &[u8], right: &[u8]) -> bool {
+    fn words(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+        text.split(u8::is_ascii_whitespace)
+            .filter(|word| !word.is_empty())
+    }
+    words(left).eq(words(right))
+}
+
+/// Replaces each conflicting hunk whose parts are all the same apart from
+/// whitespace with its first side, and each 3-way conflicting hunk where one
+/// side only changed whitespace with the other side.
+fn resolve_whitespace_only_hunks<'a>(hunks: Vec<MergeHunkRef<'a>>) -> MergeResultRef<'a> {
+    let mut builder = MergeResultBuilder::default();
+    for hunk in hunks {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    builder.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => {
+                if removes
+                    .iter()
+                    .chain(&adds)
+                    .all(|part| equal_ignoring_whitespace_runs(part, adds[0]))
+                {
+                    builder.push_resolved(adds[0]);
+                } else if let ([base], [left, right]) = (removes.as_slice(), adds.as_slice()) {
+                    // At most one side can only have changed whitespace here,
+                    // since the sides would be equal otherwise
+                    if equal_ignoring_whitespace_runs(left, base) {
+                        builder.push_resolved(right);
+                    } else if equal_ignoring_whitespace_runs(right, base) {
+                        builder.push_resolved(left);
+                    } else {
+                        builder.push_conflict(removes, adds);
+                    }
+                } else {
+                    builder.push_conflict(removes, adds);
+                }
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Merges each conflicting hunk again word by word, replacing the ones that
+/// resolve cleanly.
+fn resolve_hunks_by_word<'a>(
+    hunks: Vec<MergeHunkRef<'a>>,
+    minimization: ConflictMinimization,
+) -> MergeResultRef<'a> {
+    let mut builder = MergeResultBuilder::default();
+    for hunk in hunks {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    builder.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => {
+                let inputs = removes.iter().chain(&adds).copied().collect_vec();
+                let word_result = merge_inputs(
+                    &inputs,
+                    removes.len(),
+                    minimization,
+                    &find_word_and_nonword_ranges,
+                    |input_index, range| {
+                        let input: &'a [u8] = inputs[input_index];
+                        &input[range]
+                    },
+                );
+                if let MergeResultRef::Resolved(pieces) = word_result {
+                    for piece in pieces {
+                        builder.push_resolved(piece);
+                    }
+                } else {
+                    builder.push_conflict(removes, adds);
+                }
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Moves the lines that every part of a conflict hunk starts or ends with out
+/// of the conflict. See `MergeHunk::trim_common_lines()`.
+fn trim_conflict_hunks<'a>(hunks: Vec<MergeHunkRef<'a>>) -> MergeResultRef<'a> {
+    let mut builder = MergeResultBuilder::default();
+    for hunk in hunks {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    builder.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => {
+                let parts = removes.iter().chain(&adds).copied().collect_vec();
+                let (prefix_len, suffix_len) = common_line_affixes(&parts);
+                let trim = |part: &&'a [u8]| {
+                    let part: &'a [u8] = part;
+                    &part[prefix_len..part.len() - suffix_len]
+                };
+                let first: &'a [u8] = parts[0];
+                builder.push_resolved(&first[..prefix_len]);
+                builder.push_conflict(
+                    removes.iter().map(trim).collect_vec(),
+                    adds.iter().map(trim).collect_vec(),
+                );
+                builder.push_resolved(&first[first.len() - suffix_len..]);
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Returns the lengths in bytes of the whole lines that all `parts` start
+/// with and of the whole lines that they all end with. The two don't overlap
+/// in any of the parts.
+fn common_line_affixes(parts: &[&[u8]]) -> (usize, usize) {
+    let first = parts[0];
+    let mut prefix_len = 0;
+    for line in first.split_inclusive(|b| *b == b'\n') {
+        let end = prefix_len + line.len();
+        // A line without a newline could be the start of a longer line in
+        // another part
+        if !line.ends_with(b"\n")
+            || !parts
+                .iter()
+                .all(|part| part.get(prefix_len..end) == Some(line))
+        {
+            break;
+        }
+        prefix_len = end;
+    }
+    let max_suffix_len = parts
+        .iter()
+        .map(|part| part.len() - prefix_len)
+        .min()
+        .unwrap();
+    let mut suffix_len = 0;
+    for line in first[prefix_len..].split_inclusive(|b| *b == b'\n').rev() {
+        let len = suffix_len + line.len();
+        if len > max_suffix_len
+            || !parts.iter().all(|part| {
+                let start = part.len() - len;
+                part[start..].starts_with(line) && (start == 0 || part[start - 1] == b'\n')
+            })
+        {
+            break;
+        }
+        suffix_len = len;
+    }
+    (prefix_len, suffix_len)
+}
+
+/// Collects the hunks of a merge result, combining adjacent resolved content
+/// into a single hunk.
+#[derive(Default)]
+struct MergeResultBuilder<'a> {
+    resolved_hunk: Vec<&'a [u8]>,
+    merge_hunks: Vec<MergeHunkRef<'a>>,
+}
+
+impl<'a> MergeResultBuilder<'a> {
+    fn push_resolved(&mut self, content: &'a [u8]) {
+        if !content.is_empty() {
+            self.resolved_hunk.push(content);
+        }
+    }
+
+    fn push_conflict(&mut self, removes: Vec<&'a [u8]>, adds: Vec<&'a [u8]>) {
+        if !self.resolved_hunk.is_empty() {
+            let resolved_hunk = std::mem::take(&mut self.resolved_hunk);
+            self.merge_hunks.push(MergeHunkRef::Resolved(resolved_hunk));
+        }
+        self.merge_hunks
+            .push(MergeHunkRef::Conflict { removes, adds });
+    }
+
+    fn push_hunk(&mut self, hunk: MergeHunkRef<'a>) {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    self.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => self.push_conflict(removes, adds),
+        }
+    }
+
+    fn push_result(&mut self, result: MergeResultRef<'a>) {
+        match result {
+            MergeResultRef::Resolved(pieces) => {
+                for piece in pieces {
+                    self.push_resolved(piece);
+                }
+            }
+            MergeResultRef::Conflict(hunks) => {
+                for hunk in hunks {
+                    self.push_hunk(hunk);
+                }
+            }
+        }
+    }
+
+    fn build(mut self) -> MergeResultRef<'a> {
+        if self.merge_hunks.is_empty() {
+            MergeResultRef::Resolved(self.resolved_hunk)
+        } else {
+            if !self.resolved_hunk.is_empty() {
+                self.merge_hunks
+                    .push(MergeHunkRef::Resolved(self.resolved_hunk));
+            }
+            MergeResultRef::Conflict(self.merge_hunks)
+        }
+    }
+}
+
+/// Splits `text` into words and single non-word bytes.
+fn find_word_and_nonword_ranges(text: &[u8]) -> Vec<Range<usize>> {
+    diff::find_word_ranges(text)
+        .into_iter()
+        .merge_by(diff::find_nonword_ranges(text), |a, b| a.start < b.start)
+        .collect()
+}
+
+/// An input with trailing whitespace stripped from each line, along with what
+/// is needed to map line-aligned ranges back to the original input.
+struct StrippedInput {
+    content: Vec<u8>,
+    // The start of each line in `content`, followed by the length of `content`.
+    line_starts: Vec<usize>,
+    // The start of each line in the original input, followed by its length.
+    original_line_starts: Vec<usize>,
+}
+
+impl StrippedInput {
+    fn new(input: &[u8]) -> Self {
+        let mut content = Vec::with_capacity(input.len());
+        let mut line_starts = vec![];
+        let mut original_line_starts = vec![];
+        let mut original_pos = 0;
+        for line in input.split_inclusive(|b| *b == b'\n') {
+            line_starts.push(content.len());
+            original_line_starts.push(original_pos);
+            original_pos += line.len();
+            let (text, newline) = match line.strip_suffix(b"\n") {
+                Some(text) => (text, &b"\n"[..]),
+                None => (line, &b""[..]),
+            };
+            let text_len = text
+                .iter()
+                .rposition(|b| !matches!(b, b' ' | b'\t'))
+                .map_or(0, |pos| pos + 1);
+            content.extend_from_slice(&text[..text_len]);
+            content.extend_from_slice(newline);
+        }
+        line_starts.push(content.len());
+        original_line_starts.push(original_pos);
+        StrippedInput {
+            content,
+            line_starts,
+            original_line_starts,
+        }
+    }
+
+    /// Maps a range of whole lines in the stripped content to the
+    /// corresponding range in the original input.
+    fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let start_line = self.line_starts.partition_point(|pos| *pos < range.start);
+        let end_line = self.line_starts.partition_point(|pos| *pos <= range.end) - 1;
+        self.original_line_starts[start_line]..self.original_line_starts[end_line]
+    }
+}
+
+/// Merges `inputs`, of which the first `num_removes` are removes and the rest
+/// are adds. The inputs are compared as given, split by `tokenizer`, but the
+/// content in the result is looked up by `original_content()`, which is passed
+/// the index of an input and a range in it. See `merge()` for how each
+/// differing region is merged.
+fn merge_inputs<'a>(
+    inputs: &[&[u8]],
+    num_removes: usize,
+    minimization: ConflictMinimization,
+    tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+    original_content: impl Fn(usize, Range<usize>) -> &'a [u8],
+) -> MergeResultRef<'a> {
+    merge_inputs_with_comparator(
+        inputs,
+        num_removes,
+        minimization,
+        tokenizer,
+        original_content,
+        &|left, right| left == right,
+        SameChangeWhitespace::Exact,
+    )
+}
+
+/// Like `merge_inputs()`, but parts of a differing region are considered the
+/// same if `eq` says so. When deciding whether all sides made the same change,
+/// their added parts are also considered the same if `same_change` says so.
+fn merge_inputs_with_comparator<'a>(
+    inputs: &[&[u8]],
+    num_removes: usize,
+    minimization: ConflictMinimization,
+    tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+    original_content: impl Fn(usize, Range<usize>) -> &'a [u8],
+    eq: &impl Fn(&[u8], &[u8]) -> bool,
+    same_change: SameChangeWhitespace,
+) -> MergeResultRef<'a> {
+    let mut builder = MergeResultBuilder::default();
+    let regions = merge_input_regions(
+        inputs,
+        num_removes,
+        minimization,
+        tokenizer,
+        original_content,
+        eq,
+        same_change,
+    );
+    for (hunk, _provenance) in regions {
+        match hunk {
+            MergeHunkRef::Resolved(pieces) => {
+                for piece in pieces {
+                    builder.push_resolved(piece);
+                }
+            }
+            MergeHunkRef::Conflict { removes, adds } => builder.push_conflict(removes, adds),
+        }
+    }
+    builder.build()
+}
+
+/// Cancels out pairs of removed and added parts that `eq` considers the same.
+/// The first `num_removes` parts are removes and the rest are adds. Returns the
+/// indices of the removes and adds that are left.
+fn cancel_matching_parts(
+    parts: &[&[u8]],
+    num_removes: usize,
+    eq: &dyn Fn(&[u8], &[u8]) -> bool,
+) -> (Vec<usize>, Vec<usize>) {
+    let mut removed_indices = (0..num_removes).collect_vec();
+    let mut added_indices = (num_removes..parts.len()).collect_vec();
+    // Remove pairs of parts that match in the removes and adds.
+    let mut added_index = 0;
+    while added_index < added_indices.len() {
+        let added_part = parts[added_indices[added_index]];
+        added_index += 1;
+        for (removed_index, removed_input) in removed_indices.iter().enumerate() {
+            if eq(parts[*removed_input], added_part) {
+                added_index -= 1;
+                added_indices.remove(added_index);
+                removed_indices.remove(removed_index);
+                break;
+            }
+        }
+    }
+    (removed_indices, added_indices)
+}
+
+/// The parts at `indices` with the ones that `eq` considers the same as an
+/// earlier one left out.
+fn distinct_parts<'p>(
+    parts: &[&'p [u8]],
+    indices: &[usize],
+    eq: &dyn Fn(&[u8], &[u8]) -> bool,
+) -> Vec<&'p [u8]> {
+    let mut distinct: Vec<&[u8]> = vec![];
+    for part in indices.iter().map(|i| parts[*i]) {
+        if !distinct.iter().any(|other| eq(other, part)) {
+            distinct.push(part);
+        }
+    }
+    distinct
+}
+
+/// Merges the inputs region by region, like `merge_inputs_with_comparator()`,
+/// but returns each region of the output separately, along with where its
+/// content came from. Regions that merge to nothing are left out.
+fn merge_input_regions<'a>(
+    inputs: &[&[u8]],
+    num_removes: usize,
+    minimization: ConflictMinimization,
+    tokenizer: &impl Fn(&[u8]) -> Vec<Range<usize>>,
+    original_content: impl Fn(usize, Range<usize>) -> &'a [u8],
+    eq: &impl Fn(&[u8], &[u8]) -> bool,
+    same_change: SameChangeWhitespace,
+) -> Vec<(MergeHunkRef<'a>, MergeProvenance)> {
+    let num_adds = inputs.len() - num_removes;
+    let diff = Diff::for_tokenizer(inputs, tokenizer);
+    // The current position in each input
+    let mut positions = vec![0; inputs.len()];
+    let mut regions = vec![];
+    for diff_hunk in diff.hunks() {
+        match diff_hunk {
+            DiffHunk::Matching(content) => {
+                if num_adds > num_removes {
+                    regions.push((
+                        MergeHunkRef::Resolved(vec![original_content(
+                            0,
+                            positions[0]..positions[0] + content.len(),
+                        )]),
+                        MergeProvenance::Base,
+                    ));
+                }
+                for position in &mut positions {
+                    *position += content.len();
+                }
+            }
+            DiffHunk::Different(parts) => {
+                let ranges = parts
+                    .iter()
+                    .zip(&mut positions)
+                    .map(|(part, position)| {
+                        let range = *position..*position + part.len();
+                        *position = range.end;
+                        range
+                    })
+                    .collect_vec();
+                let part_content = |index: usize| original_content(index, ranges[index].clone());
+                let (removed_indices, added_indices) =
+                    cancel_matching_parts(&parts, num_removes, eq);
+                let distinct_removes = distinct_parts(&parts, &removed_indices, eq);
+                // Only used to decide whether the sides made the same change
+                let distinct_adds = distinct_parts(&parts, &added_indices, &|left, right| {
+                    eq(left, right) || same_change.equal(left, right)
+                });
+                let collapse = minimization != ConflictMinimization::None;
+                let resolved_from_adds = || {
+                    (
+                        MergeHunkRef::Resolved(vec![part_content(added_indices[0])]),
+                        MergeProvenance::Adds(
+                            added_indices.iter().map(|i| i - num_removes).collect_vec(),
+                        ),
+                    )
+                };
+                if removed_indices.is_empty() && added_indices.is_empty() {
+                    // The same content was added and removed, so there's
+                    // nothing left.
+                } else if removed_indices.is_empty() && added_indices.len() == 1 {
+                    // Only one side changed the content
+                    regions.push(resolved_from_adds());
+                } else if collapse && distinct_removes.is_empty() && distinct_adds.len() == 1 {
+                    // All sides added the same content
+                    regions.push(resolved_from_adds());
+                } else if collapse && distinct_removes.len() == 1 && distinct_adds.is_empty() {
+                    // All sides removed the same content
+                } else if collapse
+                    && distinct_removes.len() == 1
+                    && distinct_adds.len() == 1
+                    && added_indices.len() == removed_indices.len() + 1
+                {
+                    // All sides made the same change, and there's a matching extra base to apply it
+                    // to
+                    regions.push(resolved_from_adds());
+                } else {
+                    // Include the unfiltered lists of removed and added here, so the caller
+                    // knows which part corresponds to which input.
+                    regions.push((
+                        MergeHunkRef::Conflict {
+                            removes: (0..num_removes).map(part_content).collect_vec(),
+                            adds: (num_removes..inputs.len()).map(part_content).collect_vec(),
+                        },
+                        MergeProvenance::Conflict,
+                    ));
+                }
+            }
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use static_assertions::assert_impl_all;
+
+    use super::*;
+
+    fn merge_parts(removes: &[&[u8]], adds: &[&[u8]]) -> MergeResult {
+        merge(&MergeInput::new(removes, adds).unwrap())
+    }
+
+    // Merge results can be computed on other threads, or shared between them as
+    // the merge cache in `Store` does
+    assert_impl_all!(MergeHunk: Send, Sync);
+    assert_impl_all!(MergeResult: Send, Sync);
+    assert_impl_all!(MergeResultRef<'static>: Send, Sync);
+
+    #[test]
+    fn test_merge_input() {
+        let input = MergeInput::new(&[b"a"], &[b"b", b"c"]).unwrap();
+        assert_eq!(input, MergeInput::three_way(b"a", b"b", b"c"));
+        assert_eq!(input.removes(), &[b"a"]);
+        assert_eq!(input.adds(), &[b"b", b"c"]);
+        assert_eq!(
+            MergeInput::new(&[b"a"], &[b"b"]),
+            Err(MergeArityError {
+                num_removes: 1,
+                num_adds: 1
+            })
+        );
+        assert_eq!(
+            MergeInput::new(&[], &[]),
+            Err(MergeArityError {
+                num_removes: 0,
+                num_adds: 0
+            })
+        );
+        // Degenerate inputs have to be created explicitly
+        assert_eq!(MergeInput::addition(&[b"a", b"a"]).removes().len(), 0);
+        assert_eq!(MergeInput::deletion(&[b"a", b"a"]).adds().len(), 0);
+        // A deleted side is an explicit empty input
+        assert_eq!(
+            merge(&MergeInput::three_way(b"a\n", b"a\n", b"")),
+            MergeResult::Resolved(vec![])
+        );
+    }
+
+    #[test]
+    fn test_merge_result_num_sides() {
+        // A 3-way conflict
+        let result = merge(&MergeInput::three_way(b"a\n", b"b\n", b"c\n"));
+        assert_matches_num_sides(&result, 1, 2);
+        // An octopus merge of three sides
+        let result = merge_parts(&[b"a\n", b"a\n"], &[b"b\n", b"c\n", b"d\n"]);
+        assert_matches_num_sides(&result, 2, 3);
+        // The counts are kept even if the first hunk is resolved
+        let result = MergeResult::conflict(vec![
+            MergeHunk::Resolved(b"a\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"b\n".to_vec()],
+                adds: vec![b"c\n".to_vec(), b"d\n".to_vec()],
+            },
+        ]);
+        assert_matches_num_sides(&result, 1, 2);
+    }
+
+    #[test]
+    fn test_merge_result_resolve_hunk() {
+        let conflict = |base: &[u8], side1: &[u8], side2: &[u8]| MergeHunk::Conflict {
+            removes: vec![base.to_vec()],
+            adds: vec![side1.to_vec(), side2.to_vec()],
+        };
+        let mut result = MergeResult::conflict(vec![
+            conflict(b"a\n", b"A\n", b"1\n"),
+            MergeHunk::Resolved(b"b\n".to_vec()),
+            conflict(b"c\n", b"C\n", b"3\n"),
+            MergeHunk::Resolved(b"d\n".to_vec()),
+            conflict(b"e\n", b"E\n", b"5\n"),
+        ]);
+        assert_eq!(
+            result
+                .conflict_hunks()
+                .iter()
+                .map(|(index, _)| *index)
+                .collect_vec(),
+            vec![0, 2, 4]
+        );
+
+        // Resolve the last hunk first; the other hunks are left alone
+        result.resolve_hunk(4, HunkResolution::Add(1)).unwrap();
+        assert_eq!(
+            result,
+            MergeResult::conflict(vec![
+                conflict(b"a\n", b"A\n", b"1\n"),
+                MergeHunk::Resolved(b"b\n".to_vec()),
+                conflict(b"c\n", b"C\n", b"3\n"),
+                MergeHunk::Resolved(b"d\n".to_vec()),
+                MergeHunk::Resolved(b"5\n".to_vec()),
+            ])
+        );
+        assert_eq!(
+            result
+                .conflict_hunks()
+                .iter()
+                .map(|(index, _)| *index)
+                .collect_vec(),
+            vec![0, 2]
+        );
+
+        // Invalid resolutions leave the result unchanged
+        let before = result.clone();
+        assert_eq!(
+            result.resolve_hunk(1, HunkResolution::Add(0)),
+            Err(ResolveHunkError::NoSuchConflict(1))
+        );
+        assert_eq!(
+            result.resolve_hunk(5, HunkResolution::Add(0)),
+            Err(ResolveHunkError::NoSuchConflict(5))
+        );
+        assert_eq!(
+            result.resolve_hunk(0, HunkResolution::Add(2)),
+            Err(ResolveHunkError::NoSuchAdd(2))
+        );
+        assert_eq!(
+            result.resolve_hunk(0, HunkResolution::Remove(1)),
+            Err(ResolveHunkError::NoSuchRemove(1))
+        );
+        assert_eq!(result, before);
+
+        // Resolving the remaining hunks out of order gives a resolved result
+        result
+            .resolve_hunk(2, HunkResolution::Content(b"x\ny\n".to_vec()))
+            .unwrap();
+        assert!(!result.is_resolved());
+        result.resolve_hunk(0, HunkResolution::Remove(0)).unwrap();
+        assert_eq!(
+            result,
+            MergeResult::Resolved(b"a\nb\nx\ny\nd\n5\n".to_vec())
+        );
+        assert_eq!(result.conflict_hunks(), vec![]);
+        assert_eq!(
+            result.resolve_hunk(0, HunkResolution::Add(0)),
+            Err(ResolveHunkError::NoSuchConflict(0))
+        );
+    }
+
+    #[test]
+    fn test_merge_hunk_conflict_kind() {
+        let kind = |removes: &[&[u8]], adds: &[&[u8]]| {
+            MergeHunk::Conflict {
+                removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+                adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+            }
+            .conflict_kind()
+        };
+        assert_eq!(MergeHunk::Resolved(b"a\n".to_vec()).conflict_kind(), None);
+        assert_eq!(kind(&[b""], &[b"a\n", b"b\n"]), Some(ConflictKind::AddAdd));
+        assert_eq!(
+            kind(&[b"a\n"], &[b"", b"b\n"]),
+            Some(ConflictKind::ModifyDelete)
+        );
+        assert_eq!(
+            kind(&[b"a\n"], &[b"b\n", b"c\n"]),
+            Some(ConflictKind::DistinctChanges { num_sides: 2 })
+        );
+        // The sides changed different lines
+        assert_eq!(
+            kind(&[b"a\nb\n"], &[b"A\nb\n", b"a\nB\n"]),
+            Some(ConflictKind::AdjacentChanges)
+        );
+        // Both sides inserted lines at the same place
+        assert_eq!(
+            kind(&[b"a\nb\n"], &[b"a\nx\nb\n", b"a\ny\nb\n"]),
+            Some(ConflictKind::DistinctChanges { num_sides: 2 })
+        );
+        // Removes and adds with the same content cancel out first
+        assert_eq!(
+            kind(&[b"a\n", b"a\n"], &[b"a\n", b"b\n", b"c\n"]),
+            Some(ConflictKind::DistinctChanges { num_sides: 2 })
+        );
+        assert_eq!(
+            kind(&[b"a\n", b"a\n"], &[b"b\n", b"c\n", b"d\n"]),
+            Some(ConflictKind::DistinctChanges { num_sides: 3 })
+        );
+    }
+
+    fn assert_matches_num_sides(result: &MergeResult, removes: usize, adds: usize) {
+        match result {
+            MergeResult::Conflict {
+                num_removes,
+                num_adds,
+                ..
+            } => assert_eq!((*num_removes, *num_adds), (removes, adds)),
+            MergeResult::Resolved(_) => panic!("unexpected resolved result"),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        // Unchanged and empty on all sides
+        assert_eq!(
+            merge_parts(&[b""], &[b"", b""]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // Unchanged on all sides
+        assert_eq!(
+            merge_parts(&[b"a"], &[b"a", b"a"]),
+            MergeResult::Resolved(b"a".to_vec())
+        );
+        // One side removed, one side unchanged
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"", b"a\n"]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // One side unchanged, one side removed
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"a\n", b""]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // Both sides removed same line
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"", b""]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // One side modified, one side unchanged
+        assert_eq!(
+            merge_parts(&[b"a"], &[b"a b", b"a"]),
+            MergeResult::Resolved(b"a b".to_vec())
+        );
+        // One side unchanged, one side modified
+        assert_eq!(
+            merge_parts(&[b"a"], &[b"a", b"a b"]),
+            MergeResult::Resolved(b"a b".to_vec())
+        );
+        // All sides added same content
+        assert_eq!(
+            merge(&MergeInput::addition(&[b"a\n", b"a\n", b"a\n"])),
+            MergeResult::Resolved(b"a\n".to_vec())
+        );
+        // One side modified, two sides added. `merge_with_options()` accepts
+        // any number of removes and adds.
+        assert_eq!(
+            merge_with_options(&[b"a"], &[b"b", b"b", b"b"], &MergeOptions::default()),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a".to_vec()],
+                adds: vec![b"b".to_vec(), b"b".to_vec(), b"b".to_vec()]
+            }])
+        );
+        // All sides removed same content
+        assert_eq!(
+            merge(&MergeInput::deletion(&[b"a\n", b"a\n", b"a\n"])),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // One side modified, two sides removed
+        assert_eq!(
+            merge_with_options(&[b"a\n", b"a\n", b"a\n"], &[b""], &MergeOptions::default()),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"a\n".to_vec(), b"a\n".to_vec()],
+                adds: vec![b"".to_vec()]
+            }])
+        );
+        // Three sides made the same change
+        assert_eq!(
+            merge_parts(&[b"a", b"a"], &[b"b", b"b", b"b"]),
+            MergeResult::Resolved(b"b".to_vec())
+        );
+        // One side unchanged, one side added
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b"a\nb\n"], &MergeOptions::default()),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"".to_vec()],
+                adds: vec![b"b\n".to_vec()]
+            }])
+        );
+        // Two sides left one line unchanged, and added conflicting additional lines
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"a\nb\n", b"a\nc\n"]),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"".to_vec()],
+                    adds: vec![b"b\n".to_vec(), b"c\n".to_vec()]
+                }
+            ])
+        );
+        // One side removed, one side modified
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"", b"b\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec()],
+                adds: vec![b"".to_vec(), b"b\n".to_vec()]
+            }])
+        );
+        // One side modified, one side removed
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"b\n", b""]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec()],
+                adds: vec![b"b\n".to_vec(), b"".to_vec()]
+            }])
+        );
+        // Two sides modified in different ways
+        assert_eq!(
+            merge_parts(&[b"a"], &[b"b", b"c"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a".to_vec()],
+                adds: vec![b"b".to_vec(), b"c".to_vec()]
+            }])
+        );
+        // Two of three sides don't change, third side changes
+        assert_eq!(
+            merge_parts(&[b"a", b"a"], &[b"a", b"", b"a"]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // One side unchanged, two other sides make the same change
+        assert_eq!(
+            merge_parts(&[b"a", b"a"], &[b"", b"a", b""]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // One side unchanged, two other sides make the different change
+        assert_eq!(
+            merge_parts(&[b"a", b"a"], &[b"b", b"a", b"c"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a".to_vec(), b"a".to_vec()],
+                adds: vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]
+            }])
+        );
+        // Merge of an unresolved conflict and another branch, where the other branch
+        // undid the change from one of the inputs to the unresolved conflict in the
+        // first.
+        assert_eq!(
+            merge_parts(&[b"a", b"b"], &[b"b", b"a", b"c"]),
+            MergeResult::Resolved(b"c".to_vec())
+        );
+        // Merge of an unresolved conflict and another branch.
+        assert_eq!(
+            merge_parts(&[b"a", b"b"], &[b"c", b"d", b"e"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a".to_vec(), b"b".to_vec()],
+                adds: vec![b"c".to_vec(), b"d".to_vec(), b"e".to_vec()]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_merge_octopus() {
+        // All sides identical to the base
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"a\n", b"a\n", b"a\n", b"a\n"]),
+            MergeResult::Resolved(b"a\n".to_vec())
+        );
+        // All sides made the same change
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"b\n", b"b\n", b"b\n", b"b\n"]),
+            MergeResult::Resolved(b"b\n".to_vec())
+        );
+        // All sides distinct
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"b\n", b"c\n", b"d\n", b"e\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"a\n".to_vec(), b"a\n".to_vec()],
+                adds: vec![
+                    b"b\n".to_vec(),
+                    b"c\n".to_vec(),
+                    b"d\n".to_vec(),
+                    b"e\n".to_vec()
+                ]
+            }])
+        );
+        // Three sides unchanged, one side changed
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"a\n", b"a\n", b"b\n", b"a\n"]),
+            MergeResult::Resolved(b"b\n".to_vec())
+        );
+        // Two sides unchanged, two sides made the same change
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"a\n", b"b\n", b"a\n", b"b\n"]),
+            MergeResult::Resolved(b"b\n".to_vec())
+        );
+        // Two sides unchanged, two sides made different changes
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"b\n", b"a\n", b"c\n", b"a\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"a\n".to_vec(), b"a\n".to_vec()],
+                adds: vec![
+                    b"b\n".to_vec(),
+                    b"a\n".to_vec(),
+                    b"c\n".to_vec(),
+                    b"a\n".to_vec()
+                ]
+            }])
+        );
+        // Two sides unchanged, one side changed, one side removed
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"a\n", b"b\n", b"", b"a\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"a\n".to_vec(), b"a\n".to_vec()],
+                adds: vec![
+                    b"a\n".to_vec(),
+                    b"b\n".to_vec(),
+                    b"".to_vec(),
+                    b"a\n".to_vec()
+                ]
+            }])
+        );
+        // All sides removed the content
+        assert_eq!(
+            merge_parts(&[b"a\n", b"a\n", b"a\n"], &[b"", b"", b"", b""]),
+            MergeResult::Resolved(b"".to_vec())
+        );
+        // All sides removed the content, but not the same content, so a side
+        // that changed it would be lost
+        assert_eq!(
+            merge_parts(&[b"a\n", b"b\n", b"c\n"], &[b"", b"", b"", b""]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"b\n".to_vec(), b"c\n".to_vec()],
+                adds: vec![b"".to_vec(), b"".to_vec(), b"".to_vec(), b"".to_vec()]
+            }])
+        );
+        assert_eq!(
+            merge_parts(&[b"a\n", b"b\n"], &[b"x\n", b"", b""]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"b\n".to_vec()],
+                adds: vec![b"x\n".to_vec(), b"".to_vec(), b"".to_vec()]
+            }])
+        );
+        // Different removes left without any adds aren't a removal by all sides
+        assert_eq!(
+            merge_with_options(
+                &[b"a\n", b"b\n", b"c\n"],
+                &[b"c\n"],
+                &MergeOptions::default()
+            ),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec(), b"b\n".to_vec(), b"c\n".to_vec()],
+                adds: vec![b"c\n".to_vec()]
+            }])
+        );
+        // Each remove cancels an add, leaving the change from the remaining side
+        assert_eq!(
+            merge_parts(&[b"a\n", b"b\n", b"c\n"], &[b"b\n", b"c\n", b"a\n", b"d\n"]),
+            MergeResult::Resolved(b"d\n".to_vec())
+        );
+        // Different sides changed different lines
+        assert_eq!(
+            merge_parts(
+                &[b"a\nx\nb\n", b"a\nx\nb\n", b"a\nx\nb\n"],
+                &[b"A\nx\nb\n", b"a\nx\nb\n", b"a\nx\nB\n", b"a\nx\nb\n"]
+            ),
+            MergeResult::Resolved(b"A\nx\nB\n".to_vec())
+        );
+        // One line resolves, another line conflicts
+        assert_eq!(
+            merge_parts(
+                &[b"a\nx\nb\n", b"a\nx\nb\n", b"a\nx\nb\n"],
+                &[b"A\nx\nb\n", b"A\nx\nB\n", b"a\nx\nC\n", b"a\nx\nb\n"]
+            ),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"A\nx\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"b\n".to_vec(), b"b\n".to_vec(), b"b\n".to_vec()],
+                    adds: vec![
+                        b"b\n".to_vec(),
+                        b"B\n".to_vec(),
+                        b"C\n".to_vec(),
+                        b"b\n".to_vec()
+                    ]
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_base_choice() {
+        // The first remove and add cancel out, but the first remove has nothing
+        // in common with the others, so the diff against it finds a single
+        // region that covers everything. Merging again with the second remove as
+        // the base lets the changes to separate lines resolve.
+        assert_eq!(
+            merge_parts(
+                &[b"z\n", b"a\nx\nb\n"],
+                &[b"z\n", b"A\nx\nb\n", b"a\nx\nB\n"]
+            ),
+            MergeResult::Resolved(b"A\nx\nB\n".to_vec())
+        );
+        // Changes to the same line still conflict
+        assert_eq!(
+            merge_parts(
+                &[b"z\n", b"a\nx\nb\n"],
+                &[b"z\n", b"A\nx\nb\n", b"B\nx\nb\n"]
+            ),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"z\n".to_vec(), b"a\nx\nb\n".to_vec()],
+                adds: vec![
+                    b"z\n".to_vec(),
+                    b"A\nx\nb\n".to_vec(),
+                    b"B\nx\nb\n".to_vec()
+                ]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_merge_hunk_trim_common_lines() {
+        let conflict = |removes: &[&[u8]], adds: &[&[u8]]| MergeHunk::Conflict {
+            removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+            adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+        };
+        // Lines common to all sides at the start and end are peeled off, and the
+        // sides stay in order
+        assert_eq!(
+            conflict(&[b"a\nb\nz\n"], &[b"a\nB1\nz\n", b"a\nB2\nB3\nz\n"]).trim_common_lines(),
+            vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                conflict(&[b"b\n"], &[b"B1\n", b"B2\nB3\n"]),
+                MergeHunk::Resolved(b"z\n".to_vec()),
+            ]
+        );
+        // Lines only common to some sides are kept
+        assert_eq!(
+            conflict(&[b"a\nb\n"], &[b"a\nc\n", b"x\nd\n"]).trim_common_lines(),
+            vec![conflict(&[b"a\nb\n"], &[b"a\nc\n", b"x\nd\n"])]
+        );
+        // There's nothing to peel off if a side is empty
+        assert_eq!(
+            conflict(&[b"a\nb\n"], &[b"", b"a\nc\n"]).trim_common_lines(),
+            vec![conflict(&[b"a\nb\n"], &[b"", b"a\nc\n"])]
+        );
+        // The prefix and suffix don't overlap in sides where they'd meet
+        assert_eq!(
+            conflict(&[b"a\na\n"], &[b"a\n", b"a\nb\na\n"]).trim_common_lines(),
+            vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                conflict(&[b"a\n"], &[b"", b"b\na\n"]),
+            ]
+        );
+        // Only whole lines are peeled off, also when the last line has no newline
+        assert_eq!(
+            conflict(&[b"a\nxb"], &[b"a\nyb", b"a\nb"]).trim_common_lines(),
+            vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                conflict(&[b"xb"], &[b"yb", b"b"]),
+            ]
+        );
+        assert_eq!(
+            conflict(&[b"a\nb"], &[b"a\nbc", b"a\nbd"]).trim_common_lines(),
+            vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                conflict(&[b"b"], &[b"bc", b"bd"]),
+            ]
+        );
+        // Resolved hunks are left alone
+        assert_eq!(
+            MergeHunk::Resolved(b"a\n".to_vec()).trim_common_lines(),
+            vec![MergeHunk::Resolved(b"a\n".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_split_conflict_hunk() {
+        let conflict = |removes: &[&[u8]], adds: &[&[u8]]| MergeHunk::Conflict {
+            removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+            adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+        };
+        let hunk = conflict(&[b"a\nb\nc\n"], &[b"A\nb\nC\n", b"a\nb\nD\n"]);
+        // Split after the first line. The first half merges cleanly, the second
+        // doesn't.
+        assert_eq!(
+            split_conflict_hunk(&hunk, 2),
+            (
+                MergeHunk::Resolved(b"A\n".to_vec()),
+                conflict(&[b"b\nc\n"], &[b"b\nC\n", b"b\nD\n"])
+            )
+        );
+        // Split in the middle of "BB", which differs from the other sides, so
+        // their "b" and "c" are kept in the first half
+        assert_eq!(
+            split_conflict_hunk(&conflict(&[b"a b\n"], &[b"a BB\n", b"a c\n"]), 3),
+            (
+                conflict(&[b"a b"], &[b"a B", b"a c"]),
+                MergeHunk::Resolved(b"B\n".to_vec())
+            )
+        );
+        // At the start and at the end
+        assert_eq!(
+            split_conflict_hunk(&hunk, 0),
+            (MergeHunk::Resolved(vec![]), hunk.clone())
+        );
+        assert_eq!(
+            split_conflict_hunk(&hunk, 6),
+            (hunk.clone(), MergeHunk::Resolved(vec![]))
+        );
+    }
+
+    #[test]
+    fn test_merge_hunk_accessors() {
+        let resolved = MergeHunk::Resolved(b"a\n".to_vec());
+        assert_eq!(resolved.resolved_bytes(), Some(&b"a\n"[..]));
+        assert_eq!(resolved.conflict_removes(), None);
+        assert_eq!(resolved.conflict_adds(), None);
+
+        let conflict = MergeHunk::Conflict {
+            removes: vec![b"a\n".to_vec()],
+            adds: vec![b"b\n".to_vec(), b"c\n".to_vec()],
+        };
+        assert_eq!(conflict.resolved_bytes(), None);
+        assert_eq!(conflict.conflict_removes(), Some(&[b"a\n".to_vec()][..]));
+        assert_eq!(
+            conflict.conflict_adds(),
+            Some(&[b"b\n".to_vec(), b"c\n".to_vec()][..])
+        );
+    }
+
+    #[test]
+    fn test_merge_hunk_simplify() {
+        let conflict = |removes: &[&[u8]], adds: &[&[u8]]| MergeHunk::Conflict {
+            removes: removes.iter().map(|part| part.to_vec()).collect(),
+            adds: adds.iter().map(|part| part.to_vec()).collect(),
+        };
+        let part = |content: &[u8], indices: &[usize]| SimplifiedPart {
+            content: content.to_vec(),
+            indices: indices.to_vec(),
+        };
+
+        // Resolved hunks stay resolved
+        assert_eq!(
+            MergeHunk::Resolved(b"a".to_vec()).simplify(),
+            SimplifiedHunk::Resolved(b"a".to_vec())
+        );
+        // Nothing to simplify
+        assert_eq!(
+            conflict(&[b"a"], &[b"b", b"c"]).simplify(),
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"a", &[0])],
+                adds: vec![part(b"b", &[0]), part(b"c", &[1])],
+            }
+        );
+        // A cancelled pair leaves one add
+        assert_eq!(
+            conflict(&[b"a"], &[b"a", b"b"]).simplify(),
+            SimplifiedHunk::Resolved(b"b".to_vec())
+        );
+        // Everything cancels out
+        assert_eq!(
+            conflict(&[b"a"], &[b"a"]).simplify(),
+            SimplifiedHunk::Resolved(vec![])
+        );
+        // Identical adds and no removes
+        assert_eq!(
+            conflict(&[], &[b"a", b"a"]).simplify(),
+            SimplifiedHunk::Resolved(b"a".to_vec())
+        );
+        // Only removes are left
+        assert_eq!(
+            conflict(&[b"a", b"b"], &[b"b"]).simplify(),
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"a", &[0])],
+                adds: vec![],
+            }
+        );
+        // A pair cancels, and the rest still conflicts
+        assert_eq!(
+            conflict(&[b"a", b"b"], &[b"b", b"c", b"d"]).simplify(),
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"a", &[0])],
+                adds: vec![part(b"c", &[1]), part(b"d", &[2])],
+            }
+        );
+        // Identical sides are collapsed
+        let simplified = conflict(&[b"a", b"a"], &[b"x", b"x", b"y"]).simplify();
+        assert_eq!(
+            simplified,
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"a", &[0, 1])],
+                adds: vec![part(b"x", &[0, 1]), part(b"y", &[2])],
+            }
+        );
+        if let SimplifiedHunk::Conflict { removes, adds } = &simplified {
+            assert_eq!(removes[0].multiplicity(), 2);
+            assert_eq!(adds[0].multiplicity(), 2);
+            assert_eq!(adds[1].multiplicity(), 1);
+        }
+        // Each add cancels the first identical remove that's left
+        assert_eq!(
+            conflict(&[b"a", b"b", b"a"], &[b"a", b"x", b"a", b"y"]).simplify(),
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"b", &[1])],
+                adds: vec![part(b"x", &[1]), part(b"y", &[3])],
+            }
+        );
+        // Cancelling and collapsing combined
+        assert_eq!(
+            conflict(&[b"a", b"b", b"b"], &[b"a", b"x", b"x", b"y"]).simplify(),
+            SimplifiedHunk::Conflict {
+                removes: vec![part(b"b", &[1, 2])],
+                adds: vec![part(b"x", &[1, 2]), part(b"y", &[3])],
+            }
+        );
+    }
+
+    #[test]
+    fn test_simplified_hunk_expand() {
+        let original = MergeHunk::Conflict {
+            removes: vec![b"a".to_vec(), b"b".to_vec(), b"b".to_vec()],
+            adds: vec![b"a".to_vec(), b"x".to_vec(), b"x".to_vec(), b"y".to_vec()],
+        };
+        let simplified = original.simplify();
+        assert_eq!(
+            simplified.conflict_parts(),
+            Some((vec![&b"b"[..]], vec![&b"x"[..], &b"y"[..]]))
+        );
+        // Unchanged parts give back the original hunk
+        assert_eq!(
+            simplified.expand(
+                &original,
+                vec![b"b".to_vec()],
+                vec![b"x".to_vec(), b"y".to_vec()]
+            ),
+            Some(original.clone())
+        );
+        // New parts replace every part they stand for, and the cancelled parts
+        // are kept
+        assert_eq!(
+            simplified.expand(
+                &original,
+                vec![b"B".to_vec()],
+                vec![b"X".to_vec(), b"Y".to_vec()]
+            ),
+            Some(MergeHunk::Conflict {
+                removes: vec![b"a".to_vec(), b"B".to_vec(), b"B".to_vec()],
+                adds: vec![b"a".to_vec(), b"X".to_vec(), b"X".to_vec(), b"Y".to_vec()],
+            })
+        );
+        // The number of parts must match
+        assert_eq!(
+            simplified.expand(&original, vec![b"B".to_vec()], vec![b"X".to_vec()]),
+            None
+        );
+        // Resolved hunks can't be expanded
+        assert_eq!(
+            SimplifiedHunk::Resolved(b"a".to_vec()).expand(&original, vec![], vec![]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_merge_result_accessors() {
+        let resolved = merge_parts(&[b"a\n"], &[b"a\n", b"b\n"]);
+        assert!(resolved.is_resolved());
+        assert_eq!(resolved.conflict_count(), 0);
+        assert_eq!(resolved.resolved_bytes(), Some(&b"b\n"[..]));
+
+        let conflict = merge_parts(&[b"a\nb\nc\n"], &[b"x\nb\nz\n", b"y\nb\nw\n"]);
+        assert!(!conflict.is_resolved());
+        assert_eq!(conflict.conflict_count(), 2);
+        assert_eq!(conflict.resolved_bytes(), None);
+    }
+
+    #[test]
+    fn test_conflict_stats() {
+        assert_eq!(
+            conflict_stats(&merge_parts(&[b"a\n"], &[b"a\n", b"b\n"])),
+            ConflictStats::default()
+        );
+        let stats = conflict_stats(&merge_parts(&[b"a\nb\nc\n"], &[b"x\ny\nb\nz\n", b"w\nb\n"]));
+        assert_eq!(
+            stats,
+            ConflictStats {
+                num_hunks: 2,
+                num_sides: 2,
+                num_lines: 3,
+                num_deletion_hunks: 1,
+            }
+        );
+        assert!(stats.involves_deletion());
+        // A missing side is a deletion
+        let stats = conflict_stats(&MergeResult::conflict(vec![MergeHunk::Conflict {
+            removes: vec![b"a\n".to_vec()],
+            adds: vec![b"b\n".to_vec()],
+        }]));
+        assert_eq!(stats.num_sides, 1);
+        assert_eq!(stats.num_deletion_hunks, 1);
+        // Adding different content where there was none isn't a deletion
+        let stats = conflict_stats(&merge_parts(&[b""], &[b"a\n", b"b\n"]));
+        assert_eq!(stats.num_hunks, 1);
+        assert!(!stats.involves_deletion());
+    }
+
+    #[test]
+    fn test_count_conflicts() {
+        let conflict = |add: &[u8]| MergeHunk::Conflict {
+            removes: vec![b"a\n".to_vec()],
+            adds: vec![add.to_vec(), b"b\n".to_vec()],
+        };
+        assert_eq!(count_conflicts(&MergeResult::Resolved(b"a\n".to_vec())), 0);
+        assert_eq!(
+            count_conflicts(&MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"x\n".to_vec()),
+                conflict(b"c\n"),
+                MergeHunk::Resolved(b"y\n".to_vec()),
+            ])),
+            1
+        );
+        // Adjacent conflict hunks are counted separately
+        assert_eq!(
+            count_conflicts(&MergeResult::conflict(vec![
+                conflict(b"c\n"),
+                conflict(b"d\n"),
+                MergeHunk::Resolved(b"y\n".to_vec()),
+                conflict(b"e\n"),
+            ])),
+            3
+        );
+    }
+
+    #[test]
+    fn test_merge_with_strategy() {
+        // A clean merge is not affected by the strategy
+        assert_eq!(
+            merge_with_strategy(&[b"a\n"], &[b"a\n", b"b\n"], MergeStrategy::Ours),
+            b"b\n"
+        );
+
+        let removes: &[&[u8]] = &[b"a\nb\nc\n"];
+        let adds: &[&[u8]] = &[b"x\nb\nz\n", b"y\nb\nw\n"];
+        assert_eq!(
+            merge_with_strategy(removes, adds, MergeStrategy::Ours),
+            b"x\nb\nz\n"
+        );
+        assert_eq!(
+            merge_with_strategy(removes, adds, MergeStrategy::Theirs),
+            b"y\nb\nw\n"
+        );
+        assert_eq!(
+            merge_with_strategy(removes, adds, MergeStrategy::Union),
+            b"x\ny\nb\nz\nw\n"
+        );
+
+        // With more sides, the union has the distinct sides in input order
+        assert_eq!(
+            merge_with_strategy(
+                &[b"a\n", b"a\n"],
+                &[b"z\n", b"x\n", b"y\n"],
+                MergeStrategy::Union
+            ),
+            b"z\nx\ny\n"
+        );
+        assert_eq!(
+            merge_with_strategy(
+                &[b"a\n", b"a\n"],
+                &[b"y\n", b"x\n", b"y\n"],
+                MergeStrategy::Union
+            ),
+            b"y\nx\n"
+        );
+        assert_eq!(
+            merge_with_strategy(
+                &[b"a\n", b"a\n"],
+                &[b"z\n", b"x\n", b"y\n"],
+                MergeStrategy::Theirs
+            ),
+            b"y\n"
+        );
+    }
+
+    #[test]
+    fn test_conflict_agreement() {
+        assert_eq!(
+            MergeHunk::Resolved(b"a".to_vec()).conflict_agreement(),
+            None
+        );
+        // Two sides modified in different ways
+        let agreement = MergeHunk::Conflict {
+            removes: vec![b"a".to_vec()],
+            adds: vec![b"b".to_vec(), b"c".to_vec()],
+        }
+        .conflict_agreement()
+        .unwrap();
+        assert_eq!(agreement.add_groups, vec![vec![0], vec![1]]);
+        assert!(agreement.all_sides_differ());
+        // Sides 1 and 3 agree, side 2 differs
+        let agreement = MergeHunk::Conflict {
+            removes: vec![b"a".to_vec(), b"a".to_vec()],
+            adds: vec![b"b".to_vec(), b"c".to_vec(), b"b".to_vec()],
+        }
+        .conflict_agreement()
+        .unwrap();
+        assert_eq!(agreement.add_groups, vec![vec![0, 2], vec![1]]);
+        assert!(!agreement.all_sides_differ());
+    }
+
+    fn line_numbers(lines: DiffLineIterator) -> Vec<(u32, u32)> {
+        lines
+            .map(|line| (line.left_line_number, line.right_line_number))
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_diff_peek() {
+        let options = DiffOptions {
+            context_lines: 1,
+            ..Default::default()
+        };
+        let mut lines = diff_with_options(b"a\nb\nc\nd\n", b"a\nb\nC\nd\n", &options);
+        assert_eq!(lines.peek_next_hunk_kind(), Some(DiffLineKind::Unmodified));
+        // Peeking again doesn't advance
+        assert_eq!(lines.peek().map(|line| line.left_line_number), Some(2));
+        assert_eq!(lines.next().map(|line| line.left_line_number), Some(2));
+        assert_eq!(lines.peek_next_hunk_kind(), Some(DiffLineKind::Modified));
+        assert_eq!(lines.next().map(|line| line.left_line_number), Some(3));
+        assert_eq!(lines.next().map(|line| line.left_line_number), Some(4));
+        assert_eq!(lines.peek_next_hunk_kind(), None);
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn test_diff_range() {
+        let left = b"a\nb\nc\nd\n";
+        let right = b"a\nB\nc\n";
+        assert_eq!(
+            diff_range(left, right, 2..4, 2..4)
+                .map(|line| (
+                    line.left_line_number,
+                    line.right_line_number,
+                    line.is_unmodified()
+                ))
+                .collect_vec(),
+            vec![(2, 2, false), (3, 3, true)]
+        );
+        // Lines past the end are ignored
+        assert_eq!(
+            line_numbers(diff_range(left, left, 3..10, 3..10)),
+            vec![(3, 3), (4, 4)]
+        );
+        assert_eq!(
+            line_numbers(diff_range(left, right, 0..2, 1..2)),
+            vec![(1, 1)]
+        );
+        assert_eq!(line_numbers(diff_range(left, right, 2..2, 5..6)), vec![]);
+    }
+
+    #[test]
+    fn test_diff_context_lines() {
+        let left = b"a\nb\nc\nd\ne\nf\ng\n";
+        let right = b"a\nb\nc\nD\ne\nf\ng\n";
+        assert_eq!(
+            line_numbers(diff(left, right)),
+            vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6), (7, 7)]
+        );
+        let options = DiffOptions {
+            context_lines: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            line_numbers(diff_with_options(left, right, &options)),
+            vec![(3, 3), (4, 4), (5, 5)]
+        );
+        let options = DiffOptions {
+            context_lines: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            line_numbers(diff_with_options(left, right, &options)),
+            vec![(4, 4)]
+        );
+        // Nothing is left if there are no changes
+        assert_eq!(
+            line_numbers(diff_with_options(left, left, &options)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_diff_line_classification() {
+        let classify = |left: &'static [u8], right: &'static [u8]| {
+            diff(left, right)
+                .map(|line| {
+                    (
+                        line.is_unmodified(),
+                        line.is_insertion(),
+                        line.is_deletion(),
+                        line.is_modification(),
+                    )
+                })
+                .collect_vec()
+        };
+        let unmodified = (true, false, false, false);
+        let insertion = (false, true, false, false);
+        let deletion = (false, false, true, false);
+        let modification = (false, false, false, true);
+        assert_eq!(classify(b"a\n", b"a\nb\n"), vec![unmodified, insertion]);
+        assert_eq!(classify(b"a\nb\n", b"a\n"), vec![unmodified, deletion]);
+        assert_eq!(classify(b"a b\n", b"a c\n"), vec![modification]);
+    }
+
+    #[test]
+    fn test_diff_detect_moves() {
+        let moved_blocks = |left: &'static [u8], right: &'static [u8], detect_moves: bool| {
+            let options = DiffOptions::default().set_detect_moves(detect_moves);
+            diff_with_options(left, right, &options)
+                .map(|line| line.moved_block)
+                .collect_vec()
+        };
+        // The first two lines were moved to the end
+        assert_eq!(
+            moved_blocks(b"a\nb\nc\nd\ne\n", b"c\nd\ne\na\nb\n", true),
+            vec![Some(0), Some(0), None, None, None, Some(0), Some(0)]
+        );
+        assert_eq!(
+            moved_blocks(b"a\nb\nc\nd\ne\n", b"c\nd\ne\na\nb\n", false),
+            vec![None; 7]
+        );
+        // Deleted and inserted content that differs isn't a move
+        assert_eq!(
+            moved_blocks(b"a\nb\nc\n", b"b\nc\nd\n", true),
+            vec![None; 4]
+        );
+    }
+
+    #[test]
+    fn test_diff_algorithm() {
+        let left = b"if a {\n}\nx\n";
+        let right = b"if b {\n}\nif a {\n}\ny\n";
+        let unmodified_lines = |algorithm: DiffAlgorithm| {
+            let options = DiffOptions::default().set_algorithm(algorithm);
+            diff_with_options(left, right, &options)
+                .map(|line| line.is_unmodified())
+                .collect_vec()
+        };
+        // Patience diff doesn't line up the closing brace with the first one on
+        // the right side, since that occurs twice there
+        assert_eq!(
+            unmodified_lines(DiffAlgorithm::Patience)[..4],
+            [false, false, true, true]
+        );
+        assert_ne!(
+            unmodified_lines(DiffAlgorithm::Minimal),
+            unmodified_lines(DiffAlgorithm::Patience)
+        );
+        assert_eq!("patience".parse(), Ok(DiffAlgorithm::Patience));
+        assert!("histogram".parse::<DiffAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_for_each_diff_line() {
+        let inputs: &[(&[u8], &[u8])] = &[
+            (b"", b""),
+            (b"a\n", b""),
+            (b"a\nb\nc\n", b"a\nB b\nc\nd"),
+            (b"\xef\xbb\xbfa\nb", b"a\nb\n"),
+        ];
+        for &(left, right) in inputs {
+            let mut lines = vec![];
+            for_each_diff_line(left, right, |line| lines.push(line.clone()));
+            assert_eq!(lines, diff(left, right).collect_vec());
+        }
+    }
+
+    #[test]
+    fn test_has_changes() {
+        assert!(!has_changes(b"", b""));
+        assert!(!has_changes(b"a\nb\n", b"a\nb\n"));
+        assert!(has_changes(b"a\n", b""));
+        assert!(has_changes(b"a\nb\nc\n", b"a\nB b\nc\nd"));
+        // A changed byte order mark counts as a change
+        assert!(has_changes(b"\xef\xbb\xbfa\n", b"a\n"));
+
+        // Whitespace changes don't count if they're ignored
+        let options = DiffOptions {
+            ignore_whitespace: true,
+            ..Default::default()
+        };
+        assert!(has_changes(b"a b\n", b"a  b\t\n"));
+        assert!(!has_changes_with_options(b"a b\n", b"a  b\t\n", &options));
+        assert!(has_changes_with_options(b"a b\n", b"a  B\n", &options));
+        assert!(has_changes_with_options(
+            b"\xef\xbb\xbfa\n",
+            b"a\n",
+            &options
+        ));
+    }
+
+    #[test]
+    fn test_diff_trims_common_lines() {
+        let left = b"a\nb\nc\nd\n";
+        let right = b"a\nx\ny\nb\nc\nd\n";
+        let left_ranges = diff::find_line_ranges(left);
+        let right_ranges = diff::find_line_ranges(right);
+        assert_eq!(
+            count_common_lines(
+                (left, &left_ranges),
+                (right, &right_ranges),
+                usize::MAX,
+                usize::MAX
+            ),
+            (1, 3)
+        );
+        assert_eq!(
+            count_common_lines((left, &left_ranges), (right, &right_ranges), 0, 2),
+            (0, 2)
+        );
+        // Only the added lines are diffed, and line numbers continue across the
+        // trimmed lines
+        assert_eq!(common_lines_len(&left_ranges, left.len(), 1, 3), (2, 6));
+        assert_eq!(
+            diff_between_common_affixes(left, right, (2, 6), true, DiffAlgorithm::Minimal),
+            vec![
+                DiffHunk::Matching(b"a\n"),
+                DiffHunk::Different(vec![b"", b"x\ny\n"]),
+                DiffHunk::Matching(b"b\nc\nd\n"),
+            ]
+        );
+        assert_eq!(
+            line_numbers(diff(left, right)),
+            vec![(1, 1), (2, 2), (2, 3), (2, 4), (3, 5), (4, 6)]
+        );
+        // The prefix and the suffix don't overlap
+        let left = b"a\na\n";
+        let right = b"a\na\na\n";
+        assert_eq!(
+            count_common_lines(
+                (left, &diff::find_line_ranges(left)),
+                (right, &diff::find_line_ranges(right)),
+                usize::MAX,
+                usize::MAX
+            ),
+            (2, 0)
+        );
+        assert_eq!(
+            line_numbers(diff(left, right)),
+            vec![(1, 1), (2, 2), (3, 3)]
+        );
+        // A last line without a newline differs from one with a newline
+        assert_eq!(
+            line_numbers(diff(b"a\nb", b"a\nb\nc\n")),
+            vec![(1, 1), (2, 2), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn test_diff_ignore_whitespace() {
+        let left = b"a b\nc\n";
+        let right = b"a  b\t\nc\n";
+        assert!(!diff(left, right).all(|line| line.is_unmodified()));
+        let options = DiffOptions {
+            ignore_whitespace: true,
+            ..Default::default()
+        };
+        assert!(diff_with_options(left, right, &options).all(|line| line.is_unmodified()));
+        // Changes to non-whitespace are still reported
+        let right = b"a  B\nc\n";
+        assert!(!diff_with_options(left, right, &options).all(|line| line.is_unmodified()));
+    }
+
+    #[test]
+    fn test_diff_intra_line() {
+        let left = b"a b\n";
+        let right = b"a c\n";
+        let lines = diff(left, right).collect_vec();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            lines[0].hunks,
+            vec![
+                DiffHunk::Matching(b"a "),
+                DiffHunk::Different(vec![b"b", b""]),
+                DiffHunk::Different(vec![b"", b"c"]),
+                DiffHunk::Matching(b"\n"),
+            ]
+        );
+        let options = DiffOptions {
+            intra_line: false,
+            ..Default::default()
+        };
+        let lines = diff_with_options(left, right, &options).collect_vec();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].hunks,
+            vec![DiffHunk::Different(vec![b"a b\n", b""])]
+        );
+        assert_eq!(
+            lines[1].hunks,
+            vec![DiffHunk::Different(vec![b"", b"a c\n"])]
+        );
+    }
+
+    #[test]
+    fn test_merge_borrowed() {
+        let base: &[u8] = b"a\nb\nc\n";
+        let left: &[u8] = b"A\nb\nc\n";
+        let right: &[u8] = b"a\nb\nC\n";
+        let result = merge_borrowed(&[base], &[left, right]);
+        assert_eq!(
+            result,
+            MergeResultRef::Resolved(vec![&b"A\n"[..], &b"b\n"[..], &b"C\n"[..]])
+        );
+        // The resolved content points into the inputs
+        if let MergeResultRef::Resolved(pieces) = &result {
+            assert_eq!(pieces[0].as_ptr(), left.as_ptr());
+            assert_eq!(pieces[2].as_ptr(), right[4..].as_ptr());
+        }
+        assert_eq!(result.into_owned(), merge_parts(&[base], &[left, right]));
+
+        let right: &[u8] = b"a\nb\nd\n";
+        let left: &[u8] = b"a\nb\ne\n";
+        let result = merge_borrowed(&[base], &[left, right]);
+        assert_eq!(
+            result,
+            MergeResultRef::Conflict(vec![
+                MergeHunkRef::Resolved(vec![&b"a\nb\n"[..]]),
+                MergeHunkRef::Conflict {
+                    removes: vec![&b"c\n"[..]],
+                    adds: vec![&b"e\n"[..], &b"d\n"[..]]
+                }
+            ])
+        );
+        if let MergeResultRef::Conflict(hunks) = &result {
+            if let MergeHunkRef::Conflict { adds, .. } = &hunks[1] {
+                assert_eq!(adds[0].as_ptr(), left[4..].as_ptr());
+            }
+        }
+        assert_eq!(result.into_owned(), merge_parts(&[base], &[left, right]));
+    }
+
+    #[test]
+    fn test_remerge() {
+        // A result without conflicts is unchanged
+        let resolved = MergeResult::Resolved(b"a\nb\n".to_vec());
+        assert_eq!(remerge(resolved.clone()), resolved);
+        assert_eq!(
+            remerge(MergeResult::Resolved(vec![])),
+            MergeResult::Resolved(vec![])
+        );
+
+        // An unedited conflict stays the same
+        let result = merge_parts(&[b"a\nb\nc\n"], &[b"a\nX\nc\n", b"a\nY\nc\n"]);
+        assert_eq!(remerge(result.clone()), result);
+
+        // A conflict whose sides now agree is resolved and combined with the
+        // surrounding hunks
+        let result = MergeResult::conflict(vec![
+            MergeHunk::Resolved(b"a\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"b\n".to_vec()],
+                adds: vec![b"X\n".to_vec(), b"X\n".to_vec()],
+            },
+            MergeHunk::Resolved(b"c\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"d\n".to_vec()],
+                adds: vec![b"d\n".to_vec(), b"Y\n".to_vec()],
+            },
+        ]);
+        assert_eq!(
+            remerge(result),
+            MergeResult::Resolved(b"a\nX\nc\nY\n".to_vec())
+        );
+
+        // Only the hunks that now resolve are replaced
+        let result = MergeResult::conflict(vec![
+            MergeHunk::Resolved(b"a\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"b\n".to_vec()],
+                adds: vec![b"b\n".to_vec(), b"X\n".to_vec()],
+            },
+            MergeHunk::Conflict {
+                removes: vec![b"c\n".to_vec()],
+                adds: vec![b"Y\n".to_vec(), b"Z\n".to_vec()],
+            },
+        ]);
+        let remerged = remerge(result);
+        assert_eq!(
+            remerged,
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"a\nX\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"c\n".to_vec()],
+                    adds: vec![b"Y\n".to_vec(), b"Z\n".to_vec()],
+                },
+            ])
+        );
+        assert_eq!(remerge(remerged.clone()), remerged);
+    }
+
+    #[test]
+    fn test_merge_word_granularity() {
+        let options = MergeOptions {
+            granularity: MergeGranularity::Word,
+            ..Default::default()
+        };
+        // Adjacent words changed on each side conflict at line granularity...
+        assert_eq!(
+            merge_parts(&[b"a b c\n"], &[b"a X c\n", b"a b Y\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a b c\n".to_vec()],
+                adds: vec![b"a X c\n".to_vec(), b"a b Y\n".to_vec()]
+            }])
+        );
+        // ...but are resolved at word granularity
+        assert_eq!(
+            merge_with_options(&[b"a b c\n"], &[b"a X c\n", b"a b Y\n"], &options),
+            MergeResult::Resolved(b"a X Y\n".to_vec())
+        );
+        // Both sides changing the same word is still a conflict, reported for
+        // the whole line
+        assert_eq!(
+            merge_with_options(&[b"a b c\n"], &[b"a X c\n", b"a Y c\n"], &options),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a b c\n".to_vec()],
+                adds: vec![b"a X c\n".to_vec(), b"a Y c\n".to_vec()]
+            }])
+        );
+        // Only the hunks that resolve are replaced
+        assert_eq!(
+            merge_with_options(
+                &[b"a b\nc\nd e\n"],
+                &[b"a X\nc\nd F\n", b"Y b\nc\nd G\n"],
+                &options
+            ),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"Y X\nc\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"d e\n".to_vec()],
+                    adds: vec![b"d F\n".to_vec(), b"d G\n".to_vec()]
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_granularity_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
+        assert_eq!(
+            MergeOptions::from_settings(&settings).unwrap().granularity,
+            MergeGranularity::Line
+        );
+        let config = config::Config::builder()
+            .set_override("merge.granularity", "word")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings).unwrap().granularity,
+            MergeGranularity::Word
+        );
+        // An invalid value is reported rather than replaced by the default
+        let config = config::Config::builder()
+            .set_override("merge.granularity", "character")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap_err()
+                .to_string(),
+            "merge.granularity: must be one of line or word"
+        );
+    }
+
+    #[test]
+    fn test_resolve_whitespace_only_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
+        assert!(
+            !MergeOptions::from_settings(&settings)
+                .unwrap()
+                .resolve_whitespace_only
+        );
+        let config = config::Config::builder()
+            .set_override("merge.resolve-whitespace-only", true)
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .resolve_whitespace_only
+        );
+    }
+
+    #[test]
+    fn test_merge_labeled() {
+        let inputs: &[(&str, MergeSide, &[u8])] = &[
+            ("ours", MergeSide::Add, b"a\nb\nc\n"),
+            ("base", MergeSide::Remove, b"a\nx\nc\n"),
+            ("theirs", MergeSide::Add, b"a\ny\nc\n"),
+        ];
+        assert_eq!(
+            merge_labeled(inputs),
+            LabeledMergeResult::conflict(vec![
+                LabeledMergeHunk::Resolved(b"a\n".to_vec()),
+                LabeledMergeHunk::Conflict {
+                    removes: vec![LabeledPart {
+                        label: "base",
+                        content: b"x\n".to_vec()
+                    }],
+                    adds: vec![
+                        LabeledPart {
+                            label: "ours",
+                            content: b"b\n".to_vec()
+                        },
+                        LabeledPart {
+                            label: "theirs",
+                            content: b"y\n".to_vec()
+                        },
+                    ],
+                },
+                LabeledMergeHunk::Resolved(b"c\n".to_vec()),
+            ])
+        );
+
+        // With more sides, each part still carries the label of its input
+        let inputs: &[(String, MergeSide, &[u8])] = &[
+            ("base".to_string(), MergeSide::Remove, b"a\n"),
+            ("base".to_string(), MergeSide::Remove, b"a\n"),
+            ("branch-x".to_string(), MergeSide::Add, b"x\n"),
+            ("branch-y".to_string(), MergeSide::Add, b"a\n"),
+            ("branch-z".to_string(), MergeSide::Add, b"z\n"),
+        ];
+        let hunks = match merge_labeled(inputs) {
+            LabeledMergeResult::Conflict { hunks, .. } => hunks,
+            result => panic!("unexpected result: {result:?}"),
+        };
+        assert_eq!(hunks.len(), 1);
+        match &hunks[0] {
+            LabeledMergeHunk::Conflict { removes, adds } => {
+                assert_eq!(
+                    removes.iter().map(|part| part.label.as_str()).collect_vec(),
+                    vec!["base", "base"]
+                );
+                assert_eq!(
+                    adds.iter().map(|part| part.label.as_str()).collect_vec(),
+                    vec!["branch-x", "branch-y", "branch-z"]
+                );
+                assert_eq!(adds[2].content, b"z\n");
+            }
+            hunk => panic!("unexpected hunk: {hunk:?}"),
+        }
+
+        // Resolved merges have no labels
+        let inputs: &[(&str, MergeSide, &[u8])] = &[
+            ("base", MergeSide::Remove, b"a\n"),
+            ("ours", MergeSide::Add, b"a\n"),
+            ("theirs", MergeSide::Add, b"b\n"),
+        ];
+        assert_eq!(
+            merge_labeled(inputs),
+            LabeledMergeResult::Resolved(b"b\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_merge_with_provenance() {
+        let resolved = |content: &[u8], provenance: MergeProvenance| MergeRegion {
+            hunk: MergeHunk::Resolved(content.to_vec()),
+            provenance,
+        };
+        // Each side changed a different line
+        let input = MergeInput::three_way(b"a\nb\nc\nd\n", b"a\nx\nc\nd\n", b"a\nb\nc\ny\n");
+        assert_eq!(
+            merge_with_provenance(&input),
+            vec![
+                resolved(b"a\n", MergeProvenance::Base),
+                resolved(b"x\n", MergeProvenance::Adds(vec![0])),
+                resolved(b"c\n", MergeProvenance::Base),
+                resolved(b"y\n", MergeProvenance::Adds(vec![1])),
+            ]
+        );
+
+        // All sides made the same change, so the region is attributed to both
+        let input = MergeInput::three_way(b"a\nb\n", b"a\nx\n", b"a\nx\n");
+        assert_eq!(
+            merge_with_provenance(&input),
+            vec![
+                resolved(b"a\n", MergeProvenance::Base),
+                resolved(b"x\n", MergeProvenance::Adds(vec![0, 1])),
+            ]
+        );
+
+        // A conflict keeps its parts in input order
+        let input = MergeInput::three_way(b"a\nb\n", b"a\nx\n", b"a\ny\n");
+        assert_eq!(
+            merge_with_provenance(&input),
+            vec![
+                resolved(b"a\n", MergeProvenance::Base),
+                MergeRegion {
+                    hunk: MergeHunk::Conflict {
+                        removes: vec![b"b\n".to_vec()],
+                        adds: vec![b"x\n".to_vec(), b"y\n".to_vec()],
+                    },
+                    provenance: MergeProvenance::Conflict,
+                },
+            ]
+        );
+
+        // The regions concatenate to the output of `merge()`
+        let input = MergeInput::three_way(b"a\nb\nc\nd\n", b"a\nx\nc\nd\n", b"a\nb\nc\ny\n");
+        let content = merge_with_provenance(&input)
+            .into_iter()
+            .map(|region| match region.hunk {
+                MergeHunk::Resolved(content) => content,
+                MergeHunk::Conflict { .. } => panic!("unexpected conflict"),
+            })
+            .concat();
+        assert_eq!(merge(&input), MergeResult::Resolved(content));
+    }
+
+    #[test]
+    fn test_annotate() {
+        let range = |bytes: Range<usize>, origin: MergeSide| AnnotatedRange { bytes, origin };
+        // Each side changed a different line
+        let base = b"a\nb\nc\nd\n";
+        let hunks = match merge_parts(&[base], &[b"a\nx\nc\nd\n", b"a\nb\nc\ny\nz\n"]) {
+            MergeResult::Resolved(content) => vec![MergeHunk::Resolved(content)],
+            MergeResult::Conflict { hunks, .. } => hunks,
+        };
+        assert_eq!(
+            annotate(base, &hunks).ranges,
+            vec![
+                range(0..2, MergeSide::Remove),
+                range(2..4, MergeSide::Add),
+                range(4..6, MergeSide::Remove),
+                range(6..10, MergeSide::Add),
+            ]
+        );
+
+        // Conflict hunks are skipped
+        let hunks = vec![
+            MergeHunk::Resolved(b"a\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"b\n".to_vec()],
+                adds: vec![b"x\n".to_vec(), b"y\n".to_vec()],
+            },
+            MergeHunk::Resolved(b"c\n".to_vec()),
+        ];
+        assert_eq!(
+            annotate(b"a\nb\nc\n", &hunks).ranges,
+            vec![range(0..4, MergeSide::Remove)]
+        );
+
+        // Nothing is attributed in an empty result
+        assert_eq!(annotate(b"a\n", &[]), AnnotatedContent::default());
+    }
+
+    #[test]
+    fn test_merge_with_comparator() {
+        let ignore_whitespace = |left: &[u8], right: &[u8]| {
+            left.split(u8::is_ascii_whitespace)
+                .filter(|word| !word.is_empty())
+                .eq(right
+                    .split(u8::is_ascii_whitespace)
+                    .filter(|word| !word.is_empty()))
+        };
+        // With byte equality, the comparator variant merges like `merge()`
+        let inputs: &[(&[&[u8]], &[&[u8]])] = &[
+            (&[b"a\n"], &[b"b\n", b"a\n"]),
+            (&[b"a\n"], &[b"b\n", b"c\n"]),
+            (&[b"a\n", b"a\n"], &[b"b\n", b"a\n", b"b\n"]),
+        ];
+        for &(removes, adds) in inputs {
+            assert_eq!(
+                merge_with_comparator(removes, adds, |left, right| left == right),
+                merge_parts(removes, adds)
+            );
+        }
+        // One side only changed whitespace, so it matches the base, and the other
+        // side's change is taken
+        let removes: &[&[u8]] = &[b"a b\n"];
+        let adds: &[&[u8]] = &[b"a  b\n", b"a c\n"];
+        assert!(matches!(
+            merge_parts(removes, adds),
+            MergeResult::Conflict { .. }
+        ));
+        assert_eq!(
+            merge_with_comparator(removes, adds, ignore_whitespace),
+            MergeResult::Resolved(b"a c\n".to_vec())
+        );
+        // Both sides made the same change with different whitespace. The first
+        // side's content is used.
+        let adds: &[&[u8]] = &[b"c  d\n", b"c d\n"];
+        assert!(matches!(
+            merge_parts(removes, adds),
+            MergeResult::Conflict { .. }
+        ));
+        assert_eq!(
+            merge_with_comparator(removes, adds, ignore_whitespace),
+            MergeResult::Resolved(b"c  d\n".to_vec())
+        );
+        // Real changes on both sides still conflict
+        assert_eq!(
+            merge_with_comparator(removes, &[b"a c\n", b"a d\n"], ignore_whitespace),
+            merge_parts(removes, &[b"a c\n", b"a d\n"])
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_minimization() {
+        let merge = |removes: &[&[u8]], adds: &[&[u8]], conflict_minimization| {
+            let options = MergeOptions {
+                conflict_minimization,
+                ..Default::default()
+            };
+            merge_with_options(removes, adds, &options)
+        };
+        // Both sides made the same change to the first line, and one side
+        // changed the last line
+        let base: &[u8] = b"a\nb\nc\n";
+        let left: &[u8] = b"A\nb\nc\n";
+        let right: &[u8] = b"A\nb\nC\n";
+        let resolved = MergeResult::Resolved(b"A\nb\nC\n".to_vec());
+        assert_eq!(
+            merge(&[base], &[left, right], ConflictMinimization::Zealous),
+            resolved
+        );
+        assert_eq!(
+            merge(&[base], &[left, right], ConflictMinimization::Minimal),
+            resolved
+        );
+        assert_eq!(
+            merge(&[base], &[left, right], ConflictMinimization::None),
+            MergeResult::conflict(vec![
+                MergeHunk::Conflict {
+                    removes: vec![b"a\n".to_vec()],
+                    adds: vec![b"A\n".to_vec(), b"A\n".to_vec()]
+                },
+                MergeHunk::Resolved(b"b\nC\n".to_vec()),
+            ])
+        );
+
+        // The first remove has nothing in common with the others. Only the zealous
+        // style merges the conflict again with a better base, which resolves it.
+        let removes: &[&[u8]] = &[b"z\n", b"a\nx\nb\n"];
+        let adds: &[&[u8]] = &[b"z\n", b"A\nx\nb\n", b"a\nx\nB\n"];
+        assert_eq!(
+            merge(removes, adds, ConflictMinimization::Zealous),
+            MergeResult::Resolved(b"A\nx\nB\n".to_vec())
+        );
+        let conflict = MergeResult::conflict(vec![MergeHunk::Conflict {
+            removes: removes.iter().map(|part| part.to_vec()).collect_vec(),
+            adds: adds.iter().map(|part| part.to_vec()).collect_vec(),
+        }]);
+        assert_eq!(
+            merge(removes, adds, ConflictMinimization::Minimal),
+            conflict
+        );
+        assert_eq!(merge(removes, adds, ConflictMinimization::None), conflict);
+
+        // A region that only one side changed is resolved in every style
+        for conflict_minimization in [
+            ConflictMinimization::Zealous,
+            ConflictMinimization::Minimal,
+            ConflictMinimization::None,
+        ] {
+            assert_eq!(
+                merge(&[b"a\nb\n"], &[b"a\nb\n", b"a\nB\n"], conflict_minimization),
+                MergeResult::Resolved(b"a\nB\n".to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_conflict_minimization_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .conflict_minimization,
+            ConflictMinimization::Zealous
+        );
+        let config = config::Config::builder()
+            .set_override("merge.conflict-minimization", "none")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .conflict_minimization,
+            ConflictMinimization::None
+        );
+        assert!("diff3".parse::<ConflictMinimization>().is_err());
+    }
+
+    #[test]
+    fn test_merge_same_change_whitespace() {
+        let merge = |adds: &[&[u8]], same_change_whitespace| {
+            let options = MergeOptions {
+                same_change_whitespace,
+                ..MergeOptions::default()
+            };
+            merge_with_options(&[b"a\n"], adds, &options)
+        };
+        // By default, added lines must be exactly the same
+        assert!(!merge(&[b"a\nfoo\n", b"a\nfoo  \n"], SameChangeWhitespace::Exact).is_resolved());
+        // The first side's content is used, with its whitespace
+        assert_eq!(
+            merge(
+                &[b"a\nfoo\n", b"a\nfoo  \n"],
+                SameChangeWhitespace::Trailing
+            ),
+            MergeResult::Resolved(b"a\nfoo\n".to_vec())
+        );
+        assert_eq!(
+            merge(
+                &[b"a\nfoo \t\n", b"a\nfoo\n"],
+                SameChangeWhitespace::Trailing
+            ),
+            MergeResult::Resolved(b"a\nfoo \t\n".to_vec())
+        );
+        assert!(!merge(
+            &[b"a\nfoo bar\n", b"a\nfoobar\n"],
+            SameChangeWhitespace::Trailing
+        )
+        .is_resolved());
+        assert_eq!(
+            merge(
+                &[b"a\nfoo bar\n", b"a\nfoobar\n"],
+                SameChangeWhitespace::All
+            ),
+            MergeResult::Resolved(b"a\nfoo bar\n".to_vec())
+        );
+        // Content that differs in more than whitespace still conflicts
+        assert!(!merge(&[b"a\nfoo\n", b"a\nbar\n"], SameChangeWhitespace::All).is_resolved());
+        // Whitespace changes to the base are still changes
+        assert!(!merge(&[b"a  \n", b"b\n"], SameChangeWhitespace::All).is_resolved());
+    }
+
+    #[test]
+    fn test_same_change_whitespace_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .same_change_whitespace,
+            SameChangeWhitespace::Exact
+        );
+        let config = config::Config::builder()
+            .set_override("merge.same-change-whitespace", "trailing")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .same_change_whitespace,
+            SameChangeWhitespace::Trailing
+        );
+        assert!("none".parse::<SameChangeWhitespace>().is_err());
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity(b"", b""), 100);
+        assert_eq!(similarity(b"a\nb\n", b"a\nb\n"), 100);
+        assert_eq!(similarity(b"a\nb\n", b"c\nd\n"), 0);
+        assert_eq!(similarity(b"a\nb\n", b""), 0);
+        // Half of the bytes on each side are in common lines
+        assert_eq!(similarity(b"a\nb\n", b"a\nc\n"), 50);
+    }
+
+    #[test]
+    fn test_merge_line_endings() {
+        let options = |line_endings| MergeOptions {
+            line_endings,
+            ..Default::default()
+        };
+        // Both sides made the same change, but with different line endings
+        assert_eq!(
+            merge_parts(&[b"x\n"], &[b"a\r\n", b"a\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"x\n".to_vec()],
+                adds: vec![b"a\r\n".to_vec(), b"a\n".to_vec()]
+            }])
+        );
+        assert_eq!(
+            merge_with_options(
+                &[b"x\n"],
+                &[b"a\r\n", b"a\n"],
+                &options(LineEndings::LastSide)
+            ),
+            MergeResult::Resolved(b"a\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(
+                &[b"x\n"],
+                &[b"a\n", b"a\r\n"],
+                &options(LineEndings::LastSide)
+            ),
+            MergeResult::Resolved(b"a\r\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[b"x\n"], &[b"a\r\n", b"a\n"], &options(LineEndings::Lf)),
+            MergeResult::Resolved(b"a\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[b"x\n"], &[b"a\r\n", b"a\n"], &options(LineEndings::Crlf)),
+            MergeResult::Resolved(b"a\r\n".to_vec())
+        );
+        // One side only changed a line ending, the other side changed another
+        // line
+        assert!(!merge_parts(&[b"a\nb\n"], &[b"a\r\nb\n", b"a\nc\n"]).is_resolved());
+        assert_eq!(
+            merge_with_options(
+                &[b"a\nb\n"],
+                &[b"a\r\nb\n", b"a\nc\n"],
+                &options(LineEndings::LastSide)
+            ),
+            MergeResult::Resolved(b"a\nc\n".to_vec())
+        );
+        // Lines whose line endings don't differ keep them, even in files with
+        // mixed line endings
+        assert_eq!(
+            merge_with_options(
+                &[b"a\r\nb\nx\n"],
+                &[b"a\r\nb\nL\r\n", b"a\r\nb\nL\n"],
+                &options(LineEndings::Lf)
+            ),
+            MergeResult::Resolved(b"a\r\nb\nL\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(
+                &[b"a\r\nb\nx\n"],
+                &[b"a\r\nb\nL\r\n", b"a\r\nb\nL\n"],
+                &options(LineEndings::Crlf)
+            ),
+            MergeResult::Resolved(b"a\r\nb\nL\r\n".to_vec())
+        );
+        // Changes to more than the line endings still conflict
+        assert_eq!(
+            merge_with_options(
+                &[b"x\n"],
+                &[b"a\r\n", b"b\n"],
+                &options(LineEndings::LastSide)
+            ),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"x\n".to_vec()],
+                adds: vec![b"a\r\n".to_vec(), b"b\n".to_vec()]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_line_endings_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
+        assert_eq!(
+            MergeOptions::from_settings(&settings).unwrap().line_endings,
+            LineEndings::Exact
+        );
+        let config = config::Config::builder()
+            .set_override("merge.line-endings", "last-side")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings).unwrap().line_endings,
+            LineEndings::LastSide
+        );
+    }
+
+    #[test]
+    fn test_merge_output_line_ending() {
+        let options = |output_line_ending| MergeOptions {
+            output_line_ending,
+            ..Default::default()
+        };
+        let native: &[u8] = if cfg!(windows) { b"\r\n" } else { b"\n" };
+        // A clean merge of inputs with mixed line endings
+        let base: &[u8] = b"a\r\nb\nc\r\n";
+        let left: &[u8] = b"A\nb\nc\r\n";
+        let right: &[u8] = b"a\r\nb\nC\r\n";
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Preserve)),
+            MergeResult::Resolved(b"A\nb\nC\r\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Lf)),
+            MergeResult::Resolved(b"A\nb\nC\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Crlf)),
+            MergeResult::Resolved(b"A\r\nb\r\nC\r\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Native)),
+            MergeResult::Resolved([b"A", native, b"b", native, b"C", native].concat())
+        );
+        // The parts of conflict hunks are normalized too, and a last line
+        // without a line ending doesn't get one
+        let conflict = |line_ending: &[u8]| {
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved([b"a", line_ending].concat()),
+                MergeHunk::Conflict {
+                    removes: vec![b"b".to_vec()],
+                    adds: vec![b"B1".to_vec(), b"B2".to_vec()],
+                },
+            ])
+        };
+        let base: &[u8] = b"a\r\nb";
+        let left: &[u8] = b"a\r\nB1";
+        let right: &[u8] = b"a\r\nB2";
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Preserve)),
+            conflict(b"\r\n")
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Lf)),
+            conflict(b"\n")
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Crlf)),
+            conflict(b"\r\n")
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Native)),
+            conflict(native)
+        );
+        let base: &[u8] = b"x\n";
+        let left: &[u8] = b"a\r\nb\n";
+        let right: &[u8] = b"c\n";
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Lf)),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"x\n".to_vec()],
+                adds: vec![b"a\nb\n".to_vec(), b"c\n".to_vec()],
+            }])
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[left, right], &options(LineEnding::Crlf)),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"x\r\n".to_vec()],
+                adds: vec![b"a\r\nb\r\n".to_vec(), b"c\r\n".to_vec()],
+            }])
+        );
+        // From settings
+        let config = config::Config::builder()
+            .set_override("merge.output-line-ending", "crlf")
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        assert_eq!(
+            MergeOptions::from_settings(&settings)
+                .unwrap()
+                .output_line_ending,
+            LineEnding::Crlf
+        );
+    }
 
-#[derive(PartialEq, Eq, Clone)]
-pub enum MergeResult {
-    Resolved(Vec<u8>),
-    Conflict(Vec<MergeHunk>),
-}
+    #[test]
+    fn test_file_merge_strategies() {
+        assert_eq!(
+            "binary".parse::<FileMergeStrategy>(),
+            Ok(FileMergeStrategy::Binary)
+        );
+        assert_eq!(
+            "union".parse::<FileMergeStrategy>(),
+            Ok(FileMergeStrategy::Resolve(MergeStrategy::Union))
+        );
+        assert!("text".parse::<FileMergeStrategy>().is_err());
 
-impl Debug for MergeResult {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match self {
-            MergeResult::Resolved(data) => f
-                .debug_tuple("Resolved")
-                .field(&String::from_utf8_lossy(data))
-                .finish(),
-            MergeResult::Conflict(hunks) => f.debug_tuple("Conflict").field(hunks).finish(),
-        }
+        let strategies = FileMergeStrategies::new([
+            ("*.md".to_string(), FileMergeStrategy::Binary),
+            (
+                "/docs/*.md".to_string(),
+                FileMergeStrategy::Resolve(MergeStrategy::Union),
+            ),
+        ]);
+        let strategy_for =
+            |path: &str| strategies.strategy_for(&RepoPath::from_internal_string(path));
+        assert_eq!(strategy_for("README.md"), Some(FileMergeStrategy::Binary));
+        // The longest matching pattern wins
+        assert_eq!(
+            strategy_for("docs/config.md"),
+            Some(FileMergeStrategy::Resolve(MergeStrategy::Union))
+        );
+        assert_eq!(strategy_for("src/main.rs"), None);
     }
-}
-
-/// A region where the base and two sides match.
-#[derive(Debug, PartialEq, Eq, Clone)]
-struct SyncRegion {
-    base: Range<usize>,
-    left: Range<usize>,
-    right: Range<usize>,
-}
 
-// TODO: Should we require `add.len() == removes.len() + 1`? If that condition
-// is false, it effectively means that we should pretend that there are empty
-// strings in `removes` or `adds` to make it true. Maybe we should have to
-// caller make it explicitly that way.
-pub fn merge(removes: &[&[u8]], adds: &[&[u8]]) -> MergeResult {
-    let num_removes = removes.len();
-    // TODO: Using the first remove as base (first in the inputs) is how it's
-    // usually done for 3-way conflicts. Are there better heuristics when there are
-    // more than 3 parts?
-    let mut diff_inputs = removes.to_vec();
-    diff_inputs.extend(adds);
-
-    let diff = Diff::for_tokenizer(&diff_inputs, &diff::find_line_ranges);
-    let mut resolved_hunk: Vec<u8> = vec![];
-    let mut merge_hunks: Vec<MergeHunk> = vec![];
-    for diff_hunk in diff.hunks() {
-        match diff_hunk {
-            DiffHunk::Matching(content) => {
-                if adds.len() > removes.len() {
-                    resolved_hunk.extend(content);
-                }
-            }
-            DiffHunk::Different(parts) => {
-                let mut removed_parts = parts[..num_removes].to_vec();
-                let mut added_parts = parts[num_removes..].to_vec();
-                // Remove pairs of parts that match in the removes and adds.
-                let mut added_index = 0;
-                while added_index < added_parts.len() {
-                    let added_part = added_parts[added_index];
-                    added_index += 1;
-                    for (removed_index, removed_part) in removed_parts.iter().enumerate() {
-                        if *removed_part == added_part {
-                            added_index -= 1;
-                            added_parts.remove(added_index);
-                            removed_parts.remove(removed_index);
-                            break;
-                        }
-                    }
+    #[test]
+    fn test_merge_resolve_whitespace_only() {
+        let options = MergeOptions {
+            resolve_whitespace_only: true,
+            ..Default::default()
+        };
+        // Both sides only changed whitespace, so the first side is taken
+        assert_eq!(
+            merge_with_options(&[b"x\na\n"], &[b"x\n  a\n", b"x\n\ta \n"], &options),
+            MergeResult::Resolved(b"x\n  a\n".to_vec())
+        );
+        assert_eq!(
+            merge_parts(&[b"x\na\n"], &[b"x\n  a\n", b"x\n\ta \n"]),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"x\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"a\n".to_vec()],
+                    adds: vec![b"  a\n".to_vec(), b"\ta \n".to_vec()]
                 }
-                let distinct_removes: HashSet<&[u8]> = removed_parts.iter().copied().collect();
-                let distinct_adds: HashSet<&[u8]> = added_parts.iter().copied().collect();
-                if removed_parts.is_empty() && added_parts.is_empty() {
-                    // The same content was added and removed, so there's
-                    // nothing left.
-                } else if distinct_removes.is_empty() && distinct_adds.len() == 1 {
-                    // All sides added the same content
-                    resolved_hunk.extend(added_parts[0]);
-                } else if distinct_removes.len() == 1 && distinct_adds.is_empty() {
-                    // All sides removed the same content
-                } else if distinct_removes.len() == 1
-                    && distinct_adds.len() == 1
-                    && added_parts.len() == removed_parts.len() + 1
-                {
-                    // All sides made the same change, and there's a matching extra base to apply it
-                    // to
-                    resolved_hunk.extend(added_parts[0]);
-                } else {
-                    if !resolved_hunk.is_empty() {
-                        merge_hunks.push(MergeHunk::Resolved(resolved_hunk));
-                        resolved_hunk = vec![];
-                    }
-                    // Include the unfiltered lists of removed and added here, so the caller
-                    // knows which part corresponds to which input.
-                    merge_hunks.push(MergeHunk::Conflict {
-                        removes: parts[..num_removes]
-                            .iter()
-                            .map(|part| part.to_vec())
-                            .collect_vec(),
-                        adds: parts[num_removes..]
-                            .iter()
-                            .map(|part| part.to_vec())
-                            .collect_vec(),
-                    });
+            ])
+        );
+        // One side only changed whitespace, so the other side is taken
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b" a\n", b"b\n"], &options),
+            MergeResult::Resolved(b"b\n".to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b"b\n", b"a \n"], &options),
+            MergeResult::Resolved(b"b\n".to_vec())
+        );
+        // Real changes on both sides are still a conflict
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b" b\n", b"c\n"], &options),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec()],
+                adds: vec![b" b\n".to_vec(), b"c\n".to_vec()]
+            }])
+        );
+        // Whitespace inside the content counts
+        assert_eq!(
+            merge_with_options(&[b"a b\n"], &[b"a  b\n", b"ab\n"], &options),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a b\n".to_vec()],
+                adds: vec![b"a  b\n".to_vec(), b"ab\n".to_vec()]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_merge_resolve_whitespace_only_tabs_to_spaces() {
+        let options = MergeOptions {
+            resolve_whitespace_only: true,
+            ..Default::default()
+        };
+        let base: &[u8] = b"fn f() {\n\tfoo();\n\tbar();\n}\n";
+        let spaces: &[u8] = b"fn f() {\n    foo();\n    bar();\n}\n";
+        let changed: &[u8] = b"fn f() {\n\tfoo();\n\tbaz();\n}\n";
+        assert!(!merge_parts(&[base], &[spaces, changed]).is_resolved());
+        // The side that only converted tabs to spaces gives way to the real
+        // change, which keeps its tabs
+        assert_eq!(
+            merge_with_options(&[base], &[spaces, changed], &options),
+            MergeResult::Resolved(changed.to_vec())
+        );
+        assert_eq!(
+            merge_with_options(&[base], &[changed, spaces], &options),
+            MergeResult::Resolved(changed.to_vec())
+        );
+        // Converting tabs to spaces along with a real change still conflicts
+        let spaces_changed: &[u8] = b"fn f() {\n    foo();\n    qux();\n}\n";
+        assert!(!merge_with_options(&[base], &[spaces_changed, changed], &options).is_resolved());
+        // So does changing the whitespace between words
+        let split: &[u8] = b"fn f() {\n\tfoo();\n\tb ar();\n}\n";
+        assert!(!merge_with_options(&[base], &[split, changed], &options).is_resolved());
+    }
+
+    #[test]
+    fn test_merge_ignore_trailing_whitespace() {
+        let options = MergeOptions {
+            ignore_trailing_whitespace: true,
+            ..Default::default()
+        };
+        // Sides only differ in trailing whitespace
+        assert_eq!(
+            merge_parts(&[b"a\n"], &[b"a  \n", b"a\t\n"]),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec()],
+                adds: vec![b"a  \n".to_vec(), b"a\t\n".to_vec()]
+            }])
+        );
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b"a  \n", b"a\t\n"], &options),
+            MergeResult::Resolved(b"a\n".to_vec())
+        );
+        // One side only changed trailing whitespace, the other side made a real
+        // change
+        assert_eq!(
+            merge_parts(&[b"a\nb\n"], &[b"a\nb \n", b"a\nc\n"]),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"b\n".to_vec()],
+                    adds: vec![b"b \n".to_vec(), b"c\n".to_vec()]
                 }
-            }
-        }
+            ])
+        );
+        assert_eq!(
+            merge_with_options(&[b"a\nb\n"], &[b"a\nb \n", b"a\nc\n"], &options),
+            MergeResult::Resolved(b"a\nc\n".to_vec())
+        );
+        // Trailing whitespace is kept in the unchanged regions and in the changes
+        assert_eq!(
+            merge_with_options(&[b"a \nb\n"], &[b"a\nb\n", b"a\nc \n"], &options),
+            MergeResult::Resolved(b"a \nc \n".to_vec())
+        );
+        // Conflicts in non-whitespace content include the original content
+        assert_eq!(
+            merge_with_options(&[b"a\n"], &[b"b \n", b"c\n"], &options),
+            MergeResult::conflict(vec![MergeHunk::Conflict {
+                removes: vec![b"a\n".to_vec()],
+                adds: vec![b"b \n".to_vec(), b"c\n".to_vec()]
+            }])
+        );
+        // Whitespace at the end of the file without a newline
+        assert_eq!(
+            merge_with_options(&[b"a\nb"], &[b"a\nb  ", b"c\nb"], &options),
+            MergeResult::Resolved(b"c\nb".to_vec())
+        );
     }
 
-    if merge_hunks.is_empty() {
-        MergeResult::Resolved(resolved_hunk)
-    } else {
-        if !resolved_hunk.is_empty() {
-            merge_hunks.push(MergeHunk::Resolved(resolved_hunk));
-        }
-        MergeResult::Conflict(merge_hunks)
+    #[test]
+    fn test_diff_bom() {
+        // Only the byte order mark differs
+        let mut diff_lines = diff(b"\xef\xbb\xbfa\n", b"a\n");
+        assert_eq!(diff_lines.bom_change(), Some(BomChange::Removed));
+        assert!(diff_lines.all(|line| line.is_unmodified()));
+        // The byte order mark was added and the content changed
+        let mut diff_lines = diff(b"a\n", b"\xef\xbb\xbfb\n");
+        assert_eq!(diff_lines.bom_change(), Some(BomChange::Added));
+        assert!(!diff_lines.all(|line| line.is_unmodified()));
+        // Both sides have a byte order mark, which isn't part of the lines
+        let diff_lines = diff(b"\xef\xbb\xbfa\n", b"\xef\xbb\xbfa\n");
+        assert_eq!(diff_lines.bom_change(), None);
+        assert_eq!(
+            diff_lines.map(|line| line.hunks).collect_vec(),
+            vec![vec![DiffHunk::Matching(b"a\n")]]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_diff_files() {
+        let options = DiffOptions {
+            context_lines: 0,
+            intra_line: false,
+            ..Default::default()
+        };
+        let files: &[(&str, &[u8], &[u8])] = &[
+            ("a.txt", b"a\nb\n", b"a\nB\n"),
+            ("unchanged.txt", b"x\n", b"x\n"),
+            ("c.txt", b"", b"c\n"),
+        ];
+        assert_eq!(
+            diff_files(files, &options)
+                .map(|line| (line.source_file, line.hunks))
+                .collect_vec(),
+            vec![
+                (Some("a.txt"), vec![DiffHunk::Different(vec![b"b\n", b""])]),
+                (Some("a.txt"), vec![DiffHunk::Different(vec![b"", b"B\n"])]),
+                (Some("c.txt"), vec![DiffHunk::Different(vec![b"", b"c\n"])]),
+            ]
+        );
+        let line = diff(b"a\n", b"b\n").next().unwrap();
+        assert_eq!(line.source_file, None);
+    }
 
     #[test]
-    fn test_merge() {
-        // Unchanged and empty on all sides
+    fn test_line_hashes() {
+        // The hashes are the same in every process, so they can be stored
+        assert_eq!(line_hashes(b"a\n"), vec![620331299357648818]);
         assert_eq!(
-            merge(&[b""], &[b"", b""]),
-            MergeResult::Resolved(b"".to_vec())
+            line_hashes(b"a\nb\na"),
+            vec![hash_line(b"a\n"), hash_line(b"b\n"), hash_line(b"a")]
         );
-        // Unchanged on all sides
+        assert_ne!(line_hashes(b"a\n"), line_hashes(b"a"));
+    }
+
+    #[test]
+    fn test_lines_len() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"a\nb\n"), 2);
+        assert_eq!(count_lines(b"a\nb"), 2);
+        assert_eq!(leading_lines_len(b"a\nbc\nd", 0), 0);
+        assert_eq!(leading_lines_len(b"a\nbc\nd", 2), 5);
+        assert_eq!(leading_lines_len(b"a\nbc\nd", 3), 6);
+        assert_eq!(trailing_lines_len(b"a\nbc\nd", 0), 0);
+        assert_eq!(trailing_lines_len(b"a\nbc\nd", 1), 1);
+        assert_eq!(trailing_lines_len(b"a\nbc\nd", 2), 4);
+        assert_eq!(trailing_lines_len(b"a\nbc\nd\n", 2), 5);
+        assert_eq!(trailing_lines_len(b"a\nbc\nd\n", 3), 7);
+    }
+
+    #[test]
+    fn test_common_line_hash_affixes() {
         assert_eq!(
-            merge(&[b"a"], &[b"a", b"a"]),
-            MergeResult::Resolved(b"a".to_vec())
+            common_line_hash_affixes(&line_hashes(b""), &line_hashes(b"")),
+            (0, 0)
         );
-        // One side removed, one side unchanged
         assert_eq!(
-            merge(&[b"a\n"], &[b"", b"a\n"]),
-            MergeResult::Resolved(b"".to_vec())
+            common_line_hash_affixes(&line_hashes(b"a\nb\nc\n"), &line_hashes(b"a\nB\nc\n")),
+            (1, 1)
         );
-        // One side unchanged, one side removed
+        // The prefix and the suffix don't overlap
         assert_eq!(
-            merge(&[b"a\n"], &[b"a\n", b""]),
-            MergeResult::Resolved(b"".to_vec())
+            common_line_hash_affixes(&line_hashes(b"a\na\n"), &line_hashes(b"a\na\na\n")),
+            (2, 0)
         );
-        // Both sides removed same line
+        // The byte order mark isn't part of the first line
         assert_eq!(
-            merge(&[b"a\n"], &[b"", b""]),
-            MergeResult::Resolved(b"".to_vec())
+            common_line_hash_affixes(&line_hashes(b"\xef\xbb\xbfa\nb\n"), &line_hashes(b"a\nc\n")),
+            (1, 0)
         );
-        // One side modified, one side unchanged
+    }
+
+    #[test]
+    fn test_diff_with_line_hashes() {
+        let check = |left: &[u8], right: &[u8], options: &DiffOptions| {
+            let (left_hashes, right_hashes) = (line_hashes(left), line_hashes(right));
+            assert_eq!(
+                diff_with_line_hashes(left, right, &left_hashes, &right_hashes, options)
+                    .collect_vec(),
+                diff_with_options(left, right, options).collect_vec()
+            );
+        };
+        let inputs: &[(&[u8], &[u8])] = &[
+            (b"", b""),
+            (b"a\n", b"a\n"),
+            (b"", b"a\n"),
+            (b"a\nb\nc\n", b"a\nb b\nc\n"),
+            (b"a\nb\nc\n", b"a\nc\n"),
+            (b"a\nb", b"a\nb\n"),
+            (b"\xef\xbb\xbfa\nb\n", b"a\nc\n"),
+            (b"x\ny\nx\ny\n", b"x\ny\nz\nx\ny\n"),
+        ];
+        for options in [
+            DiffOptions::default(),
+            DiffOptions::default().set_intra_line(false),
+            DiffOptions::default().set_context_lines(0),
+            DiffOptions::default().set_min_matching_len(4),
+        ] {
+            for (left, right) in inputs {
+                check(left, right, &options);
+            }
+        }
+
+        // Hashes of other content are ignored
+        let options = DiffOptions::default();
         assert_eq!(
-            merge(&[b"a"], &[b"a b", b"a"]),
-            MergeResult::Resolved(b"a b".to_vec())
+            diff_with_line_hashes(b"a\nb\n", b"a\nc\n", &[], &line_hashes(b"x\n"), &options)
+                .collect_vec(),
+            diff_with_options(b"a\nb\n", b"a\nc\n", &options).collect_vec()
         );
-        // One side unchanged, one side modified
+        // Lines of different lengths with the same hash are still diffed
         assert_eq!(
-            merge(&[b"a"], &[b"a", b"a b"]),
-            MergeResult::Resolved(b"a b".to_vec())
+            diff_with_line_hashes(b"a\nb\n", b"a\ncd\n", &[1, 2], &[1, 2], &options).collect_vec(),
+            diff_with_options(b"a\nb\n", b"a\ncd\n", &options).collect_vec()
         );
-        // All sides added same content
+        // Lines of the same length with the same hash are still compared
         assert_eq!(
-            merge(&[], &[b"a\n", b"a\n", b"a\n"]),
-            MergeResult::Resolved(b"a\n".to_vec())
+            diff_with_line_hashes(b"a\nb\n", b"a\nc\n", &[1, 2], &[1, 2], &options).collect_vec(),
+            diff_with_options(b"a\nb\n", b"a\nc\n", &options).collect_vec()
         );
-        // One side modified, two sides added
+    }
+
+    #[test]
+    fn test_diff_with_line_hashes_large_file() {
+        let lines = (0..10_000).map(|i| format!("line {i}\n")).collect_vec();
+        let left = lines.concat();
+        let mut right_lines = lines;
+        right_lines[5_000] = "changed\n".to_string();
+        let right = right_lines.concat();
+        let left_hashes = line_hashes(left.as_bytes());
+        let right_hashes = line_hashes(right.as_bytes());
+        // Only the changed line is left to be tokenized and diffed
+        let (prefix, suffix) = common_line_hash_affixes(&left_hashes, &right_hashes);
+        assert_eq!((prefix, suffix), (5_000, 4_999));
+        assert_eq!(left_hashes.len() - prefix - suffix, 1);
+        let options = DiffOptions::default().set_context_lines(1);
+        let lines = diff_with_line_hashes(
+            left.as_bytes(),
+            right.as_bytes(),
+            &left_hashes,
+            &right_hashes,
+            &options,
+        )
+        .collect_vec();
         assert_eq!(
-            merge(&[b"a"], &[b"b", b"b", b"b"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a".to_vec()],
-                adds: vec![b"b".to_vec(), b"b".to_vec(), b"b".to_vec()]
-            }])
+            lines,
+            diff_with_options(left.as_bytes(), right.as_bytes(), &options).collect_vec()
         );
-        // All sides removed same content
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_merge_bom() {
+        // All sides have a byte order mark
         assert_eq!(
-            merge(&[b"a\n", b"a\n", b"a\n"], &[]),
-            MergeResult::Resolved(b"".to_vec())
+            merge_parts(
+                &[b"\xef\xbb\xbfa\nx\nb\n"],
+                &[b"\xef\xbb\xbfA\nx\nb\n", b"\xef\xbb\xbfa\nx\nB\n"]
+            ),
+            MergeResult::Resolved(b"\xef\xbb\xbfA\nx\nB\n".to_vec())
         );
-        // One side modified, two sides removed
+        // One side added a byte order mark, the other side changed the content
         assert_eq!(
-            merge(&[b"a\n", b"a\n", b"a\n"], &[b""]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a\n".to_vec(), b"a\n".to_vec(), b"a\n".to_vec()],
-                adds: vec![b"".to_vec()]
-            }])
+            merge_parts(&[b"a\nx\nb\n"], &[b"\xef\xbb\xbfa\nx\nb\n", b"a\nx\nB\n"]),
+            MergeResult::Resolved(b"\xef\xbb\xbfa\nx\nB\n".to_vec())
         );
-        // Three sides made the same change
+        // One side removed the byte order mark, the other side changed the content
         assert_eq!(
-            merge(&[b"a", b"a"], &[b"b", b"b", b"b"]),
-            MergeResult::Resolved(b"b".to_vec())
+            merge_parts(&[b"\xef\xbb\xbfa\n"], &[b"a\n", b"\xef\xbb\xbfb\n"]),
+            MergeResult::Resolved(b"b\n".to_vec())
         );
-        // One side unchanged, one side added
+        // Both sides added a byte order mark, which is only kept once
         assert_eq!(
-            merge(&[b"a\n"], &[b"a\nb\n"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"".to_vec()],
-                adds: vec![b"b\n".to_vec()]
-            }])
+            merge_parts(&[b"a\n"], &[b"\xef\xbb\xbfa\n", b"\xef\xbb\xbfb\n"]),
+            MergeResult::Resolved(b"\xef\xbb\xbfb\n".to_vec())
         );
-        // Two sides left one line unchanged, and added conflicting additional lines
+        // The byte order mark stays out of conflict hunks
         assert_eq!(
-            merge(&[b"a\n"], &[b"a\nb\n", b"a\nc\n"]),
-            MergeResult::Conflict(vec![
-                MergeHunk::Resolved(b"a\n".to_vec()),
+            merge_parts(
+                &[b"\xef\xbb\xbfa\n"],
+                &[b"\xef\xbb\xbfb\n", b"\xef\xbb\xbfc\n"]
+            ),
+            MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"\xef\xbb\xbf".to_vec()),
                 MergeHunk::Conflict {
-                    removes: vec![b"".to_vec()],
+                    removes: vec![b"a\n".to_vec()],
                     adds: vec![b"b\n".to_vec(), b"c\n".to_vec()]
                 }
             ])
         );
-        // One side removed, one side modified
+    }
+
+    #[test]
+    fn test_diff_min_matching_len() {
+        let left = b"a b c d\n";
+        let right = b"A b C d\n";
+        let num_changes = |lines: DiffLineIterator| {
+            lines
+                .flat_map(|line| line.hunks)
+                .filter(|hunk| matches!(hunk, DiffHunk::Different(_)))
+                .count()
+        };
+        assert_eq!(num_changes(diff(left, right)), 4);
+        // The short unchanged " b " no longer splits the changes
+        let options = DiffOptions::default().set_min_matching_len(4);
+        assert_eq!(num_changes(diff_with_options(left, right, &options)), 2);
+        let lines = diff_with_options(left, right, &options).collect_vec();
         assert_eq!(
-            merge(&[b"a\n"], &[b"", b"b\n"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a\n".to_vec()],
-                adds: vec![b"".to_vec(), b"b\n".to_vec()]
-            }])
+            lines[0].hunks,
+            vec![
+                DiffHunk::Different(vec![b"a b c", b""]),
+                DiffHunk::Different(vec![b"", b"A b C"]),
+                DiffHunk::Matching(b" d\n"),
+            ]
         );
-        // One side modified, one side removed
+        // Longer unchanged runs are kept
+        let options = DiffOptions::default().set_min_matching_len(3);
+        assert_eq!(num_changes(diff_with_options(left, right, &options)), 4);
+        // So is unchanged content at the start and end
+        let options = DiffOptions::default().set_min_matching_len(100);
+        let lines = diff_with_options(b"x a\n", b"x b\n", &options).collect_vec();
+        assert_eq!(lines[0].hunks[0], DiffHunk::Matching(b"x "));
+    }
+
+    #[test]
+    fn test_diff_options_from_settings() {
+        let settings = UserSettings::from_config(config::Config::default());
         assert_eq!(
-            merge(&[b"a\n"], &[b"b\n", b""]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a\n".to_vec()],
-                adds: vec![b"b\n".to_vec(), b"".to_vec()]
-            }])
+            DiffOptions::from_settings(&settings),
+            DiffOptions::default().set_context_lines(3)
         );
-        // Two sides modified in different ways
+        let config = config::Config::builder()
+            .set_override("diff.context-lines", 5)
+            .unwrap()
+            .set_override("diff.ignore-whitespace", true)
+            .unwrap()
+            .set_override("diff.intra-line", false)
+            .unwrap()
+            .set_override("ui.tab-width", 4)
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
         assert_eq!(
-            merge(&[b"a"], &[b"b", b"c"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a".to_vec()],
-                adds: vec![b"b".to_vec(), b"c".to_vec()]
-            }])
+            DiffOptions::from_settings(&settings),
+            DiffOptions {
+                context_lines: 5,
+                ignore_whitespace: true,
+                intra_line: false,
+                max_line_width: None,
+                tab_width: 4,
+                min_matching_len: 0,
+                control_chars: ControlCharStyle::Caret,
+            }
         );
-        // Two of three sides don't change, third side changes
+    }
+
+    #[test]
+    fn test_escape_control_chars() {
+        let escape = |data: &'static [u8], style| escape_control_chars(data, style).into_owned();
         assert_eq!(
-            merge(&[b"a", b"a"], &[b"a", b"", b"a"]),
-            MergeResult::Resolved(b"".to_vec())
+            escape(b"a\x1b[31mb\0c\x7f\n", ControlCharStyle::Caret),
+            b"a^[[31mb^@c^?\n"
         );
-        // One side unchanged, two other sides make the same change
         assert_eq!(
-            merge(&[b"a", b"a"], &[b"", b"a", b""]),
-            MergeResult::Resolved(b"".to_vec())
+            escape(b"a\x1b[31mb\0c\x7f\n", ControlCharStyle::Picture),
+            "a\u{241b}[31mb\u{2400}c\u{2421}\n".as_bytes()
         );
-        // One side unchanged, two other sides make the different change
         assert_eq!(
-            merge(&[b"a", b"a"], &[b"b", b"a", b"c"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a".to_vec(), b"a".to_vec()],
-                adds: vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]
-            }])
+            escape(b"a\x1b[31mb\n", ControlCharStyle::Verbatim),
+            b"a\x1b[31mb\n"
         );
-        // Merge of an unresolved conflict and another branch, where the other branch
-        // undid the change from one of the inputs to the unresolved conflict in the
-        // first.
+        // Tabs, newlines, and line endings are kept, but other carriage returns
+        // are escaped
         assert_eq!(
-            merge(&[b"a", b"b"], &[b"b", b"a", b"c"]),
-            MergeResult::Resolved(b"c".to_vec())
+            escape(b"a\tb\r\nc\rd\x0b", ControlCharStyle::Caret),
+            b"a\tb\r\nc^Md^K"
         );
-        // Merge of an unresolved conflict and another branch.
+        // Text without control characters isn't copied
+        assert!(matches!(
+            escape_control_chars(b"abc\n", ControlCharStyle::Caret),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!("picture".parse(), Ok(ControlCharStyle::Picture));
+        assert!("hex".parse::<ControlCharStyle>().is_err());
+    }
+
+    #[test]
+    fn test_truncate_to_width() {
+        assert_eq!(truncate_to_width(b"abc", 3), (&b"abc"[..], false));
+        assert_eq!(truncate_to_width(b"abc", 2), (&b"ab"[..], true));
+        assert_eq!(truncate_to_width(b"abc", 0), (&b""[..], true));
+        // Multibyte characters are not split, and wide characters take up two
+        // columns
+        let data = "a\u{e5}\u{1f600}b".as_bytes();
+        assert_eq!(display_width(data), 5);
+        assert_eq!(truncate_to_width(data, 2), ("a\u{e5}".as_bytes(), true));
+        assert_eq!(truncate_to_width(data, 3), ("a\u{e5}".as_bytes(), true));
         assert_eq!(
-            merge(&[b"a", b"b"], &[b"c", b"d", b"e"]),
-            MergeResult::Conflict(vec![MergeHunk::Conflict {
-                removes: vec![b"a".to_vec(), b"b".to_vec()],
-                adds: vec![b"c".to_vec(), b"d".to_vec(), b"e".to_vec()]
-            }])
+            truncate_to_width(data, 4),
+            ("a\u{e5}\u{1f600}".as_bytes(), true)
+        );
+        // Combining characters take up no columns, so they stay with the
+        // character before them
+        let data = "ae\u{301}b".as_bytes();
+        assert_eq!(display_width(data), 3);
+        assert_eq!(truncate_to_width(data, 2), ("ae\u{301}".as_bytes(), true));
+        // Invalid UTF-8 is counted byte by byte
+        assert_eq!(display_width(b"a\xe5\xff"), 3);
+        assert_eq!(truncate_to_width(b"a\xe5\xff", 2), (&b"a\xe5"[..], true));
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        let expand = |data: &[u8], column, tab_width| {
+            let (expanded, column) = expand_tabs(data, column, tab_width);
+            (String::from_utf8(expanded.into_owned()).unwrap(), column)
+        };
+        assert_eq!(expand(b"\tx", 0, 8), ("        x".to_string(), 9));
+        assert_eq!(expand(b"abc\tx", 0, 8), ("abc     x".to_string(), 9));
+        assert_eq!(
+            expand(b"abcdefgh\tx", 0, 8),
+            ("abcdefgh        x".to_string(), 17)
+        );
+        assert_eq!(expand(b"a\t\tb", 0, 4), ("a       b".to_string(), 9));
+        // The starting column is taken into account
+        assert_eq!(expand(b"\tx", 3, 4), (" x".to_string(), 5));
+        assert_eq!(expand(b"\tx", 4, 4), ("    x".to_string(), 9));
+        // Characters take up as many columns as they're wide
+        assert_eq!(
+            expand("\u{e5}\tx".as_bytes(), 0, 4),
+            ("\u{e5}   x".to_string(), 5)
+        );
+        assert_eq!(
+            expand("\u{1f600}\tx".as_bytes(), 0, 4),
+            ("\u{1f600}  x".to_string(), 5)
+        );
+        // A newline resets the column
+        assert_eq!(expand(b"ab\n\tx", 5, 4), ("ab\n    x".to_string(), 5));
+        assert_eq!(expand(b"ab\n", 5, 4), ("ab\n".to_string(), 0));
+        assert_eq!(expand(b"ab", 5, 4), ("ab".to_string(), 7));
+        // A tab width of 0 disables expansion
+        assert_eq!(expand(b"a\tb", 0, 0), ("a\tb".to_string(), 3));
+    }
+
+    #[test]
+    fn test_diff_line_truncate() {
+        let mut line = diff(b"abc def\n", b"abc xyz\n").next().unwrap();
+        let original = line.clone();
+        assert!(!line.truncate(10));
+        assert_eq!(line, original);
+        // The removed and the added words both take up space
+        assert!(line.truncate(8));
+        assert_eq!(
+            line.hunks,
+            vec![
+                DiffHunk::Matching(b"abc "),
+                DiffHunk::Different(vec![b"def", b""]),
+                DiffHunk::Different(vec![b"", b""]),
+                DiffHunk::Matching(b""),
+                DiffHunk::Matching(ELLIPSIS),
+                DiffHunk::Matching(b"\n"),
+            ]
+        );
+        assert!(!line.is_unmodified());
+
+        let options = DiffOptions {
+            max_line_width: Some(3),
+            ..Default::default()
+        };
+        let lines = diff_with_options(b"abcdef", b"abcdef", &options).collect_vec();
+        assert_eq!(
+            lines[0].hunks,
+            vec![DiffHunk::Matching(b"ab"), DiffHunk::Matching(ELLIPSIS)]
         );
     }
 }
@@ -23,6 +23,8 @@ pub mod commit_builder;
 pub mod conflicts;
 pub mod dag_walk;
 pub mod diff;
+#[cfg(feature = "serde")]
+pub mod diff_json;
 pub mod file_util;
 pub mod files;
 pub mod git;
@@ -34,6 +36,7 @@ pub mod local_backend;
 mod local_backend_model;
 pub mod lock;
 pub mod matchers;
+pub mod merge_result_encoding;
 pub mod nightly_shims;
 pub mod op_heads_store;
 pub mod op_store;
@@ -44,6 +47,7 @@ pub mod protos;
 pub mod refs;
 pub mod repo;
 pub mod repo_path;
+pub mod resolution_store;
 pub mod revset;
 pub mod revset_graph_iterator;
 pub mod rewrite;
@@ -58,3 +62,5 @@ pub mod tree_builder;
 pub mod view;
 pub mod working_copy;
 pub mod workspace;
+
+pub use files::count_conflicts;
@@ -0,0 +1,305 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A stable, versioned encoding of `MergeResult` for storing on disk.
+//!
+//! The encoding is JSON. Version 1 looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "origins": {
+//!     "removes": [{"commit": "<hex>"}],
+//!     "adds": [{"tree": "<hex>"}, null]
+//!   },
+//!   "result": {"conflict": [
+//!     {"resolved": "<hex>"},
+//!     {"removes": ["<hex>"], "adds": ["<hex>", "<hex>"]}
+//!   ]}
+//! }
+//! ```
+//!
+//! File contents are hex-encoded since they are not necessarily valid UTF-8.
+//! A resolved result is stored as `{"resolved": "<hex>"}`. The origins record
+//! which tree or commit each remove and add came from; every conflict hunk
+//! has its removes and adds in the same order as the origins. An origin is
+//! `null` if it is not known.
+//!
+//! Readers must reject versions newer than `ENCODING_VERSION`. Any change to
+//! the format bumps the version, and older versions remain readable.
+
+use thiserror::Error;
+
+use crate::backend::{CommitId, TreeId};
+use crate::files::{MergeHunk, MergeResult};
+
+/// The version written by `encode()`.
+pub const ENCODING_VERSION: u64 = 1;
+
+/// Where one side of a merge came from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MergeOrigin {
+    Tree(TreeId),
+    Commit(CommitId),
+}
+
+/// A `MergeResult` together with the origin of each of its removes and adds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredMergeResult {
+    pub result: MergeResult,
+    pub remove_origins: Vec<Option<MergeOrigin>>,
+    pub add_origins: Vec<Option<MergeOrigin>>,
+}
+
+impl StoredMergeResult {
+    /// Wraps a result whose origins are unknown.
+    pub fn without_origins(result: MergeResult) -> Self {
+        StoredMergeResult {
+            result,
+            remove_origins: vec![],
+            add_origins: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MergeResultDecodeError {
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unsupported merge result encoding version {0}")]
+    UnsupportedVersion(u64),
+    #[error("Malformed merge result: {0}")]
+    Malformed(String),
+}
+
+/// Encodes the result as JSON in the current version of the format.
+pub fn encode(stored: &StoredMergeResult) -> Vec<u8> {
+    to_json(stored).to_string().into_bytes()
+}
+
+/// Decodes a result written by `encode()` in this or an earlier version.
+pub fn decode(data: &[u8]) -> Result<StoredMergeResult, MergeResultDecodeError> {
+    let json: serde_json::Value = serde_json::from_slice(data)?;
+    from_json(&json)
+}
+
+pub fn to_json(stored: &StoredMergeResult) -> serde_json::Value {
+    serde_json::json!({
+        "version": ENCODING_VERSION,
+        "origins": {
+            "removes": origin_list_to_json(&stored.remove_origins),
+            "adds": origin_list_to_json(&stored.add_origins),
+        },
+        "result": merge_result_to_json(&stored.result),
+    })
+}
+
+pub fn from_json(json: &serde_json::Value) -> Result<StoredMergeResult, MergeResultDecodeError> {
+    let version = json
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .ok_or_else(|| malformed("missing version"))?;
+    if version != 1 {
+        return Err(MergeResultDecodeError::UnsupportedVersion(version));
+    }
+    let origins = json
+        .get("origins")
+        .ok_or_else(|| malformed("missing origins"))?;
+    let result = json
+        .get("result")
+        .ok_or_else(|| malformed("missing result"))?;
+    Ok(StoredMergeResult {
+        result: merge_result_from_json(result)?,
+        remove_origins: origin_list_from_json(origins.get("removes"))?,
+        add_origins: origin_list_from_json(origins.get("adds"))?,
+    })
+}
+
+fn malformed(message: &str) -> MergeResultDecodeError {
+    MergeResultDecodeError::Malformed(message.to_string())
+}
+
+fn origin_list_to_json(origins: &[Option<MergeOrigin>]) -> serde_json::Value {
+    serde_json::Value::Array(
+        origins
+            .iter()
+            .map(|origin| match origin {
+                None => serde_json::Value::Null,
+                Some(MergeOrigin::Tree(id)) => serde_json::json!({ "tree": id.hex() }),
+                Some(MergeOrigin::Commit(id)) => serde_json::json!({ "commit": id.hex() }),
+            })
+            .collect(),
+    )
+}
+
+fn origin_list_from_json(
+    json: Option<&serde_json::Value>,
+) -> Result<Vec<Option<MergeOrigin>>, MergeResultDecodeError> {
+    let list = json
+        .and_then(|json| json.as_array())
+        .ok_or_else(|| malformed("origins must be lists"))?;
+    list.iter()
+        .map(|origin| {
+            if origin.is_null() {
+                Ok(None)
+            } else if let Some(hex) = origin.get("tree") {
+                Ok(Some(MergeOrigin::Tree(TreeId::new(bytes_from_json(hex)?))))
+            } else if let Some(hex) = origin.get("commit") {
+                Ok(Some(MergeOrigin::Commit(CommitId::new(bytes_from_json(
+                    hex,
+                )?))))
+            } else {
+                Err(malformed("unknown origin"))
+            }
+        })
+        .collect()
+}
+
+fn merge_result_to_json(result: &MergeResult) -> serde_json::Value {
+    match result {
+        MergeResult::Resolved(content) => serde_json::json!({ "resolved": hex::encode(content) }),
+        MergeResult::Conflict { hunks, .. } => serde_json::json!({
+            "conflict": hunks.iter().map(merge_hunk_to_json).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn merge_result_from_json(json: &serde_json::Value) -> Result<MergeResult, MergeResultDecodeError> {
+    if let Some(content) = json.get("resolved") {
+        Ok(MergeResult::Resolved(bytes_from_json(content)?))
+    } else if let Some(hunks) = json.get("conflict").and_then(|hunks| hunks.as_array()) {
+        let hunks = hunks
+            .iter()
+            .map(merge_hunk_from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(MergeResult::conflict(hunks))
+    } else {
+        Err(malformed("result must be resolved or a conflict"))
+    }
+}
+
+fn merge_hunk_to_json(hunk: &MergeHunk) -> serde_json::Value {
+    match hunk {
+        MergeHunk::Resolved(content) => serde_json::json!({ "resolved": hex::encode(content) }),
+        MergeHunk::Conflict { removes, adds } => serde_json::json!({
+            "removes": removes.iter().map(hex::encode).collect::<Vec<_>>(),
+            "adds": adds.iter().map(hex::encode).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+fn merge_hunk_from_json(json: &serde_json::Value) -> Result<MergeHunk, MergeResultDecodeError> {
+    if let Some(content) = json.get("resolved") {
+        return Ok(MergeHunk::Resolved(bytes_from_json(content)?));
+    }
+    let parts = |key: &str| -> Result<Vec<Vec<u8>>, MergeResultDecodeError> {
+        json.get(key)
+            .and_then(|parts| parts.as_array())
+            .ok_or_else(|| malformed("conflict hunk must have removes and adds"))?
+            .iter()
+            .map(bytes_from_json)
+            .collect()
+    };
+    Ok(MergeHunk::Conflict {
+        removes: parts("removes")?,
+        adds: parts("adds")?,
+    })
+}
+
+fn bytes_from_json(json: &serde_json::Value) -> Result<Vec<u8>, MergeResultDecodeError> {
+    let hex = json
+        .as_str()
+        .ok_or_else(|| malformed("expected a hex string"))?;
+    hex::decode(hex).map_err(|err| MergeResultDecodeError::Malformed(err.to_string()))
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for StoredMergeResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        to_json(self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for StoredMergeResult {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = serde_json::Value::deserialize(deserializer)?;
+        from_json(&json).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict_result() -> StoredMergeResult {
+        StoredMergeResult {
+            result: MergeResult::conflict(vec![
+                MergeHunk::Resolved(b"a\n".to_vec()),
+                MergeHunk::Conflict {
+                    removes: vec![b"b\n".to_vec()],
+                    adds: vec![b"c\n".to_vec(), b"\xff\x00\n".to_vec()],
+                },
+            ]),
+            remove_origins: vec![Some(MergeOrigin::Commit(CommitId::from_hex("0123")))],
+            add_origins: vec![Some(MergeOrigin::Tree(TreeId::from_hex("abcd"))), None],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let stored = conflict_result();
+        assert_eq!(decode(&encode(&stored)).unwrap(), stored);
+
+        let stored = StoredMergeResult::without_origins(MergeResult::Resolved(b"x\n".to_vec()));
+        assert_eq!(decode(&encode(&stored)).unwrap(), stored);
+
+        let stored = StoredMergeResult::without_origins(MergeResult::Resolved(vec![]));
+        assert_eq!(decode(&encode(&stored)).unwrap(), stored);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(
+            decode(b"not json"),
+            Err(MergeResultDecodeError::Json(_))
+        ));
+        assert!(matches!(
+            decode(br#"{"version": 2}"#),
+            Err(MergeResultDecodeError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            decode(br#"{"origins": {"removes": [], "adds": []}}"#),
+            Err(MergeResultDecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            decode(
+                br#"{"version": 1, "origins": {"removes": [], "adds": []},
+                     "result": {"resolved": "not hex"}}"#
+            ),
+            Err(MergeResultDecodeError::Malformed(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let stored = conflict_result();
+        let json = serde_json::to_string(&stored).unwrap();
+        assert_eq!(
+            serde_json::from_str::<StoredMergeResult>(&json).unwrap(),
+            stored
+        );
+    }
+}
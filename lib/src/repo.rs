@@ -35,6 +35,7 @@ use crate::local_backend::LocalBackend;
 use crate::op_heads_store::{LockedOpHeads, OpHeads, OpHeadsStore};
 use crate::op_store::{BranchTarget, OpStore, OperationId, RefTarget, WorkspaceId};
 use crate::operation::Operation;
+use crate::resolution_store::ResolutionStore;
 use crate::rewrite::DescendantRebaser;
 use crate::settings::{RepoSettings, UserSettings};
 use crate::simple_op_store::SimpleOpStore;
@@ -96,6 +97,7 @@ pub struct ReadonlyRepo {
     operation: Operation,
     settings: RepoSettings,
     index_store: Arc<IndexStore>,
+    resolution_store: Arc<ResolutionStore>,
     index: OnceCell<Arc<ReadonlyIndex>>,
     view: View,
 }
@@ -146,6 +148,10 @@ impl ReadonlyRepo {
         fs::create_dir(&index_path).context(&index_path)?;
         let index_store = Arc::new(IndexStore::init(index_path));
 
+        let resolutions_path = repo_path.join("resolutions");
+        fs::create_dir(&resolutions_path).context(&resolutions_path)?;
+        let resolution_store = Arc::new(ResolutionStore::init(resolutions_path));
+
         let view = View::new(root_view);
         Ok(Arc::new(ReadonlyRepo {
             repo_path,
@@ -155,6 +161,7 @@ impl ReadonlyRepo {
             operation: init_op,
             settings: repo_settings,
             index_store,
+            resolution_store,
             index: OnceCell::new(),
             view,
         }))
@@ -178,6 +185,7 @@ impl ReadonlyRepo {
             op_store: self.op_store.clone(),
             op_heads_store: self.op_heads_store.clone(),
             index_store: self.index_store.clone(),
+            resolution_store: self.resolution_store.clone(),
         }
     }
 
@@ -224,6 +232,10 @@ impl ReadonlyRepo {
         &self.index_store
     }
 
+    pub fn resolution_store(&self) -> &Arc<ResolutionStore> {
+        &self.resolution_store
+    }
+
     pub fn settings(&self) -> &RepoSettings {
         &self.settings
     }
@@ -324,6 +336,7 @@ pub struct RepoLoader {
     op_store: Arc<dyn OpStore>,
     op_heads_store: Arc<OpHeadsStore>,
     index_store: Arc<IndexStore>,
+    resolution_store: Arc<ResolutionStore>,
 }
 
 impl RepoLoader {
@@ -358,6 +371,7 @@ impl RepoLoader {
         let op_store: Arc<dyn OpStore> = Arc::new(SimpleOpStore::load(repo_path.join("op_store")));
         let op_heads_store = Arc::new(OpHeadsStore::load(repo_path.join("op_heads")));
         let index_store = Arc::new(IndexStore::load(repo_path.join("index")));
+        let resolution_store = Arc::new(ResolutionStore::load(repo_path.join("resolutions")));
         Self {
             repo_path: repo_path.to_path_buf(),
             repo_settings,
@@ -365,6 +379,7 @@ impl RepoLoader {
             op_store,
             op_heads_store,
             index_store,
+            resolution_store,
         }
     }
 
@@ -380,6 +395,10 @@ impl RepoLoader {
         &self.index_store
     }
 
+    pub fn resolution_store(&self) -> &Arc<ResolutionStore> {
+        &self.resolution_store
+    }
+
     pub fn op_store(&self) -> &Arc<dyn OpStore> {
         &self.op_store
     }
@@ -425,6 +444,7 @@ impl RepoLoader {
             operation,
             settings: self.repo_settings.clone(),
             index_store: self.index_store.clone(),
+            resolution_store: self.resolution_store.clone(),
             index: OnceCell::with_value(index),
             view,
         };
@@ -440,6 +460,7 @@ impl RepoLoader {
             operation,
             settings: self.repo_settings.clone(),
             index_store: self.index_store.clone(),
+            resolution_store: self.resolution_store.clone(),
             index: OnceCell::new(),
             view,
         };
@@ -0,0 +1,342 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Records how conflict hunks were resolved, so the same resolution can be
+//! applied when the same conflict comes up again (like Git's "rerere").
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use blake2::{Blake2b512, Digest};
+use itertools::Itertools;
+use tempfile::NamedTempFile;
+
+use crate::backend::TreeValue;
+use crate::conflicts;
+use crate::files::{MergeHunk, MergeResult};
+use crate::repo_path::RepoPath;
+use crate::tree::Tree;
+
+/// Identifies a conflict hunk by a hash of its removed and added contents.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResolutionId(String);
+
+impl ResolutionId {
+    /// Hashes the parts of a conflict hunk. The order of the removed parts and
+    /// of the added parts doesn't matter, so the conflict is recognized even if
+    /// the sides come in a different order.
+    pub fn for_hunk(removes: &[Vec<u8>], adds: &[Vec<u8>]) -> Self {
+        let mut hasher = Blake2b512::new();
+        for parts in [removes, adds] {
+            hasher.update((parts.len() as u64).to_le_bytes());
+            for part in parts.iter().sorted() {
+                hasher.update((part.len() as u64).to_le_bytes());
+                hasher.update(part);
+            }
+        }
+        ResolutionId(hex::encode(&hasher.finalize()[..16]))
+    }
+
+    pub fn from_hex(hex: &str) -> Self {
+        ResolutionId(hex.to_string())
+    }
+
+    pub fn hex(&self) -> &str {
+        &self.0
+    }
+}
+
+fn is_resolution_file_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Stores the resolutions of conflict hunks as files named by their
+/// `ResolutionId`.
+pub struct ResolutionStore {
+    dir: PathBuf,
+    /// Paths where recorded resolutions were applied, so the caller can tell
+    /// the user about them.
+    applied: Mutex<Vec<RepoPath>>,
+}
+
+impl Debug for ResolutionStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.debug_struct("ResolutionStore")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+impl ResolutionStore {
+    /// Creates the store in `dir`, which must already exist.
+    pub fn init(dir: PathBuf) -> Self {
+        ResolutionStore::load(dir)
+    }
+
+    /// Loads the store. The directory is created when the first resolution is
+    /// recorded, so repos created before this store existed work too.
+    pub fn load(dir: PathBuf) -> Self {
+        ResolutionStore {
+            dir,
+            applied: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn get(&self, id: &ResolutionId) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::File::open(self.dir.join(id.hex())) {
+            Ok(mut file) => {
+                let mut content = vec![];
+                file.read_to_end(&mut content)?;
+                Ok(Some(content))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn record(&self, id: &ResolutionId, resolution: &[u8]) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut temp_file = NamedTempFile::new_in(&self.dir)?;
+        temp_file.write_all(resolution)?;
+        // A resolution recorded earlier is replaced by the new one
+        temp_file
+            .persist(self.dir.join(id.hex()))
+            .map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    /// Returns the IDs of all recorded resolutions, sorted.
+    pub fn list(&self) -> io::Result<Vec<ResolutionId>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut ids = vec![];
+        for entry in entries {
+            let entry = entry?;
+            // Skip temporary files left behind by interrupted writes
+            if let Some(name) = entry.file_name().to_str() {
+                if is_resolution_file_name(name) {
+                    ids.push(ResolutionId::from_hex(name));
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Forgets a recorded resolution. Returns `false` if there was none.
+    pub fn forget(&self, id: &ResolutionId) -> io::Result<bool> {
+        if !is_resolution_file_name(id.hex()) {
+            return Ok(false);
+        }
+        match std::fs::remove_file(self.dir.join(id.hex())) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replaces every conflict hunk in `hunks` by its recorded resolution.
+    /// Returns `None` unless all of them have one.
+    pub fn resolve_hunks(&self, hunks: &[MergeHunk]) -> io::Result<Option<Vec<u8>>> {
+        let mut content = vec![];
+        for hunk in hunks {
+            match hunk {
+                MergeHunk::Resolved(data) => content.extend_from_slice(data),
+                MergeHunk::Conflict { removes, adds } => {
+                    match self.get(&ResolutionId::for_hunk(removes, adds))? {
+                        Some(resolution) => content.extend(resolution),
+                        None => return Ok(None),
+                    }
+                }
+            }
+        }
+        Ok(Some(content))
+    }
+
+    /// Records what each conflict hunk in `hunks` was resolved to in
+    /// `resolved_content`. Returns the number of recorded resolutions, which is
+    /// zero if the hunks couldn't be matched up with the content.
+    pub fn record_hunk_resolutions(
+        &self,
+        hunks: &[MergeHunk],
+        resolved_content: &[u8],
+    ) -> io::Result<usize> {
+        let resolutions = match find_hunk_resolutions(hunks, resolved_content) {
+            Some(resolutions) => resolutions,
+            None => return Ok(0),
+        };
+        let conflict_hunks = hunks.iter().filter_map(|hunk| match hunk {
+            MergeHunk::Resolved(_) => None,
+            MergeHunk::Conflict { removes, adds } => Some(ResolutionId::for_hunk(removes, adds)),
+        });
+        for (id, resolution) in conflict_hunks.zip_eq(&resolutions) {
+            self.record(&id, resolution)?;
+        }
+        Ok(resolutions.len())
+    }
+
+    /// Records the resolutions of the file conflicts in `old_tree` that are
+    /// regular files in `new_tree`. Returns the number of recorded resolutions.
+    pub fn record_resolved_conflicts(&self, old_tree: &Tree, new_tree: &Tree) -> io::Result<usize> {
+        let store = old_tree.store();
+        let mut num_recorded = 0;
+        for (path, conflict_id) in old_tree.conflicts() {
+            let file_id = match new_tree.path_value(&path) {
+                Some(TreeValue::File { id, .. }) => id,
+                _ => continue,
+            };
+            let conflict = store
+                .read_conflict(&path, &conflict_id)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            let hunks = match conflicts::merge_conflict_contents(store, &path, &conflict) {
+                Some(MergeResult::Conflict { hunks, .. }) => hunks,
+                _ => continue,
+            };
+            let mut content = vec![];
+            store
+                .read_file(&path, &file_id)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+                .read_to_end(&mut content)?;
+            num_recorded += self.record_hunk_resolutions(&hunks, &content)?;
+        }
+        Ok(num_recorded)
+    }
+
+    pub fn note_applied(&self, path: RepoPath) {
+        self.applied.lock().unwrap().push(path);
+    }
+
+    /// Returns the paths where recorded resolutions were applied since the last
+    /// call.
+    pub fn take_applied(&self) -> Vec<RepoPath> {
+        std::mem::take(&mut *self.applied.lock().unwrap())
+    }
+}
+
+/// Finds what each conflict hunk in `hunks` was replaced with in `content`,
+/// using the resolved hunks around them as anchors. Returns `None` if the
+/// resolved hunks can't be found in order, e.g. because they were edited too.
+pub fn find_hunk_resolutions<'a>(hunks: &[MergeHunk], content: &'a [u8]) -> Option<Vec<&'a [u8]>> {
+    // Empty resolved hunks match anywhere, so they don't help to find the
+    // resolutions
+    let hunks = hunks
+        .iter()
+        .filter(|hunk| !matches!(hunk, MergeHunk::Resolved(data) if data.is_empty()))
+        .collect_vec();
+    let mut resolutions = vec![];
+    let mut pos = 0;
+    // Where the current conflict hunk's resolution starts
+    let mut conflict_start = None;
+    for (i, hunk) in hunks.iter().enumerate() {
+        match hunk {
+            MergeHunk::Resolved(data) => {
+                let start = if conflict_start.is_none() {
+                    content[pos..].starts_with(data).then(|| pos)?
+                } else if i == hunks.len() - 1 {
+                    // The last hunk must be at the end
+                    let start = content.len().checked_sub(data.len())?;
+                    (start >= pos && content.ends_with(data)).then(|| start)?
+                } else {
+                    pos + content[pos..]
+                        .windows(data.len())
+                        .position(|window| window == data.as_slice())?
+                };
+                if let Some(conflict_start) = conflict_start.take() {
+                    resolutions.push(&content[conflict_start..start]);
+                }
+                pos = start + data.len();
+            }
+            MergeHunk::Conflict { .. } => {
+                if conflict_start.is_some() {
+                    // Adjacent conflict hunks can't be told apart
+                    return None;
+                }
+                conflict_start = Some(pos);
+            }
+        }
+    }
+    match conflict_start {
+        Some(conflict_start) => resolutions.push(&content[conflict_start..]),
+        None if pos != content.len() => return None,
+        None => {}
+    }
+    Some(resolutions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_id_ignores_part_order() {
+        let a = b"a\n".to_vec();
+        let b = b"b\n".to_vec();
+        let c = b"c\n".to_vec();
+        assert_eq!(
+            ResolutionId::for_hunk(&[a.clone()], &[b.clone(), c.clone()]),
+            ResolutionId::for_hunk(&[a.clone()], &[c.clone(), b.clone()])
+        );
+        assert_ne!(
+            ResolutionId::for_hunk(&[a.clone()], &[b.clone(), c.clone()]),
+            ResolutionId::for_hunk(&[b], &[a, c])
+        );
+    }
+
+    #[test]
+    fn test_find_hunk_resolutions() {
+        let conflict = MergeHunk::Conflict {
+            removes: vec![b"b\n".to_vec()],
+            adds: vec![b"a\n".to_vec(), b"c\n".to_vec()],
+        };
+        // Only a conflict
+        assert_eq!(
+            find_hunk_resolutions(&[conflict.clone()], b"x\n"),
+            Some(vec![&b"x\n"[..]])
+        );
+        // Conflicts between resolved hunks
+        let hunks = vec![
+            MergeHunk::Resolved(b"1\n".to_vec()),
+            conflict.clone(),
+            MergeHunk::Resolved(b"2\n".to_vec()),
+            conflict.clone(),
+            MergeHunk::Resolved(b"3\n".to_vec()),
+        ];
+        assert_eq!(
+            find_hunk_resolutions(&hunks, b"1\nx\n2\n3\n"),
+            Some(vec![&b"x\n"[..], &b""[..]])
+        );
+        // The resolved hunks were edited
+        assert_eq!(find_hunk_resolutions(&hunks, b"1\nx\n3\n"), None);
+        assert_eq!(find_hunk_resolutions(&hunks, b"0\nx\n2\ny\n3\n"), None);
+        assert_eq!(find_hunk_resolutions(&hunks, b"1\nx\n2\ny\n3\n4\n"), None);
+        // Empty resolved hunks are skipped
+        let hunks = vec![
+            MergeHunk::Resolved(vec![]),
+            conflict.clone(),
+            MergeHunk::Resolved(b"1\n".to_vec()),
+            conflict,
+            MergeHunk::Resolved(vec![]),
+        ];
+        assert_eq!(
+            find_hunk_resolutions(&hunks, b"x\n1\ny\n"),
+            Some(vec![&b"x\n"[..], &b"y\n"[..]])
+        );
+    }
+}
@@ -21,11 +21,13 @@ use crate::commit::Commit;
 use crate::commit_builder::CommitBuilder;
 use crate::dag_walk;
 use crate::op_store::RefTarget;
-use crate::repo::{MutableRepo, RepoRef};
+use crate::repo::{MutableRepo, ReadonlyRepo, RepoRef};
 use crate::repo_path::RepoPath;
 use crate::revset::RevsetExpression;
 use crate::settings::UserSettings;
-use crate::tree::{merge_trees, Tree};
+use crate::tree::{
+    merge_trees, merge_trees_with_options, merge_trees_with_virtual_base, Tree, TreeMergeOptions,
+};
 use crate::view::RefName;
 
 pub fn merge_commit_trees(repo: RepoRef, commits: &[Commit]) -> Tree {
@@ -47,14 +49,47 @@ pub fn merge_commit_trees(repo: RepoRef, commits: &[Commit]) -> Tree {
                 .iter()
                 .map(|id| store.get_commit(id).unwrap())
                 .collect_vec();
+            // With several merge bases, they're merged into a virtual base, which
+            // can have conflicts of its own
             let ancestor_tree = merge_commit_trees(repo, &ancestors);
-            let new_tree_id = merge_trees(&new_tree, &ancestor_tree, &other_commit.tree()).unwrap();
+            let new_tree_id = if ancestors.len() > 1 {
+                merge_trees_with_virtual_base(
+                    &new_tree,
+                    &ancestor_tree,
+                    &other_commit.tree(),
+                    &TreeMergeOptions::default(),
+                )
+                .unwrap()
+            } else {
+                merge_trees(&new_tree, &ancestor_tree, &other_commit.tree()).unwrap()
+            };
             new_tree = store.get_tree(&RepoPath::root(), &new_tree_id).unwrap();
         }
         new_tree
     }
 }
 
+/// Returns the merge options from `settings`, with the repo's recorded
+/// conflict resolutions if `merge.reuse-resolutions` is enabled.
+///
+/// `rebase_commit()` and `back_out_commit()` can't report invalid settings,
+/// so they merge with the default options instead. Callers should check the
+/// settings with this function first.
+pub fn merge_options_for_repo(
+    settings: &UserSettings,
+    repo: &ReadonlyRepo,
+) -> Result<TreeMergeOptions, config::ConfigError> {
+    let mut options = TreeMergeOptions::from_settings(settings)?;
+    if settings
+        .config()
+        .get_bool("merge.reuse-resolutions")
+        .unwrap_or(false)
+    {
+        options.resolution_store = Some(repo.resolution_store().clone());
+    }
+    Ok(options)
+}
+
 pub fn rebase_commit(
     settings: &UserSettings,
     mut_repo: &mut MutableRepo,
@@ -77,7 +112,13 @@ pub fn rebase_commit(
         let old_base_tree = merge_commit_trees(mut_repo.as_repo_ref(), &old_parents);
         let new_base_tree = merge_commit_trees(mut_repo.as_repo_ref(), new_parents);
         // TODO: pass in labels for the merge parts
-        merge_trees(&new_base_tree, &old_base_tree, &old_commit.tree()).unwrap()
+        merge_trees_with_options(
+            &new_base_tree,
+            &old_base_tree,
+            &old_commit.tree(),
+            &merge_options_for_repo(settings, mut_repo.base_repo()).unwrap_or_default(),
+        )
+        .unwrap()
     };
     let new_parent_ids = new_parents
         .iter()
@@ -98,7 +139,13 @@ pub fn back_out_commit(
     let old_base_tree = merge_commit_trees(mut_repo.as_repo_ref(), &old_commit.parents());
     let new_base_tree = merge_commit_trees(mut_repo.as_repo_ref(), new_parents);
     // TODO: pass in labels for the merge parts
-    let new_tree_id = merge_trees(&new_base_tree, &old_commit.tree(), &old_base_tree).unwrap();
+    let new_tree_id = merge_trees_with_options(
+        &new_base_tree,
+        &old_commit.tree(),
+        &old_base_tree,
+        &merge_options_for_repo(settings, mut_repo.base_repo()).unwrap_or_default(),
+    )
+    .unwrap();
     let new_parent_ids = new_parents
         .iter()
         .map(|commit| commit.id().clone())
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fmt::Display;
 use std::path::Path;
+use std::str::FromStr;
 
 use chrono::DateTime;
 
@@ -146,4 +148,21 @@ impl UserSettings {
     pub fn config(&self) -> &config::Config {
         &self.config
     }
+
+    /// Parses the string at `key`, or returns `None` if it isn't set. An
+    /// invalid value is an error that says what's wrong with it.
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, config::ConfigError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.config.get_string(key) {
+            Ok(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|err| config::ConfigError::Message(format!("{key}: {err}"))),
+            Err(config::ConfigError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 }
@@ -12,17 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Read;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::backend;
 use crate::backend::{
     Backend, BackendResult, CommitId, Conflict, ConflictId, FileId, SymlinkId, TreeId,
 };
 use crate::commit::Commit;
+use crate::files::{MergeHunk, MergeResult};
+use crate::nightly_shims::BTreeMapExt;
 use crate::repo_path::RepoPath;
-use crate::tree::Tree;
+use crate::tree::{FileMergeKey, Tree};
 use crate::tree_builder::TreeBuilder;
 
 /// Wraps the low-level backend and makes it return more convenient types. Also
@@ -32,6 +34,7 @@ pub struct Store {
     backend: Box<dyn Backend>,
     commit_cache: RwLock<HashMap<CommitId, Arc<backend::Commit>>>,
     tree_cache: RwLock<HashMap<(RepoPath, TreeId), Arc<backend::Tree>>>,
+    merge_cache: Mutex<MergeCache>,
 }
 
 impl Store {
@@ -40,6 +43,7 @@ impl Store {
             backend,
             commit_cache: Default::default(),
             tree_cache: Default::default(),
+            merge_cache: Mutex::new(MergeCache::new(MERGE_CACHE_CAPACITY)),
         })
     }
 
@@ -126,6 +130,24 @@ impl Store {
         self.backend.write_file(path, contents)
     }
 
+    /// Returns the result of an earlier merge of the same file contents, e.g.
+    /// when rebasing a stack of commits that all conflict in the same way.
+    pub fn get_cached_merge(&self, key: &FileMergeKey) -> Option<Arc<MergeResult>> {
+        self.merge_cache.lock().unwrap().get(key)
+    }
+
+    pub fn cache_merge(&self, key: FileMergeKey, merge_result: MergeResult) -> Arc<MergeResult> {
+        let data = Arc::new(merge_result);
+        self.merge_cache.lock().unwrap().insert(key, data.clone());
+        data
+    }
+
+    /// Drops the cached merge results, e.g. to free their memory after a
+    /// large rebase.
+    pub fn clear_merge_cache(&self) {
+        self.merge_cache.lock().unwrap().clear();
+    }
+
     pub fn read_symlink(&self, path: &RepoPath, id: &SymlinkId) -> BackendResult<String> {
         self.backend.read_symlink(path, id)
     }
@@ -150,3 +172,129 @@ impl Store {
         TreeBuilder::new(self.clone(), base_tree_id)
     }
 }
+
+/// The maximum total size in bytes of the contents in a `Store`'s merge cache.
+const MERGE_CACHE_CAPACITY: usize = 64 << 20;
+
+/// Merge results with up to `capacity` bytes of content. When a new result
+/// doesn't fit, the least recently used ones are evicted.
+#[derive(Debug)]
+struct MergeCache {
+    capacity: usize,
+    size: usize,
+    /// The results and when they were last used
+    entries: HashMap<FileMergeKey, (Arc<MergeResult>, u64)>,
+    /// The keys of `entries` by when they were last used
+    recency: BTreeMap<u64, FileMergeKey>,
+    next_use: u64,
+}
+
+impl MergeCache {
+    fn new(capacity: usize) -> Self {
+        MergeCache {
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    fn get(&mut self, key: &FileMergeKey) -> Option<Arc<MergeResult>> {
+        let (merge_result, last_use) = self.entries.get_mut(key)?;
+        let key = self.recency.remove(last_use).unwrap();
+        *last_use = self.next_use;
+        self.recency.insert(self.next_use, key);
+        self.next_use += 1;
+        Some(merge_result.clone())
+    }
+
+    fn insert(&mut self, key: FileMergeKey, merge_result: Arc<MergeResult>) {
+        if let Some((old_result, last_use)) = self.entries.remove(&key) {
+            self.recency.remove(&last_use);
+            self.size -= merge_result_size(&old_result);
+        }
+        let size = merge_result_size(&merge_result);
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let evicted_key = self.recency.pop_first_value().unwrap();
+            let (evicted_result, _) = self.entries.remove(&evicted_key).unwrap();
+            self.size -= merge_result_size(&evicted_result);
+        }
+        self.size += size;
+        self.recency.insert(self.next_use, key.clone());
+        self.entries.insert(key, (merge_result, self.next_use));
+        self.next_use += 1;
+    }
+
+    fn clear(&mut self) {
+        self.size = 0;
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+/// The number of bytes of content in `merge_result`.
+fn merge_result_size(merge_result: &MergeResult) -> usize {
+    match merge_result {
+        MergeResult::Resolved(content) => content.len(),
+        MergeResult::Conflict { hunks, .. } => hunks
+            .iter()
+            .map(|hunk| match hunk {
+                MergeHunk::Resolved(content) => content.len(),
+                MergeHunk::Conflict { removes, adds } => {
+                    removes.iter().chain(adds).map(Vec::len).sum()
+                }
+            })
+            .sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FileId;
+    use crate::files::MergeOptions;
+
+    fn key(id: u8) -> FileMergeKey {
+        FileMergeKey::new(
+            vec![],
+            vec![FileId::new(vec![id])],
+            &MergeOptions::default(),
+        )
+    }
+
+    fn resolved(size: usize) -> Arc<MergeResult> {
+        Arc::new(MergeResult::Resolved(vec![b'a'; size]))
+    }
+
+    #[test]
+    fn test_merge_cache_evicts_least_recently_used() {
+        let mut cache = MergeCache::new(10);
+        cache.insert(key(1), resolved(4));
+        cache.insert(key(2), resolved(4));
+        // Using the first result makes the second one the least recently used
+        assert!(cache.get(&key(1)).is_some());
+        cache.insert(key(3), resolved(4));
+        assert!(cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(2)).is_none());
+        assert!(cache.get(&key(3)).is_some());
+        assert_eq!(cache.size, 8);
+
+        // Replacing a result doesn't count it twice
+        cache.insert(key(3), resolved(6));
+        assert_eq!(cache.size, 10);
+        assert!(cache.get(&key(1)).is_some());
+
+        // Results bigger than the whole cache aren't cached
+        cache.insert(key(4), resolved(11));
+        assert!(cache.get(&key(4)).is_none());
+        assert_eq!(cache.size, 10);
+
+        cache.clear();
+        assert!(cache.get(&key(1)).is_none());
+        assert_eq!(cache.size, 0);
+    }
+}
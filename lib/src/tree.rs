@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::{Debug, Error, Formatter};
 use std::io::Read;
 use std::iter::Peekable;
@@ -26,11 +27,15 @@ use crate::backend::{
     BackendError, Conflict, ConflictId, ConflictPart, FileId, TreeEntriesNonRecursiveIterator,
     TreeEntry, TreeId, TreeValue,
 };
-use crate::files::MergeResult;
+use crate::files::{
+    FileMergeStrategies, FileMergeStrategy, MergeOptions, MergeResult, MergeStrategy,
+};
 use crate::matchers::{EverythingMatcher, Matcher};
 use crate::repo_path::{RepoPath, RepoPathComponent, RepoPathJoin};
+use crate::resolution_store::ResolutionStore;
+use crate::settings::UserSettings;
 use crate::store::Store;
-use crate::{backend, files};
+use crate::{backend, conflicts, files};
 
 #[derive(Debug, Error)]
 pub enum TreeMergeError {
@@ -39,6 +44,11 @@ pub enum TreeMergeError {
         source: std::io::Error,
         file_id: FileId,
     },
+    #[error("Failed to read recorded resolutions for {}", .path.to_internal_file_string())]
+    ResolutionStoreError {
+        source: std::io::Error,
+        path: RepoPath,
+    },
     #[error("Backend error: {0}")]
     BackendError(#[from] BackendError),
 }
@@ -514,6 +524,159 @@ pub fn merge_trees(
     side1_tree: &Tree,
     base_tree: &Tree,
     side2_tree: &Tree,
+) -> Result<TreeId, TreeMergeError> {
+    merge_trees_with_options(
+        side1_tree,
+        base_tree,
+        side2_tree,
+        &TreeMergeOptions::default(),
+    )
+}
+
+/// Options for `merge_trees_with_options()`.
+#[derive(Clone, Debug, Default)]
+pub struct TreeMergeOptions {
+    /// How the contents of files are merged.
+    pub file_options: MergeOptions,
+    /// Where to look up recorded resolutions for conflicts. Conflicts are left
+    /// as they are when this is `None`.
+    pub resolution_store: Option<Arc<ResolutionStore>>,
+    /// How to resolve conflicts in files matching the patterns.
+    pub file_strategies: FileMergeStrategies,
+    /// Whether to follow files that one side renamed, so the other side's
+    /// changes to them are applied at the new path.
+    pub detect_renames: bool,
+}
+
+impl TreeMergeOptions {
+    pub fn from_settings(settings: &UserSettings) -> Result<Self, config::ConfigError> {
+        let defaults = TreeMergeOptions::default();
+        Ok(TreeMergeOptions {
+            file_options: MergeOptions::from_settings(settings)?,
+            file_strategies: FileMergeStrategies::from_settings(settings),
+            detect_renames: settings
+                .config()
+                .get_bool("merge.detect-renames")
+                .unwrap_or(defaults.detect_renames),
+            ..defaults
+        })
+    }
+}
+
+/// Like `merge_trees()`, but merges the trees using `options`.
+pub fn merge_trees_with_options(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+) -> Result<TreeId, TreeMergeError> {
+    let mut ignore_progress = |_: &MergeProgress| {};
+    let mut progress = MergeProgressTracker::new(0, &mut ignore_progress);
+    merge_trees_tracked(side1_tree, base_tree, side2_tree, options, &mut progress)
+}
+
+/// The progress of `merge_trees_with_progress()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeProgress<'a> {
+    /// The number of files merged so far
+    pub merged: usize,
+    /// The number of files that both sides changed in different ways, which
+    /// are the ones whose contents may need to be merged
+    pub total: usize,
+    /// The file that's being merged
+    pub path: &'a RepoPath,
+}
+
+/// Like `merge_trees_with_options()`, but calls `progress` before merging each
+/// file that both sides changed. Subtrees that are unchanged on either side
+/// are taken as they are without being visited, so merging large trees with
+/// few changes is quick.
+pub fn merge_trees_with_progress(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+    progress: &mut dyn FnMut(&MergeProgress),
+) -> Result<TreeId, TreeMergeError> {
+    let total = count_files_changed_by_both(side1_tree, base_tree, side2_tree);
+    let mut progress = MergeProgressTracker::new(total, progress);
+    merge_trees_tracked(side1_tree, base_tree, side2_tree, options, &mut progress)
+}
+
+struct MergeProgressTracker<'a> {
+    merged: usize,
+    total: usize,
+    callback: &'a mut dyn FnMut(&MergeProgress),
+    /// Files that one side deleted and the other side changed, which are the
+    /// ones where following a rename can help
+    delete_modify_conflicts: Vec<RepoPath>,
+}
+
+impl<'a> MergeProgressTracker<'a> {
+    fn new(total: usize, callback: &'a mut dyn FnMut(&MergeProgress)) -> Self {
+        MergeProgressTracker {
+            merged: 0,
+            total,
+            callback,
+            delete_modify_conflicts: vec![],
+        }
+    }
+
+    fn merging_file(&mut self, path: &RepoPath) {
+        (self.callback)(&MergeProgress {
+            merged: self.merged,
+            total: self.total,
+            path,
+        });
+        self.merged += 1;
+    }
+}
+
+/// The number of files that the two sides changed in different ways. Only the
+/// subtrees that changed are visited.
+fn count_files_changed_by_both(side1_tree: &Tree, base_tree: &Tree, side2_tree: &Tree) -> usize {
+    if base_tree.id() == side1_tree.id()
+        || base_tree.id() == side2_tree.id()
+        || side1_tree.id() == side2_tree.id()
+    {
+        return 0;
+    }
+    let side1_changes: HashMap<RepoPath, Option<TreeValue>> = base_tree
+        .diff(side1_tree, &EverythingMatcher)
+        .map(|(path, diff)| (path, diff.into_options().1))
+        .collect();
+    base_tree
+        .diff(side2_tree, &EverythingMatcher)
+        .filter(|(path, diff)| {
+            side1_changes.get(path).map_or(false, |side1_value| {
+                side1_value.as_ref() != diff.as_options().1
+            })
+        })
+        .count()
+}
+
+fn merge_trees_tracked(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+    progress: &mut MergeProgressTracker,
+) -> Result<TreeId, TreeMergeError> {
+    if options.detect_renames && base_tree.dir().is_root() {
+        let (tree_id, _renames) =
+            merge_trees_with_renames_tracked(side1_tree, base_tree, side2_tree, options, progress)?;
+        return Ok(tree_id);
+    }
+    merge_trees_by_path(side1_tree, base_tree, side2_tree, options, progress)
+}
+
+/// Merges the trees path by path, without following renames.
+fn merge_trees_by_path(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+    progress: &mut MergeProgressTracker,
 ) -> Result<TreeId, TreeMergeError> {
     let store = base_tree.store();
     let dir = base_tree.dir();
@@ -545,8 +708,15 @@ pub fn merge_trees(
             // value
         } else {
             // The two sides changed in different ways
-            let new_value =
-                merge_tree_value(store, dir, &basename, maybe_base, maybe_side1, maybe_side2)?;
+            let new_value = merge_tree_value(
+                store,
+                &dir.join(&basename),
+                maybe_base,
+                maybe_side1,
+                maybe_side2,
+                options,
+                progress,
+            )?;
             match new_value {
                 None => new_tree.remove(&basename),
                 Some(value) => new_tree.set(basename, value),
@@ -556,6 +726,261 @@ pub fn merge_trees(
     Ok(store.write_tree(dir, &new_tree)?)
 }
 
+/// The minimum `files::similarity()` between a deleted file and an added file
+/// for them to be considered a rename.
+pub const RENAME_SIMILARITY_THRESHOLD: u32 = 50;
+
+/// A rename that `merge_trees_with_renames()` took into account: one side
+/// renamed `source` to `target` and the other side changed `source`, so the
+/// change was applied at `target`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergedRename {
+    pub source: RepoPath,
+    pub target: RepoPath,
+}
+
+impl MergedRename {
+    /// A message about the rename to show to the user.
+    pub fn note(&self) -> String {
+        format!(
+            "Applied changes to {} to {}, where the other side renamed it",
+            self.source.to_internal_file_string(),
+            self.target.to_internal_file_string()
+        )
+    }
+}
+
+/// Like `merge_trees_with_options()`, but follows renames even if
+/// `options.detect_renames` is off, and also returns the renames it followed.
+/// A file that one side deleted and the other side changed is a delete/modify
+/// conflict when merging path by path. If the deleting side added a file that
+/// is at least `RENAME_SIMILARITY_THRESHOLD` percent similar to the base
+/// content, that was a rename, so the file is instead merged at the new path
+/// with the base and the changed file. Files that both sides renamed merge by
+/// path, so a file renamed to different paths ends up at both. The trees must
+/// be root trees.
+pub fn merge_trees_with_renames(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+) -> Result<(TreeId, Vec<MergedRename>), TreeMergeError> {
+    let mut ignore_progress = |_: &MergeProgress| {};
+    let mut progress = MergeProgressTracker::new(0, &mut ignore_progress);
+    merge_trees_with_renames_tracked(side1_tree, base_tree, side2_tree, options, &mut progress)
+}
+
+fn merge_trees_with_renames_tracked(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+    progress: &mut MergeProgressTracker,
+) -> Result<(TreeId, Vec<MergedRename>), TreeMergeError> {
+    let store = base_tree.store();
+    assert!(base_tree.dir().is_root());
+    let merged_tree_id = merge_trees_by_path(side1_tree, base_tree, side2_tree, options, progress)?;
+    let delete_modify_conflicts = std::mem::take(&mut progress.delete_modify_conflicts);
+    if delete_modify_conflicts.is_empty() {
+        return Ok((merged_tree_id, vec![]));
+    }
+    let (side1_sources, side2_sources): (Vec<_>, Vec<_>) = delete_modify_conflicts
+        .into_iter()
+        .partition(|path| side1_tree.path_value(path).is_none());
+    let mut tree_builder = store.tree_builder(merged_tree_id);
+    let mut merged_renames = vec![];
+    for (renamed_by_side1, sources) in [(true, side1_sources), (false, side2_sources)] {
+        let (renaming_tree, other_tree) = if renamed_by_side1 {
+            (side1_tree, side2_tree)
+        } else {
+            (side2_tree, side1_tree)
+        };
+        for (source, target) in find_renames(base_tree, renaming_tree, other_tree, &sources)? {
+            if let Some(new_value) = merge_renamed_file(
+                base_tree,
+                &source,
+                &target,
+                renamed_by_side1,
+                side1_tree,
+                side2_tree,
+                options,
+            )? {
+                tree_builder.remove(source.clone());
+                tree_builder.set(target.clone(), new_value);
+                merged_renames.push(MergedRename { source, target });
+            }
+        }
+    }
+    Ok((tree_builder.write_tree(), merged_renames))
+}
+
+/// Finds where `side_tree` renamed the files at `sources`, which it deleted,
+/// as pairs of the old and the new path. Each source is paired with the most
+/// similar file that `side_tree` added and `other_tree` doesn't have, if any
+/// is similar enough. Only the sources and the added files are read.
+fn find_renames(
+    base_tree: &Tree,
+    side_tree: &Tree,
+    other_tree: &Tree,
+    sources: &[RepoPath],
+) -> Result<Vec<(RepoPath, RepoPath)>, TreeMergeError> {
+    if sources.is_empty() {
+        return Ok(vec![]);
+    }
+    let store = base_tree.store();
+    let added = base_tree
+        .diff(side_tree, &EverythingMatcher)
+        .filter_map(|(path, diff)| match diff {
+            Diff::Added(TreeValue::File { id, .. }) if other_tree.path_value(&path).is_none() => {
+                Some((path, id))
+            }
+            _ => None,
+        })
+        .collect_vec();
+    if added.is_empty() {
+        return Ok(vec![]);
+    }
+    let added_contents = added
+        .iter()
+        .map(|(path, id)| read_file_content(store, path, id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut renames = vec![];
+    let mut used = vec![false; added.len()];
+    for source in sources {
+        let source_id = match base_tree.path_value(source) {
+            Some(TreeValue::File { id, .. }) => id,
+            _ => continue,
+        };
+        let source_content = read_file_content(store, source, &source_id)?;
+        let best = added_contents
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !used[*index])
+            .map(|(index, content)| (index, files::similarity(&source_content, content)))
+            .filter(|(_, similarity)| *similarity >= RENAME_SIMILARITY_THRESHOLD)
+            .max_by_key(|(_, similarity)| *similarity);
+        if let Some((index, _)) = best {
+            used[index] = true;
+            renames.push((source.clone(), added[index].0.clone()));
+        }
+    }
+    Ok(renames)
+}
+
+/// Merges a file that one side renamed from `source` to `target` and the
+/// other side may have changed at `source`. Returns `None` if the other side
+/// didn't change the file or deleted it, or if it has something at `target`
+/// itself, since the merge by path already handles those cases.
+fn merge_renamed_file(
+    base_tree: &Tree,
+    source: &RepoPath,
+    target: &RepoPath,
+    renamed_by_side1: bool,
+    side1_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+) -> Result<Option<TreeValue>, TreeMergeError> {
+    let (renaming_tree, other_tree) = if renamed_by_side1 {
+        (side1_tree, side2_tree)
+    } else {
+        (side2_tree, side1_tree)
+    };
+    let base_value = base_tree.path_value(source);
+    let changed_value = other_tree.path_value(source);
+    if !matches!(changed_value, Some(TreeValue::File { .. }))
+        || changed_value == base_value
+        || other_tree.path_value(target).is_some()
+    {
+        return Ok(None);
+    }
+    let renamed_value = renaming_tree.path_value(target);
+    let (side1_value, side2_value) = if renamed_by_side1 {
+        (renamed_value, changed_value)
+    } else {
+        (changed_value, renamed_value)
+    };
+    let mut ignore_progress = |_: &MergeProgress| {};
+    merge_tree_value(
+        base_tree.store(),
+        target,
+        base_value.as_ref(),
+        side1_value.as_ref(),
+        side2_value.as_ref(),
+        options,
+        &mut MergeProgressTracker::new(0, &mut ignore_progress),
+    )
+}
+
+fn read_file_content(
+    store: &Store,
+    path: &RepoPath,
+    id: &FileId,
+) -> Result<Vec<u8>, TreeMergeError> {
+    let mut content = vec![];
+    store
+        .read_file(path, id)?
+        .read_to_end(&mut content)
+        .map_err(|err| TreeMergeError::ReadError {
+            source: err,
+            file_id: id.clone(),
+        })?;
+    Ok(content)
+}
+
+/// Like `merge_trees_with_options()`, but for a `base_tree` that's a virtual
+/// merge base, made by merging several merge bases, so it can have conflicts.
+/// Files that conflict in the merge and are a file conflict in the base are
+/// merged again with the base's conflict materialized with conflict markers,
+/// like Git's recursive merge does. That resolves regions where both sides
+/// resolved the base's conflict in the same way. Files that still conflict
+/// keep the conflict from the regular merge.
+pub fn merge_trees_with_virtual_base(
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &TreeMergeOptions,
+) -> Result<TreeId, TreeMergeError> {
+    let store = base_tree.store();
+    let merged_tree_id = merge_trees_with_options(side1_tree, base_tree, side2_tree, options)?;
+    let merged_tree = store.get_tree(base_tree.dir(), &merged_tree_id)?;
+    let mut tree_builder = store.tree_builder(merged_tree_id);
+    for (path, _) in merged_tree.conflicts() {
+        let base_conflict = match base_tree.path_value(&path) {
+            Some(TreeValue::Conflict(id)) => store.read_conflict(&path, &id)?,
+            _ => continue,
+        };
+        let executable = match read_file_conflict(store, &path, &base_conflict)? {
+            Some(FileConflictContents {
+                executable: Ok(executable),
+                ..
+            }) => executable,
+            _ => continue,
+        };
+        let mut base_content = vec![];
+        conflicts::materialize_conflict(store, &path, &base_conflict, &mut base_content).unwrap();
+        let base_value = TreeValue::File {
+            id: store.write_file(&path, &mut base_content.as_slice())?,
+            executable,
+        };
+        let mut ignore_progress = |_: &MergeProgress| {};
+        let new_value = merge_tree_value(
+            store,
+            &path,
+            Some(&base_value),
+            side1_tree.path_value(&path).as_ref(),
+            side2_tree.path_value(&path).as_ref(),
+            options,
+            &mut MergeProgressTracker::new(0, &mut ignore_progress),
+        )?;
+        match new_value {
+            Some(TreeValue::Conflict(_)) => {}
+            Some(value) => tree_builder.set(path, value),
+            None => tree_builder.remove(path),
+        }
+    }
+    Ok(tree_builder.write_tree())
+}
+
 /// Returns `Some(TreeId)` if this is a directory or missing. If it's missing,
 /// we treat it as an empty tree.
 fn maybe_tree_id<'id>(
@@ -571,11 +996,12 @@ fn maybe_tree_id<'id>(
 
 fn merge_tree_value(
     store: &Arc<Store>,
-    dir: &RepoPath,
-    basename: &RepoPathComponent,
+    path: &RepoPath,
     maybe_base: Option<&TreeValue>,
     maybe_side1: Option<&TreeValue>,
     maybe_side2: Option<&TreeValue>,
+    options: &TreeMergeOptions,
+    progress: &mut MergeProgressTracker,
 ) -> Result<Option<TreeValue>, TreeMergeError> {
     // Resolve non-trivial conflicts:
     //   * resolve tree conflicts by recursing
@@ -589,11 +1015,11 @@ fn merge_tree_value(
     let side2_tree_id = maybe_tree_id(maybe_side2, empty_tree_id);
     Ok(match (base_tree_id, side1_tree_id, side2_tree_id) {
         (Some(base_id), Some(side1_id), Some(side2_id)) => {
-            let subdir = dir.join(basename);
-            let base_tree = store.get_tree(&subdir, base_id)?;
-            let side1_tree = store.get_tree(&subdir, side1_id)?;
-            let side2_tree = store.get_tree(&subdir, side2_id)?;
-            let merged_tree_id = merge_trees(&side1_tree, &base_tree, &side2_tree)?;
+            let base_tree = store.get_tree(path, base_id)?;
+            let side1_tree = store.get_tree(path, side1_id)?;
+            let side2_tree = store.get_tree(path, side2_id)?;
+            let merged_tree_id =
+                merge_trees_by_path(&side1_tree, &base_tree, &side2_tree, options, progress)?;
             if merged_tree_id == *empty_tree_id {
                 None
             } else {
@@ -601,6 +1027,13 @@ fn merge_tree_value(
             }
         }
         _ => {
+            let is_file = |value: Option<&TreeValue>| matches!(value, Some(TreeValue::File { .. }));
+            if is_file(maybe_base)
+                && (is_file(maybe_side1) && maybe_side2.is_none()
+                    || maybe_side1.is_none() && is_file(maybe_side2))
+            {
+                progress.delete_modify_conflicts.push(path.clone());
+            }
             // Start by creating a Conflict object. Conflicts can cleanly represent a single
             // resolved state, the absence of a state, or a conflicted state.
             let mut conflict = Conflict::default();
@@ -619,8 +1052,8 @@ fn merge_tree_value(
                     value: side2.clone(),
                 });
             }
-            let filename = dir.join(basename);
-            let conflict = simplify_conflict(store, &filename, &conflict)?;
+            progress.merging_file(path);
+            let conflict = simplify_conflict(store, path, &conflict)?;
             if conflict.adds.is_empty() {
                 // If there are no values to add, then the path doesn't exist
                 return Ok(None);
@@ -630,103 +1063,339 @@ fn merge_tree_value(
                 return Ok(Some(conflict.adds[0].value.clone()));
             }
             if let Some((merged_content, executable)) =
-                try_resolve_file_conflict(store, &filename, &conflict)?
+                try_resolve_file_conflict(store, path, &conflict, options)?
             {
-                let id = store.write_file(&filename, &mut merged_content.as_slice())?;
-                Some(TreeValue::File { id, executable })
+                let id = store.write_file(path, &mut merged_content.as_slice())?;
+                match executable {
+                    Ok(executable) => Some(TreeValue::File { id, executable }),
+                    Err(mode_conflict) => {
+                        // Keep the merged content, so only the executable bits
+                        // are left to resolve
+                        let conflict_id =
+                            store.write_conflict(path, &mode_conflict.to_conflict(&id))?;
+                        Some(TreeValue::Conflict(conflict_id))
+                    }
+                }
             } else {
-                let conflict_id = store.write_conflict(&filename, &conflict)?;
+                let conflict_id = store.write_conflict(path, &conflict)?;
                 Some(TreeValue::Conflict(conflict_id))
             }
         }
     })
 }
 
-fn try_resolve_file_conflict(
+/// The contents of the removed and added files of a conflict, and whether the
+/// merged file should be executable.
+pub struct FileConflictContents {
+    pub removes: Vec<Vec<u8>>,
+    pub adds: Vec<Vec<u8>>,
+    /// The merged executable bit, or the conflict between the sides' bits
+    pub executable: Result<bool, ModeConflict>,
+}
+
+/// The executable bits of the parts of a file conflict whose sides changed
+/// the bit in different ways, for example because they added the file with
+/// different bits. The mode is merged separately from the contents, so this
+/// can be resolved without touching the contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeConflict {
+    pub removes: Vec<bool>,
+    pub adds: Vec<bool>,
+}
+
+impl ModeConflict {
+    /// A message about the conflict to show to the user.
+    pub fn note(&self) -> &'static str {
+        "sides disagree on executable bit"
+    }
+
+    /// The executable bit of the side that `strategy` takes. `Union` makes the
+    /// file executable if any side is.
+    pub fn resolve_with_strategy(&self, strategy: MergeStrategy) -> bool {
+        match strategy {
+            MergeStrategy::Ours => self.adds[0],
+            MergeStrategy::Theirs => self.adds[self.adds.len() - 1],
+            MergeStrategy::Union => self.adds.iter().any(|executable| *executable),
+        }
+    }
+
+    /// A conflict between files with the same content, `id`, and the
+    /// conflicting executable bits.
+    fn to_conflict(&self, id: &FileId) -> Conflict {
+        let to_parts = |bits: &[bool]| {
+            bits.iter()
+                .map(|executable| ConflictPart {
+                    value: TreeValue::File {
+                        id: id.clone(),
+                        executable: *executable,
+                    },
+                })
+                .collect_vec()
+        };
+        Conflict {
+            removes: to_parts(&self.removes),
+            adds: to_parts(&self.adds),
+        }
+    }
+}
+
+/// Merges the executable bits of the parts of a file conflict the way
+/// contents are merged: each remove cancels out an add with the same bit, and
+/// the remaining adds must agree.
+pub fn merge_executable(removes: &[bool], adds: &[bool]) -> Result<bool, ModeConflict> {
+    let mut remaining_adds = adds.to_vec();
+    for remove in removes {
+        if let Some(index) = remaining_adds.iter().position(|add| add == remove) {
+            remaining_adds.remove(index);
+        }
+    }
+    match remaining_adds.as_slice() {
+        [first, rest @ ..] if rest.iter().all(|add| add == first) => Ok(*first),
+        _ => Err(ModeConflict {
+            removes: removes.to_vec(),
+            adds: adds.to_vec(),
+        }),
+    }
+}
+
+/// Returns the conflict between the executable bits of a file conflict whose
+/// contents merge cleanly, or `None` if the contents conflict, the bits merge,
+/// or the conflict involves anything other than files.
+pub fn mode_only_conflict(
     store: &Store,
     filename: &RepoPath,
     conflict: &Conflict,
-) -> Result<Option<(Vec<u8>, bool)>, TreeMergeError> {
-    // If there are any non-file parts in the conflict, we can't merge it. We check
-    // early so we don't waste time reading file contents if we can't merge them
-    // anyway. At the same time we determine whether the resulting file should
-    // be executable.
-    let mut exec_delta = 0;
-    let mut regular_delta = 0;
+) -> Result<Option<ModeConflict>, TreeMergeError> {
+    let contents = match read_file_conflict(store, filename, conflict)? {
+        Some(contents) => contents,
+        None => return Ok(None),
+    };
+    let mode_conflict = match contents.executable {
+        Ok(_) => return Ok(None),
+        Err(mode_conflict) => mode_conflict,
+    };
+    let merge_result = files::merge_with_options(
+        &contents.removes.iter().map(Vec::as_slice).collect_vec(),
+        &contents.adds.iter().map(Vec::as_slice).collect_vec(),
+        &MergeOptions::default(),
+    );
+    Ok(match merge_result {
+        MergeResult::Resolved(_) => Some(mode_conflict),
+        MergeResult::Conflict { .. } => None,
+    })
+}
+
+/// The ids of the removed and added files of a conflict, and whether the
+/// merged file should be executable.
+struct FileConflictIds {
+    removes: Vec<FileId>,
+    adds: Vec<FileId>,
+    executable: Result<bool, ModeConflict>,
+}
+
+/// Returns `None` if there are any non-file parts in the conflict.
+fn file_conflict_ids(conflict: &Conflict) -> Option<FileConflictIds> {
     let mut removed_file_ids = vec![];
+    let mut removed_executable = vec![];
     let mut added_file_ids = vec![];
+    let mut added_executable = vec![];
     for part in &conflict.removes {
         match &part.value {
             TreeValue::File { id, executable } => {
-                if *executable {
-                    exec_delta -= 1;
-                } else {
-                    regular_delta -= 1;
-                }
                 removed_file_ids.push(id.clone());
+                removed_executable.push(*executable);
             }
             _ => {
-                return Ok(None);
+                return None;
             }
         }
     }
     for part in &conflict.adds {
         match &part.value {
             TreeValue::File { id, executable } => {
-                if *executable {
-                    exec_delta += 1;
-                } else {
-                    regular_delta += 1;
-                }
                 added_file_ids.push(id.clone());
+                added_executable.push(*executable);
             }
             _ => {
-                return Ok(None);
+                return None;
             }
         }
     }
-    let executable = if exec_delta > 0 && regular_delta <= 0 {
-        true
-    } else if regular_delta > 0 && exec_delta <= 0 {
-        false
-    } else {
-        // We're unable to determine whether the result should be executable
-        return Ok(None);
-    };
-    let mut removed_contents = vec![];
-    let mut added_contents = vec![];
-    for file_id in removed_file_ids {
+    Some(FileConflictIds {
+        removes: removed_file_ids,
+        adds: added_file_ids,
+        executable: merge_executable(&removed_executable, &added_executable),
+    })
+}
+
+fn read_file_contents(
+    store: &Store,
+    filename: &RepoPath,
+    file_ids: &[FileId],
+) -> Result<Vec<Vec<u8>>, TreeMergeError> {
+    let mut contents = vec![];
+    for file_id in file_ids {
         let mut content = vec![];
         store
-            .read_file(filename, &file_id)?
+            .read_file(filename, file_id)?
             .read_to_end(&mut content)
             .map_err(|err| TreeMergeError::ReadError {
                 source: err,
-                file_id,
+                file_id: file_id.clone(),
             })?;
-        removed_contents.push(content);
+        contents.push(content);
     }
-    for file_id in added_file_ids {
-        let mut content = vec![];
-        store
-            .read_file(filename, &file_id)?
-            .read_to_end(&mut content)
-            .map_err(|err| TreeMergeError::ReadError {
-                source: err,
-                file_id,
-            })?;
-        added_contents.push(content);
+    Ok(contents)
+}
+
+/// Reads the contents of each side of a file conflict, e.g. to pass them to an
+/// external merge tool. Returns `None` if the conflict involves anything other
+/// than files.
+pub fn read_file_conflict(
+    store: &Store,
+    filename: &RepoPath,
+    conflict: &Conflict,
+) -> Result<Option<FileConflictContents>, TreeMergeError> {
+    // If there are any non-file parts in the conflict, we can't merge it. We check
+    // early so we don't waste time reading file contents if we can't merge them
+    // anyway.
+    let file_ids = match file_conflict_ids(conflict) {
+        Some(file_ids) => file_ids,
+        None => return Ok(None),
+    };
+    Ok(Some(FileConflictContents {
+        removes: read_file_contents(store, filename, &file_ids.removes)?,
+        adds: read_file_contents(store, filename, &file_ids.adds)?,
+        executable: file_ids.executable,
+    }))
+}
+
+/// Identifies a merge of file contents by the ids of the removed and added
+/// files and the options for merging the contents. Since file ids are content
+/// hashes, two merges with the same key have the same result, which lets
+/// `Store` cache the results of tree merges.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FileMergeKey {
+    removes: Vec<FileId>,
+    adds: Vec<FileId>,
+    options: MergeOptions,
+}
+
+impl FileMergeKey {
+    pub fn new(removes: Vec<FileId>, adds: Vec<FileId>, options: &MergeOptions) -> Self {
+        FileMergeKey {
+            removes,
+            adds,
+            options: options.clone(),
+        }
     }
-    let merge_result = files::merge(
-        &removed_contents.iter().map(Vec::as_slice).collect_vec(),
-        &added_contents.iter().map(Vec::as_slice).collect_vec(),
+}
+
+fn try_resolve_file_conflict(
+    store: &Store,
+    filename: &RepoPath,
+    conflict: &Conflict,
+    options: &TreeMergeOptions,
+) -> Result<Option<(Vec<u8>, Result<bool, ModeConflict>)>, TreeMergeError> {
+    let file_strategy = options.file_strategies.strategy_for(filename);
+    if file_strategy == Some(FileMergeStrategy::Binary) {
+        return Ok(None);
+    }
+    let file_ids = match file_conflict_ids(conflict) {
+        Some(file_ids) => file_ids,
+        None => return Ok(None),
+    };
+    let key = FileMergeKey::new(
+        file_ids.removes.clone(),
+        file_ids.adds.clone(),
+        &options.file_options,
     );
-    match merge_result {
-        MergeResult::Resolved(merged_content) => Ok(Some((merged_content, executable))),
-        MergeResult::Conflict(_) => Ok(None),
+    let mut contents = None;
+    let merge_result = match store.get_cached_merge(&key) {
+        Some(merge_result) => merge_result,
+        None => {
+            let removes = read_file_contents(store, filename, &file_ids.removes)?;
+            let adds = read_file_contents(store, filename, &file_ids.adds)?;
+            let merge_result = store.cache_merge(
+                key,
+                files::merge_with_options(
+                    &removes.iter().map(Vec::as_slice).collect_vec(),
+                    &adds.iter().map(Vec::as_slice).collect_vec(),
+                    &options.file_options,
+                ),
+            );
+            contents = Some((removes, adds));
+            merge_result
+        }
+    };
+    match merge_result.as_ref() {
+        MergeResult::Resolved(merged_content) => {
+            Ok(Some((merged_content.clone(), file_ids.executable)))
+        }
+        MergeResult::Conflict { hunks, .. } => {
+            if let Some(FileMergeStrategy::Resolve(strategy)) = file_strategy {
+                let (removes, adds) = match contents {
+                    Some(contents) => contents,
+                    None => (
+                        read_file_contents(store, filename, &file_ids.removes)?,
+                        read_file_contents(store, filename, &file_ids.adds)?,
+                    ),
+                };
+                // Resolving hunks of binary files line by line would corrupt
+                // them, so they stay conflicted
+                if removes
+                    .iter()
+                    .chain(&adds)
+                    .any(|content| files::is_binary(content))
+                {
+                    return Ok(None);
+                }
+                let merged_content = files::resolve_hunks_with_strategy(hunks, strategy);
+                return Ok(Some((merged_content, file_ids.executable)));
+            }
+            if let Some(resolution_store) = &options.resolution_store {
+                let resolved = resolution_store.resolve_hunks(hunks).map_err(|err| {
+                    TreeMergeError::ResolutionStoreError {
+                        source: err,
+                        path: filename.clone(),
+                    }
+                })?;
+                if let Some(merged_content) = resolved {
+                    resolution_store.note_applied(filename.clone());
+                    return Ok(Some((merged_content, file_ids.executable)));
+                }
+            }
+            Ok(None)
+        }
     }
 }
 
+/// Resolves a file conflict by merging the file contents and resolving any
+/// conflicting hunks according to `strategy`, which also resolves a conflict
+/// between the executable bits. Returns the merged content and whether the
+/// file should be executable, or `None` if the conflict involves anything
+/// other than files, such as a file that was deleted on one side.
+pub fn resolve_file_conflict_with_strategy(
+    store: &Store,
+    filename: &RepoPath,
+    conflict: &Conflict,
+    strategy: MergeStrategy,
+) -> Result<Option<(Vec<u8>, bool)>, TreeMergeError> {
+    let contents = match read_file_conflict(store, filename, conflict)? {
+        Some(contents) => contents,
+        None => return Ok(None),
+    };
+    let merged_content = files::merge_with_strategy(
+        &contents.removes.iter().map(Vec::as_slice).collect_vec(),
+        &contents.adds.iter().map(Vec::as_slice).collect_vec(),
+        strategy,
+    );
+    let executable = contents
+        .executable
+        .unwrap_or_else(|mode_conflict| mode_conflict.resolve_with_strategy(strategy));
+    Ok(Some((merged_content, executable)))
+}
+
 fn conflict_part_to_conflict(
     store: &Store,
     path: &RepoPath,
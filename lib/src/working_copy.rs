@@ -16,13 +16,14 @@ use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::fs::{DirEntry, File, Metadata, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{BufWriter, Read, Write};
 use std::ops::Bound;
 #[cfg(unix)]
 use std::os::unix::fs::symlink;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 
@@ -32,23 +33,90 @@ use tempfile::NamedTempFile;
 use thiserror::Error;
 
 use crate::backend::{
-    BackendError, ConflictId, FileId, MillisSinceEpoch, SymlinkId, TreeId, TreeValue,
+    BackendError, BackendResult, Conflict, ConflictId, ConflictPart, FileId, MillisSinceEpoch,
+    SymlinkId, TreeId, TreeValue,
 };
-use crate::conflicts::{materialize_conflict, update_conflict_from_content};
+use crate::conflicts::{
+    check_conflict_markers, materialize_conflict_with_labels, merge_conflict_contents,
+    update_conflict_from_content_with_style, update_conflict_from_hunks, ConflictLabeler,
+    ConflictLabels, ConflictMarkerStyle, ConflictParseError,
+};
+use crate::files::{DiffOptions, MergeResult};
 use crate::gitignore::GitIgnoreFile;
 use crate::lock::FileLock;
 use crate::matchers::{DifferenceMatcher, Matcher, PrefixMatcher};
+use crate::merge_result_encoding;
+use crate::merge_result_encoding::StoredMergeResult;
 use crate::op_store::{OperationId, WorkspaceId};
 use crate::repo_path::{RepoPath, RepoPathComponent, RepoPathJoin};
+use crate::settings::UserSettings;
 use crate::store::Store;
 use crate::tree::{Diff, Tree};
 use crate::tree_builder::TreeBuilder;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FileType {
-    Normal { executable: bool },
+    Normal {
+        executable: bool,
+    },
     Symlink,
-    Conflict { id: ConflictId },
+    Conflict {
+        id: ConflictId,
+        /// How the conflict was written to the file
+        checkout_mode: ConflictCheckoutMode,
+    },
+}
+
+/// The suffix of the sidecar file next to a conflicted file that was checked
+/// out as one of its sides. The sidecar holds the whole conflict, encoded by
+/// `merge_result_encoding`.
+pub const CONFLICT_SIDECAR_SUFFIX: &str = ".jj-conflict";
+
+/// How conflicted files are written to the working copy on checkout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictCheckoutMode {
+    /// Writes the conflict with conflict markers.
+    Markers,
+    /// Writes the first side of the conflict, and the whole conflict to a
+    /// sidecar file.
+    Left,
+    /// Writes the first base of the conflict, which is empty if there's none,
+    /// and the whole conflict to a sidecar file.
+    Base,
+}
+
+impl Default for ConflictCheckoutMode {
+    fn default() -> Self {
+        ConflictCheckoutMode::Markers
+    }
+}
+
+impl FromStr for ConflictCheckoutMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markers" => Ok(ConflictCheckoutMode::Markers),
+            "left" => Ok(ConflictCheckoutMode::Left),
+            "base" => Ok(ConflictCheckoutMode::Base),
+            _ => Err("must be one of markers, left, or base"),
+        }
+    }
+}
+
+impl ConflictCheckoutMode {
+    pub fn from_settings(settings: &UserSettings) -> Result<Self, config::ConfigError> {
+        Ok(settings
+            .get_parsed("ui.conflict-checkout")?
+            .unwrap_or_default())
+    }
+}
+
+/// The path of the sidecar file of the conflicted file at `disk_path`.
+pub fn conflict_sidecar_path(disk_path: &Path) -> PathBuf {
+    let mut path = disk_path.as_os_str().to_owned();
+    path.push(CONFLICT_SIDECAR_SUFFIX);
+    PathBuf::from(path)
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -81,9 +149,14 @@ impl FileState {
         }
     }
 
-    fn for_conflict(id: ConflictId, size: u64, metadata: &Metadata) -> Self {
+    fn for_conflict(
+        id: ConflictId,
+        checkout_mode: ConflictCheckoutMode,
+        size: u64,
+        metadata: &Metadata,
+    ) -> Self {
         FileState {
-            file_type: FileType::Conflict { id },
+            file_type: FileType::Conflict { id, checkout_mode },
             mtime: mtime_from_metadata(metadata),
             size,
         }
@@ -114,6 +187,11 @@ pub struct TreeState {
     // Currently only path prefixes
     sparse_patterns: Vec<RepoPath>,
     own_mtime: MillisSinceEpoch,
+    conflict_marker_style: ConflictMarkerStyle,
+    conflict_checkout_mode: ConflictCheckoutMode,
+    conflict_labeler: Option<ConflictLabeler>,
+    // Conflicted files found with malformed markers by the last snapshot
+    malformed_conflicts: Vec<(RepoPath, ConflictParseError)>,
 }
 
 fn file_state_from_proto(proto: &crate::protos::working_copy::FileState) -> FileState {
@@ -123,7 +201,18 @@ fn file_state_from_proto(proto: &crate::protos::working_copy::FileState) -> File
         crate::protos::working_copy::FileType::Symlink => FileType::Symlink,
         crate::protos::working_copy::FileType::Conflict => {
             let id = ConflictId::new(proto.conflict_id.to_vec());
-            FileType::Conflict { id }
+            let checkout_mode = match proto.conflict_checkout_mode.enum_value_or_default() {
+                crate::protos::working_copy::ConflictCheckoutMode::Markers => {
+                    ConflictCheckoutMode::Markers
+                }
+                crate::protos::working_copy::ConflictCheckoutMode::Left => {
+                    ConflictCheckoutMode::Left
+                }
+                crate::protos::working_copy::ConflictCheckoutMode::Base => {
+                    ConflictCheckoutMode::Base
+                }
+            };
+            FileType::Conflict { id, checkout_mode }
         }
     };
     FileState {
@@ -139,8 +228,19 @@ fn file_state_to_proto(file_state: &FileState) -> crate::protos::working_copy::F
         FileType::Normal { executable: false } => crate::protos::working_copy::FileType::Normal,
         FileType::Normal { executable: true } => crate::protos::working_copy::FileType::Executable,
         FileType::Symlink => crate::protos::working_copy::FileType::Symlink,
-        FileType::Conflict { id } => {
+        FileType::Conflict { id, checkout_mode } => {
             proto.conflict_id = id.to_bytes();
+            proto.conflict_checkout_mode = EnumOrUnknown::new(match checkout_mode {
+                ConflictCheckoutMode::Markers => {
+                    crate::protos::working_copy::ConflictCheckoutMode::Markers
+                }
+                ConflictCheckoutMode::Left => {
+                    crate::protos::working_copy::ConflictCheckoutMode::Left
+                }
+                ConflictCheckoutMode::Base => {
+                    crate::protos::working_copy::ConflictCheckoutMode::Base
+                }
+            });
             crate::protos::working_copy::FileType::Conflict
         }
     };
@@ -257,6 +357,13 @@ fn file_state(metadata: &Metadata) -> Option<FileState> {
     })
 }
 
+fn has_conflict_sidecar(file_state: &FileState) -> bool {
+    matches!(
+        file_state.file_type,
+        FileType::Conflict { checkout_mode, .. } if checkout_mode != ConflictCheckoutMode::Markers
+    )
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct CheckoutStats {
     pub updated_files: u32,
@@ -276,6 +383,8 @@ pub enum SnapshotError {
     InvalidUtf8Path { path: OsString },
     #[error("Symlink {path} target is not valid UTF-8")]
     InvalidUtf8SymlinkTarget { path: PathBuf, target: PathBuf },
+    #[error("Conflict sidecar file {path} is invalid: {message}")]
+    InvalidConflictSidecar { path: PathBuf, message: String },
     #[error("Internal backend error: {0}")]
     InternalBackendError(#[from] BackendError),
 }
@@ -341,6 +450,30 @@ impl TreeState {
         &self.sparse_patterns
     }
 
+    /// Files that had conflicts and whose conflict markers the last snapshot
+    /// found to be malformed, so they were taken as resolved.
+    pub fn malformed_conflicts(&self) -> &[(RepoPath, ConflictParseError)] {
+        &self.malformed_conflicts
+    }
+
+    /// Sets the style of the conflict markers written to files on checkout.
+    /// Markers in any style are parsed when snapshotting.
+    pub fn set_conflict_marker_style(&mut self, conflict_marker_style: ConflictMarkerStyle) {
+        self.conflict_marker_style = conflict_marker_style;
+    }
+
+    /// Sets how conflicted files are written on checkout. Files are parsed
+    /// according to how they were written when snapshotting.
+    pub fn set_conflict_checkout_mode(&mut self, conflict_checkout_mode: ConflictCheckoutMode) {
+        self.conflict_checkout_mode = conflict_checkout_mode;
+    }
+
+    /// Sets how the markers of conflicts written on checkout are labeled. They
+    /// aren't labeled if it's `None`.
+    pub fn set_conflict_labeler(&mut self, conflict_labeler: Option<ConflictLabeler>) {
+        self.conflict_labeler = conflict_labeler;
+    }
+
     fn sparse_matcher(&self) -> Box<dyn Matcher> {
         Box::new(PrefixMatcher::new(&self.sparse_patterns))
     }
@@ -363,6 +496,10 @@ impl TreeState {
             file_states: BTreeMap::new(),
             sparse_patterns: vec![RepoPath::root()],
             own_mtime: MillisSinceEpoch(0),
+            conflict_marker_style: ConflictMarkerStyle::default(),
+            conflict_checkout_mode: ConflictCheckoutMode::default(),
+            conflict_labeler: None,
+            malformed_conflicts: vec![],
         }
     }
 
@@ -462,6 +599,7 @@ impl TreeState {
     /// Look for changes to the working copy. If there are any changes, create
     /// a new tree from it.
     pub fn snapshot(&mut self, base_ignores: Arc<GitIgnoreFile>) -> Result<bool, SnapshotError> {
+        self.malformed_conflicts.clear();
         let sparse_matcher = self.sparse_matcher();
         let mut work = vec![(
             RepoPath::root(),
@@ -470,6 +608,20 @@ impl TreeState {
         )];
         let mut tree_builder = self.store.tree_builder(self.tree_id.clone());
         let mut deleted_files: HashSet<_> = self.file_states.keys().cloned().collect();
+        // The sidecar files of conflicts belong to the conflicts, so they aren't
+        // snapshotted as files of their own
+        let sidecar_paths: HashSet<_> = self
+            .file_states
+            .iter()
+            .filter(|(_, state)| has_conflict_sidecar(state))
+            .map(|(path, _)| {
+                RepoPath::from_internal_string(&format!(
+                    "{}{}",
+                    path.to_internal_file_string(),
+                    CONFLICT_SIDECAR_SUFFIX
+                ))
+            })
+            .collect();
         while let Some((dir, disk_dir, git_ignore)) = work.pop() {
             if sparse_matcher.visit(&dir).is_nothing() {
                 continue;
@@ -498,7 +650,7 @@ impl TreeState {
                         continue;
                     }
                     work.push((sub_path, entry.path(), git_ignore.clone()));
-                } else {
+                } else if !sidecar_paths.contains(&sub_path) {
                     deleted_files.remove(&sub_path);
                     if sparse_matcher.matches(&sub_path) {
                         self.update_file_state(
@@ -513,6 +665,8 @@ impl TreeState {
         }
 
         for file in &deleted_files {
+            // Deleting a conflicted file resolves the conflict
+            self.remove_conflict_sidecar(file, &file.to_fs_path(&self.working_copy_path));
             self.file_states.remove(file);
             tree_builder.remove(file.clone());
         }
@@ -590,6 +744,43 @@ impl TreeState {
                 if current_file_state.mtime >= self.own_mtime {
                     current_file_state.mtime = MillisSinceEpoch(0);
                 }
+                if let (FileType::Conflict { id, checkout_mode }, FileType::Normal { .. }) =
+                    (&current_file_state.file_type, &new_file_state.file_type)
+                {
+                    if *checkout_mode != ConflictCheckoutMode::Markers {
+                        let (id, checkout_mode) = (id.clone(), *checkout_mode);
+                        let stat_unchanged = new_file_state.mtime == current_file_state.mtime
+                            && new_file_state.size == current_file_state.size;
+                        if self.is_conflict_side_unchanged(
+                            &repo_path,
+                            &disk_path,
+                            &id,
+                            checkout_mode,
+                            stat_unchanged,
+                        )? {
+                            let new_id = self.read_conflict_sidecar(&repo_path, &disk_path, &id)?;
+                            if new_id != id {
+                                tree_builder
+                                    .set(repo_path.clone(), TreeValue::Conflict(new_id.clone()));
+                            }
+                            new_file_state.file_type = FileType::Conflict {
+                                id: new_id,
+                                checkout_mode,
+                            };
+                            self.file_states.insert(repo_path, new_file_state);
+                            return Ok(());
+                        }
+                        // The file is the resolution of the conflict, so the sidecar
+                        // isn't needed anymore
+                        fs::remove_file(conflict_sidecar_path(&disk_path)).ok();
+                        let file_type = new_file_state.file_type.clone();
+                        self.file_states.insert(repo_path.clone(), new_file_state);
+                        let file_value =
+                            self.write_path_to_store(&repo_path, &disk_path, file_type)?;
+                        tree_builder.set(repo_path, file_value);
+                        return Ok(());
+                    }
+                }
                 let mut clean = current_file_state == &new_file_state;
                 // Because the file system doesn't have a built-in way of indicating a conflict,
                 // we look at the current state instead. If that indicates that the path has a
@@ -610,27 +801,32 @@ impl TreeState {
                         // If the file contained a conflict before and is now a normal file on disk
                         // (new_file_state cannot be a Conflict at this point), we try to parse
                         // any conflict markers in the file into a conflict.
-                        if let (FileType::Conflict { id }, FileType::Normal { executable: _ }) =
+                        if let (FileType::Conflict { id, .. }, FileType::Normal { executable: _ }) =
                             (&current_file_state.file_type, &new_file_state.file_type)
                         {
                             let mut file = File::open(&disk_path).unwrap();
                             let mut content = vec![];
                             file.read_to_end(&mut content).unwrap();
-                            if let Some(new_conflict_id) = update_conflict_from_content(
+                            if let Some(new_conflict_id) = update_conflict_from_content_with_style(
                                 self.store.as_ref(),
                                 &repo_path,
                                 id,
+                                self.conflict_marker_style,
                                 &content,
                             )
                             .unwrap()
                             {
                                 new_file_state.file_type = FileType::Conflict {
                                     id: new_conflict_id.clone(),
+                                    checkout_mode: ConflictCheckoutMode::Markers,
                                 };
                                 *current_file_state = new_file_state;
                                 tree_builder.set(repo_path, TreeValue::Conflict(new_conflict_id));
                                 return Ok(());
                             }
+                            if let Err(err) = check_conflict_markers(&content) {
+                                self.malformed_conflicts.push((repo_path.clone(), err));
+                            }
                         }
                     }
                 }
@@ -645,6 +841,85 @@ impl TreeState {
         Ok(())
     }
 
+    /// Whether a conflicted file that was checked out as one of the conflict's
+    /// sides still has that side's content and its sidecar file, so the path
+    /// is still conflicted. Deleting the sidecar marks the conflict resolved.
+    fn is_conflict_side_unchanged(
+        &self,
+        repo_path: &RepoPath,
+        disk_path: &Path,
+        id: &ConflictId,
+        checkout_mode: ConflictCheckoutMode,
+        stat_unchanged: bool,
+    ) -> Result<bool, SnapshotError> {
+        if !conflict_sidecar_path(disk_path).is_file() {
+            return Ok(false);
+        }
+        if stat_unchanged {
+            return Ok(true);
+        }
+        let conflict = self.store.read_conflict(repo_path, id)?;
+        let side = self.read_conflict_side(repo_path, &conflict, checkout_mode)?;
+        let content = fs::read(disk_path).map_err(|err| SnapshotError::IoError {
+            message: format!("Failed to read file {}", disk_path.display()),
+            err,
+        })?;
+        Ok(content == side)
+    }
+
+    /// Reconstructs the conflict at `repo_path` from its sidecar file, so
+    /// edits to the sidecar, e.g. by a merge tool, are recorded. The sidecar
+    /// must have as many removes and adds as the conflict `id` it was written
+    /// for. Returns `id` if the sidecar is unchanged.
+    fn read_conflict_sidecar(
+        &self,
+        repo_path: &RepoPath,
+        disk_path: &Path,
+        id: &ConflictId,
+    ) -> Result<ConflictId, SnapshotError> {
+        let sidecar_path = conflict_sidecar_path(disk_path);
+        let data = fs::read(&sidecar_path).map_err(|err| SnapshotError::IoError {
+            message: format!("Failed to read file {}", sidecar_path.display()),
+            err,
+        })?;
+        let invalid_sidecar = |message: String| SnapshotError::InvalidConflictSidecar {
+            path: sidecar_path.clone(),
+            message,
+        };
+        let stored =
+            merge_result_encoding::decode(&data).map_err(|err| invalid_sidecar(err.to_string()))?;
+        let conflict = self.store.read_conflict(repo_path, id)?;
+        match stored.result {
+            MergeResult::Conflict {
+                hunks,
+                num_removes,
+                num_adds,
+            } if num_removes == conflict.removes.len() && num_adds == conflict.adds.len() => Ok(
+                update_conflict_from_hunks(self.store.as_ref(), repo_path, conflict, &hunks)?,
+            ),
+            // There's nothing to reconstruct if the sides' contents merged
+            // cleanly
+            MergeResult::Resolved(_) => Ok(id.clone()),
+            MergeResult::Conflict { .. } => Err(invalid_sidecar(format!(
+                "expected a conflict with {} removes and {} adds",
+                conflict.removes.len(),
+                conflict.adds.len()
+            ))),
+        }
+    }
+
+    /// Removes the sidecar file of the conflicted file at `path`, if it was
+    /// checked out with one.
+    fn remove_conflict_sidecar(&self, path: &RepoPath, disk_path: &Path) {
+        if self
+            .file_states
+            .get(path)
+            .map_or(false, has_conflict_sidecar)
+        {
+            fs::remove_file(conflict_sidecar_path(disk_path)).ok();
+        }
+    }
+
     fn write_path_to_store(
         &self,
         repo_path: &RepoPath,
@@ -736,6 +1011,13 @@ impl TreeState {
     ) -> Result<FileState, CheckoutError> {
         create_parent_dirs(&self.working_copy_path, path)?;
         let conflict = self.store.read_conflict(path, id)?;
+        if self.conflict_checkout_mode != ConflictCheckoutMode::Markers {
+            // Conflicts that can't be merged as file contents are still
+            // written with markers
+            if let Some(merge_result) = merge_conflict_contents(&self.store, path, &conflict) {
+                return self.write_conflict_side(disk_path, path, id, &conflict, merge_result);
+            }
+        }
         let mut file = OpenOptions::new()
             .write(true)
             .create_new(true) // Don't overwrite un-ignored file. Don't follow symlink.
@@ -744,21 +1026,130 @@ impl TreeState {
                 message: format!("Failed to open file {} for writing", disk_path.display()),
                 err,
             })?;
-        let mut conflict_data = vec![];
-        materialize_conflict(self.store.as_ref(), path, &conflict, &mut conflict_data)
-            .expect("Failed to materialize conflict to in-memory buffer");
-        file.write_all(&conflict_data)
+        // Write the conflict straight to the file instead of materializing it in
+        // memory first, since conflicted files can be large
+        let labels = match &self.conflict_labeler {
+            Some(labeler) => labeler(path, &conflict),
+            None => ConflictLabels::default(),
+        };
+        let mut writer = BufWriter::new(&mut file);
+        materialize_conflict_with_labels(
+            self.store.as_ref(),
+            path,
+            &conflict,
+            &DiffOptions::default(),
+            self.conflict_marker_style,
+            &labels,
+            &mut writer,
+        )
+        .and_then(|()| writer.flush())
+        .map_err(|err| CheckoutError::IoError {
+            message: format!("Failed to write conflict to file {}", disk_path.display()),
+            err,
+        })?;
+        drop(writer);
+        // TODO: Set the executable bit correctly (when possible) and preserve that on
+        // Windows like we do with the executable bit for regular files.
+        let metadata = file
+            .metadata()
+            .map_err(|err| CheckoutError::for_stat_error(err, disk_path))?;
+        Ok(FileState::for_conflict(
+            id.clone(),
+            ConflictCheckoutMode::Markers,
+            metadata.len(),
+            &metadata,
+        ))
+    }
+
+    /// Writes the side of a conflict chosen by the conflict checkout mode to
+    /// the file, and the whole conflict to the file's sidecar.
+    fn write_conflict_side(
+        &self,
+        disk_path: &Path,
+        path: &RepoPath,
+        id: &ConflictId,
+        conflict: &Conflict,
+        merge_result: MergeResult,
+    ) -> Result<FileState, CheckoutError> {
+        let content = self.read_conflict_side(path, conflict, self.conflict_checkout_mode)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true) // Don't overwrite un-ignored file. Don't follow symlink.
+            .open(disk_path)
             .map_err(|err| CheckoutError::IoError {
-                message: format!("Failed to write conflict to file {}", disk_path.display()),
+                message: format!("Failed to open file {} for writing", disk_path.display()),
                 err,
             })?;
-        let size = conflict_data.len() as u64;
-        // TODO: Set the executable bit correctly (when possible) and preserve that on
-        // Windows like we do with the executable bit for regular files.
+        file.write_all(&content)
+            .map_err(|err| CheckoutError::IoError {
+                message: format!("Failed to write file {}", disk_path.display()),
+                err,
+            })?;
+        let sidecar_path = conflict_sidecar_path(disk_path);
+        let encoded =
+            merge_result_encoding::encode(&StoredMergeResult::without_origins(merge_result));
+        // Like the file itself, an untracked file at the sidecar's path isn't
+        // overwritten. The sidecar of a conflict that was checked out before
+        // has already been removed.
+        let sidecar_result = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&sidecar_path)
+            .and_then(|mut sidecar| {
+                sidecar.write_all(&encoded).map_err(|err| {
+                    fs::remove_file(&sidecar_path).ok();
+                    err
+                })
+            });
+        if let Err(err) = sidecar_result {
+            // Without its sidecar, the side would be snapshotted as the
+            // resolution of the conflict.
+            drop(file);
+            fs::remove_file(disk_path).ok();
+            return Err(CheckoutError::IoError {
+                message: format!(
+                    "Failed to write conflict to file {}",
+                    sidecar_path.display()
+                ),
+                err,
+            });
+        }
         let metadata = file
             .metadata()
             .map_err(|err| CheckoutError::for_stat_error(err, disk_path))?;
-        Ok(FileState::for_conflict(id.clone(), size, &metadata))
+        Ok(FileState::for_conflict(
+            id.clone(),
+            self.conflict_checkout_mode,
+            metadata.len(),
+            &metadata,
+        ))
+    }
+
+    /// The content of the side of the conflict that `checkout_mode` writes to
+    /// the file. A side that's missing, such as the base of a conflict between
+    /// two added files, is empty.
+    fn read_conflict_side(
+        &self,
+        path: &RepoPath,
+        conflict: &Conflict,
+        checkout_mode: ConflictCheckoutMode,
+    ) -> BackendResult<Vec<u8>> {
+        let part = match checkout_mode {
+            ConflictCheckoutMode::Markers => panic!("conflict markers show all sides"),
+            ConflictCheckoutMode::Left => conflict.adds.first(),
+            ConflictCheckoutMode::Base => conflict.removes.first(),
+        };
+        let mut content = vec![];
+        if let Some(ConflictPart {
+            value: TreeValue::File { id, .. },
+        }) = part
+        {
+            self.store
+                .read_file(path, id)?
+                .read_to_end(&mut content)
+                .map_err(|err| BackendError::Other(err.to_string()))?;
+        }
+        Ok(content)
     }
 
     #[cfg_attr(windows, allow(unused_variables))]
@@ -834,6 +1225,10 @@ impl TreeState {
         };
         let mut apply_diff = |path: RepoPath, diff: Diff<TreeValue>| -> Result<(), CheckoutError> {
             let disk_path = path.to_fs_path(&self.working_copy_path);
+            // The sidecar of a conflict is removed or rewritten along with the file
+            if !matches!(diff, Diff::Added(_)) {
+                self.remove_conflict_sidecar(&path, &disk_path);
+            }
 
             // TODO: Check that the file has not changed before overwriting/removing it.
             match diff {
@@ -934,7 +1329,10 @@ impl TreeState {
                     let file_type = match after {
                         TreeValue::File { id: _, executable } => FileType::Normal { executable },
                         TreeValue::Symlink(_id) => FileType::Symlink,
-                        TreeValue::Conflict(id) => FileType::Conflict { id },
+                        TreeValue::Conflict(id) => FileType::Conflict {
+                            id,
+                            checkout_mode: ConflictCheckoutMode::Markers,
+                        },
                         TreeValue::GitSubmodule(_id) => {
                             println!("ignoring git submodule at {:?}", path);
                             continue;
@@ -970,6 +1368,9 @@ pub struct WorkingCopy {
     state_path: PathBuf,
     checkout_state: OnceCell<CheckoutState>,
     tree_state: OnceCell<TreeState>,
+    conflict_marker_style: ConflictMarkerStyle,
+    conflict_checkout_mode: ConflictCheckoutMode,
+    conflict_labeler: Option<ConflictLabeler>,
 }
 
 impl WorkingCopy {
@@ -998,6 +1399,9 @@ impl WorkingCopy {
             state_path,
             checkout_state: OnceCell::new(),
             tree_state: OnceCell::new(),
+            conflict_marker_style: ConflictMarkerStyle::default(),
+            conflict_checkout_mode: ConflictCheckoutMode::default(),
+            conflict_labeler: None,
         }
     }
 
@@ -1008,6 +1412,9 @@ impl WorkingCopy {
             state_path,
             checkout_state: OnceCell::new(),
             tree_state: OnceCell::new(),
+            conflict_marker_style: ConflictMarkerStyle::default(),
+            conflict_checkout_mode: ConflictCheckoutMode::default(),
+            conflict_labeler: None,
         }
     }
 
@@ -1058,13 +1465,41 @@ impl WorkingCopy {
         &self.checkout_state().workspace_id
     }
 
+    /// Sets the style of the conflict markers written to files on checkout.
+    pub fn set_conflict_marker_style(&mut self, conflict_marker_style: ConflictMarkerStyle) {
+        self.conflict_marker_style = conflict_marker_style;
+        if let Some(tree_state) = self.tree_state.get_mut() {
+            tree_state.set_conflict_marker_style(conflict_marker_style);
+        }
+    }
+
+    /// Sets how conflicted files are written on checkout.
+    pub fn set_conflict_checkout_mode(&mut self, conflict_checkout_mode: ConflictCheckoutMode) {
+        self.conflict_checkout_mode = conflict_checkout_mode;
+        if let Some(tree_state) = self.tree_state.get_mut() {
+            tree_state.set_conflict_checkout_mode(conflict_checkout_mode);
+        }
+    }
+
+    /// Sets how the markers of conflicts written on checkout are labeled.
+    pub fn set_conflict_labeler(&mut self, conflict_labeler: Option<ConflictLabeler>) {
+        self.conflict_labeler = conflict_labeler.clone();
+        if let Some(tree_state) = self.tree_state.get_mut() {
+            tree_state.set_conflict_labeler(conflict_labeler);
+        }
+    }
+
     fn tree_state(&self) -> &TreeState {
         self.tree_state.get_or_init(|| {
-            TreeState::load(
+            let mut tree_state = TreeState::load(
                 self.store.clone(),
                 self.working_copy_path.clone(),
                 self.state_path.clone(),
-            )
+            );
+            tree_state.set_conflict_marker_style(self.conflict_marker_style);
+            tree_state.set_conflict_checkout_mode(self.conflict_checkout_mode);
+            tree_state.set_conflict_labeler(self.conflict_labeler.clone());
+            tree_state
         })
     }
 
@@ -1168,6 +1603,16 @@ impl LockedWorkingCopy<'_> {
         Ok(tree_state.current_tree_id().clone())
     }
 
+    /// Files whose conflict markers the last snapshot found to be malformed.
+    pub fn malformed_conflicts(&self) -> &[(RepoPath, ConflictParseError)] {
+        self.wc.tree_state().malformed_conflicts()
+    }
+
+    /// Sets how the markers of conflicts written on checkout are labeled.
+    pub fn set_conflict_labeler(&mut self, conflict_labeler: Option<ConflictLabeler>) {
+        self.wc.set_conflict_labeler(conflict_labeler);
+    }
+
     pub fn check_out(&mut self, new_tree: &Tree) -> Result<CheckoutStats, CheckoutError> {
         // TODO: Write a "pending_checkout" file with the new TreeId so we can
         // continue an interrupted update if we find such a file.
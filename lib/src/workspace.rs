@@ -20,12 +20,13 @@ use std::sync::Arc;
 use thiserror::Error;
 
 use crate::backend::Backend;
+use crate::conflicts::ConflictMarkerStyle;
 use crate::git_backend::GitBackend;
 use crate::local_backend::LocalBackend;
 use crate::op_store::WorkspaceId;
 use crate::repo::{BackendFactories, IoResultExt, PathError, ReadonlyRepo, RepoLoader};
 use crate::settings::UserSettings;
-use crate::working_copy::WorkingCopy;
+use crate::working_copy::{ConflictCheckoutMode, WorkingCopy};
 
 #[derive(Error, Debug)]
 pub enum WorkspaceInitError {
@@ -35,6 +36,8 @@ pub enum WorkspaceInitError {
     NonUnicodePath,
     #[error(transparent)]
     Path(#[from] PathError),
+    #[error(transparent)]
+    Config(#[from] config::ConfigError),
 }
 
 #[derive(Error, Debug)]
@@ -47,6 +50,8 @@ pub enum WorkspaceLoadError {
     NonUnicodePath,
     #[error(transparent)]
     Path(#[from] PathError),
+    #[error(transparent)]
+    Config(#[from] config::ConfigError),
 }
 
 /// Represents a workspace, i.e. what's typically the .jj/ directory and its
@@ -77,6 +82,8 @@ fn init_working_copy(
     jj_dir: &Path,
     workspace_id: WorkspaceId,
 ) -> Result<(WorkingCopy, Arc<ReadonlyRepo>), WorkspaceInitError> {
+    let conflict_marker_style = ConflictMarkerStyle::from_settings(user_settings)?;
+    let conflict_checkout_mode = ConflictCheckoutMode::from_settings(user_settings)?;
     let working_copy_state_path = jj_dir.join("working_copy");
     std::fs::create_dir(&working_copy_state_path).context(&working_copy_state_path)?;
 
@@ -91,13 +98,15 @@ fn init_working_copy(
     );
     let repo = tx.commit();
 
-    let working_copy = WorkingCopy::init(
+    let mut working_copy = WorkingCopy::init(
         repo.store().clone(),
         workspace_root.to_path_buf(),
         working_copy_state_path,
         repo.op_id().clone(),
         workspace_id,
     );
+    working_copy.set_conflict_marker_style(conflict_marker_style);
+    working_copy.set_conflict_checkout_mode(conflict_checkout_mode);
     Ok((working_copy, repo))
 }
 
@@ -224,11 +233,14 @@ impl Workspace {
         }
         let repo_loader = RepoLoader::init(user_settings, &repo_dir, backend_factories);
         let working_copy_state_path = jj_dir.join("working_copy");
-        let working_copy = WorkingCopy::load(
+        let mut working_copy = WorkingCopy::load(
             repo_loader.store().clone(),
             workspace_root.clone(),
             working_copy_state_path,
         );
+        working_copy.set_conflict_marker_style(ConflictMarkerStyle::from_settings(user_settings)?);
+        working_copy
+            .set_conflict_checkout_mode(ConflictCheckoutMode::from_settings(user_settings)?);
         Ok(Workspace::new(&workspace_root, working_copy, repo_loader)?)
     }
 
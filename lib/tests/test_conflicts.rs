@@ -13,8 +13,13 @@
 // limitations under the License.
 
 use jujutsu_lib::backend::{Conflict, ConflictPart, TreeValue};
-use jujutsu_lib::conflicts::{materialize_conflict, parse_conflict, update_conflict_from_content};
-use jujutsu_lib::files::MergeHunk;
+use jujutsu_lib::conflicts::{
+    check_conflict_markers, conflict_hunks_with_context, materialize_conflict,
+    materialize_conflict_with_labels, materialize_conflict_with_options, parse_conflict,
+    stored_conflict_stats, update_conflict_from_content, update_conflict_from_content_with_style,
+    ConflictLabels, ConflictMarkerStyle, ConflictParseError, ConflictParseErrorKind, ContextLine,
+};
+use jujutsu_lib::files::{ConflictStats, DiffOptions, MergeHunk, MergeResult};
 use jujutsu_lib::repo_path::RepoPath;
 use jujutsu_lib::store::Store;
 use testutils::TestRepo;
@@ -193,6 +198,86 @@ line 5
     );
 }
 
+#[test]
+fn test_stored_conflict_stats() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(store, &path, content),
+            executable: false,
+        },
+    };
+    let base = "line 1\nline 2\nline 3\nline 4\nline 5\n";
+
+    // One side modified a line that the other side deleted
+    let conflict = Conflict {
+        removes: vec![file_part(base)],
+        adds: vec![
+            file_part("line 1\nline 2\nleft\nline 4\nline 5\n"),
+            file_part("line 1\nline 2\nline 4\nline 5\n"),
+        ],
+    };
+    assert_eq!(
+        stored_conflict_stats(store, &path, &conflict),
+        ConflictStats {
+            num_hunks: 1,
+            num_sides: 2,
+            num_lines: 1,
+            num_deletion_hunks: 1,
+        }
+    );
+
+    // Two conflicting changes
+    let conflict = Conflict {
+        removes: vec![file_part(base)],
+        adds: vec![
+            file_part("left 1\nline 2\nline 3\nline 4\nleft 5\n"),
+            file_part("right 1\nline 2\nline 3\nline 4\nright 5\nright 6\n"),
+        ],
+    };
+    assert_eq!(
+        stored_conflict_stats(store, &path, &conflict),
+        ConflictStats {
+            num_hunks: 2,
+            num_sides: 2,
+            num_lines: 3,
+            num_deletion_hunks: 0,
+        }
+    );
+
+    // One side deleted the file
+    let conflict = Conflict {
+        removes: vec![file_part(base)],
+        adds: vec![file_part("line 1\nline 2\nleft\nline 4\nline 5\n")],
+    };
+    let stats = stored_conflict_stats(store, &path, &conflict);
+    assert_eq!(stats.num_sides, 1);
+    assert!(stats.involves_deletion());
+
+    // Conflicts that aren't between regular files count as one hunk
+    let conflict = Conflict {
+        removes: vec![],
+        adds: vec![
+            file_part("a\n"),
+            ConflictPart {
+                value: TreeValue::Symlink(store.write_symlink(&path, "target").unwrap()),
+            },
+        ],
+    };
+    assert_eq!(
+        stored_conflict_stats(store, &path, &conflict),
+        ConflictStats {
+            num_hunks: 1,
+            num_sides: 2,
+            num_lines: 0,
+            num_deletion_hunks: 0,
+        }
+    );
+}
+
 #[test]
 fn test_materialize_conflict_delete_modify() {
     let test_repo = TestRepo::init(false);
@@ -428,6 +513,138 @@ line 5
     )
 }
 
+#[test]
+fn test_parse_conflict_crlf_and_trailing_whitespace() {
+    // Markers may end in "\r\n" and have trailing whitespace, as editors tend to
+    // leave them
+    assert_eq!(
+        parse_conflict(
+            b"line 1\r\n<<<<<<< \r\n%%%%%%%\r\n line 2\r\n-line 3\r\n+left\r\n+++++++ \t\r\nright\r\n>>>>>>>\r\nline 5\r\n",
+            1,
+            2
+        ),
+        Some(vec![
+            MergeHunk::Resolved(b"line 1\r\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"line 2\r\nline 3\r\n".to_vec()],
+                adds: vec![b"line 2\r\nleft\r\n".to_vec(), b"right\r\n".to_vec()]
+            },
+            MergeHunk::Resolved(b"line 5\r\n".to_vec())
+        ])
+    );
+    // The end marker may be the last line without a newline
+    assert_eq!(
+        parse_conflict(
+            b"<<<<<<<
++++++++
+left
+-------
+base
++++++++
+right
+>>>>>>>",
+            1,
+            2
+        ),
+        Some(vec![MergeHunk::Conflict {
+            removes: vec![b"base\n".to_vec()],
+            adds: vec![b"left\n".to_vec(), b"right\n".to_vec()]
+        }])
+    );
+}
+
+#[test]
+fn test_check_conflict_markers() {
+    let error = |line_number, kind| Err(ConflictParseError { line_number, kind });
+
+    assert_eq!(check_conflict_markers(b"line 1\nline 2\n"), Ok(()));
+    assert_eq!(
+        check_conflict_markers(
+            b"line 1
+<<<<<<<
+%%%%%%%
+-base
++left
++++++++
+right
+>>>>>>>
+"
+        ),
+        Ok(())
+    );
+    // Shorter markers inside a conflict are content
+    assert_eq!(
+        check_conflict_markers(
+            b"<<<<<<<<<<<
++++++++++++
+<<<<<<<
+-----------
+>>>>>>>
++++++++++++
+right
+>>>>>>>>>>>
+"
+        ),
+        Ok(())
+    );
+
+    // Missing closing marker
+    assert_eq!(
+        check_conflict_markers(
+            b"line 1
+<<<<<<<
+%%%%%%%
+-base
++left
++++++++
+right
+line 5
+"
+        ),
+        error(2, ConflictParseErrorKind::UnclosedConflict)
+    );
+    // Nested-looking markers
+    assert_eq!(
+        check_conflict_markers(
+            b"<<<<<<<
+%%%%%%%
+-base
++left
++++++++
+<<<<<<<
+right
+>>>>>>>
+>>>>>>>
+"
+        ),
+        error(6, ConflictParseErrorKind::NestedStartMarker)
+    );
+    // An end marker without a start marker
+    assert_eq!(
+        check_conflict_markers(
+            b"line 1
+>>>>>>>
+"
+        ),
+        error(2, ConflictParseErrorKind::UnmatchedEndMarker)
+    );
+    // The diff part is invalid (missing space before "line 3")
+    assert_eq!(
+        check_conflict_markers(
+            b"line 1
+<<<<<<<
+%%%%%%%
+-base
+line 3
++++++++
+right
+>>>>>>>
+"
+        ),
+        error(2, ConflictParseErrorKind::InvalidConflict)
+    );
+}
+
 #[test]
 fn test_update_conflict_from_content() {
     let test_repo = TestRepo::init(false);
@@ -520,8 +737,711 @@ fn test_update_conflict_from_content() {
     )
 }
 
+#[test]
+fn test_simplified_conflict_round_trip() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(store, &path, content),
+            executable: false,
+        },
+    };
+    // Two of the three sides made the same change
+    let conflict = Conflict {
+        removes: vec![file_part("line 1\nline 2\n"), file_part("line 1\nline 2\n")],
+        adds: vec![
+            file_part("left 1\nline 2\n"),
+            file_part("left 1\nline 2\n"),
+            file_part("right 1\nline 2\n"),
+        ],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    // The repeated sides are only shown once
+    let materialized = materialize_conflict_string(store, &path, &conflict);
+    insta::assert_snapshot!(&materialized, @r###"
+    <<<<<<<
+    %%%%%%%
+    -line 1
+    +left 1
+    +++++++
+    right 1
+    >>>>>>>
+    line 2
+    "###);
+    let result =
+        update_conflict_from_content(store, &path, &conflict_id, materialized.as_bytes()).unwrap();
+    assert_eq!(result, Some(conflict_id.clone()));
+
+    // An edit to a shown side applies to all the sides it stands for
+    let result = update_conflict_from_content(
+        store,
+        &path,
+        &conflict_id,
+        b"<<<<<<<\n%%%%%%%\n-line 1\n+LEFT 1\n+++++++\nright 1\n>>>>>>>\nline 2\n",
+    )
+    .unwrap();
+    let new_conflict = store.read_conflict(&path, &result.unwrap()).unwrap();
+    assert_eq!(
+        new_conflict,
+        Conflict {
+            removes: vec![file_part("line 1\nline 2\n"), file_part("line 1\nline 2\n")],
+            adds: vec![
+                file_part("LEFT 1\nline 2\n"),
+                file_part("LEFT 1\nline 2\n"),
+                file_part("right 1\nline 2\n"),
+            ],
+        }
+    );
+}
+
+#[test]
+fn test_parse_conflict_git_style() {
+    assert_eq!(
+        parse_conflict(
+            b"line 1
+<<<<<<< Side #1
+left
+||||||| Base
+line 2
+=======
+right
+>>>>>>> Side #2
+line 3
+",
+            1,
+            2
+        ),
+        Some(vec![
+            MergeHunk::Resolved(b"line 1\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"line 2\n".to_vec()],
+                adds: vec![b"left\n".to_vec(), b"right\n".to_vec()]
+            },
+            MergeHunk::Resolved(b"line 3\n".to_vec())
+        ])
+    );
+    // Any labels are accepted
+    assert_eq!(
+        parse_conflict(
+            b"<<<<<<< HEAD
+left
+||||||| merged common ancestors
+base
+=======
+right
+>>>>>>> main
+",
+            1,
+            2
+        ),
+        Some(vec![MergeHunk::Conflict {
+            removes: vec![b"base\n".to_vec()],
+            adds: vec![b"left\n".to_vec(), b"right\n".to_vec()]
+        }])
+    );
+    // Without the base, it's not a valid conflict
+    assert_eq!(
+        parse_conflict(
+            b"<<<<<<< Side #1
+left
+=======
+right
+>>>>>>> Side #2
+",
+            1,
+            2
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_conflict_marker_styles() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    let path = RepoPath::from_internal_string("dir/file");
+    let base_file_id = testutils::write_file(store, &path, "line 1\nline 2\nline 3\n");
+    let left_file_id = testutils::write_file(store, &path, "left 1\nline 2\nleft 3\n");
+    let right_file_id = testutils::write_file(store, &path, "right 1\nline 2\nright 3\n");
+    let conflict = Conflict {
+        removes: vec![ConflictPart {
+            value: TreeValue::File {
+                id: base_file_id,
+                executable: false,
+            },
+        }],
+        adds: vec![
+            ConflictPart {
+                value: TreeValue::File {
+                    id: left_file_id,
+                    executable: false,
+                },
+            },
+            ConflictPart {
+                value: TreeValue::File {
+                    id: right_file_id,
+                    executable: false,
+                },
+            },
+        ],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+
+    let materialized = |style| {
+        let mut result: Vec<u8> = vec![];
+        materialize_conflict_with_options(
+            store,
+            &path,
+            &conflict,
+            &DiffOptions::default(),
+            style,
+            &mut result,
+        )
+        .unwrap();
+        String::from_utf8(result).unwrap()
+    };
+    insta::assert_snapshot!(materialized(ConflictMarkerStyle::Snapshot), @r###"
+    <<<<<<<
+    +++++++
+    left 1
+    -------
+    line 1
+    +++++++
+    right 1
+    >>>>>>>
+    line 2
+    <<<<<<<
+    +++++++
+    left 3
+    -------
+    line 3
+    +++++++
+    right 3
+    >>>>>>>
+    "###);
+    insta::assert_snapshot!(materialized(ConflictMarkerStyle::Git), @r###"
+    <<<<<<< Side #1
+    left 1
+    ||||||| Base
+    line 1
+    =======
+    right 1
+    >>>>>>> Side #2
+    line 2
+    <<<<<<< Side #1
+    left 3
+    ||||||| Base
+    line 3
+    =======
+    right 3
+    >>>>>>> Side #2
+    "###);
+
+    // Unchanged content in each style gives back the old conflict id
+    for style in [
+        ConflictMarkerStyle::Diff,
+        ConflictMarkerStyle::Snapshot,
+        ConflictMarkerStyle::Git,
+    ] {
+        let result = update_conflict_from_content_with_style(
+            store,
+            &path,
+            &conflict_id,
+            style,
+            materialized(style).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(result, Some(conflict_id.clone()), "{:?}", style);
+    }
+
+    // A partially resolved conflict is parsed whatever the style
+    let new_base_file_id = testutils::write_file(store, &path, "resolved 1\nline 2\nline 3\n");
+    let new_left_file_id = testutils::write_file(store, &path, "resolved 1\nline 2\nleft 3\n");
+    let new_right_file_id = testutils::write_file(store, &path, "resolved 1\nline 2\nright 3\n");
+    let expected_conflict = Conflict {
+        removes: vec![ConflictPart {
+            value: TreeValue::File {
+                id: new_base_file_id,
+                executable: false,
+            },
+        }],
+        adds: vec![
+            ConflictPart {
+                value: TreeValue::File {
+                    id: new_left_file_id,
+                    executable: false,
+                },
+            },
+            ConflictPart {
+                value: TreeValue::File {
+                    id: new_right_file_id,
+                    executable: false,
+                },
+            },
+        ],
+    };
+    for (style, content) in [
+        (
+            ConflictMarkerStyle::Snapshot,
+            "resolved 1\nline 2\n<<<<<<<\n+++++++\nleft 3\n-------\nline 3\n+++++++\nright \
+             3\n>>>>>>>\n",
+        ),
+        (
+            ConflictMarkerStyle::Git,
+            "resolved 1\nline 2\n<<<<<<< Side #1\nleft 3\n||||||| Base\nline 3\n=======\nright \
+             3\n>>>>>>> Side #2\n",
+        ),
+    ] {
+        let result = update_conflict_from_content_with_style(
+            store,
+            &path,
+            &conflict_id,
+            style,
+            content.as_bytes(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            store.read_conflict(&path, &result).unwrap(),
+            expected_conflict,
+            "{:?}",
+            style
+        );
+    }
+
+    // Git-style markers are only used for conflicts with two sides
+    let mut three_sided_conflict = conflict.clone();
+    three_sided_conflict
+        .removes
+        .push(three_sided_conflict.removes[0].clone());
+    three_sided_conflict
+        .adds
+        .push(three_sided_conflict.adds[0].clone());
+    let mut result: Vec<u8> = vec![];
+    materialize_conflict_with_options(
+        store,
+        &path,
+        &three_sided_conflict,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::Git,
+        &mut result,
+    )
+    .unwrap();
+    assert!(!String::from_utf8(result).unwrap().contains("||||||| Base"));
+}
+
+#[test]
+fn test_conflict_labels() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    let path = RepoPath::from_internal_string("dir/file");
+    let base_file_id = testutils::write_file(store, &path, "line 1\nline 2\nline 3\n");
+    let left_file_id = testutils::write_file(store, &path, "left 1\nline 2\nleft 3\n");
+    let right_file_id = testutils::write_file(store, &path, "right 1\nline 2\nright 3\n");
+    let conflict = Conflict {
+        removes: vec![ConflictPart {
+            value: TreeValue::File {
+                id: base_file_id,
+                executable: false,
+            },
+        }],
+        adds: vec![
+            ConflictPart {
+                value: TreeValue::File {
+                    id: left_file_id,
+                    executable: false,
+                },
+            },
+            ConflictPart {
+                value: TreeValue::File {
+                    id: right_file_id,
+                    executable: false,
+                },
+            },
+        ],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    let labels = ConflictLabels {
+        removes: vec![Some("base commit".to_string())],
+        adds: vec![
+            Some("left commit".to_string()),
+            Some("right commit".to_string()),
+        ],
+    };
+
+    let materialized = |style| {
+        let mut result: Vec<u8> = vec![];
+        materialize_conflict_with_labels(
+            store,
+            &path,
+            &conflict,
+            &DiffOptions::default(),
+            style,
+            &labels,
+            &mut result,
+        )
+        .unwrap();
+        String::from_utf8(result).unwrap()
+    };
+    insta::assert_snapshot!(materialized(ConflictMarkerStyle::Diff), @r###"
+    <<<<<<<
+    %%%%%%% diff from base commit to left commit
+    -line 1
+    +left 1
+    +++++++ right commit
+    right 1
+    >>>>>>>
+    line 2
+    <<<<<<<
+    %%%%%%% diff from base commit to left commit
+    -line 3
+    +left 3
+    +++++++ right commit
+    right 3
+    >>>>>>>
+    "###);
+    insta::assert_snapshot!(materialized(ConflictMarkerStyle::Snapshot), @r###"
+    <<<<<<<
+    +++++++ left commit
+    left 1
+    ------- base commit
+    line 1
+    +++++++ right commit
+    right 1
+    >>>>>>>
+    line 2
+    <<<<<<<
+    +++++++ left commit
+    left 3
+    ------- base commit
+    line 3
+    +++++++ right commit
+    right 3
+    >>>>>>>
+    "###);
+    insta::assert_snapshot!(materialized(ConflictMarkerStyle::Git), @r###"
+    <<<<<<< left commit
+    left 1
+    ||||||| base commit
+    line 1
+    =======
+    right 1
+    >>>>>>> right commit
+    line 2
+    <<<<<<< left commit
+    left 3
+    ||||||| base commit
+    line 3
+    =======
+    right 3
+    >>>>>>> right commit
+    "###);
+
+    // The labels are ignored when parsing the conflict again
+    for style in [
+        ConflictMarkerStyle::Diff,
+        ConflictMarkerStyle::Snapshot,
+        ConflictMarkerStyle::Git,
+    ] {
+        let result = update_conflict_from_content_with_style(
+            store,
+            &path,
+            &conflict_id,
+            style,
+            materialized(style).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(result, Some(conflict_id.clone()), "{:?}", style);
+    }
+}
+
+#[test]
+fn test_conflict_at_end_of_file_without_newline() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let conflict_with_newlines = |base: bool, left: bool, right: bool| {
+        let file_value = |content: &str, newline: bool| {
+            let content = if newline {
+                format!("{content}\n")
+            } else {
+                content.to_string()
+            };
+            ConflictPart {
+                value: TreeValue::File {
+                    id: testutils::write_file(store, &path, &format!("line 1\n{content}")),
+                    executable: false,
+                },
+            }
+        };
+        Conflict {
+            removes: vec![file_value("line 2", base)],
+            adds: vec![file_value("left 2", left), file_value("right 2", right)],
+        }
+    };
+    let materialized = |conflict: &Conflict, style| {
+        let mut result: Vec<u8> = vec![];
+        materialize_conflict_with_options(
+            store,
+            &path,
+            conflict,
+            &DiffOptions::default(),
+            style,
+            &mut result,
+        )
+        .unwrap();
+        String::from_utf8(result).unwrap()
+    };
+
+    // The markers are on their own lines, and the parts without a newline are
+    // followed by a marker saying so
+    let conflict = conflict_with_newlines(true, false, true);
+    insta::assert_snapshot!(materialized(&conflict, ConflictMarkerStyle::Diff), @r###"
+    line 1
+    <<<<<<<
+    %%%%%%%
+    -line 2
+    +left 2
+    \\\\\\\ No newline at end of file
+    +++++++
+    right 2
+    >>>>>>>
+    "###);
+    insta::assert_snapshot!(materialized(&conflict, ConflictMarkerStyle::Snapshot), @r###"
+    line 1
+    <<<<<<<
+    +++++++
+    left 2
+    \\\\\\\ No newline at end of file
+    -------
+    line 2
+    +++++++
+    right 2
+    >>>>>>>
+    "###);
+    insta::assert_snapshot!(materialized(&conflict, ConflictMarkerStyle::Git), @r###"
+    line 1
+    <<<<<<< Side #1
+    left 2
+    \\\\\\\ No newline at end of file
+    ||||||| Base
+    line 2
+    =======
+    right 2
+    >>>>>>> Side #2
+    "###);
+
+    // Whichever sides lack the newline, the conflict round-trips in each style
+    for base in [false, true] {
+        for left in [false, true] {
+            for right in [false, true] {
+                let conflict = conflict_with_newlines(base, left, right);
+                let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+                for style in [
+                    ConflictMarkerStyle::Diff,
+                    ConflictMarkerStyle::Snapshot,
+                    ConflictMarkerStyle::Git,
+                ] {
+                    let content = materialized(&conflict, style);
+                    assert!(content.ends_with(">>>>>>>\n") || content.ends_with("Side #2\n"));
+                    let result = update_conflict_from_content_with_style(
+                        store,
+                        &path,
+                        &conflict_id,
+                        style,
+                        content.as_bytes(),
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        result,
+                        Some(conflict_id.clone()),
+                        "{:?} {base} {left} {right}",
+                        style
+                    );
+                }
+            }
+        }
+    }
+}
+
 fn materialize_conflict_string(store: &Store, path: &RepoPath, conflict: &Conflict) -> String {
     let mut result: Vec<u8> = vec![];
     materialize_conflict(store, path, conflict, &mut result).unwrap();
     String::from_utf8(result).unwrap()
 }
+
+#[test]
+fn test_conflict_markers_lengthened_for_marker_like_content() {
+    let test_repo = TestRepo::init(false);
+    let store = test_repo.repo.store();
+
+    // The file already contains lines that look like conflict markers, also in
+    // the conflicting region
+    let path = RepoPath::from_internal_string("file");
+    let base_file_id = testutils::write_file(store, &path, "line 1\n<<<<<<< HEAD\nline 3\n");
+    let left_file_id =
+        testutils::write_file(store, &path, "line 1\n<<<<<<< HEAD\nleft 3\n<<<<<<< HEAD\n");
+    let right_file_id = testutils::write_file(store, &path, "line 1\n<<<<<<< HEAD\nright 3\n");
+    let conflict = Conflict {
+        removes: vec![ConflictPart {
+            value: TreeValue::File {
+                id: base_file_id,
+                executable: false,
+            },
+        }],
+        adds: vec![
+            ConflictPart {
+                value: TreeValue::File {
+                    id: left_file_id,
+                    executable: false,
+                },
+            },
+            ConflictPart {
+                value: TreeValue::File {
+                    id: right_file_id,
+                    executable: false,
+                },
+            },
+        ],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+
+    let materialized = materialize_conflict_string(store, &path, &conflict);
+    insta::assert_snapshot!(&materialized, @r###"
+    line 1
+    <<<<<<< HEAD
+    <<<<<<<<<<<
+    %%%%%%%%%%%
+    -line 3
+    +right 3
+    +++++++++++
+    left 3
+    <<<<<<< HEAD
+    >>>>>>>>>>>
+    "###);
+    assert_eq!(
+        update_conflict_from_content(store, &path, &conflict_id, materialized.as_bytes()).unwrap(),
+        Some(conflict_id.clone())
+    );
+
+    // The literal marker-like line is kept when the conflict is partially
+    // resolved
+    let result = update_conflict_from_content(
+        store,
+        &path,
+        &conflict_id,
+        materialized.replace("line 1", "resolved 1").as_bytes(),
+    )
+    .unwrap()
+    .unwrap();
+    let new_conflict = store.read_conflict(&path, &result).unwrap();
+    insta::assert_snapshot!(materialize_conflict_string(store, &path, &new_conflict), @r###"
+    resolved 1
+    <<<<<<< HEAD
+    <<<<<<<<<<<
+    %%%%%%%%%%%
+    -line 3
+    +right 3
+    +++++++++++
+    left 3
+    <<<<<<< HEAD
+    >>>>>>>>>>>
+    "###);
+
+    // The markers of a conflict must all have the same length
+    assert_eq!(
+        parse_conflict(
+            b"<<<<<<<<<<<\n+++++++++++\nleft\n-------\nbase\n+++++++++++\nright\n>>>>>>>>>>>\n",
+            1,
+            2
+        ),
+        None
+    );
+    assert_eq!(
+        parse_conflict(
+            b"<<<<<<<<<<<\n+++++++++++\nleft\n-----------\nbase\n+++++++++++\nright\n>>>>>>>\n",
+            1,
+            2
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_conflict_hunks_with_context() {
+    let conflict_hunk = || MergeHunk::Conflict {
+        removes: vec![b"x\n".to_vec()],
+        adds: vec![b"y\n".to_vec(), b"z\n".to_vec()],
+    };
+    let merge_result = MergeResult::conflict(vec![
+        MergeHunk::Resolved(b"a\nb\nc\n".to_vec()),
+        conflict_hunk(),
+        MergeHunk::Resolved(b"d\n".to_vec()),
+        conflict_hunk(),
+        MergeHunk::Resolved(b"e\nf\n".to_vec()),
+    ]);
+    let line = |line_number, content: &'static str| ContextLine {
+        line_number,
+        content: content.as_bytes(),
+    };
+
+    // Each conflict hunk takes 7 lines in the "diff" style. Context stops at the
+    // start and end of the file and at the other conflict hunk.
+    let contexts = conflict_hunks_with_context(
+        &merge_result,
+        2,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::Diff,
+    );
+    assert_eq!(contexts.len(), 2);
+    assert_eq!(contexts[0].hunk, &conflict_hunk());
+    assert_eq!(contexts[0].line_number, 4);
+    assert_eq!(contexts[0].before, vec![line(2, "b\n"), line(3, "c\n")]);
+    assert_eq!(contexts[0].after, vec![line(11, "d\n")]);
+    assert_eq!(contexts[1].line_number, 12);
+    assert_eq!(contexts[1].before, vec![line(11, "d\n")]);
+    assert_eq!(contexts[1].after, vec![line(19, "e\n"), line(20, "f\n")]);
+
+    // The line numbers depend on the marker style, which takes 8 lines here
+    let contexts = conflict_hunks_with_context(
+        &merge_result,
+        5,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::Snapshot,
+    );
+    assert_eq!(
+        contexts[0].before,
+        vec![line(1, "a\n"), line(2, "b\n"), line(3, "c\n")]
+    );
+    assert_eq!(contexts[0].after, vec![line(12, "d\n")]);
+    assert_eq!(contexts[1].line_number, 13);
+    assert_eq!(contexts[1].after, vec![line(21, "e\n"), line(22, "f\n")]);
+
+    // No context was requested
+    let contexts = conflict_hunks_with_context(
+        &merge_result,
+        0,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::Diff,
+    );
+    assert_eq!(contexts[0].before, vec![]);
+    assert_eq!(contexts[0].after, vec![]);
+
+    // A resolved merge has no conflict hunks
+    let resolved = MergeResult::Resolved(b"a\n".to_vec());
+    assert_eq!(
+        conflict_hunks_with_context(
+            &resolved,
+            3,
+            &DiffOptions::default(),
+            ConflictMarkerStyle::Diff
+        ),
+        vec![]
+    );
+}
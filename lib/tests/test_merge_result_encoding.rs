@@ -0,0 +1,54 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use jujutsu_lib::backend::{CommitId, TreeId};
+use jujutsu_lib::files::{MergeHunk, MergeResult};
+use jujutsu_lib::merge_result_encoding::{decode, encode, MergeOrigin, StoredMergeResult};
+
+const FIXTURE_V1: &[u8] = include_bytes!("fixtures/merge_result_v1.json");
+
+fn fixture_v1_contents() -> StoredMergeResult {
+    StoredMergeResult {
+        result: MergeResult::conflict(vec![
+            MergeHunk::Resolved(b"a\n".to_vec()),
+            MergeHunk::Conflict {
+                removes: vec![b"b\n".to_vec()],
+                adds: vec![b"c\n".to_vec(), b"\xff\x00\n".to_vec()],
+            },
+        ]),
+        remove_origins: vec![Some(MergeOrigin::Commit(CommitId::from_hex(
+            "0123456789abcdef",
+        )))],
+        add_origins: vec![
+            Some(MergeOrigin::Tree(TreeId::from_hex("fedcba9876543210"))),
+            None,
+        ],
+    }
+}
+
+#[test]
+fn test_decode_v1_fixture() {
+    // A file written by version 1 of the encoding must stay readable
+    assert_eq!(decode(FIXTURE_V1).unwrap(), fixture_v1_contents());
+}
+
+#[test]
+fn test_encode_matches_v1_fixture() {
+    // The current encoding must produce the same JSON as the fixture, modulo
+    // whitespace
+    let expected: serde_json::Value = serde_json::from_slice(FIXTURE_V1).unwrap();
+    let actual: serde_json::Value =
+        serde_json::from_slice(&encode(&fixture_v1_contents())).unwrap();
+    assert_eq!(actual, expected);
+}
@@ -14,12 +14,15 @@
 
 use assert_matches::assert_matches;
 use itertools::Itertools;
-use jujutsu_lib::backend::{ConflictPart, TreeValue};
+use jujutsu_lib::backend::{ConflictPart, TreeId, TreeValue};
 use jujutsu_lib::commit_builder::CommitBuilder;
+use jujutsu_lib::files::{
+    FileMergeStrategies, MergeGranularity, MergeOptions, MergeResult, MergeStrategy,
+};
 use jujutsu_lib::repo_path::{RepoPath, RepoPathComponent};
 use jujutsu_lib::rewrite::rebase_commit;
 use jujutsu_lib::tree;
-use jujutsu_lib::tree::Tree;
+use jujutsu_lib::tree::{FileMergeKey, MergedRename, ModeConflict, Tree, TreeMergeOptions};
 use test_case::test_case;
 use testutils::TestRepo;
 
@@ -634,3 +637,508 @@ fn test_simplify_conflict_after_resolving_parent(use_git: bool) {
 
 // TODO: Add tests for simplification of multi-way conflicts. Both the content
 // and the executable bit need testing.
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_file_merge_strategies(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let changelog_path = RepoPath::from_internal_string("CHANGELOG.md");
+    let lock_path = RepoPath::from_internal_string("dir/Cargo.lock");
+    let image_path = RepoPath::from_internal_string("image.bin");
+    let data_path = RepoPath::from_internal_string("data.txt");
+    let other_path = RepoPath::from_internal_string("other");
+    let base_tree = testutils::create_tree(
+        repo,
+        &[
+            (&changelog_path, "a\n"),
+            (&lock_path, "a\n"),
+            (&image_path, "\0a\n"),
+            (&data_path, "a\nb\nc\n"),
+            (&other_path, "a\n"),
+        ],
+    );
+    let side1_tree = testutils::create_tree(
+        repo,
+        &[
+            (&changelog_path, "a\nb\n"),
+            (&lock_path, "b\n"),
+            (&image_path, "\0b\n"),
+            (&data_path, "A\nb\nc\n"),
+            (&other_path, "b\n"),
+        ],
+    );
+    let side2_tree = testutils::create_tree(
+        repo,
+        &[
+            (&changelog_path, "a\nc\n"),
+            (&lock_path, "c\n"),
+            (&image_path, "\0c\n"),
+            (&data_path, "a\nb\nC\n"),
+            (&other_path, "c\n"),
+        ],
+    );
+
+    let options = TreeMergeOptions {
+        file_strategies: FileMergeStrategies::new([
+            ("CHANGELOG.md".to_string(), "union".parse().unwrap()),
+            ("Cargo.lock".to_string(), "theirs".parse().unwrap()),
+            ("*.bin".to_string(), "union".parse().unwrap()),
+            ("data.txt".to_string(), "binary".parse().unwrap()),
+        ]),
+        ..Default::default()
+    };
+    let merged_tree_id =
+        tree::merge_trees_with_options(&side1_tree, &base_tree, &side2_tree, &options).unwrap();
+    let merged_tree = store.get_tree(&RepoPath::root(), &merged_tree_id).unwrap();
+    let file_content = |path: &RepoPath| match merged_tree.path_value(path) {
+        Some(TreeValue::File { id, .. }) => testutils::read_file(store, path, &id),
+        other => panic!("unexpected value: {:#?}", other),
+    };
+
+    // Conflicting hunks are resolved with the strategy for the file
+    assert_eq!(file_content(&changelog_path), b"a\nb\nc\n");
+    // Patterns without a slash match in any directory
+    assert_eq!(file_content(&lock_path), b"c\n");
+    // Binary content isn't merged line by line, even with a textual strategy
+    assert_matches!(
+        merged_tree.path_value(&image_path),
+        Some(TreeValue::Conflict(_))
+    );
+    // The binary strategy leaves the file conflicted even though its lines
+    // would merge cleanly
+    assert_matches!(
+        merged_tree.path_value(&data_path),
+        Some(TreeValue::Conflict(_))
+    );
+    // Files that don't match any pattern are merged as usual
+    assert_matches!(
+        merged_tree.path_value(&other_path),
+        Some(TreeValue::Conflict(_))
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_trees_with_virtual_base(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let write_tree =
+        |contents: &str| -> Tree { testutils::create_tree(repo, &[(&path, contents)]) };
+    let get_tree = |tree_id: TreeId| store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    // B and C, the merge bases, conflict on the first line. Both sides resolved
+    // that conflict in the same way, and one side also changed the last line.
+    let tree_a = write_tree("1\nx\n2\n");
+    let tree_b = write_tree("b1\nx\n2\n");
+    let tree_c = write_tree("c1\nx\n2\n");
+    let virtual_base_tree = get_tree(tree::merge_trees(&tree_b, &tree_a, &tree_c).unwrap());
+    assert_matches!(
+        virtual_base_tree.path_value(&path),
+        Some(TreeValue::Conflict(_))
+    );
+    let side1_tree = write_tree("bc1\nx\nD2\n");
+    let side2_tree = write_tree("bc1\nx\n2\n");
+
+    // Merging the conflict from the virtual base as it is doesn't resolve it
+    let merged_tree =
+        get_tree(tree::merge_trees(&side1_tree, &virtual_base_tree, &side2_tree).unwrap());
+    assert_matches!(merged_tree.path_value(&path), Some(TreeValue::Conflict(_)));
+
+    // With the conflict in the base materialized, both sides made the same change
+    let merged_tree = get_tree(
+        tree::merge_trees_with_virtual_base(
+            &side1_tree,
+            &virtual_base_tree,
+            &side2_tree,
+            &TreeMergeOptions::default(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(merged_tree.path_value(&path), side1_tree.path_value(&path));
+
+    // Conflicts that don't involve the virtual base's conflict are kept
+    let side2_tree = write_tree("bc2\nx\n2\n");
+    let merged_tree = get_tree(
+        tree::merge_trees_with_virtual_base(
+            &side1_tree,
+            &virtual_base_tree,
+            &side2_tree,
+            &TreeMergeOptions::default(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(
+        merged_tree.id(),
+        &tree::merge_trees(&side1_tree, &virtual_base_tree, &side2_tree).unwrap()
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_trees_with_renames(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let old_path = RepoPath::from_internal_string("dir/old");
+    let new_path = RepoPath::from_internal_string("new");
+    let other_path = RepoPath::from_internal_string("other");
+    let get_tree = |tree_id: TreeId| store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let base_tree = testutils::create_tree(repo, &[(&old_path, "1\n2\n3\n4\n")]);
+    // Side 1 renames the file and changes the first line. Side 2 changes the
+    // last line.
+    let side1_tree = testutils::create_tree(repo, &[(&new_path, "one\n2\n3\n4\n")]);
+    let side2_tree = testutils::create_tree(repo, &[(&old_path, "1\n2\n3\nfour\n")]);
+    let expected_tree = testutils::create_tree(repo, &[(&new_path, "one\n2\n3\nfour\n")]);
+
+    // Merging path by path results in a delete/modify conflict
+    let merged_tree = get_tree(tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap());
+    assert_matches!(
+        merged_tree.path_value(&old_path),
+        Some(TreeValue::Conflict(_))
+    );
+
+    // Following the rename applies the change to the renamed file
+    let (merged_tree_id, renames) = tree::merge_trees_with_renames(
+        &side1_tree,
+        &base_tree,
+        &side2_tree,
+        &TreeMergeOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(merged_tree_id, *expected_tree.id());
+    assert_eq!(
+        renames,
+        vec![MergedRename {
+            source: old_path.clone(),
+            target: new_path.clone(),
+        }]
+    );
+    // Also when the other side renamed the file
+    let (merged_tree_id, _) = tree::merge_trees_with_renames(
+        &side2_tree,
+        &base_tree,
+        &side1_tree,
+        &TreeMergeOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(merged_tree_id, *expected_tree.id());
+
+    // The setting makes the regular tree merge follow renames
+    let options = TreeMergeOptions {
+        detect_renames: true,
+        ..TreeMergeOptions::default()
+    };
+    assert_eq!(
+        tree::merge_trees_with_options(&side1_tree, &base_tree, &side2_tree, &options).unwrap(),
+        *expected_tree.id()
+    );
+
+    // A file that isn't similar enough isn't a rename
+    let side1_tree = testutils::create_tree(repo, &[(&new_path, "a\nb\nc\n4\n")]);
+    let (merged_tree_id, renames) = tree::merge_trees_with_renames(
+        &side1_tree,
+        &base_tree,
+        &side2_tree,
+        &TreeMergeOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        merged_tree_id,
+        tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap()
+    );
+    assert_eq!(renames, vec![]);
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_trees_with_diverging_renames(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let old_path = RepoPath::from_internal_string("old");
+    let side1_path = RepoPath::from_internal_string("new1");
+    let side2_path = RepoPath::from_internal_string("new2");
+    let base_tree = testutils::create_tree(repo, &[(&old_path, "1\n2\n3\n")]);
+    let side1_tree = testutils::create_tree(repo, &[(&side1_path, "1\n2\n3\n")]);
+    let side2_tree = testutils::create_tree(repo, &[(&side2_path, "1\n2\nthree\n")]);
+
+    // Both sides renamed the file to different names. The paths merge cleanly,
+    // so the file ends up at both.
+    let (merged_tree_id, renames) = tree::merge_trees_with_renames(
+        &side1_tree,
+        &base_tree,
+        &side2_tree,
+        &TreeMergeOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(renames, vec![]);
+    assert_eq!(
+        merged_tree_id,
+        tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap()
+    );
+    let merged_tree = store.get_tree(&RepoPath::root(), &merged_tree_id).unwrap();
+    assert_eq!(merged_tree.path_value(&old_path), None);
+    assert_eq!(
+        merged_tree.path_value(&side1_path),
+        side1_tree.path_value(&side1_path)
+    );
+    assert_eq!(
+        merged_tree.path_value(&side2_path),
+        side2_tree.path_value(&side2_path)
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_trees_with_progress(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    let unchanged_path = RepoPath::from_internal_string("unchanged/file");
+    let side1_path = RepoPath::from_internal_string("dir/side1");
+    let both_path = RepoPath::from_internal_string("dir/both");
+    let same_path = RepoPath::from_internal_string("dir/same");
+    let base_tree = testutils::create_tree(
+        repo,
+        &[
+            (&unchanged_path, "unchanged\n"),
+            (&side1_path, "base\n"),
+            (&both_path, "a\nb\nc\n"),
+            (&same_path, "base\n"),
+        ],
+    );
+    let side1_tree = testutils::create_tree(
+        repo,
+        &[
+            (&unchanged_path, "unchanged\n"),
+            (&side1_path, "side 1\n"),
+            (&both_path, "x\nb\nc\n"),
+            (&same_path, "same\n"),
+        ],
+    );
+    let side2_tree = testutils::create_tree(
+        repo,
+        &[
+            (&unchanged_path, "unchanged\n"),
+            (&side1_path, "base\n"),
+            (&both_path, "a\nb\ny\n"),
+            (&same_path, "same\n"),
+        ],
+    );
+
+    // Only the file that the sides changed in different ways is reported
+    let mut reported = vec![];
+    let merged_tree_id = tree::merge_trees_with_progress(
+        &side1_tree,
+        &base_tree,
+        &side2_tree,
+        &TreeMergeOptions::default(),
+        &mut |progress| reported.push((progress.merged, progress.total, progress.path.clone())),
+    )
+    .unwrap();
+    assert_eq!(reported, vec![(0, 1, both_path.clone())]);
+    assert_eq!(
+        merged_tree_id,
+        tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap()
+    );
+
+    // Nothing is reported if one side is unchanged
+    let mut reported = vec![];
+    let merged_tree_id = tree::merge_trees_with_progress(
+        &side1_tree,
+        &base_tree,
+        &base_tree,
+        &TreeMergeOptions::default(),
+        &mut |progress| reported.push(progress.path.clone()),
+    )
+    .unwrap();
+    assert_eq!(reported, vec![]);
+    assert_eq!(merged_tree_id, *side1_tree.id());
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_trees_caches_file_merges(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let base_tree = testutils::create_tree(repo, &[(&path, "a\nb\nc\n")]);
+    let side1_tree = testutils::create_tree(repo, &[(&path, "x\nb\nc\n")]);
+    let side2_tree = testutils::create_tree(repo, &[(&path, "a\nb\ny\n")]);
+    let file_id = |tree: &Tree| match tree.path_value(&path) {
+        Some(TreeValue::File { id, .. }) => id,
+        value => panic!("unexpected value {value:?}"),
+    };
+    let key = |options: &MergeOptions| {
+        FileMergeKey::new(
+            vec![file_id(&base_tree)],
+            vec![file_id(&side1_tree), file_id(&side2_tree)],
+            options,
+        )
+    };
+    let word_options = MergeOptions {
+        granularity: MergeGranularity::Word,
+        ..MergeOptions::default()
+    };
+    assert_eq!(store.get_cached_merge(&key(&MergeOptions::default())), None);
+
+    let merged_tree_id = tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap();
+    assert_eq!(
+        store
+            .get_cached_merge(&key(&MergeOptions::default()))
+            .as_deref(),
+        Some(&MergeResult::Resolved(b"x\nb\ny\n".to_vec()))
+    );
+    // Options that affect how the contents are merged are part of the key
+    assert_eq!(store.get_cached_merge(&key(&word_options)), None);
+
+    // Merging the same contents again gives the same result
+    let side1_tree = testutils::create_tree(
+        repo,
+        &[
+            (&path, "x\nb\nc\n"),
+            (&RepoPath::from_internal_string("other"), ""),
+        ],
+    );
+    let merged_tree = store.get_tree(&RepoPath::root(), &merged_tree_id).unwrap();
+    let remerged_tree_id = tree::merge_trees(&side1_tree, &base_tree, &side2_tree).unwrap();
+    let remerged_tree = store
+        .get_tree(&RepoPath::root(), &remerged_tree_id)
+        .unwrap();
+    assert_eq!(
+        remerged_tree.path_value(&path),
+        merged_tree.path_value(&path)
+    );
+}
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_executable_bit(use_git: bool) {
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+    let store = repo.store();
+
+    let path = RepoPath::from_internal_string("file");
+    let write_tree = |contents: &str, executable: bool| -> Tree {
+        let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+        if executable {
+            testutils::write_executable_file(&mut tree_builder, &path, contents);
+        } else {
+            testutils::write_normal_file(&mut tree_builder, &path, contents);
+        }
+        let tree_id = tree_builder.write_tree();
+        store.get_tree(&RepoPath::root(), &tree_id).unwrap()
+    };
+    let merge = |side1_tree: &Tree, base_tree: &Tree, side2_tree: &Tree| {
+        let merged_tree_id = tree::merge_trees(side1_tree, base_tree, side2_tree).unwrap();
+        store
+            .get_tree(&RepoPath::root(), &merged_tree_id)
+            .unwrap()
+            .path_value(&path)
+    };
+
+    // Each side changes either the content or the executable bit, and the
+    // content and the bit are merged separately
+    let base_tree = write_tree("a\nb\nc\n", false);
+    let content1_tree = write_tree("x\nb\nc\n", false);
+    let content2_tree = write_tree("a\nb\ny\n", false);
+    let mode1_tree = write_tree("a\nb\nc\n", true);
+    let mode2_tree = write_tree("a\nb\nc\n", true);
+    let expected_value = |contents: &str, executable: bool| {
+        Some(TreeValue::File {
+            id: testutils::write_file(store, &path, contents),
+            executable,
+        })
+    };
+    assert_eq!(
+        merge(&content1_tree, &base_tree, &content2_tree),
+        expected_value("x\nb\ny\n", false)
+    );
+    assert_eq!(
+        merge(&content1_tree, &base_tree, &mode2_tree),
+        expected_value("x\nb\nc\n", true)
+    );
+    assert_eq!(
+        merge(&mode1_tree, &base_tree, &content2_tree),
+        expected_value("a\nb\ny\n", true)
+    );
+    assert_eq!(
+        merge(&mode1_tree, &base_tree, &mode2_tree),
+        expected_value("a\nb\nc\n", true)
+    );
+
+    // Sides that add the file with different bits conflict only on the bit
+    let empty_tree = store
+        .get_tree(&RepoPath::root(), store.empty_tree_id())
+        .unwrap();
+    let added1_tree = write_tree("x\nb\nc\n", true);
+    let added2_tree = write_tree("x\nb\nc\n", false);
+    let conflict_id = match merge(&added1_tree, &empty_tree, &added2_tree) {
+        Some(TreeValue::Conflict(conflict_id)) => conflict_id,
+        value => panic!("unexpected value: {value:?}"),
+    };
+    let conflict = store.read_conflict(&path, &conflict_id).unwrap();
+    let mode_conflict = tree::mode_only_conflict(store, &path, &conflict)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        mode_conflict,
+        ModeConflict {
+            removes: vec![],
+            adds: vec![true, false],
+        }
+    );
+    // Resolving the bit keeps the content
+    let contents = b"x\nb\nc\n".to_vec();
+    for (strategy, executable) in [
+        (MergeStrategy::Ours, true),
+        (MergeStrategy::Theirs, false),
+        (MergeStrategy::Union, true),
+    ] {
+        assert_eq!(
+            tree::resolve_file_conflict_with_strategy(store, &path, &conflict, strategy).unwrap(),
+            Some((contents.clone(), executable))
+        );
+    }
+
+    // A conflict in the content isn't a mode-only conflict
+    let added2_tree = write_tree("y\nb\nc\n", false);
+    let conflict_id = match merge(&added1_tree, &empty_tree, &added2_tree) {
+        Some(TreeValue::Conflict(conflict_id)) => conflict_id,
+        value => panic!("unexpected value: {value:?}"),
+    };
+    let conflict = store.read_conflict(&path, &conflict_id).unwrap();
+    assert_eq!(
+        tree::mode_only_conflict(store, &path, &conflict).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_merge_executable() {
+    assert_eq!(tree::merge_executable(&[false], &[true, false]), Ok(true));
+    assert_eq!(tree::merge_executable(&[false], &[true, true]), Ok(true));
+    assert_eq!(tree::merge_executable(&[true], &[true, true]), Ok(true));
+    assert_eq!(
+        tree::merge_executable(&[false, true], &[true, false, false]),
+        Ok(false)
+    );
+    assert_eq!(
+        tree::merge_executable(&[], &[true, false]),
+        Err(ModeConflict {
+            removes: vec![],
+            adds: vec![true, false],
+        })
+    );
+}
@@ -12,10 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use assert_matches::assert_matches;
+use jujutsu_lib::backend::TreeValue;
+use jujutsu_lib::commit::Commit;
 use jujutsu_lib::commit_builder::CommitBuilder;
 use jujutsu_lib::op_store::{RefTarget, WorkspaceId};
 use jujutsu_lib::repo_path::RepoPath;
-use jujutsu_lib::rewrite::DescendantRebaser;
+use jujutsu_lib::rewrite::{merge_commit_trees, DescendantRebaser};
+use jujutsu_lib::tree::merge_trees;
 use maplit::{hashmap, hashset};
 use test_case::test_case;
 use testutils::{assert_rebased, create_random_commit, CommitGraphBuilder, TestRepo};
@@ -1395,3 +1399,51 @@ fn test_rebase_descendants_update_checkout_abandoned_merge(use_git: bool) {
     let checkout = repo.store().get_commit(new_checkout_id).unwrap();
     assert_eq!(checkout.parent_ids(), vec![commit_b.id().clone()]);
 }
+
+#[test_case(false ; "local backend")]
+#[test_case(true ; "git backend")]
+fn test_merge_commit_trees_criss_cross(use_git: bool) {
+    let settings = testutils::user_settings();
+    let test_repo = TestRepo::init(use_git);
+    let repo = &test_repo.repo;
+
+    // D and E both merge B and C, so B and C are both merge bases of D and E.
+    // D then changed the first line, and E the last line.
+    //
+    // D E
+    // |X|
+    // B C
+    // |/
+    // A
+    let mut tx = repo.start_transaction(&settings, "test");
+    let path = RepoPath::from_internal_string("file");
+    let mut create_commit = |parents: Vec<&Commit>, contents: &str| {
+        let tree = testutils::create_tree(repo, &[(&path, contents)]);
+        let parent_ids = parents.iter().map(|parent| parent.id().clone()).collect();
+        CommitBuilder::for_new_commit(&settings, parent_ids, tree.id().clone())
+            .write_to_repo(tx.mut_repo())
+    };
+    let root_commit = repo.store().root_commit();
+    let commit_a = create_commit(vec![&root_commit], "1\nx\n2\n");
+    let commit_b = create_commit(vec![&commit_a], "B1\nx\n2\n");
+    let commit_c = create_commit(vec![&commit_a], "1\nx\nC2\n");
+    let commit_d = create_commit(vec![&commit_b, &commit_c], "D1\nx\nC2\n");
+    let commit_e = create_commit(vec![&commit_c, &commit_b], "B1\nx\nE2\n");
+
+    // Merging with either merge base alone conflicts
+    let store = repo.store();
+    for base in [&commit_b, &commit_c] {
+        let tree_id = merge_trees(&commit_d.tree(), &base.tree(), &commit_e.tree()).unwrap();
+        let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+        assert_matches!(tree.path_value(&path), Some(TreeValue::Conflict(_)));
+    }
+
+    // Merging with the merge of the merge bases resolves cleanly
+    let merged_tree = merge_commit_trees(tx.repo().as_repo_ref(), &[commit_d, commit_e]);
+    match merged_tree.path_value(&path) {
+        Some(TreeValue::File { id, .. }) => {
+            assert_eq!(testutils::read_file(store, &path, &id), b"D1\nx\nE2\n");
+        }
+        value => panic!("unexpected value: {value:?}"),
+    }
+}
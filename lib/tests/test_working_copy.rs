@@ -22,7 +22,11 @@ use std::sync::Arc;
 
 use itertools::Itertools;
 use jujutsu_lib::backend::{Conflict, ConflictPart, TreeValue};
+use jujutsu_lib::conflicts::{materialize_conflict, ConflictLabels};
+use jujutsu_lib::files::{MergeHunk, MergeResult};
 use jujutsu_lib::gitignore::GitIgnoreFile;
+use jujutsu_lib::merge_result_encoding;
+use jujutsu_lib::merge_result_encoding::StoredMergeResult;
 #[cfg(unix)]
 use jujutsu_lib::op_store::OperationId;
 use jujutsu_lib::op_store::WorkspaceId;
@@ -30,7 +34,7 @@ use jujutsu_lib::repo::ReadonlyRepo;
 use jujutsu_lib::repo_path::{RepoPath, RepoPathComponent, RepoPathJoin};
 use jujutsu_lib::settings::UserSettings;
 use jujutsu_lib::tree_builder::TreeBuilder;
-use jujutsu_lib::working_copy::WorkingCopy;
+use jujutsu_lib::working_copy::{conflict_sidecar_path, ConflictCheckoutMode, WorkingCopy};
 use test_case::test_case;
 use testutils::{create_random_commit, TestWorkspace};
 
@@ -281,6 +285,297 @@ fn test_checkout_file_transitions(use_git: bool) {
     }
 }
 
+#[test]
+fn test_checkout_large_conflict() {
+    // Conflicts are written to the file as they're materialized. Check that a
+    // large one with many conflict hunks comes out whole.
+    let settings = testutils::user_settings();
+    let mut test_workspace = TestWorkspace::init(&settings, false);
+    let repo = &test_workspace.repo;
+    let store = repo.store();
+    let workspace_root = test_workspace.workspace.workspace_root().clone();
+
+    let path = RepoPath::from_internal_string("file");
+    let contents = |changed: &str| {
+        (0..20_000)
+            .map(|i| {
+                if i % 1_000 == 500 {
+                    format!("{changed} {i}\n")
+                } else {
+                    format!("line {i}\n")
+                }
+            })
+            .collect::<String>()
+    };
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(store, &path, content),
+            executable: false,
+        },
+    };
+    let conflict = Conflict {
+        removes: vec![file_part(&contents("base"))],
+        adds: vec![file_part(&contents("left")), file_part(&contents("right"))],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+    tree_builder.set(path.clone(), TreeValue::Conflict(conflict_id));
+    let tree_id = tree_builder.write_tree();
+    let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let wc = test_workspace.workspace.working_copy_mut();
+    wc.check_out(repo.op_id().clone(), None, &tree).unwrap();
+
+    let mut expected = vec![];
+    materialize_conflict(store, &path, &conflict, &mut expected).unwrap();
+    let actual = std::fs::read(path.to_fs_path(&workspace_root)).unwrap();
+    assert_eq!(actual.len(), expected.len());
+    assert!(actual == expected);
+    assert_eq!(
+        actual
+            .windows(7)
+            .filter(|window| window == b"<<<<<<<")
+            .count(),
+        20
+    );
+}
+
+#[test]
+fn test_checkout_conflict_with_sidecar() {
+    // With a conflict checkout mode other than markers, a conflicted file gets one
+    // side of the conflict, and the whole conflict goes to a sidecar file
+    let settings = testutils::user_settings();
+    let mut test_workspace = TestWorkspace::init(&settings, false);
+    let repo = &test_workspace.repo;
+    let store = repo.store().clone();
+    let workspace_root = test_workspace.workspace.workspace_root().clone();
+
+    let file_part = |path: &RepoPath, content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(&store, path, content),
+            executable: false,
+        },
+    };
+    let paths = ["edited", "sidecar-deleted", "untouched"].map(RepoPath::from_internal_string);
+    let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+    for path in &paths {
+        let conflict = Conflict {
+            removes: vec![file_part(path, "base\n")],
+            adds: vec![file_part(path, "left\n"), file_part(path, "right\n")],
+        };
+        let conflict_id = store.write_conflict(path, &conflict).unwrap();
+        tree_builder.set(path.clone(), TreeValue::Conflict(conflict_id));
+    }
+    let tree_id = tree_builder.write_tree();
+    let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let wc = test_workspace.workspace.working_copy_mut();
+    wc.set_conflict_checkout_mode(ConflictCheckoutMode::Left);
+    wc.check_out(repo.op_id().clone(), None, &tree).unwrap();
+    let [edited_path, sidecar_deleted_path, untouched_path] =
+        paths.clone().map(|path| path.to_fs_path(&workspace_root));
+    assert_eq!(std::fs::read(&untouched_path).unwrap(), b"left\n");
+    let sidecar = std::fs::read(conflict_sidecar_path(&untouched_path)).unwrap();
+    let stored = merge_result_encoding::decode(&sidecar).unwrap();
+    assert_eq!(
+        stored.result,
+        MergeResult::conflict(vec![MergeHunk::Conflict {
+            removes: vec![b"base\n".to_vec()],
+            adds: vec![b"left\n".to_vec(), b"right\n".to_vec()]
+        }])
+    );
+
+    // Nothing changed, so the paths are still conflicted, and the sidecars aren't
+    // snapshotted as files
+    let mut locked_wc = wc.start_mutation();
+    let new_tree_id = locked_wc.snapshot(GitIgnoreFile::empty()).unwrap();
+    locked_wc.discard();
+    assert_eq!(new_tree_id, tree_id);
+
+    // Editing the file resolves the conflict, and deleting the sidecar resolves it
+    // as the side in the file
+    std::fs::write(&edited_path, "resolved\n").unwrap();
+    std::fs::remove_file(conflict_sidecar_path(&sidecar_deleted_path)).unwrap();
+    let mut locked_wc = wc.start_mutation();
+    let new_tree_id = locked_wc.snapshot(GitIgnoreFile::empty()).unwrap();
+    locked_wc.finish(repo.op_id().clone());
+    let new_tree = store.get_tree(&RepoPath::root(), &new_tree_id).unwrap();
+    let file_value = |content: &str| TreeValue::File {
+        id: testutils::write_file(&store, &paths[0], content),
+        executable: false,
+    };
+    assert_eq!(
+        new_tree.path_value(&paths[0]),
+        Some(file_value("resolved\n"))
+    );
+    assert_eq!(new_tree.path_value(&paths[1]), Some(file_value("left\n")));
+    assert_eq!(new_tree.path_value(&paths[2]), tree.path_value(&paths[2]));
+    assert!(!conflict_sidecar_path(&edited_path).exists());
+    assert!(conflict_sidecar_path(&untouched_path).exists());
+    assert_eq!(new_tree.entries().count(), 3);
+
+    // Checking out another tree removes the remaining sidecar
+    let empty_tree = store
+        .get_tree(&RepoPath::root(), store.empty_tree_id())
+        .unwrap();
+    wc.check_out(repo.op_id().clone(), Some(&new_tree_id), &empty_tree)
+        .unwrap();
+    assert!(!untouched_path.exists());
+    assert!(!conflict_sidecar_path(&untouched_path).exists());
+
+    // The base can be written instead
+    wc.set_conflict_checkout_mode(ConflictCheckoutMode::Base);
+    wc.check_out(repo.op_id().clone(), None, &tree).unwrap();
+    assert_eq!(std::fs::read(&untouched_path).unwrap(), b"base\n");
+    assert!(conflict_sidecar_path(&untouched_path).exists());
+}
+
+#[test]
+fn test_checkout_conflict_with_labels() {
+    // The conflict markers written on checkout are labeled by the labeler
+    let settings = testutils::user_settings();
+    let mut test_workspace = TestWorkspace::init(&settings, false);
+    let repo = &test_workspace.repo;
+    let store = repo.store().clone();
+    let workspace_root = test_workspace.workspace.workspace_root().clone();
+
+    let path = RepoPath::from_internal_string("file");
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(&store, &path, content),
+            executable: false,
+        },
+    };
+    let conflict = Conflict {
+        removes: vec![file_part("base\n")],
+        adds: vec![file_part("left\n"), file_part("right\n")],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+    tree_builder.set(path.clone(), TreeValue::Conflict(conflict_id));
+    let tree_id = tree_builder.write_tree();
+    let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let wc = test_workspace.workspace.working_copy_mut();
+    wc.set_conflict_labeler(Some(Arc::new(|_path, _conflict| ConflictLabels {
+        removes: vec![Some("base-label".to_string())],
+        adds: vec![
+            Some("left-label".to_string()),
+            Some("right-label".to_string()),
+        ],
+    })));
+    wc.check_out(repo.op_id().clone(), None, &tree).unwrap();
+    let content = std::fs::read_to_string(path.to_fs_path(&workspace_root)).unwrap();
+    assert!(content.contains("base-label"), "{}", content);
+    assert!(content.contains("right-label"), "{}", content);
+
+    // Labels are ignored when parsing, so editing one leaves the same conflict
+    std::fs::write(
+        path.to_fs_path(&workspace_root),
+        content.replace("right-label", "edited label"),
+    )
+    .unwrap();
+    let mut locked_wc = wc.start_mutation();
+    let new_tree_id = locked_wc.snapshot(GitIgnoreFile::empty()).unwrap();
+    locked_wc.discard();
+    assert_eq!(new_tree_id, tree_id);
+}
+
+#[test]
+fn test_checkout_conflict_keeps_existing_sidecar_path() {
+    // An untracked file where the sidecar would go isn't overwritten
+    let settings = testutils::user_settings();
+    let mut test_workspace = TestWorkspace::init(&settings, false);
+    let repo = &test_workspace.repo;
+    let store = repo.store().clone();
+    let workspace_root = test_workspace.workspace.workspace_root().clone();
+
+    let path = RepoPath::from_internal_string("file");
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(&store, &path, content),
+            executable: false,
+        },
+    };
+    let conflict = Conflict {
+        removes: vec![file_part("base\n")],
+        adds: vec![file_part("left\n"), file_part("right\n")],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+    tree_builder.set(path.clone(), TreeValue::Conflict(conflict_id));
+    let tree_id = tree_builder.write_tree();
+    let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let sidecar_path = conflict_sidecar_path(&path.to_fs_path(&workspace_root));
+    std::fs::write(&sidecar_path, "untracked\n").unwrap();
+    let wc = test_workspace.workspace.working_copy_mut();
+    wc.set_conflict_checkout_mode(ConflictCheckoutMode::Left);
+    let result = wc.check_out(repo.op_id().clone(), None, &tree);
+    assert!(result.is_err());
+    assert_eq!(std::fs::read(&sidecar_path).unwrap(), b"untracked\n");
+    // The side isn't left behind without its sidecar, or it would be snapshotted
+    // as the resolution
+    assert!(!path.to_fs_path(&workspace_root).exists());
+}
+
+#[test]
+fn test_snapshot_conflict_from_sidecar() {
+    // Editing the sidecar of an untouched conflicted file edits the conflict
+    let settings = testutils::user_settings();
+    let mut test_workspace = TestWorkspace::init(&settings, false);
+    let repo = &test_workspace.repo;
+    let store = repo.store().clone();
+    let workspace_root = test_workspace.workspace.workspace_root().clone();
+
+    let path = RepoPath::from_internal_string("file");
+    let file_part = |content: &str| ConflictPart {
+        value: TreeValue::File {
+            id: testutils::write_file(&store, &path, content),
+            executable: false,
+        },
+    };
+    let conflict = Conflict {
+        removes: vec![file_part("base\n")],
+        adds: vec![file_part("left\n"), file_part("right\n")],
+    };
+    let conflict_id = store.write_conflict(&path, &conflict).unwrap();
+    let mut tree_builder = store.tree_builder(store.empty_tree_id().clone());
+    tree_builder.set(path.clone(), TreeValue::Conflict(conflict_id));
+    let tree_id = tree_builder.write_tree();
+    let tree = store.get_tree(&RepoPath::root(), &tree_id).unwrap();
+
+    let wc = test_workspace.workspace.working_copy_mut();
+    wc.set_conflict_checkout_mode(ConflictCheckoutMode::Left);
+    wc.check_out(repo.op_id().clone(), None, &tree).unwrap();
+    let sidecar_path = conflict_sidecar_path(&path.to_fs_path(&workspace_root));
+    let edited =
+        StoredMergeResult::without_origins(MergeResult::conflict(vec![MergeHunk::Conflict {
+            removes: vec![b"base\n".to_vec()],
+            adds: vec![b"left\n".to_vec(), b"edited right\n".to_vec()],
+        }]));
+    std::fs::write(&sidecar_path, merge_result_encoding::encode(&edited)).unwrap();
+    let mut locked_wc = wc.start_mutation();
+    let new_tree_id = locked_wc.snapshot(GitIgnoreFile::empty()).unwrap();
+    locked_wc.finish(repo.op_id().clone());
+    let new_tree = store.get_tree(&RepoPath::root(), &new_tree_id).unwrap();
+    let expected_conflict = Conflict {
+        removes: vec![file_part("base\n")],
+        adds: vec![file_part("left\n"), file_part("edited right\n")],
+    };
+    let expected_conflict_id = store.write_conflict(&path, &expected_conflict).unwrap();
+    assert_eq!(
+        new_tree.path_value(&path),
+        Some(TreeValue::Conflict(expected_conflict_id))
+    );
+
+    // A sidecar that isn't a conflict of the same shape is an error
+    std::fs::write(&sidecar_path, "not a conflict\n").unwrap();
+    let mut locked_wc = wc.start_mutation();
+    assert!(locked_wc.snapshot(GitIgnoreFile::empty()).is_err());
+    locked_wc.discard();
+}
+
 #[test]
 fn test_reset() {
     let settings = testutils::user_settings();
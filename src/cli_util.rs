@@ -27,9 +27,10 @@ use clap::builder::{NonEmptyStringValueParser, TypedValueParser, ValueParserFact
 use clap::{Arg, ArgMatches, Command, Error, FromArgMatches};
 use git2::{Oid, Repository};
 use itertools::Itertools;
-use jujutsu_lib::backend::{BackendError, CommitId, TreeId};
+use jujutsu_lib::backend::{BackendError, CommitId, Conflict, ConflictPart, TreeId};
 use jujutsu_lib::commit::Commit;
 use jujutsu_lib::commit_builder::CommitBuilder;
+use jujutsu_lib::conflicts::{ConflictLabeler, ConflictLabels};
 use jujutsu_lib::git::{GitExportError, GitImportError};
 use jujutsu_lib::gitignore::GitIgnoreFile;
 use jujutsu_lib::matchers::{EverythingMatcher, Matcher, PrefixMatcher, Visit};
@@ -44,7 +45,7 @@ use jujutsu_lib::revset::{
 };
 use jujutsu_lib::settings::UserSettings;
 use jujutsu_lib::transaction::Transaction;
-use jujutsu_lib::tree::{Tree, TreeMergeError};
+use jujutsu_lib::tree::{Tree, TreeMergeError, TreeMergeOptions};
 use jujutsu_lib::working_copy::{
     CheckoutStats, LockedWorkingCopy, ResetError, SnapshotError, WorkingCopy,
 };
@@ -52,7 +53,7 @@ use jujutsu_lib::workspace::{Workspace, WorkspaceInitError, WorkspaceLoadError};
 use jujutsu_lib::{dag_walk, file_util, git, revset};
 
 use crate::config::read_config;
-use crate::diff_edit::DiffEditError;
+use crate::diff_edit::{DiffEditError, ExternalMergeError};
 use crate::formatter::Formatter;
 use crate::templater::TemplateFormatter;
 use crate::ui::{ColorChoice, Ui};
@@ -115,8 +116,11 @@ impl From<BackendError> for CommandError {
 }
 
 impl From<WorkspaceInitError> for CommandError {
-    fn from(_: WorkspaceInitError) -> Self {
-        user_error("The target repo already exists")
+    fn from(err: WorkspaceInitError) -> Self {
+        match err {
+            WorkspaceInitError::Config(err) => CommandError::ConfigError(err.to_string()),
+            _ => user_error("The target repo already exists"),
+        }
     }
 }
 
@@ -154,6 +158,12 @@ impl From<DiffEditError> for CommandError {
     }
 }
 
+impl From<ExternalMergeError> for CommandError {
+    fn from(err: ExternalMergeError) -> Self {
+        user_error(format!("Failed to resolve conflicts: {err}"))
+    }
+}
+
 impl From<git2::Error> for CommandError {
     fn from(err: git2::Error) -> Self {
         user_error(format!("Git operation failed: {err}"))
@@ -260,8 +270,12 @@ jj init --git-repo=.",
                     )),
                     WorkspaceLoadError::Path(e) => user_error(format!("{}: {}", e, e.error)),
                     WorkspaceLoadError::NonUnicodePath => user_error(err.to_string()),
+                    WorkspaceLoadError::Config(err) => CommandError::ConfigError(err.to_string()),
                 }
             })?;
+        // Rebases can't report invalid merge settings, so they're checked up
+        // front
+        TreeMergeOptions::from_settings(ui.settings())?;
         let repo_loader = workspace.repo_loader();
         let op_heads = resolve_op_for_load(
             repo_loader.op_store(),
@@ -481,6 +495,10 @@ impl WorkspaceCommandHelper {
         &self.repo
     }
 
+    pub fn settings(&self) -> &UserSettings {
+        &self.settings
+    }
+
     pub fn working_copy(&self) -> &WorkingCopy {
         self.workspace.working_copy()
     }
@@ -662,6 +680,7 @@ impl WorkspaceCommandHelper {
             }
         };
         let base_ignores = self.base_ignores();
+        let reuse_resolutions = self.reuse_resolutions();
         let mut locked_wc = self.workspace.working_copy_mut().start_mutation();
         // Check if the working copy commit matches the repo's view. It's fine if it
         // doesn't, but we'll need to reload the repo so the new commit is
@@ -703,6 +722,7 @@ impl WorkspaceCommandHelper {
                         short_operation_hash(wc_operation.id()),
                         short_operation_hash(repo_operation.id()),
                     )?;
+                    locked_wc.set_conflict_labeler(Some(conflict_labeler(&repo, &wc_commit)));
                     locked_wc.check_out(&wc_commit.tree()).map_err(|err| {
                         CommandError::InternalError(format!(
                             "Failed to check out commit {}: {}",
@@ -728,6 +748,20 @@ impl WorkspaceCommandHelper {
             }
         }
         let new_tree_id = locked_wc.snapshot(base_ignores)?;
+        let malformed_conflicts = locked_wc.malformed_conflicts().to_vec();
+        if new_tree_id != *wc_commit.tree_id() && reuse_resolutions {
+            let new_tree = self
+                .repo
+                .store()
+                .get_tree(&RepoPath::root(), &new_tree_id)?;
+            let num_recorded = self
+                .repo
+                .resolution_store()
+                .record_resolved_conflicts(&wc_commit.tree(), &new_tree)?;
+            if num_recorded > 0 {
+                writeln!(ui, "Recorded {} conflict resolutions", num_recorded)?;
+            }
+        }
         if new_tree_id != *wc_commit.tree_id() {
             let mut tx = self
                 .repo
@@ -753,6 +787,34 @@ impl WorkspaceCommandHelper {
             self.repo = tx.commit();
         }
         locked_wc.finish(self.repo.op_id().clone());
+        for (path, err) in &malformed_conflicts {
+            ui.write_warn(format!(
+                "File {} has malformed conflict markers ({}); treating as resolved text\n",
+                self.format_file_path(path),
+                err
+            ))?;
+        }
+        self.print_reused_resolutions(ui)?;
+        Ok(())
+    }
+
+    /// Whether conflict resolutions should be recorded and reused
+    /// (`merge.reuse-resolutions`).
+    fn reuse_resolutions(&self) -> bool {
+        self.settings
+            .config()
+            .get_bool("merge.reuse-resolutions")
+            .unwrap_or(false)
+    }
+
+    fn print_reused_resolutions(&self, ui: &mut Ui) -> Result<(), std::io::Error> {
+        for path in self.repo.resolution_store().take_applied() {
+            writeln!(
+                ui,
+                "Reused recorded conflict resolution in {}",
+                self.format_file_path(&path)
+            )?;
+        }
         Ok(())
     }
 
@@ -860,6 +922,7 @@ impl WorkspaceCommandHelper {
             .map(|commit_id| store.get_commit(commit_id))
             .transpose()?;
         self.repo = tx.commit();
+        self.print_reused_resolutions(ui)?;
         if self.may_update_working_copy {
             let stats = update_working_copy(
                 ui,
@@ -1100,6 +1163,7 @@ fn update_working_copy(
     let stats = if Some(new_commit.tree_id()) != old_tree_id.as_ref() {
         // TODO: CheckoutError::ConcurrentCheckout should probably just result in a
         // warning for most commands (but be an error for the checkout command)
+        wc.set_conflict_labeler(Some(conflict_labeler(repo, &new_commit)));
         let stats = wc
             .check_out(
                 repo.op_id().clone(),
@@ -1134,6 +1198,60 @@ fn update_working_copy(
     Ok(stats)
 }
 
+/// Returns a labeler for the conflicts in `commit`. A side of a conflict at a
+/// path is labeled with the parent that has the same value there, and a base
+/// with the parents' common ancestor that has it, so it's easier to tell where
+/// each part came from. Parts that don't match any of them are left
+/// unlabeled.
+pub fn conflict_labeler(repo: &ReadonlyRepo, commit: &Commit) -> ConflictLabeler {
+    let parents = commit.parents();
+    let parent_ids = parents
+        .iter()
+        .map(|parent| parent.id().clone())
+        .collect_vec();
+    let ancestors = if parent_ids.len() > 1 {
+        repo.index()
+            .common_ancestors(&parent_ids[..1], &parent_ids[1..])
+            .iter()
+            // An ancestor that can't be read just leaves its parts unlabeled
+            .filter_map(|id| repo.store().get_commit(id).ok())
+            .collect_vec()
+    } else {
+        vec![]
+    };
+    Arc::new(move |path: &RepoPath, conflict: &Conflict| {
+        let label = |candidates: &[Commit], part: &ConflictPart| {
+            candidates
+                .iter()
+                .find(|candidate| candidate.tree().path_value(path).as_ref() == Some(&part.value))
+                .map(|candidate| {
+                    let first_line = candidate.description().lines().next().unwrap_or_default();
+                    format!(
+                        "{} {}",
+                        &candidate.change_id().hex()[..12],
+                        if first_line.is_empty() {
+                            "(no description set)"
+                        } else {
+                            first_line
+                        }
+                    )
+                })
+        };
+        ConflictLabels {
+            removes: conflict
+                .removes
+                .iter()
+                .map(|part| label(&ancestors, part))
+                .collect(),
+            adds: conflict
+                .adds
+                .iter()
+                .map(|part| label(&parents, part))
+                .collect(),
+        }
+    })
+}
+
 pub fn write_commit_summary(
     formatter: &mut dyn Formatter,
     repo: RepoRef,
@@ -1229,9 +1347,10 @@ pub struct GlobalArgs {
         default_value = "@"
     )]
     pub at_operation: String,
-    /// When to colorize output (always, never, auto)
+    /// When to colorize output
     #[arg(
         long,
+        value_enum,
         value_name = "WHEN",
         global = true,
         help_heading = "Global Options"
@@ -1435,10 +1554,11 @@ pub fn handle_command_result(ui: &mut Ui, result: Result<(), CommandError>) -> i
     match result {
         Ok(()) => 0,
         Err(CommandError::UserError { message, hint }) => {
-            ui.write_error(&format!("Error: {}\n", message)).unwrap();
+            let mut ui_message = ui.message().error(format!("Error: {}\n", message));
             if let Some(hint) = hint {
-                ui.write_hint(&format!("Hint: {}\n", hint)).unwrap();
+                ui_message = ui_message.hint(format!("Hint: {}\n", hint));
             }
+            ui_message.flush().unwrap();
             1
         }
         Err(CommandError::ConfigError(message)) => {
@@ -27,12 +27,15 @@ use chrono::{FixedOffset, LocalResult, TimeZone, Utc};
 use clap::builder::NonEmptyStringValueParser;
 use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Subcommand};
 use itertools::Itertools;
-use jujutsu_lib::backend::{BackendError, CommitId, Timestamp, TreeValue};
+use jujutsu_lib::backend::{BackendError, CommitId, Conflict, Timestamp, TreeId, TreeValue};
 use jujutsu_lib::commit::Commit;
 use jujutsu_lib::commit_builder::CommitBuilder;
+use jujutsu_lib::conflicts::{ConflictLabeler, ConflictLabels, ConflictMarkerStyle};
 use jujutsu_lib::dag_walk::topo_order_reverse;
 use jujutsu_lib::diff::{Diff, DiffHunk};
-use jujutsu_lib::files::DiffLine;
+use jujutsu_lib::files::{
+    BomChange, ConflictStats, ControlCharStyle, DiffLine, DiffOptions, MergeStrategy,
+};
 use jujutsu_lib::git::{GitFetchError, GitRefUpdate};
 use jujutsu_lib::index::IndexEntry;
 use jujutsu_lib::matchers::{EverythingMatcher, Matcher};
@@ -41,12 +44,15 @@ use jujutsu_lib::operation::Operation;
 use jujutsu_lib::refs::{classify_branch_push_action, BranchPushAction, BranchPushUpdate};
 use jujutsu_lib::repo::{ReadonlyRepo, RepoRef};
 use jujutsu_lib::repo_path::RepoPath;
+use jujutsu_lib::resolution_store::ResolutionId;
 use jujutsu_lib::revset::{RevsetAliasesMap, RevsetExpression};
 use jujutsu_lib::revset_graph_iterator::{RevsetGraphEdge, RevsetGraphEdgeType};
-use jujutsu_lib::rewrite::{back_out_commit, merge_commit_trees, rebase_commit, DescendantRebaser};
+use jujutsu_lib::rewrite::{
+    back_out_commit, merge_commit_trees, merge_options_for_repo, rebase_commit, DescendantRebaser,
+};
 use jujutsu_lib::settings::UserSettings;
 use jujutsu_lib::store::Store;
-use jujutsu_lib::tree::{merge_trees, Tree, TreeDiffIterator};
+use jujutsu_lib::tree::{merge_trees, merge_trees_with_options, Tree, TreeDiffIterator};
 use jujutsu_lib::view::View;
 use jujutsu_lib::workspace::Workspace;
 use jujutsu_lib::{conflicts, diff, file_util, files, git, revset, tree};
@@ -54,10 +60,11 @@ use maplit::{hashmap, hashset};
 use pest::Parser;
 
 use crate::cli_util::{
-    print_checkout_stats, print_failed_git_export, resolve_base_revs, short_commit_description,
-    short_commit_hash, user_error, user_error_with_hint, write_commit_summary, Args, CommandError,
-    CommandHelper, RevisionArg, WorkspaceCommandHelper,
+    conflict_labeler, print_checkout_stats, print_failed_git_export, resolve_base_revs,
+    short_commit_description, short_commit_hash, user_error, user_error_with_hint,
+    write_commit_summary, Args, CommandError, CommandHelper, RevisionArg, WorkspaceCommandHelper,
 };
+use crate::diff_edit;
 use crate::formatter::{Formatter, PlainTextFormatter};
 use crate::graphlog::{AsciiGraphDrawer, Edge};
 use crate::progress::Progress;
@@ -89,6 +96,9 @@ enum Commands {
     Squash(SquashArgs),
     Unsquash(UnsquashArgs),
     Restore(RestoreArgs),
+    Resolve(ResolveArgs),
+    #[command(subcommand)]
+    Resolutions(ResolutionsCommands),
     Touchup(TouchupArgs),
     Split(SplitArgs),
     /// Merge work from multiple branches
@@ -519,6 +529,35 @@ struct RestoreArgs {
     paths: Vec<String>,
 }
 
+/// Resolve conflicted files with a built-in or external merge tool
+///
+/// With a built-in tool, the file contents are merged and each conflicting
+/// region is resolved by the tool: `:ours` takes the first side, `:theirs`
+/// takes the last side, and `:union` takes every side in order, skipping sides
+/// identical to an earlier one.
+///
+/// Any other tool name refers to an external 3-way merge tool configured in
+/// `merge-tools.<name>`. Without `--tool`, the tools listed in
+/// `ui.merge-editor` are tried in order. A file stays conflicted if the tool
+/// exits with a non-zero code. External tools can only resolve conflicts with
+/// two sides.
+///
+/// Conflicts that involve anything other than files (e.g. a file that was
+/// deleted on one side) are left unresolved.
+#[derive(clap::Args, Clone, Debug)]
+struct ResolveArgs {
+    /// The revision to resolve conflicts in
+    #[arg(long, short, default_value = "@")]
+    revision: RevisionArg,
+    /// The merge tool to use (`:ours`, `:theirs`, `:union`, or the name of an
+    /// external tool)
+    #[arg(long)]
+    tool: Option<String>,
+    /// Resolve only conflicts at these paths (instead of all paths)
+    #[arg(value_hint = clap::ValueHint::AnyPath)]
+    paths: Vec<String>,
+}
+
 /// Touch up the content changes in a revision
 ///
 /// Starts a diff editor (`meld` by default) on the changes in the revision.
@@ -739,6 +778,32 @@ struct OperationUndoArgs {
     operation: String,
 }
 
+/// Manage recorded conflict resolutions
+///
+/// When `merge.reuse-resolutions` is enabled, resolving a conflict in the
+/// working copy records the resolution, and later merges that produce an
+/// identical conflict reuse it.
+#[derive(Subcommand, Clone, Debug)]
+enum ResolutionsCommands {
+    List(ResolutionsListArgs),
+    Forget(ResolutionsForgetArgs),
+}
+
+/// List recorded conflict resolutions
+#[derive(clap::Args, Clone, Debug)]
+struct ResolutionsListArgs {}
+
+/// Forget recorded conflict resolutions
+#[derive(clap::Args, Clone, Debug)]
+#[command(group(ArgGroup::new("target").args(&["ids", "all"]).required(true)))]
+struct ResolutionsForgetArgs {
+    /// Ids of the resolutions to forget
+    ids: Vec<String>,
+    /// Forget all recorded resolutions
+    #[arg(long)]
+    all: bool,
+}
+
 /// Commands for working with workspaces
 #[derive(Subcommand, Clone, Debug)]
 enum WorkspaceCommands {
@@ -1222,7 +1287,16 @@ fn cmd_print(ui: &mut Ui, command: &CommandHelper, args: &PrintArgs) -> Result<(
         Some(TreeValue::Conflict(id)) => {
             let conflict = repo.store().read_conflict(&path, &id)?;
             let mut contents = vec![];
-            conflicts::materialize_conflict(repo.store(), &path, &conflict, &mut contents).unwrap();
+            conflicts::materialize_conflict_with_labels(
+                repo.store(),
+                &path,
+                &conflict,
+                &DiffOptions::default(),
+                ConflictMarkerStyle::from_settings(ui.settings())?,
+                &conflict_labeler(repo, &commit)(&path, &conflict),
+                &mut contents,
+            )
+            .unwrap();
             ui.stdout_formatter().write_all(&contents)?;
         }
         _ => {
@@ -1235,15 +1309,29 @@ fn cmd_print(ui: &mut Ui, command: &CommandHelper, args: &PrintArgs) -> Result<(
 fn show_color_words_diff_hunks(
     left: &[u8],
     right: &[u8],
+    diff_options: &DiffOptions,
     formatter: &mut dyn Formatter,
 ) -> io::Result<()> {
-    let num_context_lines = 3;
+    let num_context_lines = diff_options.context_lines;
+    // The context lines are filtered below so we can indicate where lines were
+    // skipped.
+    let line_diff_options = diff_options.clone().set_context_lines(usize::MAX);
     let mut context = VecDeque::new();
     // Have we printed "..." for any skipped context?
     let mut skipped_context = false;
     // Are the lines in `context` to be printed before the next modified line?
     let mut context_before = true;
-    for diff_line in files::diff(left, right) {
+    let diff_lines = files::diff_with_options(left, right, &line_diff_options);
+    match diff_lines.bom_change() {
+        Some(BomChange::Added) => formatter.with_label(DIFF_ADDED_LABEL, |formatter| {
+            formatter.write_bytes(b"    (byte order mark added)\n")
+        })?,
+        Some(BomChange::Removed) => formatter.with_label(DIFF_REMOVED_LABEL, |formatter| {
+            formatter.write_bytes(b"    (byte order mark removed)\n")
+        })?,
+        None => {}
+    }
+    for diff_line in diff_lines {
         if diff_line.is_unmodified() {
             context.push_back(diff_line.clone());
             if context.len() > num_context_lines {
@@ -1254,7 +1342,7 @@ fn show_color_words_diff_hunks(
                 }
                 if !context_before {
                     for line in &context {
-                        show_color_words_diff_line(formatter, line)?;
+                        show_color_words_diff_line(formatter, line, diff_options)?;
                     }
                     context.clear();
                     context_before = true;
@@ -1266,21 +1354,23 @@ fn show_color_words_diff_hunks(
             }
         } else {
             for line in &context {
-                show_color_words_diff_line(formatter, line)?;
+                show_color_words_diff_line(formatter, line, diff_options)?;
             }
             context.clear();
-            show_color_words_diff_line(formatter, &diff_line)?;
+            show_color_words_diff_line(formatter, &diff_line, diff_options)?;
             context_before = false;
             skipped_context = false;
         }
     }
     if !context_before {
         for line in &context {
-            show_color_words_diff_line(formatter, line)?;
+            show_color_words_diff_line(formatter, line, diff_options)?;
         }
     }
 
     // If the last diff line doesn't end with newline, add it.
+    let (_, left) = files::split_bom(left);
+    let (_, right) = files::split_bom(right);
     let no_hunk = left.is_empty() && right.is_empty();
     let any_last_newline = left.ends_with(b"\n") || right.ends_with(b"\n");
     if !skipped_context && !no_hunk && !any_last_newline {
@@ -1290,12 +1380,28 @@ fn show_color_words_diff_hunks(
     Ok(())
 }
 
+/// Label for removed content in diffs. Color rules can target it within the
+/// "diff" label, e.g. `"diff removed"`.
+const DIFF_REMOVED_LABEL: &str = "removed";
+/// Label for added content in diffs, e.g. `"diff added"`.
+const DIFF_ADDED_LABEL: &str = "added";
+/// Label for the words that changed within a removed or added line of a
+/// Git-format diff, nested in the line's label, e.g. `"diff removed token"`.
+const DIFF_TOKEN_LABEL: &str = "token";
+/// Label for lines that are part of a moved block, when `diff.detect-moves`
+/// is enabled, e.g. `"diff moved removed"`.
+const DIFF_MOVED_LABEL: &str = "moved";
+
 fn show_color_words_diff_line(
     formatter: &mut dyn Formatter,
     diff_line: &DiffLine,
+    diff_options: &DiffOptions,
 ) -> io::Result<()> {
+    if diff_line.moved_block.is_some() {
+        formatter.add_label(DIFF_MOVED_LABEL)?;
+    }
     if diff_line.has_left_content {
-        formatter.with_label("removed", |formatter| {
+        formatter.with_label(DIFF_REMOVED_LABEL, |formatter| {
             formatter.write_bytes(format!("{:>4}", diff_line.left_line_number).as_bytes())
         })?;
         formatter.write_bytes(b" ")?;
@@ -1303,30 +1409,49 @@ fn show_color_words_diff_line(
         formatter.write_bytes(b"     ")?;
     }
     if diff_line.has_right_content {
-        formatter.with_label("added", |formatter| {
+        formatter.with_label(DIFF_ADDED_LABEL, |formatter| {
             formatter.write_bytes(format!("{:>4}", diff_line.right_line_number).as_bytes())
         })?;
         formatter.write_bytes(b": ")?;
     } else {
         formatter.write_bytes(b"    : ")?;
     }
+    // Tabs are expanded relative to the start of the content, after the line
+    // numbers. Control characters are escaped first, so the columns account for
+    // them.
+    let mut column = 0;
+    let mut expand = |data: &[u8]| {
+        let escaped = files::escape_control_chars(data, diff_options.control_chars);
+        let (expanded, next_column) = files::expand_tabs(&escaped, column, diff_options.tab_width);
+        column = next_column;
+        expanded.into_owned()
+    };
     for hunk in &diff_line.hunks {
         match hunk {
             DiffHunk::Matching(data) => {
-                formatter.write_bytes(data)?;
+                formatter.write_bytes(&expand(data))?;
             }
             DiffHunk::Different(data) => {
                 let before = data[0];
                 let after = data[1];
                 if !before.is_empty() {
-                    formatter.with_label("removed", |formatter| formatter.write_bytes(before))?;
+                    let expanded = expand(before);
+                    formatter.with_label(DIFF_REMOVED_LABEL, |formatter| {
+                        formatter.write_bytes(&expanded)
+                    })?;
                 }
                 if !after.is_empty() {
-                    formatter.with_label("added", |formatter| formatter.write_bytes(after))?;
+                    let expanded = expand(after);
+                    formatter.with_label(DIFF_ADDED_LABEL, |formatter| {
+                        formatter.write_bytes(&expanded)
+                    })?;
                 }
             }
         }
     }
+    if diff_line.moved_block.is_some() {
+        formatter.remove_label()?;
+    }
 
     Ok(())
 }
@@ -1334,28 +1459,50 @@ fn show_color_words_diff_line(
 fn cmd_diff(ui: &mut Ui, command: &CommandHelper, args: &DiffArgs) -> Result<(), CommandError> {
     ui.request_pager();
     let workspace_command = command.workspace_helper(ui)?;
+    let repo = workspace_command.repo();
     let from_tree;
     let to_tree;
+    let labelers;
     if args.from.is_some() || args.to.is_some() {
         let from = workspace_command.resolve_single_rev(args.from.as_deref().unwrap_or("@"))?;
         from_tree = from.tree();
         let to = workspace_command.resolve_single_rev(args.to.as_deref().unwrap_or("@"))?;
         to_tree = to.tree();
+        labelers = DiffConflictLabelers {
+            left: Some(conflict_labeler(repo, &from)),
+            right: Some(conflict_labeler(repo, &to)),
+        };
     } else {
         let commit =
             workspace_command.resolve_single_rev(args.revision.as_deref().unwrap_or("@"))?;
         let parents = commit.parents();
-        from_tree = merge_commit_trees(workspace_command.repo().as_repo_ref(), &parents);
-        to_tree = commit.tree()
+        from_tree = merge_commit_trees(repo.as_repo_ref(), &parents);
+        to_tree = commit.tree();
+        labelers = DiffConflictLabelers::for_commit(repo, &commit);
     }
     let matcher = workspace_command.matcher_from_values(&args.paths)?;
     let diff_iterator = from_tree.diff(&to_tree, matcher.as_ref());
-    show_diff(
-        ui.stdout_formatter().as_mut(),
-        &workspace_command,
-        diff_iterator,
-        diff_format_for(ui, &args.format),
-    )?;
+    let format = diff_format_for(ui, &args.format);
+    let diff_options = diff_options_for(ui);
+    if diff_group_by_dir(ui) {
+        show_diff_grouped_by_dir(
+            ui.stdout_formatter().as_mut(),
+            &workspace_command,
+            diff_iterator,
+            format,
+            &diff_options,
+            &labelers,
+        )?;
+    } else {
+        show_diff(
+            ui.stdout_formatter().as_mut(),
+            &workspace_command,
+            diff_iterator,
+            format,
+            &diff_options,
+            &labelers,
+        )?;
+    }
     Ok(())
 }
 
@@ -1398,6 +1545,8 @@ fn cmd_show(ui: &mut Ui, command: &CommandHelper, args: &ShowArgs) -> Result<(),
         &workspace_command,
         diff_iterator,
         diff_format_for(ui, &args.format),
+        &diff_options_for(ui),
+        &DiffConflictLabelers::for_commit(workspace_command.repo(), &commit),
     )?;
     Ok(())
 }
@@ -1426,34 +1575,280 @@ fn diff_format_for(ui: &Ui, args: &DiffFormatArgs) -> DiffFormat {
     }
 }
 
+/// Labelers for the conflicts on the two sides of a diff. A side whose tree
+/// isn't the tree of a commit, such as the merged parents of a merge commit,
+/// isn't labeled.
+#[derive(Clone, Default)]
+struct DiffConflictLabelers {
+    left: Option<ConflictLabeler>,
+    right: Option<ConflictLabeler>,
+}
+
+impl DiffConflictLabelers {
+    /// For the diff between the parents of `commit` and `commit`.
+    fn for_commit(repo: &ReadonlyRepo, commit: &Commit) -> Self {
+        let left = match commit.parents().as_slice() {
+            [parent] => Some(conflict_labeler(repo, parent)),
+            _ => None,
+        };
+        DiffConflictLabelers {
+            left,
+            right: Some(conflict_labeler(repo, commit)),
+        }
+    }
+}
+
 fn show_diff(
     formatter: &mut dyn Formatter,
     workspace_command: &WorkspaceCommandHelper,
-    tree_diff: TreeDiffIterator,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
     format: DiffFormat,
+    diff_options: &DiffOptions,
+    labelers: &DiffConflictLabelers,
 ) -> Result<(), CommandError> {
     match format {
         DiffFormat::Summary => {
             show_diff_summary(formatter, workspace_command, tree_diff)?;
         }
         DiffFormat::Git => {
-            show_git_diff(formatter, workspace_command, tree_diff)?;
+            show_git_diff(
+                formatter,
+                workspace_command,
+                tree_diff,
+                diff_options,
+                labelers,
+            )?;
         }
         DiffFormat::ColorWords => {
-            show_color_words_diff(formatter, workspace_command, tree_diff)?;
+            show_color_words_diff(
+                formatter,
+                workspace_command,
+                tree_diff,
+                diff_options,
+                labelers,
+            )?;
         }
     }
     Ok(())
 }
 
+/// Width of the line-number gutter printed before each line by the
+/// color-words format.
+const COLOR_WORDS_GUTTER_WIDTH: usize = 11;
+
+fn diff_options_for(ui: &Ui) -> DiffOptions {
+    let options = DiffOptions::from_settings(ui.settings());
+    let truncate = ui
+        .settings()
+        .config()
+        .get_bool("diff.truncate-long-lines")
+        .unwrap_or(true);
+    if !ui.stdout_is_tty() {
+        // There's no terminal to protect, and patches must keep their content
+        return options.set_control_chars(ControlCharStyle::Verbatim);
+    }
+    if !truncate {
+        return options;
+    }
+    let max_line_width = ui
+        .size()
+        .map(|(cols, _rows)| usize::from(cols).saturating_sub(COLOR_WORDS_GUTTER_WIDTH))
+        .filter(|width| *width > 0);
+    options.set_max_line_width(max_line_width)
+}
+
+fn diff_group_by_dir(ui: &Ui) -> bool {
+    ui.settings()
+        .config()
+        .get_bool("diff.group-by-dir")
+        .unwrap_or(false)
+}
+
+/// Number of changed files and lines, as shown in a diffstat.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct DiffStat {
+    files: usize,
+    added_lines: usize,
+    removed_lines: usize,
+}
+
+impl DiffStat {
+    fn add(&mut self, other: &DiffStat) {
+        self.files += other.files;
+        self.added_lines += other.added_lines;
+        self.removed_lines += other.removed_lines;
+    }
+}
+
+impl std::fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn plural(count: usize) -> &'static str {
+            if count == 1 {
+                ""
+            } else {
+                "s"
+            }
+        }
+        write!(
+            f,
+            "{} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+            self.files,
+            plural(self.files),
+            self.added_lines,
+            plural(self.added_lines),
+            self.removed_lines,
+            plural(self.removed_lines)
+        )
+    }
+}
+
+fn diff_stat_for_content(left_content: &[u8], right_content: &[u8]) -> DiffStat {
+    let mut stat = DiffStat {
+        files: 1,
+        ..Default::default()
+    };
+    let diff = Diff::for_tokenizer(&[left_content, right_content], &diff::find_line_ranges);
+    for hunk in diff.hunks() {
+        if let DiffHunk::Different(content) = hunk {
+            stat.removed_lines += content[0].split_inclusive(|b| *b == b'\n').count();
+            stat.added_lines += content[1].split_inclusive(|b| *b == b'\n').count();
+        }
+    }
+    stat
+}
+
+fn diff_stat_for_path(
+    repo: &Arc<ReadonlyRepo>,
+    path: &RepoPath,
+    diff: &tree::Diff<TreeValue>,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+    labelers: &DiffConflictLabelers,
+) -> Result<DiffStat, CommandError> {
+    let (left_content, right_content) = match diff {
+        tree::Diff::Added(right_value) => (
+            vec![],
+            diff_content(
+                repo,
+                path,
+                right_value,
+                diff_options,
+                marker_style,
+                labelers.right.as_ref(),
+            )?,
+        ),
+        tree::Diff::Modified(left_value, right_value) => (
+            diff_content(
+                repo,
+                path,
+                left_value,
+                diff_options,
+                marker_style,
+                labelers.left.as_ref(),
+            )?,
+            diff_content(
+                repo,
+                path,
+                right_value,
+                diff_options,
+                marker_style,
+                labelers.right.as_ref(),
+            )?,
+        ),
+        tree::Diff::Removed(left_value) => (
+            diff_content(
+                repo,
+                path,
+                left_value,
+                diff_options,
+                marker_style,
+                labelers.left.as_ref(),
+            )?,
+            vec![],
+        ),
+    };
+    Ok(diff_stat_for_content(&left_content, &right_content))
+}
+
+/// Like `show_diff()`, but groups the per-file diffs by top-level directory.
+/// Each group is preceded by a header with the directory's diffstat, and the
+/// total diffstat is printed at the end.
+fn show_diff_grouped_by_dir(
+    formatter: &mut dyn Formatter,
+    workspace_command: &WorkspaceCommandHelper,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
+    format: DiffFormat,
+    diff_options: &DiffOptions,
+    labelers: &DiffConflictLabelers,
+) -> Result<(), CommandError> {
+    let repo = workspace_command.repo();
+    let marker_style = ConflictMarkerStyle::from_settings(workspace_command.settings())?;
+    let mut total_stat = DiffStat::default();
+    // Files at the root can sort between directories, so they're gathered into
+    // a single group first. The tree diff is ordered by path, so all entries in
+    // a top-level directory are adjacent.
+    let (root_entries, dir_entries): (Vec<_>, Vec<_>) =
+        tree_diff.partition(|(path, _)| path.components().len() <= 1);
+    let mut groups = vec![];
+    if !root_entries.is_empty() {
+        groups.push(("./".to_string(), root_entries));
+    }
+    for (dir, group) in &dir_entries
+        .into_iter()
+        .group_by(|(path, _)| path.components()[0].clone())
+    {
+        groups.push((format!("{}/", dir.as_str()), group.collect_vec()));
+    }
+    for (dir_name, entries) in groups {
+        let mut dir_stat = DiffStat::default();
+        for (path, diff) in &entries {
+            dir_stat.add(&diff_stat_for_path(
+                repo,
+                path,
+                diff,
+                diff_options,
+                marker_style,
+            )?);
+        }
+        total_stat.add(&dir_stat);
+        formatter.with_label("diff", |formatter| {
+            formatter.with_label("directory_header", |formatter| {
+                writeln!(formatter, "Directory {}: {}", dir_name, dir_stat)
+            })
+        })?;
+        show_diff(
+            formatter,
+            workspace_command,
+            entries.into_iter(),
+            format,
+            diff_options,
+            labelers,
+        )?;
+    }
+    formatter.with_label("diff", |formatter| {
+        formatter.with_label("directory_header", |formatter| {
+            writeln!(formatter, "Total: {}", total_stat)
+        })
+    })?;
+    Ok(())
+}
+
 fn diff_as_bytes(
     workspace_command: &WorkspaceCommandHelper,
-    tree_diff: TreeDiffIterator,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
     format: DiffFormat,
+    diff_options: &DiffOptions,
 ) -> Result<Vec<u8>, CommandError> {
     let mut diff_bytes: Vec<u8> = vec![];
     let mut formatter = PlainTextFormatter::new(&mut diff_bytes);
-    show_diff(&mut formatter, workspace_command, tree_diff, format)?;
+    show_diff(
+        &mut formatter,
+        workspace_command,
+        tree_diff,
+        format,
+        diff_options,
+        &DiffConflictLabelers::default(),
+    )?;
     Ok(diff_bytes)
 }
 
@@ -1461,6 +1856,9 @@ fn diff_content(
     repo: &Arc<ReadonlyRepo>,
     path: &RepoPath,
     value: &TreeValue,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+    labeler: Option<&ConflictLabeler>,
 ) -> Result<Vec<u8>, CommandError> {
     match value {
         TreeValue::File { id, .. } => {
@@ -1485,7 +1883,18 @@ fn diff_content(
         TreeValue::Conflict(id) => {
             let conflict = repo.store().read_conflict(path, id).unwrap();
             let mut content = vec![];
-            conflicts::materialize_conflict(repo.store(), path, &conflict, &mut content).unwrap();
+            let labels =
+                labeler.map_or_else(ConflictLabels::default, |labeler| labeler(path, &conflict));
+            conflicts::materialize_conflict_with_labels(
+                repo.store(),
+                path,
+                &conflict,
+                diff_options,
+                marker_style,
+                &labels,
+                &mut content,
+            )
+            .unwrap();
             Ok(content)
         }
     }
@@ -1510,24 +1919,48 @@ fn basic_diff_file_type(value: &TreeValue) -> String {
 fn show_color_words_diff(
     formatter: &mut dyn Formatter,
     workspace_command: &WorkspaceCommandHelper,
-    tree_diff: TreeDiffIterator,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
+    diff_options: &DiffOptions,
+    labelers: &DiffConflictLabelers,
 ) -> Result<(), CommandError> {
     let repo = workspace_command.repo();
+    let marker_style = ConflictMarkerStyle::from_settings(workspace_command.settings())?;
     formatter.add_label("diff")?;
     for (path, diff) in tree_diff {
         let ui_path = workspace_command.format_file_path(&path);
         match diff {
             tree::Diff::Added(right_value) => {
-                let right_content = diff_content(repo, &path, &right_value)?;
+                let right_content = diff_content(
+                    repo,
+                    &path,
+                    &right_value,
+                    diff_options,
+                    marker_style,
+                    labelers.right.as_ref(),
+                )?;
                 let description = basic_diff_file_type(&right_value);
                 formatter.with_label("header", |formatter| {
                     formatter.write_str(&format!("Added {} {}:\n", description, ui_path))
                 })?;
-                show_color_words_diff_hunks(&[], &right_content, formatter)?;
+                show_color_words_diff_hunks(&[], &right_content, diff_options, formatter)?;
             }
             tree::Diff::Modified(left_value, right_value) => {
-                let left_content = diff_content(repo, &path, &left_value)?;
-                let right_content = diff_content(repo, &path, &right_value)?;
+                let left_content = diff_content(
+                    repo,
+                    &path,
+                    &left_value,
+                    diff_options,
+                    marker_style,
+                    labelers.left.as_ref(),
+                )?;
+                let right_content = diff_content(
+                    repo,
+                    &path,
+                    &right_value,
+                    diff_options,
+                    marker_style,
+                    labelers.right.as_ref(),
+                )?;
                 let description = match (left_value, right_value) {
                     (
                         TreeValue::File {
@@ -1572,15 +2005,27 @@ fn show_color_words_diff(
                 formatter.with_label("header", |formatter| {
                     formatter.write_str(&format!("{} {}:\n", description, ui_path))
                 })?;
-                show_color_words_diff_hunks(&left_content, &right_content, formatter)?;
+                show_color_words_diff_hunks(
+                    &left_content,
+                    &right_content,
+                    diff_options,
+                    formatter,
+                )?;
             }
             tree::Diff::Removed(left_value) => {
-                let left_content = diff_content(repo, &path, &left_value)?;
+                let left_content = diff_content(
+                    repo,
+                    &path,
+                    &left_value,
+                    diff_options,
+                    marker_style,
+                    labelers.left.as_ref(),
+                )?;
                 let description = basic_diff_file_type(&left_value);
                 formatter.with_label("header", |formatter| {
                     formatter.write_str(&format!("Removed {} {}:\n", description, ui_path))
                 })?;
-                show_color_words_diff_hunks(&left_content, &[], formatter)?;
+                show_color_words_diff_hunks(&left_content, &[], diff_options, formatter)?;
             }
         }
     }
@@ -1598,6 +2043,9 @@ fn git_diff_part(
     repo: &Arc<ReadonlyRepo>,
     path: &RepoPath,
     value: &TreeValue,
+    diff_options: &DiffOptions,
+    marker_style: ConflictMarkerStyle,
+    labeler: Option<&ConflictLabeler>,
 ) -> Result<GitDiffPart, CommandError> {
     let mode;
     let hash;
@@ -1634,7 +2082,18 @@ fn git_diff_part(
             mode = "100644".to_string();
             hash = id.hex();
             let conflict = repo.store().read_conflict(path, id).unwrap();
-            conflicts::materialize_conflict(repo.store(), path, &conflict, &mut content).unwrap();
+            let labels =
+                labeler.map_or_else(ConflictLabels::default, |labeler| labeler(path, &conflict));
+            conflicts::materialize_conflict_with_labels(
+                repo.store(),
+                path,
+                &conflict,
+                diff_options,
+                marker_style,
+                &labels,
+                &mut content,
+            )
+            .unwrap();
         }
     }
     let hash = hash[0..10].to_string();
@@ -1655,13 +2114,72 @@ enum DiffLineType {
 struct UnifiedDiffHunk<'content> {
     left_line_range: Range<usize>,
     right_line_range: Range<usize>,
-    lines: Vec<(DiffLineType, &'content [u8])>,
+    /// Each line with the ranges of the words that changed within it, relative
+    /// to the start of the line. Context lines have no changed words.
+    lines: Vec<(DiffLineType, &'content [u8], Vec<Range<usize>>)>,
+}
+
+/// Finds the ranges of words that differ between `left` and `right`, so they
+/// can be emphasized within the changed lines. Returns no ranges if nothing
+/// matches, since emphasizing everything would emphasize nothing.
+fn changed_token_ranges(left: &[u8], right: &[u8]) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let mut left_ranges = vec![];
+    let mut right_ranges = vec![];
+    let mut left_pos = 0;
+    let mut right_pos = 0;
+    let mut any_matching = false;
+    for hunk in diff::diff(left, right) {
+        match hunk {
+            DiffHunk::Matching(content) => {
+                any_matching = true;
+                left_pos += content.len();
+                right_pos += content.len();
+            }
+            DiffHunk::Different(content) => {
+                left_ranges.push(left_pos..left_pos + content[0].len());
+                right_ranges.push(right_pos..right_pos + content[1].len());
+                left_pos += content[0].len();
+                right_pos += content[1].len();
+            }
+        }
+    }
+    if any_matching {
+        (left_ranges, right_ranges)
+    } else {
+        (vec![], vec![])
+    }
+}
+
+/// Splits `content` into lines, each with the parts of `token_ranges` that fall
+/// within it.
+fn split_lines_with_tokens<'content>(
+    content: &'content [u8],
+    token_ranges: &[Range<usize>],
+) -> Vec<(&'content [u8], Vec<Range<usize>>)> {
+    let mut line_start = 0;
+    content
+        .split_inclusive(|b| *b == b'\n')
+        .map(|line| {
+            let line_end = line_start + line.len();
+            let tokens = token_ranges
+                .iter()
+                .filter_map(|range| {
+                    let start = range.start.max(line_start);
+                    let end = range.end.min(line_end);
+                    (start < end).then(|| start - line_start..end - line_start)
+                })
+                .collect_vec();
+            line_start = line_end;
+            (line, tokens)
+        })
+        .collect_vec()
 }
 
 fn unified_diff_hunks<'content>(
     left_content: &'content [u8],
     right_content: &'content [u8],
     num_context_lines: usize,
+    intra_line: bool,
 ) -> Vec<UnifiedDiffHunk<'content>> {
     let mut hunks = vec![];
     let mut current_hunk = UnifiedDiffHunk {
@@ -1684,7 +2202,9 @@ fn unified_diff_hunks<'content>(
                 current_hunk.left_line_range.end += num_after_lines;
                 current_hunk.right_line_range.end += num_after_lines;
                 for line in lines.iter().take(num_after_lines) {
-                    current_hunk.lines.push((DiffLineType::Context, line));
+                    current_hunk
+                        .lines
+                        .push((DiffLineType::Context, line, vec![]));
                 }
                 let num_skip_lines = lines
                     .len()
@@ -1706,23 +2226,32 @@ fn unified_diff_hunks<'content>(
                 current_hunk.left_line_range.end += num_before_lines;
                 current_hunk.right_line_range.end += num_before_lines;
                 for line in lines.iter().skip(num_after_lines + num_skip_lines) {
-                    current_hunk.lines.push((DiffLineType::Context, line));
+                    current_hunk
+                        .lines
+                        .push((DiffLineType::Context, line, vec![]));
                 }
             }
             DiffHunk::Different(content) => {
                 show_context_after = true;
-                let left_lines = content[0].split_inclusive(|b| *b == b'\n').collect_vec();
-                let right_lines = content[1].split_inclusive(|b| *b == b'\n').collect_vec();
+                let (left_tokens, right_tokens) = if intra_line {
+                    changed_token_ranges(content[0], content[1])
+                } else {
+                    (vec![], vec![])
+                };
+                let left_lines = split_lines_with_tokens(content[0], &left_tokens);
+                let right_lines = split_lines_with_tokens(content[1], &right_tokens);
                 if !left_lines.is_empty() {
                     current_hunk.left_line_range.end += left_lines.len();
-                    for line in left_lines {
-                        current_hunk.lines.push((DiffLineType::Removed, line));
+                    for (line, tokens) in left_lines {
+                        current_hunk
+                            .lines
+                            .push((DiffLineType::Removed, line, tokens));
                     }
                 }
                 if !right_lines.is_empty() {
                     current_hunk.right_line_range.end += right_lines.len();
-                    for line in right_lines {
-                        current_hunk.lines.push((DiffLineType::Added, line));
+                    for (line, tokens) in right_lines {
+                        current_hunk.lines.push((DiffLineType::Added, line, tokens));
                     }
                 }
             }
@@ -1731,7 +2260,7 @@ fn unified_diff_hunks<'content>(
     if !current_hunk
         .lines
         .iter()
-        .all(|(diff_type, _line)| *diff_type == DiffLineType::Context)
+        .all(|(diff_type, _line, _tokens)| *diff_type == DiffLineType::Context)
     {
         hunks.push(current_hunk);
     }
@@ -1742,8 +2271,14 @@ fn show_unified_diff_hunks(
     formatter: &mut dyn Formatter,
     left_content: &[u8],
     right_content: &[u8],
+    diff_options: &DiffOptions,
 ) -> Result<(), CommandError> {
-    for hunk in unified_diff_hunks(left_content, right_content, 3) {
+    for hunk in unified_diff_hunks(
+        left_content,
+        right_content,
+        diff_options.context_lines,
+        diff_options.intra_line,
+    ) {
         formatter.with_label("hunk_header", |formatter| {
             writeln!(
                 formatter,
@@ -1754,24 +2289,37 @@ fn show_unified_diff_hunks(
                 hunk.right_line_range.len()
             )
         })?;
-        for (line_type, content) in hunk.lines {
+        for (line_type, content, tokens) in hunk.lines {
             match line_type {
                 DiffLineType::Context => {
                     formatter.with_label("context", |formatter| {
                         formatter.write_str(" ")?;
-                        formatter.write_all(content)
+                        formatter.write_all(&files::escape_control_chars(
+                            content,
+                            diff_options.control_chars,
+                        ))
                     })?;
                 }
                 DiffLineType::Removed => {
-                    formatter.with_label("removed", |formatter| {
+                    formatter.with_label(DIFF_REMOVED_LABEL, |formatter| {
                         formatter.write_str("-")?;
-                        formatter.write_all(content)
+                        write_line_with_tokens(
+                            formatter,
+                            content,
+                            &tokens,
+                            diff_options.control_chars,
+                        )
                     })?;
                 }
                 DiffLineType::Added => {
-                    formatter.with_label("added", |formatter| {
+                    formatter.with_label(DIFF_ADDED_LABEL, |formatter| {
                         formatter.write_str("+")?;
-                        formatter.write_all(content)
+                        write_line_with_tokens(
+                            formatter,
+                            content,
+                            &tokens,
+                            diff_options.control_chars,
+                        )
                     })?;
                 }
             }
@@ -1783,18 +2331,51 @@ fn show_unified_diff_hunks(
     Ok(())
 }
 
+fn write_line_with_tokens(
+    formatter: &mut dyn Formatter,
+    line: &[u8],
+    tokens: &[Range<usize>],
+    control_chars: ControlCharStyle,
+) -> io::Result<()> {
+    let mut pos = 0;
+    for token in tokens {
+        formatter.write_all(&files::escape_control_chars(
+            &line[pos..token.start],
+            control_chars,
+        ))?;
+        formatter.with_label(DIFF_TOKEN_LABEL, |formatter| {
+            formatter.write_all(&files::escape_control_chars(
+                &line[token.clone()],
+                control_chars,
+            ))
+        })?;
+        pos = token.end;
+    }
+    formatter.write_all(&files::escape_control_chars(&line[pos..], control_chars))
+}
+
 fn show_git_diff(
     formatter: &mut dyn Formatter,
     workspace_command: &WorkspaceCommandHelper,
-    tree_diff: TreeDiffIterator,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
+    diff_options: &DiffOptions,
+    labelers: &DiffConflictLabelers,
 ) -> Result<(), CommandError> {
     let repo = workspace_command.repo();
+    let marker_style = ConflictMarkerStyle::from_settings(workspace_command.settings())?;
     formatter.add_label("diff")?;
     for (path, diff) in tree_diff {
         let path_string = path.to_internal_file_string();
         match diff {
             tree::Diff::Added(right_value) => {
-                let right_part = git_diff_part(repo, &path, &right_value)?;
+                let right_part = git_diff_part(
+                    repo,
+                    &path,
+                    &right_value,
+                    diff_options,
+                    marker_style,
+                    labelers.right.as_ref(),
+                )?;
                 formatter.with_label("file_header", |formatter| {
                     writeln!(formatter, "diff --git a/{} b/{}", path_string, path_string)?;
                     writeln!(formatter, "new file mode {}", &right_part.mode)?;
@@ -1802,11 +2383,25 @@ fn show_git_diff(
                     writeln!(formatter, "--- /dev/null")?;
                     writeln!(formatter, "+++ b/{}", path_string)
                 })?;
-                show_unified_diff_hunks(formatter, &[], &right_part.content)?;
+                show_unified_diff_hunks(formatter, &[], &right_part.content, diff_options)?;
             }
             tree::Diff::Modified(left_value, right_value) => {
-                let left_part = git_diff_part(repo, &path, &left_value)?;
-                let right_part = git_diff_part(repo, &path, &right_value)?;
+                let left_part = git_diff_part(
+                    repo,
+                    &path,
+                    &left_value,
+                    diff_options,
+                    marker_style,
+                    labelers.left.as_ref(),
+                )?;
+                let right_part = git_diff_part(
+                    repo,
+                    &path,
+                    &right_value,
+                    diff_options,
+                    marker_style,
+                    labelers.right.as_ref(),
+                )?;
                 formatter.with_label("file_header", |formatter| {
                     writeln!(formatter, "diff --git a/{} b/{}", path_string, path_string)?;
                     if left_part.mode != right_part.mode {
@@ -1828,10 +2423,22 @@ fn show_git_diff(
                     }
                     Ok(())
                 })?;
-                show_unified_diff_hunks(formatter, &left_part.content, &right_part.content)?;
+                show_unified_diff_hunks(
+                    formatter,
+                    &left_part.content,
+                    &right_part.content,
+                    diff_options,
+                )?;
             }
             tree::Diff::Removed(left_value) => {
-                let left_part = git_diff_part(repo, &path, &left_value)?;
+                let left_part = git_diff_part(
+                    repo,
+                    &path,
+                    &left_value,
+                    diff_options,
+                    marker_style,
+                    labelers.left.as_ref(),
+                )?;
                 formatter.with_label("file_header", |formatter| {
                     writeln!(formatter, "diff --git a/{} b/{}", path_string, path_string)?;
                     writeln!(formatter, "deleted file mode {}", &left_part.mode)?;
@@ -1839,7 +2446,7 @@ fn show_git_diff(
                     writeln!(formatter, "--- a/{}", path_string)?;
                     writeln!(formatter, "+++ /dev/null")
                 })?;
-                show_unified_diff_hunks(formatter, &left_part.content, &[])?;
+                show_unified_diff_hunks(formatter, &left_part.content, &[], diff_options)?;
             }
         }
     }
@@ -1850,7 +2457,7 @@ fn show_git_diff(
 fn show_diff_summary(
     formatter: &mut dyn Formatter,
     workspace_command: &WorkspaceCommandHelper,
-    tree_diff: TreeDiffIterator,
+    tree_diff: impl Iterator<Item = (RepoPath, tree::Diff<TreeValue>)>,
 ) -> io::Result<()> {
     formatter.with_label("diff", |formatter| {
         for (repo_path, diff) in tree_diff {
@@ -1992,8 +2599,22 @@ fn cmd_status(
             formatter.with_label("conflict", |formatter| {
                 writeln!(formatter, "There are unresolved conflicts at these paths:")
             })?;
-            for (path, _) in conflicts {
-                writeln!(formatter, "{}", &workspace_command.format_file_path(&path))?;
+            for (path, conflict_id) in conflicts {
+                let conflict = repo.store().read_conflict(&path, &conflict_id)?;
+                let description = match tree::mode_only_conflict(repo.store(), &path, &conflict)? {
+                    Some(mode_conflict) => mode_conflict.note().to_string(),
+                    None => describe_conflict_stats(&conflicts::stored_conflict_stats(
+                        repo.store(),
+                        &path,
+                        &conflict,
+                    )),
+                };
+                writeln!(
+                    formatter,
+                    "{}: {}",
+                    &workspace_command.format_file_path(&path),
+                    description
+                )?;
             }
         }
     }
@@ -2001,6 +2622,28 @@ fn cmd_status(
     Ok(())
 }
 
+/// Describes how many conflicts a file has, like "2 conflicts (one involves a
+/// deletion)".
+fn describe_conflict_stats(stats: &ConflictStats) -> String {
+    let mut description = if stats.num_hunks == 1 {
+        "1 conflict".to_string()
+    } else {
+        format!("{} conflicts", stats.num_hunks)
+    };
+    match (stats.num_hunks, stats.num_deletion_hunks) {
+        (_, 0) => {}
+        (1, _) => description.push_str(" (involves a deletion)"),
+        (_, 1) => description.push_str(" (one involves a deletion)"),
+        (num_hunks, num_deletion_hunks) if num_deletion_hunks >= num_hunks => {
+            description.push_str(" (all involve a deletion)")
+        }
+        (_, num_deletion_hunks) => {
+            description.push_str(&format!(" ({num_deletion_hunks} involve a deletion)"))
+        }
+    }
+    description
+}
+
 fn log_template(settings: &UserSettings) -> String {
     // TODO: define a method on boolean values, so we can get auto-coloring
     //       with e.g. `conflict.then("conflict")`
@@ -2053,6 +2696,7 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
     let store = repo.store();
     let diff_format = (args.patch || args.diff_format.git || args.diff_format.summary)
         .then(|| diff_format_for(ui, &args.diff_format));
+    let diff_options = diff_options_for(ui);
 
     let template_string = match &args.template {
         Some(value) => value.to_string(),
@@ -2126,6 +2770,7 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
                         &commit,
                         matcher.as_ref(),
                         diff_format,
+                        &diff_options,
                     )?;
                 }
                 let node_symbol = if is_checkout { b"@" } else { b"o" };
@@ -2152,6 +2797,7 @@ fn cmd_log(ui: &mut Ui, command: &CommandHelper, args: &LogArgs) -> Result<(), C
                         &commit,
                         matcher.as_ref(),
                         diff_format,
+                        &diff_options,
                     )?;
                 }
             }
@@ -2187,12 +2833,20 @@ fn show_patch(
     commit: &Commit,
     matcher: &dyn Matcher,
     format: DiffFormat,
+    diff_options: &DiffOptions,
 ) -> Result<(), CommandError> {
     let parents = commit.parents();
     let from_tree = merge_commit_trees(workspace_command.repo().as_repo_ref(), &parents);
     let to_tree = commit.tree();
     let diff_iterator = from_tree.diff(&to_tree, matcher);
-    show_diff(formatter, workspace_command, diff_iterator, format)
+    show_diff(
+        formatter,
+        workspace_command,
+        diff_iterator,
+        format,
+        diff_options,
+        &DiffConflictLabelers::for_commit(workspace_command.repo(), commit),
+    )
 }
 
 fn cmd_obslog(ui: &mut Ui, command: &CommandHelper, args: &ObslogArgs) -> Result<(), CommandError> {
@@ -2208,6 +2862,7 @@ fn cmd_obslog(ui: &mut Ui, command: &CommandHelper, args: &ObslogArgs) -> Result
 
     let diff_format = (args.patch || args.diff_format.git || args.diff_format.summary)
         .then(|| diff_format_for(ui, &args.diff_format));
+    let diff_options = diff_options_for(ui);
 
     let template_string = match &args.template {
         Some(value) => value.to_string(),
@@ -2250,6 +2905,7 @@ fn cmd_obslog(ui: &mut Ui, command: &CommandHelper, args: &ObslogArgs) -> Result
                     &workspace_command,
                     &commit,
                     diff_format,
+                    &diff_options,
                 )?;
             }
             let node_symbol = if Some(commit.id()) == wc_commit_id {
@@ -2263,7 +2919,13 @@ fn cmd_obslog(ui: &mut Ui, command: &CommandHelper, args: &ObslogArgs) -> Result
         for commit in commits {
             template.format(&commit, formatter)?;
             if let Some(diff_format) = diff_format {
-                show_predecessor_patch(formatter, &workspace_command, &commit, diff_format)?;
+                show_predecessor_patch(
+                    formatter,
+                    &workspace_command,
+                    &commit,
+                    diff_format,
+                    &diff_options,
+                )?;
             }
         }
     }
@@ -2276,6 +2938,7 @@ fn show_predecessor_patch(
     workspace_command: &WorkspaceCommandHelper,
     commit: &Commit,
     diff_format: DiffFormat,
+    diff_options: &DiffOptions,
 ) -> Result<(), CommandError> {
     let predecessors = commit.predecessors();
     let predecessor = match predecessors.first() {
@@ -2284,7 +2947,18 @@ fn show_predecessor_patch(
     };
     let predecessor_tree = rebase_to_dest_parent(workspace_command, predecessor, commit)?;
     let diff_iterator = predecessor_tree.diff(&commit.tree(), &EverythingMatcher);
-    show_diff(formatter, workspace_command, diff_iterator, diff_format)
+    let labelers = DiffConflictLabelers {
+        left: None,
+        right: Some(conflict_labeler(workspace_command.repo(), commit)),
+    };
+    show_diff(
+        formatter,
+        workspace_command,
+        diff_iterator,
+        diff_format,
+        diff_options,
+        &labelers,
+    )
 }
 
 fn cmd_interdiff(
@@ -2300,11 +2974,17 @@ fn cmd_interdiff(
     let from_tree = rebase_to_dest_parent(&workspace_command, &from, &to)?;
     let matcher = workspace_command.matcher_from_values(&args.paths)?;
     let diff_iterator = from_tree.diff(&to.tree(), matcher.as_ref());
+    let labelers = DiffConflictLabelers {
+        left: None,
+        right: Some(conflict_labeler(workspace_command.repo(), &to)),
+    };
     show_diff(
         ui.stdout_formatter().as_mut(),
         &workspace_command,
         diff_iterator,
         diff_format_for(ui, &args.format),
+        &diff_options_for(ui),
+        &labelers,
     )
 }
 
@@ -2650,7 +3330,14 @@ from the source will be moved into the destination.
         .store()
         .get_tree(&RepoPath::root(), &new_parent_tree_id)?;
     // Apply the reverse of the selected changes onto the source
-    let new_source_tree_id = merge_trees(&source_tree, &new_parent_tree, &parent_tree)?;
+    let merge_options = merge_options_for_repo(ui.settings(), repo)?;
+    let new_source_tree_id = merge_trees_with_progress_indicator(
+        ui,
+        &source_tree,
+        &new_parent_tree,
+        &parent_tree,
+        &merge_options,
+    )?;
     let abandon_source = new_source_tree_id == *parent_tree.id();
     if abandon_source {
         mut_repo.record_abandoned_commit(source.id().clone());
@@ -2670,7 +3357,13 @@ from the source will be moved into the destination.
         destination = mut_repo.store().get_commit(&rebased_destination_id)?;
     }
     // Apply the selected changes onto the destination
-    let new_destination_tree_id = merge_trees(&destination.tree(), &parent_tree, &new_parent_tree)?;
+    let new_destination_tree_id = merge_trees_with_progress_indicator(
+        ui,
+        &destination.tree(),
+        &parent_tree,
+        &new_parent_tree,
+        &merge_options,
+    )?;
     let description = combine_messages(
         ui,
         workspace_command.repo(),
@@ -2897,6 +3590,138 @@ side. If you don't make any changes, then the operation will be aborted.
     Ok(())
 }
 
+fn cmd_resolve(
+    ui: &mut Ui,
+    command: &CommandHelper,
+    args: &ResolveArgs,
+) -> Result<(), CommandError> {
+    let strategy = match args.tool.as_deref() {
+        Some(tool) if tool.starts_with(':') => {
+            Some(tool[1..].parse::<MergeStrategy>().map_err(|_| {
+                user_error_with_hint(
+                    format!("Unknown merge tool: {}", tool),
+                    "The built-in merge tools are :ours, :theirs, and :union",
+                )
+            })?)
+        }
+        _ => None,
+    };
+    let external_tools = match strategy {
+        Some(_) => vec![],
+        None => diff_edit::merge_tool_names(ui.settings(), args.tool.as_deref())?,
+    };
+    let mut workspace_command = command.workspace_helper(ui)?;
+    let commit = workspace_command.resolve_single_rev(&args.revision)?;
+    workspace_command.check_rewriteable(&commit)?;
+    let matcher = workspace_command.matcher_from_values(&args.paths)?;
+    let tree = commit.tree();
+    let conflicts = tree
+        .conflicts()
+        .into_iter()
+        .filter(|(path, _)| matcher.matches(path))
+        .collect_vec();
+    if conflicts.is_empty() {
+        return Err(user_error("No conflicts found"));
+    }
+    let store = tree.store();
+    let mut tree_builder = store.tree_builder(commit.tree_id().clone());
+    let mut unresolved_paths = vec![];
+    for (repo_path, conflict_id) in conflicts {
+        let conflict = store.read_conflict(&repo_path, &conflict_id)?;
+        let resolved = match strategy {
+            Some(strategy) => {
+                tree::resolve_file_conflict_with_strategy(store, &repo_path, &conflict, strategy)?
+            }
+            None => resolve_with_external_tool(ui, store, &repo_path, &conflict, &external_tools)?,
+        };
+        match resolved {
+            Some((content, executable)) => {
+                let id = store.write_file(&repo_path, &mut content.as_slice())?;
+                tree_builder.set(repo_path, TreeValue::File { id, executable });
+            }
+            None => unresolved_paths.push(repo_path),
+        }
+    }
+    let tree_id = tree_builder.write_tree();
+    if &tree_id != commit.tree_id() {
+        let mut tx = workspace_command.start_transaction(&format!(
+            "resolve conflicts in commit {}",
+            commit.id().hex()
+        ));
+        let mut_repo = tx.mut_repo();
+        let new_commit = CommitBuilder::for_rewrite_from(ui.settings(), &commit)
+            .set_tree(tree_id)
+            .write_to_repo(mut_repo);
+        ui.write("Created ")?;
+        write_commit_summary(
+            ui.stdout_formatter().as_mut(),
+            mut_repo.as_repo_ref(),
+            &workspace_command.workspace_id(),
+            &new_commit,
+            ui.settings(),
+        )?;
+        ui.write("\n")?;
+        workspace_command.finish_transaction(ui, tx)?;
+    }
+    if !unresolved_paths.is_empty() {
+        writeln!(
+            ui,
+            "Could not resolve these conflicts with {}:",
+            args.tool.as_deref().unwrap_or("the merge tool")
+        )?;
+        for path in unresolved_paths {
+            writeln!(ui, "  {}", workspace_command.format_file_path(&path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the first available of `tool_names` on a conflict with two sides.
+/// Returns the merged content and whether the file should be executable, or
+/// `None` if the conflict can't be passed to the tool or the tool didn't
+/// resolve it.
+fn resolve_with_external_tool(
+    ui: &mut Ui,
+    store: &Store,
+    repo_path: &RepoPath,
+    conflict: &Conflict,
+    tool_names: &[String],
+) -> Result<Option<(Vec<u8>, bool)>, CommandError> {
+    let contents = match tree::read_file_conflict(store, repo_path, conflict)? {
+        Some(contents) if contents.removes.len() == 1 && contents.adds.len() == 2 => contents,
+        _ => return Ok(None),
+    };
+    // The tool only merges the contents, so it can't resolve a conflict between
+    // the executable bits
+    let executable = match contents.executable {
+        Ok(executable) => executable,
+        Err(_) => return Ok(None),
+    };
+    // Start the tool with the conflict markers in the output file, like the
+    // working copy would have
+    let mut initial_output = vec![];
+    conflicts::materialize_conflict_with_options(
+        store,
+        repo_path,
+        conflict,
+        &DiffOptions::default(),
+        ConflictMarkerStyle::from_settings(ui.settings())?,
+        &mut initial_output,
+    )?;
+    let file_name = repo_path
+        .components()
+        .last()
+        .map_or("file", |component| component.as_str());
+    let inputs = diff_edit::MergeToolInputs {
+        base: &contents.removes[0],
+        left: &contents.adds[0],
+        right: &contents.adds[1],
+        output: &initial_output,
+    };
+    let merged = diff_edit::run_merge_tool(ui.settings(), tool_names, file_name, &inputs)?;
+    Ok(merged.map(|content| (content, executable)))
+}
+
 fn cmd_touchup(
     ui: &mut Ui,
     command: &CommandHelper,
@@ -2946,7 +3771,12 @@ fn description_template_for_cmd_split(
     overall_commit_description: &str,
     diff_iter: TreeDiffIterator,
 ) -> Result<String, CommandError> {
-    let diff_summary_bytes = diff_as_bytes(workspace_command, diff_iter, DiffFormat::Summary)?;
+    let diff_summary_bytes = diff_as_bytes(
+        workspace_command,
+        diff_iter,
+        DiffFormat::Summary,
+        &DiffOptions::default(),
+    )?;
     let diff_summary = std::str::from_utf8(&diff_summary_bytes).expect(
         "Summary diffs and repo paths must always be valid UTF8.",
         // Double-check this assumption for diffs that include file content.
@@ -3878,6 +4708,59 @@ fn cmd_workspace_list(
     Ok(())
 }
 
+fn cmd_resolutions(
+    ui: &mut Ui,
+    command: &CommandHelper,
+    subcommand: &ResolutionsCommands,
+) -> Result<(), CommandError> {
+    match subcommand {
+        ResolutionsCommands::List(command_matches) => {
+            cmd_resolutions_list(ui, command, command_matches)
+        }
+        ResolutionsCommands::Forget(command_matches) => {
+            cmd_resolutions_forget(ui, command, command_matches)
+        }
+    }
+}
+
+fn cmd_resolutions_list(
+    ui: &mut Ui,
+    command: &CommandHelper,
+    _args: &ResolutionsListArgs,
+) -> Result<(), CommandError> {
+    let workspace_command = command.workspace_helper(ui)?;
+    let resolution_store = workspace_command.repo().resolution_store();
+    for id in resolution_store.list()? {
+        let content = resolution_store.get(&id)?.unwrap_or_default();
+        let first_line = content.split(|b| *b == b'\n').next().unwrap_or_default();
+        writeln!(ui, "{}: {}", id.hex(), String::from_utf8_lossy(first_line))?;
+    }
+    Ok(())
+}
+
+fn cmd_resolutions_forget(
+    ui: &mut Ui,
+    command: &CommandHelper,
+    args: &ResolutionsForgetArgs,
+) -> Result<(), CommandError> {
+    let workspace_command = command.workspace_helper(ui)?;
+    let resolution_store = workspace_command.repo().resolution_store();
+    let ids = if args.all {
+        resolution_store.list()?
+    } else {
+        args.ids
+            .iter()
+            .map(|hex| ResolutionId::from_hex(hex))
+            .collect_vec()
+    };
+    for id in &ids {
+        if !resolution_store.forget(id)? {
+            return Err(user_error(format!("No such resolution: {}", id.hex())));
+        }
+    }
+    Ok(())
+}
+
 fn cmd_sparse(ui: &mut Ui, command: &CommandHelper, args: &SparseArgs) -> Result<(), CommandError> {
     if args.list {
         let workspace_command = command.workspace_helper(ui)?;
@@ -4147,12 +5030,50 @@ fn do_git_clone(
     Ok((workspace_command, maybe_default_branch))
 }
 
+/// Merges the trees like `merge_trees_with_options()`, showing the progress
+/// indicator if the merge takes long enough.
+fn merge_trees_with_progress_indicator(
+    ui: &mut Ui,
+    side1_tree: &Tree,
+    base_tree: &Tree,
+    side2_tree: &Tree,
+    options: &tree::TreeMergeOptions,
+) -> Result<TreeId, tree::TreeMergeError> {
+    if !ui.use_progress_indicator() {
+        return merge_trees_with_options(side1_tree, base_tree, side2_tree, options);
+    }
+    let mut progress = Progress::new(Instant::now(), ui.progress_delay());
+    let result = tree::merge_trees_with_progress(
+        side1_tree,
+        base_tree,
+        side2_tree,
+        options,
+        &mut |merge_progress| {
+            if merge_progress.merged < merge_progress.total {
+                let overall = merge_progress.merged as f32 / merge_progress.total as f32;
+                let git_progress = git::Progress {
+                    bytes_downloaded: None,
+                    overall,
+                };
+                _ = progress.update(Instant::now(), &git_progress, ui);
+            }
+        },
+    );
+    let done = git::Progress {
+        bytes_downloaded: None,
+        overall: 1.0,
+    };
+    _ = progress.update(Instant::now(), &done, ui);
+    result
+}
+
 #[allow(clippy::explicit_auto_deref)] // https://github.com/rust-lang/rust-clippy/issues/9763
 fn with_remote_callbacks<T>(ui: &mut Ui, f: impl FnOnce(git::RemoteCallbacks<'_>) -> T) -> T {
     let mut ui = Mutex::new(ui);
     let mut callback = None;
     if ui.get_mut().unwrap().use_progress_indicator() {
-        let mut progress = Progress::new(Instant::now());
+        let delay = ui.get_mut().unwrap().progress_delay();
+        let mut progress = Progress::new(Instant::now(), delay);
         let ui = &ui;
         callback = Some(move |x: &git::Progress| {
             _ = progress.update(Instant::now(), x, *ui.lock().unwrap());
@@ -4634,6 +5555,8 @@ pub fn run_command(
         Commands::Squash(sub_args) => cmd_squash(ui, command_helper, sub_args),
         Commands::Unsquash(sub_args) => cmd_unsquash(ui, command_helper, sub_args),
         Commands::Restore(sub_args) => cmd_restore(ui, command_helper, sub_args),
+        Commands::Resolve(sub_args) => cmd_resolve(ui, command_helper, sub_args),
+        Commands::Resolutions(sub_args) => cmd_resolutions(ui, command_helper, sub_args),
         Commands::Touchup(sub_args) => cmd_touchup(ui, command_helper, sub_args),
         Commands::Split(sub_args) => cmd_split(ui, command_helper, sub_args),
         Commands::Merge(sub_args) => cmd_merge(ui, command_helper, sub_args),
@@ -4651,10 +5574,81 @@ pub fn run_command(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
+    use crate::formatter::ColorFormatter;
 
     #[test]
     fn verify_app() {
         default_app().debug_assert();
     }
+
+    #[test]
+    fn test_unified_diff_token_labels() {
+        let colors = HashMap::from([
+            ("removed".to_string(), "red".to_string()),
+            ("removed token".to_string(), "bright red".to_string()),
+            ("added".to_string(), "green".to_string()),
+            ("added token".to_string(), "bright green".to_string()),
+        ]);
+        let mut output: Vec<u8> = vec![];
+        let mut formatter = ColorFormatter::new(&mut output, Arc::new(colors));
+        show_unified_diff_hunks(
+            &mut formatter,
+            b"foo bar baz\n",
+            b"foo qux baz\n",
+            &DiffOptions::default(),
+        )
+        .unwrap();
+        drop(formatter);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "@@ -1,1 +1,1 @@\n\
+             \x1b[31m-foo \x1b[1;31mbar\x1b[31m baz\n\x1b[0m\
+             \x1b[32m+foo \x1b[1;32mqux\x1b[32m baz\n\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_diff_escapes_control_chars() {
+        let render = |diff_options: &DiffOptions| {
+            let left = b"a\x1b[2Jb\n";
+            let right = b"a\x1b[2Jc\n";
+            let mut output: Vec<u8> = vec![];
+            let mut formatter = PlainTextFormatter::new(&mut output);
+            show_color_words_diff_hunks(left, right, diff_options, &mut formatter).unwrap();
+            show_unified_diff_hunks(&mut formatter, left, right, diff_options).unwrap();
+            drop(formatter);
+            output
+        };
+        // The escape sequence doesn't reach the terminal
+        let output = render(&DiffOptions::default());
+        assert!(!output.contains(&b'\x1b'));
+        assert!(String::from_utf8(output).unwrap().contains("a^[[2J"));
+        let output = render(&DiffOptions::default().set_control_chars(ControlCharStyle::Verbatim));
+        assert!(output.contains(&b'\x1b'));
+    }
+    #[test]
+    fn test_color_words_diff_moved_label() {
+        let render = |diff_options: &DiffOptions| {
+            let colors = HashMap::from([
+                ("removed".to_string(), "red".to_string()),
+                ("moved removed".to_string(), "magenta".to_string()),
+            ]);
+            let mut output: Vec<u8> = vec![];
+            let mut formatter = ColorFormatter::new(&mut output, Arc::new(colors));
+            show_color_words_diff_hunks(
+                b"a\nb\nc\nd\ne\n",
+                b"c\nd\ne\na\nb\n",
+                diff_options,
+                &mut formatter,
+            )
+            .unwrap();
+            drop(formatter);
+            String::from_utf8(output).unwrap()
+        };
+        assert!(!render(&DiffOptions::default()).contains("\x1b[35m"));
+        assert!(render(&DiffOptions::default().set_detect_moves(true)).contains("\x1b[35m"));
+    }
 }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Arc;
@@ -54,6 +54,26 @@ pub enum DiffEditError {
     SnapshotError(SnapshotError),
 }
 
+#[derive(Debug, Error)]
+pub enum ExternalMergeError {
+    #[error("Invalid config: {0}")]
+    ConfigError(#[from] ConfigError),
+    #[error("No merge tool configured; set ui.merge-editor or pass --tool")]
+    NoToolConfigured,
+    #[error("Merge tool '{tool_name}' has no merge-tools.{tool_name}.merge-args configured")]
+    MergeArgsNotConfigured { tool_name: String },
+    #[error("Error setting up temporary files: {0:?}")]
+    SetUpDirError(#[source] std::io::Error),
+    #[error("Error executing merge tool '{tool_binary}': {source}")]
+    ExecuteToolError {
+        tool_binary: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to read the merge tool's output: {0:?}")]
+    ReadOutputError(#[source] std::io::Error),
+}
+
 impl From<CheckoutError> for DiffEditError {
     fn from(err: CheckoutError) -> Self {
         DiffEditError::CheckoutError(err)
@@ -180,6 +200,112 @@ pub fn edit_diff(
     Ok(right_tree_state.current_tree_id().clone())
 }
 
+/// The contents to start a 3-way merge tool on. `output` is what the output
+/// file contains before the tool runs, typically the file with conflict
+/// markers.
+pub struct MergeToolInputs<'a> {
+    pub base: &'a [u8],
+    pub left: &'a [u8],
+    pub right: &'a [u8],
+    pub output: &'a [u8],
+}
+
+/// Returns the names of the merge tools to try, in order of priority. That's
+/// `tool_name` if given, and otherwise `ui.merge-editor`, which can be a tool
+/// name or a list of them.
+pub fn merge_tool_names(
+    settings: &UserSettings,
+    tool_name: Option<&str>,
+) -> Result<Vec<String>, ExternalMergeError> {
+    if let Some(tool_name) = tool_name {
+        return Ok(vec![tool_name.to_owned()]);
+    }
+    let config = settings.config();
+    let names = if let Ok(name) = config.get_string("ui.merge-editor") {
+        vec![name]
+    } else {
+        match config.get_array("ui.merge-editor") {
+            Ok(values) => values
+                .into_iter()
+                .map(|value| value.into_string())
+                .collect::<Result<_, _>>()?,
+            Err(ConfigError::NotFound(_)) => vec![],
+            Err(err) => return Err(err.into()),
+        }
+    };
+    if names.is_empty() {
+        return Err(ExternalMergeError::NoToolConfigured);
+    }
+    Ok(names)
+}
+
+/// Runs the first of `tool_names` that can be started, with the inputs written
+/// to temporary files. The tool's `merge-args` may refer to the files as
+/// `$base`, `$left`, `$right`, and `$output`. Tools whose program isn't found
+/// are skipped. Returns the contents of the output file, or `None` if the tool
+/// exited with a non-zero code, which means the file is still conflicted.
+pub fn run_merge_tool(
+    settings: &UserSettings,
+    tool_names: &[String],
+    file_name: &str,
+    inputs: &MergeToolInputs,
+) -> Result<Option<Vec<u8>>, ExternalMergeError> {
+    // The files are removed when `temp_dir` is dropped, whichever way we return
+    let temp_dir = tempfile::Builder::new()
+        .prefix("jj-merge-")
+        .tempdir()
+        .map_err(ExternalMergeError::SetUpDirError)?;
+    // Keep the file name so tools can tell the file type
+    let path_for = |variable: &str| temp_dir.path().join(format!("{variable}_{file_name}"));
+    let mut paths = vec![];
+    for (variable, content) in [
+        ("base", inputs.base),
+        ("left", inputs.left),
+        ("right", inputs.right),
+        ("output", inputs.output),
+    ] {
+        let path = path_for(variable);
+        std::fs::write(&path, content).map_err(ExternalMergeError::SetUpDirError)?;
+        paths.push((variable, path));
+    }
+
+    let mut last_err = ExternalMergeError::NoToolConfigured;
+    for tool_name in tool_names {
+        let tool = get_tool(settings, tool_name)?;
+        if tool.merge_args.is_empty() {
+            return Err(ExternalMergeError::MergeArgsNotConfigured {
+                tool_name: tool_name.clone(),
+            });
+        }
+        let args = tool.merge_args.iter().map(|arg| {
+            paths.iter().fold(arg.clone(), |arg, (variable, path)| {
+                arg.replace(&format!("${variable}"), &path.to_string_lossy())
+            })
+        });
+        let exit_status = match Command::new(&tool.program).args(args).status() {
+            Ok(exit_status) => exit_status,
+            Err(err) => {
+                let not_found = err.kind() == ErrorKind::NotFound;
+                last_err = ExternalMergeError::ExecuteToolError {
+                    tool_binary: tool.program,
+                    source: err,
+                };
+                if not_found {
+                    continue;
+                }
+                return Err(last_err);
+            }
+        };
+        if !exit_status.success() {
+            return Ok(None);
+        }
+        let output =
+            std::fs::read(path_for("output")).map_err(ExternalMergeError::ReadOutputError)?;
+        return Ok(Some(output));
+    }
+    Err(last_err)
+}
+
 /// Merge/diff tool loaded from the settings.
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -189,6 +315,11 @@ struct MergeTool {
     /// Arguments to pass to the program when editing diffs.
     #[serde(default)]
     pub edit_args: Vec<String>,
+    /// Arguments to pass to the program when resolving 3-way conflicts.
+    /// `$base`, `$left`, `$right`, and `$output` are replaced by the paths to
+    /// the files.
+    #[serde(default)]
+    pub merge_args: Vec<String>,
 }
 
 impl MergeTool {
@@ -196,6 +327,7 @@ impl MergeTool {
         MergeTool {
             program: program.to_owned(),
             edit_args: vec![],
+            merge_args: vec![],
         }
     }
 }
@@ -13,14 +13,18 @@
 // limitations under the License.
 
 use std::collections::HashMap;
+use std::env;
 use std::io;
 use std::io::{Error, Read, Write};
 use std::sync::Arc;
 
 use jujutsu_lib::settings::UserSettings;
+use unicode_width::UnicodeWidthChar;
 
 // Lets the caller label strings and translates the labels to colors
 pub trait Formatter: Write {
+    /// Writes `data` as is. It doesn't need to be valid UTF-8, so this is what
+    /// should be used for file content such as diffs.
     fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
         self.write_all(data)
     }
@@ -64,18 +68,59 @@ enum FormatterFactoryKind {
     PlainText,
     Color {
         colors: Arc<HashMap<String, String>>,
+        depth: ColorDepth,
     },
 }
 
+/// The colors a terminal can show. Colors given as `#rrggbb` are written as
+/// the closest color available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// The 8 basic colors and their bright variants
+    Basic,
+    /// The 256-color palette
+    Ansi256,
+    /// Any 24-bit color
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Guesses what the terminal supports from the `$COLORTERM` and `$TERM`
+    /// environment variables.
+    pub fn from_env() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Basic
+        }
+    }
+}
+
 impl FormatterFactory {
     pub fn prepare(settings: &UserSettings, color: bool) -> Self {
-        let kind = if color {
+        if color {
             let colors = Arc::new(config_colors(settings));
-            FormatterFactoryKind::Color { colors }
+            FormatterFactory {
+                kind: FormatterFactoryKind::Color {
+                    colors,
+                    depth: ColorDepth::from_env(),
+                },
+            }
         } else {
-            FormatterFactoryKind::PlainText
-        };
-        FormatterFactory { kind }
+            FormatterFactory::plain_text()
+        }
+    }
+
+    /// Creates a factory for formatters that ignore labels and write the text
+    /// as is. Unlike `prepare()`, this doesn't need any settings.
+    pub fn plain_text() -> Self {
+        FormatterFactory {
+            kind: FormatterFactoryKind::PlainText,
+        }
     }
 
     pub fn new_formatter<'output, W: Write + 'output>(
@@ -84,15 +129,28 @@ impl FormatterFactory {
     ) -> Box<dyn Formatter + 'output> {
         match &self.kind {
             FormatterFactoryKind::PlainText => Box::new(PlainTextFormatter::new(output)),
-            FormatterFactoryKind::Color { colors } => {
-                Box::new(ColorFormatter::new(output, colors.clone()))
-            }
+            FormatterFactoryKind::Color { colors, depth } => Box::new(
+                ColorFormatter::with_color_depth(output, colors.clone(), *depth),
+            ),
         }
     }
 
     pub fn is_color(&self) -> bool {
         matches!(&self.kind, FormatterFactoryKind::Color { .. })
     }
+
+    /// Whether formatters from this factory style text with `labels`, which
+    /// are separated by spaces like the keys of the `colors` settings, as if
+    /// each label had been added in turn.
+    pub fn is_label_styled(&self, labels: &str) -> bool {
+        match &self.kind {
+            FormatterFactoryKind::PlainText => false,
+            FormatterFactoryKind::Color { colors, depth } => {
+                let labels: Vec<String> = labels.split_whitespace().map(str::to_owned).collect();
+                color_for_name(best_color_name(colors, &labels), *depth) != b"\x1b[0m"
+            }
+        }
+    }
 }
 
 pub struct PlainTextFormatter<W> {
@@ -125,9 +183,79 @@ impl<W: Write> Formatter for PlainTextFormatter<W> {
     }
 }
 
+/// A formatter that measures what's written to it instead of writing it
+/// anywhere, e.g. to line up columns. Labels are ignored, since they only add
+/// colors, so the width is the same whether colors are enabled or not.
+#[derive(Default)]
+pub struct MeasuringFormatter {
+    output: Vec<u8>,
+}
+
+impl MeasuringFormatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of columns the written text takes up. See `text_width()`.
+    pub fn width(&self) -> usize {
+        text_width(&String::from_utf8_lossy(&self.output))
+    }
+}
+
+impl Write for MeasuringFormatter {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.output.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Formatter for MeasuringFormatter {
+    fn add_label(&mut self, _label: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn remove_label(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The number of columns `text` takes up on a terminal. Wide characters, such
+/// as CJK characters, take up two columns, and combining characters and other
+/// control characters none. ANSI escape sequences, such as the colors
+/// `ColorFormatter` writes, are skipped. If `text` has several lines, this is
+/// the width of the widest one.
+pub fn text_width(text: &str) -> usize {
+    text.lines().map(line_width).max().unwrap_or(0)
+}
+
+fn line_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A control sequence like `\x1b[1;31m` ends with a byte in this range
+            if chars.clone().next() == Some('[') {
+                for c in chars.by_ref().skip(1) {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
 pub struct ColorFormatter<W> {
     output: W,
     colors: Arc<HashMap<String, String>>,
+    depth: ColorDepth,
     labels: Vec<String>,
     cached_colors: HashMap<Vec<String>, Vec<u8>>,
     current_color: Vec<u8>,
@@ -221,8 +349,25 @@ fn config_colors(user_settings: &UserSettings) -> HashMap<String, String> {
         String::from("bright white"),
     );
     result.insert(String::from("diff hunk_header"), String::from("cyan"));
+    result.insert(
+        String::from("diff directory_header"),
+        String::from("bright yellow"),
+    );
     result.insert(String::from("diff removed"), String::from("red"));
     result.insert(String::from("diff added"), String::from("green"));
+    result.insert(
+        String::from("diff removed token"),
+        String::from("bright red"),
+    );
+    result.insert(
+        String::from("diff added token"),
+        String::from("bright green"),
+    );
+    result.insert(String::from("diff moved removed"), String::from("magenta"));
+    result.insert(
+        String::from("diff moved added"),
+        String::from("bright cyan"),
+    );
     result.insert(String::from("diff modified"), String::from("cyan"));
 
     result.insert(String::from("op-log id"), String::from("blue"));
@@ -258,9 +403,18 @@ fn config_colors(user_settings: &UserSettings) -> HashMap<String, String> {
 
 impl<W> ColorFormatter<W> {
     pub fn new(output: W, colors: Arc<HashMap<String, String>>) -> ColorFormatter<W> {
+        ColorFormatter::with_color_depth(output, colors, ColorDepth::Basic)
+    }
+
+    pub fn with_color_depth(
+        output: W,
+        colors: Arc<HashMap<String, String>>,
+        depth: ColorDepth,
+    ) -> ColorFormatter<W> {
         ColorFormatter {
             output,
             colors,
+            depth,
             labels: vec![],
             cached_colors: HashMap::new(),
             current_color: b"\x1b[0m".to_vec(),
@@ -271,55 +425,100 @@ impl<W> ColorFormatter<W> {
         if let Some(cached) = self.cached_colors.get(&self.labels) {
             cached.clone()
         } else {
-            let mut best_match = (-1, "");
-            for (key, value) in self.colors.as_ref() {
-                let mut num_matching = 0;
-                let mut valid = true;
-                for label in key.split_whitespace() {
-                    if !self.labels.contains(&label.to_string()) {
-                        valid = false;
-                        break;
-                    }
-                    num_matching += 1;
-                }
-                if !valid {
-                    continue;
-                }
-                if num_matching >= best_match.0 {
-                    best_match = (num_matching, value)
-                }
-            }
-
-            let color = self.color_for_name(best_match.1);
+            let color = color_for_name(best_color_name(&self.colors, &self.labels), self.depth);
             self.cached_colors
                 .insert(self.labels.clone(), color.clone());
             color
         }
     }
+}
+
+/// The name of the color for text with `labels`: the value of the key in
+/// `colors` with the most labels that are all in `labels`.
+fn best_color_name<'a>(colors: &'a HashMap<String, String>, labels: &[String]) -> &'a str {
+    let mut best_match = (-1, "");
+    for (key, value) in colors {
+        let mut num_matching = 0;
+        let mut valid = true;
+        for label in key.split_whitespace() {
+            if !labels.contains(&label.to_string()) {
+                valid = false;
+                break;
+            }
+            num_matching += 1;
+        }
+        if !valid {
+            continue;
+        }
+        if num_matching >= best_match.0 {
+            best_match = (num_matching, value)
+        }
+    }
+    best_match.1
+}
 
-    fn color_for_name(&self, color_name: &str) -> Vec<u8> {
-        match color_name {
-            "black" => b"\x1b[30m".to_vec(),
-            "red" => b"\x1b[31m".to_vec(),
-            "green" => b"\x1b[32m".to_vec(),
-            "yellow" => b"\x1b[33m".to_vec(),
-            "blue" => b"\x1b[34m".to_vec(),
-            "magenta" => b"\x1b[35m".to_vec(),
-            "cyan" => b"\x1b[36m".to_vec(),
-            "white" => b"\x1b[37m".to_vec(),
-            "bright black" => b"\x1b[1;30m".to_vec(),
-            "bright red" => b"\x1b[1;31m".to_vec(),
-            "bright green" => b"\x1b[1;32m".to_vec(),
-            "bright yellow" => b"\x1b[1;33m".to_vec(),
-            "bright blue" => b"\x1b[1;34m".to_vec(),
-            "bright magenta" => b"\x1b[1;35m".to_vec(),
-            "bright cyan" => b"\x1b[1;36m".to_vec(),
-            "bright white" => b"\x1b[1;37m".to_vec(),
-            _ => b"\x1b[0m".to_vec(),
+fn color_for_name(color_name: &str, depth: ColorDepth) -> Vec<u8> {
+    if let Some(rgb) = parse_rgb_color(color_name) {
+        return rgb_color_code(rgb, depth);
+    }
+    match color_name {
+        "black" => b"\x1b[30m".to_vec(),
+        "red" => b"\x1b[31m".to_vec(),
+        "green" => b"\x1b[32m".to_vec(),
+        "yellow" => b"\x1b[33m".to_vec(),
+        "blue" => b"\x1b[34m".to_vec(),
+        "magenta" => b"\x1b[35m".to_vec(),
+        "cyan" => b"\x1b[36m".to_vec(),
+        "white" => b"\x1b[37m".to_vec(),
+        "bright black" => b"\x1b[1;30m".to_vec(),
+        "bright red" => b"\x1b[1;31m".to_vec(),
+        "bright green" => b"\x1b[1;32m".to_vec(),
+        "bright yellow" => b"\x1b[1;33m".to_vec(),
+        "bright blue" => b"\x1b[1;34m".to_vec(),
+        "bright magenta" => b"\x1b[1;35m".to_vec(),
+        "bright cyan" => b"\x1b[1;36m".to_vec(),
+        "bright white" => b"\x1b[1;37m".to_vec(),
+        _ => b"\x1b[0m".to_vec(),
+    }
+}
+
+/// Parses a color given as `#rrggbb`.
+fn parse_rgb_color(color_name: &str) -> Option<[u8; 3]> {
+    let hex = color_name.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// The escape code for the color closest to `rgb` that's available at `depth`.
+fn rgb_color_code([r, g, b]: [u8; 3], depth: ColorDepth) -> Vec<u8> {
+    match depth {
+        ColorDepth::TrueColor => format!("\x1b[38;2;{r};{g};{b}m").into_bytes(),
+        ColorDepth::Ansi256 => format!("\x1b[38;5;{}m", rgb_to_ansi256([r, g, b])).into_bytes(),
+        ColorDepth::Basic => {
+            // Each channel is either on or off in the basic colors
+            let index = u8::from(r > 127) | u8::from(g > 127) << 1 | u8::from(b > 127) << 2;
+            format!("\x1b[{}m", 30 + index).into_bytes()
         }
     }
 }
 
+/// The index of the closest color in the 256-color palette: one of the 24
+/// shades of gray for grays, and a color in the 6x6x6 cube otherwise.
+fn rgb_to_ansi256([r, g, b]: [u8; 3]) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            gray => 232 + ((u16::from(gray) - 8) * 24 / 247) as u8,
+        };
+    }
+    let level = |channel: u8| ((u16::from(channel) * 5 + 127) / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
 impl<W: Write> Write for ColorFormatter<W> {
     fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
         self.output.write(data)
@@ -351,3 +550,90 @@ impl<W: Write> Formatter for ColorFormatter<W> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_formatter_factory() {
+        let factory = FormatterFactory::plain_text();
+        assert!(!factory.is_color());
+        let mut output = vec![];
+        let mut formatter = factory.new_formatter(&mut output);
+        formatter.add_label("error").unwrap();
+        formatter.write_str("text\n").unwrap();
+        formatter.remove_label().unwrap();
+        drop(formatter);
+        assert_eq!(output, b"text\n");
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width(""), 0);
+        assert_eq!(text_width("abc"), 3);
+        // CJK characters take up two columns
+        assert_eq!(text_width("日本語"), 6);
+        // Combining characters take up none
+        assert_eq!(text_width("e\u{301}"), 1);
+        // Colors take up none either
+        assert_eq!(text_width("\x1b[1;31mred\x1b[0m"), 3);
+        // The widest line counts
+        assert_eq!(text_width("a\nbcd\nef\n"), 3);
+    }
+
+    #[test]
+    fn test_measuring_formatter() {
+        let write = |formatter: &mut dyn Formatter| {
+            formatter.with_label("error", |formatter| formatter.write_str("日本"))?;
+            formatter.write_str(" x")
+        };
+        let mut measuring_formatter = MeasuringFormatter::new();
+        write(&mut measuring_formatter).unwrap();
+        assert_eq!(measuring_formatter.width(), 6);
+
+        // Colored output takes up the same columns as what's measured
+        let colors = Arc::new(HashMap::from([("error".to_string(), "red".to_string())]));
+        let mut output = vec![];
+        let mut color_formatter = ColorFormatter::new(&mut output, colors);
+        write(&mut color_formatter).unwrap();
+        drop(color_formatter);
+        assert_ne!(output, "日本 x".as_bytes());
+        assert_eq!(
+            text_width(&String::from_utf8(output).unwrap()),
+            measuring_formatter.width()
+        );
+    }
+
+    #[test]
+    fn test_color_formatter_color_depth() {
+        let colors = Arc::new(HashMap::from([
+            ("error".to_string(), "#ff8000".to_string()),
+            ("warning".to_string(), "yellow".to_string()),
+        ]));
+        let write = |depth| {
+            let mut output = vec![];
+            let mut formatter =
+                ColorFormatter::with_color_depth(&mut output, colors.clone(), depth);
+            let formatter: &mut dyn Formatter = &mut formatter;
+            formatter
+                .with_label("error", |formatter| formatter.write_str("e"))
+                .unwrap();
+            formatter
+                .with_label("warning", |formatter| formatter.write_str("w"))
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        // `#rrggbb` colors are written as the closest available color, and
+        // named colors are the same at every depth
+        assert_eq!(
+            write(ColorDepth::TrueColor),
+            "\x1b[38;2;255;128;0me\x1b[0m\x1b[33mw\x1b[0m"
+        );
+        assert_eq!(
+            write(ColorDepth::Ansi256),
+            "\x1b[38;5;214me\x1b[0m\x1b[33mw\x1b[0m"
+        );
+        assert_eq!(write(ColorDepth::Basic), "\x1b[33me\x1b[0m\x1b[33mw\x1b[0m");
+    }
+}
@@ -57,6 +57,7 @@ fn main() {
 
     jujutsu::cleanup_guard::init();
     let (mut ui, result) = create_ui();
+    ui.install_pager_signal_guard();
     let result = result.and_then(|()| run(&mut ui, reload_log_filter));
     let exit_code = handle_command_result(&mut ui, result);
     ui.finalize_writes();
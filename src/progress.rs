@@ -7,6 +7,8 @@ use jujutsu_lib::git;
 use crate::cleanup_guard::CleanupGuard;
 use crate::ui::Ui;
 
+/// Displays the progress of an operation. Nothing is displayed until the
+/// operation has taken longer than the delay given to `Progress::new()`.
 pub struct Progress {
     next_print: Instant,
     rate: RateEstimate,
@@ -15,9 +17,9 @@ pub struct Progress {
 }
 
 impl Progress {
-    pub fn new(now: Instant) -> Self {
+    pub fn new(now: Instant, delay: Duration) -> Self {
         Self {
-            next_print: now + INITIAL_DELAY,
+            next_print: now + delay,
             rate: RateEstimate::new(),
             buffer: String::new(),
             guard: None,
@@ -33,7 +35,10 @@ impl Progress {
         use std::fmt::Write as _;
 
         if progress.overall == 1.0 {
-            write!(ui, "\r{}", Clear(ClearType::CurrentLine))?;
+            // Only clear the line if the progress was displayed
+            if self.guard.is_some() {
+                write!(ui, "\r{}", Clear(ClearType::CurrentLine))?;
+            }
             return Ok(());
         }
 
@@ -95,7 +100,6 @@ fn draw_progress(progress: f32, buffer: &mut String, width: usize) {
 }
 
 const UPDATE_HZ: u32 = 30;
-const INITIAL_DELAY: Duration = Duration::from_millis(250);
 
 /// Find the smallest binary prefix with which the whole part of `x` is at most
 /// three digits, and return the scaled `x` and that prefix.
@@ -182,4 +186,38 @@ mod tests {
         assert_eq!(buf, "█████▍    ");
         buf.clear();
     }
+
+    #[test]
+    fn test_delay() {
+        let settings = jujutsu_lib::settings::UserSettings::default();
+        let start = Instant::now();
+        let delay = Duration::from_millis(200);
+        let halfway = git::Progress {
+            bytes_downloaded: None,
+            overall: 0.5,
+        };
+        let done = git::Progress {
+            bytes_downloaded: None,
+            overall: 1.0,
+        };
+
+        // An operation that finishes before the delay doesn't print anything
+        let (mut ui, buffers) = Ui::for_buffers(settings.clone());
+        let mut progress = Progress::new(start, delay);
+        progress.update(start, &halfway, &mut ui).unwrap();
+        progress.update(start + delay / 2, &done, &mut ui).unwrap();
+        drop(progress);
+        assert_eq!(buffers.stdout_bytes(), b"");
+
+        // A slower operation prints its progress and clears it when it's done
+        let (mut ui, buffers) = Ui::for_buffers(settings);
+        let mut progress = Progress::new(start, delay);
+        progress.update(start + delay, &halfway, &mut ui).unwrap();
+        assert!(!buffers.stdout_bytes().is_empty());
+        progress.update(start + delay * 2, &done, &mut ui).unwrap();
+        drop(progress);
+        let output = String::from_utf8(buffers.stdout_bytes()).unwrap();
+        assert!(output.contains(" 50% "));
+        assert!(output.ends_with(&crossterm::cursor::Show.to_string()));
+    }
 }
@@ -24,11 +24,11 @@ use pest_derive::Parser;
 use crate::formatter::PlainTextFormatter;
 use crate::templater::{
     AuthorProperty, BranchProperty, ChangeIdProperty, CommitIdKeyword, CommitterProperty,
-    ConditionalTemplate, ConflictProperty, ConstantTemplateProperty, DescriptionProperty,
-    DivergentProperty, DynamicLabelTemplate, GitRefsProperty, IsGitHeadProperty,
-    IsWorkingCopyProperty, LabelTemplate, ListTemplate, LiteralTemplate, SignatureTimestamp,
-    StringPropertyTemplate, TagProperty, Template, TemplateFunction, TemplateProperty,
-    WorkingCopiesProperty,
+    ConditionalTemplate, ConflictCountProperty, ConflictProperty, ConstantTemplateProperty,
+    DescriptionProperty, DivergentProperty, DynamicLabelTemplate, GitRefsProperty,
+    IsGitHeadProperty, IsWorkingCopyProperty, LabelTemplate, ListTemplate, LiteralTemplate,
+    SignatureTimestamp, StringPropertyTemplate, TagProperty, Template, TemplateFunction,
+    TemplateProperty, WorkingCopiesProperty,
 };
 
 #[derive(Parser)]
@@ -300,6 +300,7 @@ fn parse_commit_keyword<'a>(
         "is_git_head" => Property::Boolean(Box::new(IsGitHeadProperty::new(repo))),
         "divergent" => Property::Boolean(Box::new(DivergentProperty::new(repo))),
         "conflict" => Property::Boolean(Box::new(ConflictProperty)),
+        "conflict_count" => Property::String(Box::new(ConflictCountProperty)),
         name => panic!("unexpected identifier: {}", name),
     };
     (property, pair.as_str().to_string())
@@ -20,6 +20,7 @@ use std::ops::{Add, AddAssign};
 use itertools::Itertools;
 use jujutsu_lib::backend::{ChangeId, CommitId, Signature, Timestamp};
 use jujutsu_lib::commit::Commit;
+use jujutsu_lib::conflicts;
 use jujutsu_lib::op_store::WorkspaceId;
 use jujutsu_lib::repo::RepoRef;
 use jujutsu_lib::revset::RevsetExpression;
@@ -362,6 +363,25 @@ impl TemplateProperty<Commit, bool> for ConflictProperty {
     }
 }
 
+/// The number of conflict hunks in all conflicted files of the commit.
+pub struct ConflictCountProperty;
+
+impl TemplateProperty<Commit, String> for ConflictCountProperty {
+    fn extract(&self, context: &Commit) -> String {
+        let tree = context.tree();
+        let store = tree.store();
+        let count: usize = tree
+            .conflicts()
+            .iter()
+            .map(|(path, id)| {
+                let conflict = store.read_conflict(path, id).unwrap();
+                conflicts::stored_conflict_stats(store, path, &conflict).num_hunks
+            })
+            .sum();
+        count.to_string()
+    }
+}
+
 pub struct ConditionalTemplate<'a, C> {
     pub condition: Box<dyn TemplateProperty<C, bool> + 'a>,
     pub true_template: Box<dyn Template<C> + 'a>,
@@ -12,25 +12,60 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::{Stderr, Stdout, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Stderr, Stdout, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Child, ChildStdin, Command, Stdio};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{fmt, io, mem};
 
+use chrono::Local;
 use crossterm::tty::IsTty;
 use jujutsu_lib::settings::UserSettings;
+use tempfile::NamedTempFile;
 
+use crate::cleanup_guard::CleanupGuard;
 use crate::formatter::{Formatter, FormatterFactory};
 
 pub struct Ui {
+    /// The color choice that `color` was resolved from
+    color_choice: ColorChoice,
     color: bool,
+    /// Whether a formatter that may have colored stdout has been created
+    stdout_colored: Cell<bool>,
     paginate: PaginationChoice,
     progress_indicator: bool,
+    /// Whether `write_hint()` writes to stderr even if stdout isn't a terminal
+    hints_to_stderr: bool,
     cwd: PathBuf,
     formatter_factory: FormatterFactory,
     output: UiOutput,
+    /// If set, the output is only paged once it has more lines than this
+    pager_threshold: Option<usize>,
+    /// Whether `install_pager_signal_guard()` was called
+    guard_pager: bool,
+    /// Waits for the pager of `UiOutput::Paged` if the process is interrupted
+    pager_guard: Option<PagerGuard>,
     settings: UserSettings,
+    /// The messages written so far, if `enable_message_log()` was called
+    message_log: Option<Vec<UiMessage>>,
+    /// Where hints, warnings, and errors are logged, if `set_log_file()` was
+    /// called
+    log_file: Option<BufWriter<File>>,
+}
+
+/// A message written to a `Ui`, as recorded by `Ui::enable_message_log()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UiMessage {
+    Hint(String),
+    Warning(String),
+    Error(String),
+    Output(String),
 }
 
 fn progress_indicator_setting(settings: &UserSettings) -> bool {
@@ -40,7 +75,24 @@ fn progress_indicator_setting(settings: &UserSettings) -> bool {
         .unwrap_or(true)
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+fn hints_to_stderr_setting(settings: &UserSettings) -> bool {
+    settings
+        .config()
+        .get_bool("ui.hints-to-stderr")
+        .unwrap_or(false)
+}
+
+fn progress_delay_setting(settings: &UserSettings) -> Duration {
+    settings
+        .config()
+        .get_int("ui.progress-delay-ms")
+        .ok()
+        .and_then(|ms| u64::try_from(ms).ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(250))
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ColorChoice {
     Always,
     Never,
@@ -106,34 +158,287 @@ impl Default for PaginationChoice {
     }
 }
 
-fn pager_setting(settings: &UserSettings) -> String {
+/// How the output is handed over to the pager.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PagerMode {
+    /// Output is piped to the pager's stdin as it's written.
+    Pipe,
+    /// Output is written to a temporary file, which is passed to the pager as
+    /// an argument once the command is done.
+    File,
+}
+
+impl Default for PagerMode {
+    fn default() -> Self {
+        PagerMode::Pipe
+    }
+}
+
+impl FromStr for PagerMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pipe" => Ok(PagerMode::Pipe),
+            "file" => Ok(PagerMode::File),
+            _ => Err("must be one of pipe or file"),
+        }
+    }
+}
+
+fn pager_mode_setting(settings: &UserSettings) -> PagerMode {
     settings
         .config()
-        .get_string("ui.pager")
-        .unwrap_or_else(|_| "less".to_string())
+        .get_string("ui.pager-mode")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_default()
+}
+
+fn pager_threshold_setting(settings: &UserSettings) -> Option<usize> {
+    settings
+        .config()
+        .get_int("ui.pager-threshold")
+        .ok()
+        .and_then(|lines| usize::try_from(lines).ok())
+}
+
+/// The pager command, or `None` if the pager is set to an empty string, `cat`,
+/// or `:`, which disables paging. Returns an error message if `ui.pager`
+/// doesn't have one of the shapes `PagerCommand` accepts.
+fn pager_setting(settings: &UserSettings) -> Result<Option<PagerCommand>, String> {
+    let pager_cmd = match settings.config().get::<config::Value>("ui.pager") {
+        Ok(value) => PagerCommand::from_config(value)?,
+        Err(config::ConfigError::NotFound(_)) => PagerCommand::from("less"),
+        Err(err) => return Err(err.to_string()),
+    };
+    if pager_cmd.is_disabled() {
+        Ok(None)
+    } else {
+        Ok(Some(pager_cmd))
+    }
+}
+
+/// The pager set by `ui.pager`, as either a string, which is split on
+/// whitespace into the program and its arguments, such as `"less -FRX"`, a
+/// list of the program and its arguments, or a table like
+/// `{ command = ["less"], env = { LESS = "FRX" } }`. The environment variables
+/// are only set for the pager.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PagerCommand {
+    /// The program and its arguments
+    command: Vec<String>,
+    env: HashMap<String, String>,
+}
+
+impl PagerCommand {
+    fn from_config(value: config::Value) -> Result<Self, String> {
+        let invalid = || {
+            "ui.pager must be a string, a list of strings, or a table with a `command` list \
+             and an `env` table of strings"
+                .to_string()
+        };
+        match value.kind {
+            config::ValueKind::String(_) | config::ValueKind::Array(_) => Ok(PagerCommand {
+                command: pager_words_from_config(value).ok_or_else(invalid)?,
+                env: HashMap::new(),
+            }),
+            config::ValueKind::Table(mut table) => {
+                let command = table
+                    .remove("command")
+                    .and_then(pager_words_from_config)
+                    .ok_or_else(invalid)?;
+                let env = match table.remove("env") {
+                    Some(value) => pager_env_from_config(value).ok_or_else(invalid)?,
+                    None => HashMap::new(),
+                };
+                if !table.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(PagerCommand { command, env })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Whether the command doesn't page the output, like `cat` or `:`.
+    fn is_disabled(&self) -> bool {
+        match self.command.as_slice() {
+            [] => true,
+            [program] => program == "cat" || program == ":",
+            _ => false,
+        }
+    }
+
+    fn to_command(&self) -> Command {
+        let mut args = self.command.iter();
+        let mut command = Command::new(args.next().map_or("", String::as_str));
+        command.args(args).envs(&self.env);
+        command
+    }
+}
+
+impl From<&str> for PagerCommand {
+    fn from(pager_cmd: &str) -> Self {
+        PagerCommand {
+            command: pager_cmd.split_whitespace().map(str::to_owned).collect(),
+            env: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Display for PagerCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.command.join(" "))
+    }
+}
+
+fn pager_words_from_config(value: config::Value) -> Option<Vec<String>> {
+    match value.kind {
+        config::ValueKind::String(pager_cmd) => {
+            Some(pager_cmd.split_whitespace().map(str::to_owned).collect())
+        }
+        config::ValueKind::Array(elements) => elements
+            .into_iter()
+            .map(|element| match element.kind {
+                config::ValueKind::String(word) => Some(word),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn pager_env_from_config(value: config::Value) -> Option<HashMap<String, String>> {
+    match value.kind {
+        config::ValueKind::Table(table) => table
+            .into_iter()
+            .map(|(name, value)| match value.kind {
+                config::ValueKind::String(value) => Some((name, value)),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
 }
 
 impl Ui {
     pub fn for_terminal(settings: UserSettings) -> Ui {
         let cwd = std::env::current_dir().unwrap();
-        let color = use_color(color_setting(&settings));
+        let color_choice = color_setting(&settings);
+        let color = use_color(color_choice);
         let progress_indicator = progress_indicator_setting(&settings);
+        let hints_to_stderr = hints_to_stderr_setting(&settings);
         let formatter_factory = FormatterFactory::prepare(&settings, color);
         Ui {
+            color_choice,
             color,
+            stdout_colored: Cell::new(false),
             cwd,
             formatter_factory,
             paginate: PaginationChoice::Auto,
             progress_indicator,
+            hints_to_stderr,
             output: UiOutput::new_terminal(),
+            pager_threshold: pager_threshold_setting(&settings),
+            guard_pager: false,
+            pager_guard: None,
             settings,
+            message_log: None,
+            log_file: None,
+        }
+    }
+
+    /// Creates a `Ui` whose output is kept in memory instead of being written
+    /// to the terminal, so tests can check what a command printed. Output is
+    /// never paged, and only colored if `ui.color` is "always".
+    pub fn for_buffers(settings: UserSettings) -> (Ui, BufferHandles) {
+        let buffers = BufferHandles::default();
+        let output = UiOutput::Buffered {
+            stdout: buffers.stdout.clone(),
+            stderr: buffers.stderr.clone(),
+        };
+        (Ui::for_memory_output(settings, output), buffers)
+    }
+
+    /// Same as `Ui::for_buffers()`, for tests that capture the output this
+    /// way.
+    pub fn with_captured_output(settings: UserSettings) -> (Ui, OutputCapture) {
+        Ui::for_buffers(settings)
+    }
+
+    /// Like `for_buffers()`, but the output is owned by the `Ui` and read back
+    /// with `test_stdout()` and `test_stderr()`.
+    pub fn for_test(settings: UserSettings) -> Ui {
+        let output = UiOutput::Test {
+            stdout: vec![],
+            stderr: vec![],
+            unflushed_stdout: RefCell::new(vec![]),
+        };
+        Ui::for_memory_output(settings, output)
+    }
+
+    fn for_memory_output(settings: UserSettings, output: UiOutput) -> Ui {
+        let cwd = std::env::current_dir().unwrap();
+        let color_choice = color_setting(&settings);
+        let color = color_choice == ColorChoice::Always;
+        let progress_indicator = progress_indicator_setting(&settings);
+        let hints_to_stderr = hints_to_stderr_setting(&settings);
+        let formatter_factory = FormatterFactory::prepare(&settings, color);
+        Ui {
+            color_choice,
+            color,
+            stdout_colored: Cell::new(false),
+            cwd,
+            formatter_factory,
+            paginate: PaginationChoice::No,
+            progress_indicator,
+            hints_to_stderr,
+            output,
+            pager_threshold: None,
+            guard_pager: false,
+            pager_guard: None,
+            settings,
+            message_log: None,
+            log_file: None,
+        }
+    }
+
+    /// The bytes written to stdout so far by a `Ui` created by `for_test()`.
+    /// What was written with `stdout_formatter()` only shows up here once the
+    /// `Ui` is flushed or written to again, since that formatter only has a
+    /// shared `&Ui` to write through.
+    ///
+    /// Panics if the `Ui` wasn't created by `for_test()`.
+    pub fn test_stdout(&self) -> &[u8] {
+        match &self.output {
+            UiOutput::Test { stdout, .. } => stdout,
+            _ => panic!("this Ui wasn't created by Ui::for_test()"),
+        }
+    }
+
+    /// Like `test_stdout()`, but for stderr.
+    pub fn test_stderr(&self) -> &[u8] {
+        match &self.output {
+            UiOutput::Test { stderr, .. } => stderr,
+            _ => panic!("this Ui wasn't created by Ui::for_test()"),
         }
     }
 
     /// Reconfigures the underlying outputs with the new color choice.
     pub fn reset_color(&mut self, choice: ColorChoice) {
-        self.color = use_color(choice);
+        self.color_choice = choice;
+        self.color = match choice {
+            ColorChoice::Auto => self.stdout_is_tty(),
+            _ => use_color(choice),
+        };
         if self.formatter_factory.is_color() != self.color {
+            if self.stdout_colored.replace(false) {
+                // Don't leave the terminal in the color of a label that wasn't
+                // removed. This isn't output the caller wrote, so it isn't
+                // logged.
+                _ = self.write_stdout_unlogged(b"\x1b[0m");
+            }
             self.formatter_factory = FormatterFactory::prepare(&self.settings, self.color);
         }
     }
@@ -143,6 +448,16 @@ impl Ui {
         self.paginate = choice;
     }
 
+    /// Makes pagers requested from now on only start once the output has more
+    /// than `lines` lines. Until then, the output is held back, and if the
+    /// command finishes first, `finalize_writes()` writes it to the terminal
+    /// without paging it. Output is also paged once more than 64 KiB is held
+    /// back, however few lines that is. Like `less -F`, but the pager isn't
+    /// started at all for short output. Only applies to `PagerMode::Pipe`.
+    pub fn set_pager_threshold(&mut self, lines: usize) {
+        self.pager_threshold = Some(lines);
+    }
+
     /// Switches the output to use the pager, if allowed.
     pub fn request_pager(&mut self) {
         if self.paginate == PaginationChoice::No {
@@ -150,19 +465,106 @@ impl Ui {
         }
 
         match self.output {
-            UiOutput::Paged { .. } => {}
+            UiOutput::Paged { .. }
+            | UiOutput::ThresholdPaged { .. }
+            | UiOutput::FilePaged { .. }
+            | UiOutput::Buffered { .. }
+            | UiOutput::Test { .. } => {}
             UiOutput::Terminal { .. } => {
                 if io::stdout().is_tty() {
-                    self.output = UiOutput::new_paged_else_terminal(&self.settings);
+                    let pager_cmd = match pager_setting(&self.settings) {
+                        Ok(Some(pager_cmd)) => pager_cmd,
+                        Ok(None) => return,
+                        Err(message) => {
+                            self.write_error(&format!("Config error: {message}\n")).ok();
+                            return;
+                        }
+                    };
+                    if pager_mode_setting(&self.settings) == PagerMode::File {
+                        match UiOutput::new_file_paged(pager_cmd) {
+                            Ok(output) => self.output = output,
+                            Err(err) => {
+                                self.write_error(&format!(
+                                    "Failed to create temporary file for pager: {err}\n"
+                                ))
+                                .ok();
+                            }
+                        }
+                        return;
+                    }
+                    if let Some(threshold) = self.pager_threshold {
+                        self.output =
+                            UiOutput::new_threshold_paged(pager_cmd, threshold, self.guard_pager);
+                        return;
+                    }
+                    match UiOutput::new_paged(&pager_cmd) {
+                        Ok(output) => {
+                            self.output = output;
+                            self.update_pager_guard();
+                        }
+                        Err(err) => {
+                            // Keep writing to the terminal, but tell the user why
+                            // the output isn't paged
+                            let (message, hint) = pager_spawn_error(&pager_cmd, &err);
+                            self.write_error(&message).ok();
+                            if let Some(hint) = hint {
+                                self.write_hint(hint).ok();
+                            }
+                        }
+                    }
                 }
             }
         }
     }
 
+    /// Makes an interrupt (`SIGINT` or `SIGTERM`) while the output is piped
+    /// to the pager close the pager's input and wait for the pager to exit
+    /// before the process exits, like `finalize_writes()` does. Otherwise, the
+    /// pager can be left running after the shell took the terminal back. Takes
+    /// effect for the current pager and any started later, except that a pager
+    /// waiting for the threshold of `set_pager_threshold()` is only guarded if
+    /// it's requested after this is called.
+    ///
+    /// Signal handlers are process-wide, so this is opt-in, and it relies on
+    /// the handlers set up by `cleanup_guard::init()`. Only implemented on
+    /// Unix; elsewhere, this does nothing. The pager's input isn't buffered,
+    /// so there's nothing to flush, but output written by another thread at
+    /// the time of the interrupt may still be lost.
+    pub fn install_pager_signal_guard(&mut self) {
+        self.guard_pager = true;
+        self.update_pager_guard();
+    }
+
+    fn update_pager_guard(&mut self) {
+        if !self.guard_pager || self.pager_guard.is_some() {
+            return;
+        }
+        if let UiOutput::Paged {
+            child, child_stdin, ..
+        } = &self.output
+        {
+            self.pager_guard = PagerGuard::new(child, child_stdin);
+        }
+    }
+
     pub fn color(&self) -> bool {
         self.color
     }
 
+    /// Whether output with `label` is styled, taking the `colors` settings into
+    /// account. `label` may be several labels separated by spaces, like the
+    /// keys of the `colors` settings. Always false if color is off, so callers
+    /// can, for example, skip a color legend.
+    pub fn label_is_styled(&self, label: &str) -> bool {
+        self.color && self.formatter_factory.is_label_styled(label)
+    }
+
+    /// The choice `color()` was resolved from, either from the `ui.color`
+    /// setting or from the last call to `reset_color()`.
+    pub fn color_choice(&self) -> ColorChoice {
+        self.color_choice
+    }
+
     pub fn cwd(&self) -> &Path {
         &self.cwd
     }
@@ -189,99 +591,407 @@ impl Ui {
     /// Labels added to the returned formatter should be removed by caller.
     /// Otherwise the last color would persist.
     pub fn stdout_formatter<'a>(&'a self) -> Box<dyn Formatter + 'a> {
+        if self.formatter_factory.is_color() {
+            self.stdout_colored.set(true);
+        }
         match &self.output {
             UiOutput::Terminal { stdout, .. } => self.new_formatter(stdout.lock()),
             UiOutput::Paged { child_stdin, .. } => self.new_formatter(child_stdin),
+            UiOutput::ThresholdPaged { pager, .. } => self.new_formatter(pager.clone()),
+            UiOutput::FilePaged { file, .. } => self.new_formatter(file.as_file()),
+            UiOutput::Buffered { stdout, .. } => self.new_formatter(stdout.clone()),
+            UiOutput::Test {
+                unflushed_stdout, ..
+            } => self.new_formatter(TestStdout(unflushed_stdout)),
         }
     }
 
     /// Creates a formatter for the locked stderr stream.
-    pub fn stderr_formatter<'a>(&'a self) -> Box<dyn Formatter + 'a> {
-        match &self.output {
-            UiOutput::Terminal { stderr, .. } => self.new_formatter(stderr.lock()),
-            UiOutput::Paged { child_stdin, .. } => self.new_formatter(child_stdin),
-        }
+    ///
+    /// While the output is paged, the formatted data is buffered and written
+    /// to stderr once the pager exits.
+    pub fn stderr_formatter<'a>(&'a mut self) -> Box<dyn Formatter + 'a> {
+        self.output.stderr_formatter(&self.formatter_factory)
     }
 
     /// Whether continuous feedback should be displayed for long-running
     /// operations
     pub fn use_progress_indicator(&self) -> bool {
-        self.progress_indicator && io::stdout().is_tty()
+        self.progress_indicator && self.stdout_is_tty()
+    }
+
+    /// How long an operation has to run before its progress is displayed, so
+    /// fast operations don't flash a progress bar
+    pub fn progress_delay(&self) -> Duration {
+        progress_delay_setting(&self.settings)
+    }
+
+    /// Starts recording the hints, warnings, errors, and output written from
+    /// now on, so tests can check which messages were emitted.
+    pub fn enable_message_log(&mut self) {
+        self.message_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns the messages recorded since the last call, or nothing if the
+    /// message log isn't enabled.
+    pub fn take_messages(&mut self) -> Vec<UiMessage> {
+        self.message_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn log_message(&mut self, message: impl FnOnce() -> UiMessage) {
+        if let Some(log) = &mut self.message_log {
+            log.push(message());
+        }
+    }
+
+    /// Appends every hint, warning, and error written from now on to the file
+    /// at `path`, as `[timestamp] [level] message` lines.
+    pub fn set_log_file(&mut self, path: &Path) -> io::Result<()> {
+        let file = File::options().create(true).append(true).open(path)?;
+        self.log_file = Some(BufWriter::new(file));
+        Ok(())
     }
 
     pub fn write(&mut self, text: &str) -> io::Result<()> {
+        self.write_counted(text)?;
+        Ok(())
+    }
+
+    pub fn write_stderr(&mut self, text: &str) -> io::Result<()> {
+        self.write_stderr_counted(text)?;
+        Ok(())
+    }
+
+    /// Like `write()`, but returns the number of bytes written to stdout or,
+    /// while the output is paged, to the pager.
+    pub fn write_counted(&mut self, text: &str) -> io::Result<usize> {
+        self.log_message(|| UiMessage::Output(text.to_owned()));
         let data = text.as_bytes();
+        self.write_stdout_unlogged(data)?;
+        Ok(data.len())
+    }
+
+    /// Writes `data` to stdout or the pager without adding it to the message
+    /// log.
+    fn write_stdout_unlogged(&mut self, data: &[u8]) -> io::Result<()> {
         match &mut self.output {
-            UiOutput::Terminal { stdout, .. } => stdout.write_all(data),
-            UiOutput::Paged { child_stdin, .. } => child_stdin.write_all(data),
+            UiOutput::Terminal { stdout, .. } => stdout.write_all(data)?,
+            UiOutput::Paged { child_stdin, .. } => child_stdin.write_all(data)?,
+            UiOutput::ThresholdPaged { pager, .. } => pager.write_all(data)?,
+            UiOutput::FilePaged { file, .. } => file.write_all(data)?,
+            UiOutput::Buffered { stdout, .. } => stdout.write_all(data)?,
+            UiOutput::Test {
+                stdout,
+                unflushed_stdout,
+                ..
+            } => {
+                stdout.append(unflushed_stdout.get_mut());
+                stdout.write_all(data)?;
+            }
         }
+        Ok(())
     }
 
-    pub fn write_stderr(&mut self, text: &str) -> io::Result<()> {
+    /// Like `write_stderr()`, but returns the number of bytes written. While
+    /// the output is paged, that's the number of bytes buffered until the
+    /// pager exits.
+    pub fn write_stderr_counted(&mut self, text: &str) -> io::Result<usize> {
         let data = text.as_bytes();
         match &mut self.output {
-            UiOutput::Terminal { stderr, .. } => stderr.write_all(data),
-            UiOutput::Paged { child_stdin, .. } => child_stdin.write_all(data),
+            UiOutput::Terminal { stderr, .. } => stderr.write_all(data)?,
+            UiOutput::Paged { stderr_buffer, .. }
+            | UiOutput::ThresholdPaged { stderr_buffer, .. }
+            | UiOutput::FilePaged { stderr_buffer, .. } => stderr_buffer.write_all(data)?,
+            UiOutput::Buffered { stderr, .. } => stderr.write_all(data)?,
+            UiOutput::Test { stderr, .. } => stderr.write_all(data)?,
         }
+        Ok(data.len())
+    }
+
+    /// Like `write()`, but returns `self` so multi-part messages can be
+    /// written as `ui.write_chain("a")?.write_chain("b")?`.
+    pub fn write_chain(&mut self, text: &str) -> io::Result<&mut Self> {
+        self.write(text)?;
+        Ok(self)
+    }
+
+    /// Like `write_stderr()`, but returns `self` for chaining.
+    pub fn write_stderr_chain(&mut self, text: &str) -> io::Result<&mut Self> {
+        self.write_stderr(text)?;
+        Ok(self)
     }
 
     pub fn write_fmt(&mut self, fmt: fmt::Arguments<'_>) -> io::Result<()> {
+        self.log_message(|| UiMessage::Output(fmt.to_string()));
         match &mut self.output {
             UiOutput::Terminal { stdout, .. } => stdout.write_fmt(fmt),
             UiOutput::Paged { child_stdin, .. } => child_stdin.write_fmt(fmt),
+            UiOutput::ThresholdPaged { pager, .. } => pager.write_fmt(fmt),
+            UiOutput::FilePaged { file, .. } => file.write_fmt(fmt),
+            UiOutput::Buffered { stdout, .. } => stdout.write_fmt(fmt),
+            UiOutput::Test {
+                stdout,
+                unflushed_stdout,
+                ..
+            } => {
+                stdout.append(unflushed_stdout.get_mut());
+                stdout.write_fmt(fmt)
+            }
+        }
+    }
+
+    /// Starts a message made of several errors, warnings, and hints, such as
+    /// an error followed by hints, which are written with a single stderr
+    /// formatter, as `ui.message().error("...").hint("...").flush()`. Stderr
+    /// stays locked until the message is dropped, so the parts can't be
+    /// interleaved with other output.
+    pub fn message(&mut self) -> MessageBuilder<'_> {
+        MessageBuilder {
+            formatter: self.output.stderr_formatter(&self.formatter_factory),
+            message_log: &mut self.message_log,
+            log_file: &mut self.log_file,
+            result: Ok(()),
         }
     }
 
+    /// Writes a hint to stderr, or to stdout if stdout isn't a terminal, so
+    /// the hint stays in order with the output when both are redirected to
+    /// the same place. `ui.hints-to-stderr` keeps hints on stderr. Hints that
+    /// are part of a `message()` always go to stderr.
     pub fn write_hint(&mut self, text: impl AsRef<str>) -> io::Result<()> {
-        let mut formatter = self.stderr_formatter();
-        formatter.add_label("hint")?;
-        formatter.write_str(text.as_ref())?;
-        formatter.remove_label()?;
-        Ok(())
+        if let UiOutput::Terminal { stdout, .. } = &self.output {
+            if !self.hints_to_stderr && !stdout.is_tty() {
+                let message = MessageBuilder {
+                    formatter: self.formatter_factory.new_formatter(stdout.lock()),
+                    message_log: &mut self.message_log,
+                    log_file: &mut self.log_file,
+                    result: Ok(()),
+                };
+                return message.hint(text).flush();
+            }
+        }
+        self.message().hint(text).flush()
     }
 
     pub fn write_warn(&mut self, text: impl AsRef<str>) -> io::Result<()> {
-        let mut formatter = self.stderr_formatter();
-        formatter.add_label("warning")?;
-        formatter.write_str(text.as_ref())?;
-        formatter.remove_label()?;
-        Ok(())
+        self.message().warn(text).flush()
     }
 
     pub fn write_error(&mut self, text: &str) -> io::Result<()> {
-        let mut formatter = self.stderr_formatter();
-        formatter.add_label("error")?;
-        formatter.write_str(text)?;
-        formatter.remove_label()?;
-        Ok(())
+        self.message().error(text).flush()
     }
 
     pub fn flush(&mut self) -> io::Result<()> {
         match &mut self.output {
             UiOutput::Terminal { stdout, .. } => stdout.flush(),
             UiOutput::Paged { child_stdin, .. } => child_stdin.flush(),
+            UiOutput::ThresholdPaged { pager, .. } => pager.flush(),
+            UiOutput::FilePaged { file, .. } => file.flush(),
+            UiOutput::Buffered { stdout, .. } => stdout.flush(),
+            UiOutput::Test {
+                stdout,
+                unflushed_stdout,
+                ..
+            } => {
+                stdout.append(unflushed_stdout.get_mut());
+                Ok(())
+            }
         }
     }
 
     pub fn finalize_writes(&mut self) {
-        if let UiOutput::Paged {
-            mut child,
-            child_stdin,
-        } = mem::replace(&mut self.output, UiOutput::new_terminal())
-        {
-            drop(child_stdin);
-            if let Err(e) = child.wait() {
-                // It's possible (though unlikely) that this write fails, but
-                // this function gets called so late that there's not much we
-                // can do about it.
-                self.write_error(&format!("Failed to wait on pager {}", e))
-                    .ok();
+        match self.take_pager_to_wait_for() {
+            Some(PagerToWaitFor::Child {
+                mut child,
+                stderr_buffer,
+            }) => {
+                let wait_result = child.wait();
+                self.finish_paged(wait_result.map(drop), &stderr_buffer);
+            }
+            Some(PagerToWaitFor::File {
+                pager_cmd,
+                mut file,
+                stderr_buffer,
+            }) => {
+                let status = file
+                    .flush()
+                    .and_then(|()| pager_cmd.to_command().arg(file.path()).status());
+                self.finish_file_paged(&pager_cmd, &file, status.map(drop), &stderr_buffer);
+                // The temporary file is removed when `file` is dropped here,
+                // however the pager exited.
+            }
+            None => {}
+        }
+        self.flush_log_file();
+    }
+
+    /// Like `finalize_writes()`, but waits for the pager without blocking the
+    /// executor thread, so async entry points can await it. The pager of
+    /// `PagerMode::Pipe` was started before any runtime existed, so it's
+    /// waited on from a blocking task; the pager of `PagerMode::File` is
+    /// started here with `tokio::process`.
+    #[cfg(feature = "tokio")]
+    pub async fn finalize_writes_async(&mut self) {
+        match self.take_pager_to_wait_for() {
+            Some(PagerToWaitFor::Child {
+                mut child,
+                stderr_buffer,
+            }) => {
+                let wait_result = tokio::task::spawn_blocking(move || child.wait())
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)));
+                self.finish_paged(wait_result.map(drop), &stderr_buffer);
+            }
+            Some(PagerToWaitFor::File {
+                pager_cmd,
+                mut file,
+                stderr_buffer,
+            }) => {
+                let status = match file.flush() {
+                    Ok(()) => {
+                        tokio::process::Command::from(pager_cmd.to_command())
+                            .arg(file.path())
+                            .status()
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+                self.finish_file_paged(&pager_cmd, &file, status.map(drop), &stderr_buffer);
             }
+            None => {}
+        }
+        self.flush_log_file();
+    }
+
+    /// Switches back to writing to the terminal, and returns the pager that
+    /// `finalize_writes()` has yet to wait for, if any. The pager's stdin is
+    /// closed first, so it sees the end of the output. Output that
+    /// `UiOutput::ThresholdPaged` held back without starting a pager is
+    /// written out here.
+    fn take_pager_to_wait_for(&mut self) -> Option<PagerToWaitFor> {
+        match mem::replace(&mut self.output, UiOutput::new_terminal()) {
+            UiOutput::Paged {
+                child,
+                child_stdin,
+                stderr_buffer,
+            } => {
+                drop(self.pager_guard.take());
+                drop(child_stdin);
+                Some(PagerToWaitFor::Child {
+                    child,
+                    stderr_buffer,
+                })
+            }
+            UiOutput::ThresholdPaged {
+                pager,
+                stderr_buffer,
+            } => match pager.take_end() {
+                ThresholdPagerEnd::Paged(StartedPager {
+                    child,
+                    child_stdin,
+                    guard,
+                }) => {
+                    drop(guard);
+                    drop(child_stdin);
+                    Some(PagerToWaitFor::Child {
+                        child,
+                        stderr_buffer,
+                    })
+                }
+                ThresholdPagerEnd::Unpaged {
+                    output,
+                    spawn_error,
+                } => {
+                    self.finish_unpaged(&output, spawn_error, &stderr_buffer);
+                    None
+                }
+            },
+            UiOutput::FilePaged {
+                pager_cmd,
+                file,
+                stderr_buffer,
+            } => Some(PagerToWaitFor::File {
+                pager_cmd,
+                file,
+                stderr_buffer,
+            }),
+            output => {
+                self.output = output;
+                None
+            }
+        }
+    }
+
+    /// Reports the outcome of waiting for the pager of `UiOutput::Paged`.
+    fn finish_paged(&mut self, wait_result: io::Result<()>, stderr_buffer: &[u8]) {
+        // Messages written to stderr while paging were held back so they
+        // wouldn't interleave with the pager's output.
+        io::stderr().write_all(stderr_buffer).ok();
+        if let Err(e) = wait_result {
+            // It's possible (though unlikely) that this write fails, but
+            // this function gets called so late that there's not much we
+            // can do about it.
+            self.write_error(&format!("Failed to wait on pager {}", e))
+                .ok();
+        }
+    }
+
+    /// Writes the output that `UiOutput::ThresholdPaged` held back, since it
+    /// never reached the threshold, or reports why the pager couldn't be
+    /// started, if that's why the output wasn't paged.
+    fn finish_unpaged(
+        &mut self,
+        output: &[u8],
+        spawn_error: Option<(PagerCommand, io::Error)>,
+        stderr_buffer: &[u8],
+    ) {
+        io::stdout().write_all(output).ok();
+        io::stdout().flush().ok();
+        io::stderr().write_all(stderr_buffer).ok();
+        if let Some((pager_cmd, err)) = spawn_error {
+            let (message, hint) = pager_spawn_error(&pager_cmd, &err);
+            self.write_error(&message).ok();
+            if let Some(hint) = hint {
+                self.write_hint(hint).ok();
+            }
+        }
+    }
+
+    /// Reports the outcome of running the pager of `UiOutput::FilePaged`.
+    fn finish_file_paged(
+        &mut self,
+        pager_cmd: &PagerCommand,
+        file: &NamedTempFile,
+        status: io::Result<()>,
+        stderr_buffer: &[u8],
+    ) {
+        io::stderr().write_all(stderr_buffer).ok();
+        if let Err(err) = status {
+            // Don't lose the output just because the pager couldn't
+            // be started
+            let (message, hint) = pager_spawn_error(pager_cmd, &err);
+            self.write_error(&message).ok();
+            if let Some(hint) = hint {
+                self.write_hint(hint).ok();
+            }
+            if let Ok(content) = std::fs::read(file.path()) {
+                io::stdout().write_all(&content).ok();
+            }
+        }
+    }
+
+    fn flush_log_file(&mut self) {
+        // Flushed last so errors from waiting on the pager are logged too
+        if let Some(log_file) = &mut self.log_file {
+            log_file.flush().ok();
         }
     }
 
     pub fn prompt(&mut self, prompt: &str) -> io::Result<String> {
-        if !io::stdout().is_tty() {
+        if !self.stdout_is_tty() {
             return Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "Cannot prompt for input since the output is not connected to a terminal",
@@ -294,35 +1004,125 @@ impl Ui {
         Ok(buf)
     }
 
+    /// Like `prompt()`, but reads lines until one that only contains
+    /// `terminator` (usually `DEFAULT_PROMPT_TERMINATOR`), or until the end of
+    /// the input. The terminator line isn't part of the returned text. Unlike
+    /// `prompt()`, the input doesn't need to come from a terminal.
+    pub fn prompt_multiline(&mut self, prompt: &str, terminator: &str) -> io::Result<String> {
+        let stdin = io::stdin();
+        if stdin.is_tty() {
+            self.write_hint(format!(
+                "Enter a line with only \"{terminator}\" to finish, or press Ctrl-D.\n"
+            ))?;
+        }
+        writeln!(self, "{}:", prompt)?;
+        self.flush()?;
+        read_until_terminator(&mut stdin.lock(), terminator)
+    }
+
     pub fn prompt_password(&mut self, prompt: &str) -> io::Result<String> {
-        if !io::stdout().is_tty() {
+        self.prompt_password_to(prompt, io::stderr())
+    }
+
+    /// Like `prompt_password()`, but writes the prompt to `out` instead of
+    /// stderr. The password is still read from the terminal without echoing
+    /// it.
+    pub fn prompt_password_to<W: Write>(&mut self, prompt: &str, mut out: W) -> io::Result<String> {
+        if !self.stdout_is_tty() {
             return Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "Cannot prompt for input since the output is not connected to a terminal",
             ));
         }
-        rpassword::prompt_password(format!("{prompt}: "))
+        write!(out, "{prompt}: ")?;
+        out.flush()?;
+        rpassword::read_password()
+    }
+
+    /// Whether stdout is connected to a terminal (possibly through the pager).
+    /// Output kept in memory never is.
+    pub fn stdout_is_tty(&self) -> bool {
+        match &self.output {
+            UiOutput::Terminal { .. }
+            | UiOutput::Paged { .. }
+            | UiOutput::ThresholdPaged { .. }
+            | UiOutput::FilePaged { .. } => io::stdout().is_tty(),
+            UiOutput::Buffered { .. } | UiOutput::Test { .. } => false,
+        }
+    }
+
+    /// Whether the pager is still running, so a command that produces a lot
+    /// of output can stop once the user quit the pager, instead of producing
+    /// output that only fails to write with `BrokenPipe`. Returns `true` if
+    /// the output isn't piped to a pager, or the pager hasn't been started
+    /// yet.
+    pub fn pager_alive(&mut self) -> bool {
+        match &mut self.output {
+            UiOutput::Paged { child, .. } => matches!(child.try_wait(), Ok(None)),
+            UiOutput::ThresholdPaged { pager, .. } => match &mut pager.0.lock().unwrap().pager {
+                Some(started) => matches!(started.child.try_wait(), Ok(None)),
+                None => true,
+            },
+            UiOutput::Terminal { .. }
+            | UiOutput::FilePaged { .. }
+            | UiOutput::Buffered { .. }
+            | UiOutput::Test { .. } => true,
+        }
     }
 
+    /// The size of the terminal as `(columns, rows)`. The `COLUMNS` and `LINES`
+    /// environment variables take precedence over the size reported by the
+    /// terminal, so scripts can choose the width even if the output isn't a
+    /// terminal. In that case, both variables must be set.
     pub fn size(&self) -> Option<(u16, u16)> {
-        crossterm::terminal::size().ok()
+        terminal_size_from(
+            std::env::var("COLUMNS").ok().as_deref(),
+            std::env::var("LINES").ok().as_deref(),
+            crossterm::terminal::size().ok(),
+        )
     }
 
     /// Construct a guard object which writes `data` when dropped. Useful for
     /// restoring terminal state.
+    ///
+    /// While the output is paged, the guard writes to the pager. The guard
+    /// must then be dropped before `finalize_writes()`, or the pager won't see
+    /// the end of its input.
     pub fn output_guard(&self, text: String) -> OutputGuard {
-        OutputGuard {
-            text,
-            output: match self.output {
-                UiOutput::Terminal { .. } => io::stdout(),
-                // TODO we don't actually need to write in this case, so it
-                // might be better to no-op
-                UiOutput::Paged { .. } => io::stdout(),
+        let output: Box<dyn Write + Send> = match &self.output {
+            UiOutput::Terminal { .. } => Box::new(io::stdout()),
+            UiOutput::Paged { child_stdin, .. } => match duplicate_pager_stdin(child_stdin) {
+                Some(file) => Box::new(file),
+                None => Box::new(io::stdout()),
             },
-        }
+            UiOutput::ThresholdPaged { pager, .. } => Box::new(pager.clone()),
+            UiOutput::FilePaged { file, .. } => match file.as_file().try_clone() {
+                Ok(file) => Box::new(file),
+                Err(_) => Box::new(io::stdout()),
+            },
+            UiOutput::Buffered { stdout, .. } => Box::new(stdout.clone()),
+            // The guard can't hold on to the `Ui`, and there's no terminal
+            // state to restore anyway
+            UiOutput::Test { .. } => Box::new(io::sink()),
+        };
+        OutputGuard { text, output }
     }
 }
 
+/// Computes `Ui::size()` from the values of the `COLUMNS` and `LINES`
+/// environment variables and the size reported by the terminal. Values that
+/// aren't valid sizes are ignored.
+fn terminal_size_from(
+    columns: Option<&str>,
+    lines: Option<&str>,
+    terminal_size: Option<(u16, u16)>,
+) -> Option<(u16, u16)> {
+    let parse = |value: Option<&str>| value?.trim().parse::<u16>().ok();
+    let columns = parse(columns).or_else(|| terminal_size.map(|(cols, _)| cols))?;
+    let rows = parse(lines).or_else(|| terminal_size.map(|(_, rows)| rows))?;
+    Some((columns, rows))
+}
+
 enum UiOutput {
     Terminal {
         stdout: Stdout,
@@ -331,10 +1131,169 @@ enum UiOutput {
     Paged {
         child: Child,
         child_stdin: ChildStdin,
+        stderr_buffer: Vec<u8>,
     },
+    /// Output is held back until it reaches the threshold set by
+    /// `Ui::set_pager_threshold()`, and then piped to the pager.
+    ThresholdPaged {
+        pager: ThresholdPager,
+        stderr_buffer: Vec<u8>,
+    },
+    /// Output is collected in a temporary file, which is shown with the pager
+    /// in `finalize_writes()`.
+    FilePaged {
+        pager_cmd: PagerCommand,
+        file: NamedTempFile,
+        stderr_buffer: Vec<u8>,
+    },
+    Buffered {
+        stdout: SharedBuffer,
+        stderr: SharedBuffer,
+    },
+    /// Output is kept in buffers owned by the `Ui`. `Ui::stdout_formatter()`
+    /// writes through a shared `&Ui`, so it writes to `unflushed_stdout`,
+    /// which is moved to the end of `stdout` when the `Ui` is next written to
+    /// or flushed.
+    Test {
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        unflushed_stdout: RefCell<Vec<u8>>,
+    },
+}
+
+/// Writes the parts of a message to stderr with a single formatter. Created by
+/// `Ui::message()`.
+///
+/// Errors are kept until `flush()`, so the parts can be chained. Once writing
+/// a part failed, the later parts are skipped.
+#[must_use = "call flush() to find out whether the message was written"]
+pub struct MessageBuilder<'a> {
+    formatter: Box<dyn Formatter + 'a>,
+    message_log: &'a mut Option<Vec<UiMessage>>,
+    log_file: &'a mut Option<BufWriter<File>>,
+    result: io::Result<()>,
+}
+
+impl MessageBuilder<'_> {
+    pub fn hint(self, text: impl AsRef<str>) -> Self {
+        self.part("hint", "hint", UiMessage::Hint, text.as_ref())
+    }
+
+    pub fn warn(self, text: impl AsRef<str>) -> Self {
+        self.part("warning", "warn", UiMessage::Warning, text.as_ref())
+    }
+
+    pub fn error(self, text: impl AsRef<str>) -> Self {
+        self.part("error", "error", UiMessage::Error, text.as_ref())
+    }
+
+    /// Flushes the formatter and returns the first error from writing the
+    /// message, if any.
+    pub fn flush(mut self) -> io::Result<()> {
+        self.result?;
+        self.formatter.flush()
+    }
+
+    fn part(
+        mut self,
+        label: &str,
+        level: &str,
+        to_message: impl FnOnce(String) -> UiMessage,
+        text: &str,
+    ) -> Self {
+        if self.result.is_ok() {
+            if let Some(log) = self.message_log.as_mut() {
+                log.push(to_message(text.to_owned()));
+            }
+            self.result = self.write_log_entry(level, text).and_then(|()| {
+                self.formatter
+                    .with_label(label, |formatter| formatter.write_str(text))
+            });
+        }
+        self
+    }
+
+    fn write_log_entry(&mut self, level: &str, text: &str) -> io::Result<()> {
+        if let Some(log_file) = self.log_file.as_mut() {
+            let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z");
+            let message = text.strip_suffix('\n').unwrap_or(text);
+            writeln!(log_file, "[{timestamp}] [{level}] {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Gives access to what was written to a `Ui` created by
+/// `Ui::for_buffers()`.
+#[derive(Clone, Debug, Default)]
+pub struct BufferHandles {
+    stdout: SharedBuffer,
+    stderr: SharedBuffer,
+}
+
+impl BufferHandles {
+    /// The bytes written to stdout so far.
+    pub fn stdout_bytes(&self) -> Vec<u8> {
+        self.stdout.0.lock().unwrap().clone()
+    }
+
+    /// The bytes written to stderr so far.
+    pub fn stderr_bytes(&self) -> Vec<u8> {
+        self.stderr.0.lock().unwrap().clone()
+    }
+}
+
+/// Gives access to what was written to a `Ui` created by
+/// `Ui::with_captured_output()`.
+pub type OutputCapture = BufferHandles;
+
+/// Writes to the unflushed stdout of `UiOutput::Test`.
+struct TestStdout<'a>(&'a RefCell<Vec<u8>>);
+
+impl Write for TestStdout<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An in-memory buffer that can be written through any of its clones.
+#[derive(Clone, Debug, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl UiOutput {
+    /// Creates a formatter for the locked stderr stream, or for the buffer
+    /// that replaces it while the output is paged.
+    fn stderr_formatter<'a>(
+        &'a mut self,
+        formatter_factory: &FormatterFactory,
+    ) -> Box<dyn Formatter + 'a> {
+        match self {
+            UiOutput::Terminal { stderr, .. } => formatter_factory.new_formatter(stderr.lock()),
+            UiOutput::Paged { stderr_buffer, .. }
+            | UiOutput::ThresholdPaged { stderr_buffer, .. }
+            | UiOutput::FilePaged { stderr_buffer, .. } => {
+                formatter_factory.new_formatter(stderr_buffer)
+            }
+            UiOutput::Buffered { stderr, .. } => formatter_factory.new_formatter(stderr.clone()),
+            UiOutput::Test { stderr, .. } => formatter_factory.new_formatter(stderr),
+        }
+    }
+
     fn new_terminal() -> UiOutput {
         UiOutput::Terminal {
             stdout: io::stdout(),
@@ -342,27 +1301,287 @@ impl UiOutput {
         }
     }
 
-    fn new_paged_else_terminal(settings: &UserSettings) -> UiOutput {
-        let pager_cmd = pager_setting(settings);
-        let child_result = Command::new(pager_cmd).stdin(Stdio::piped()).spawn();
-        match child_result {
+    fn new_paged(pager_cmd: &PagerCommand) -> io::Result<UiOutput> {
+        let mut child = pager_cmd.to_command().stdin(Stdio::piped()).spawn()?;
+        let child_stdin = child.stdin.take().unwrap();
+        Ok(UiOutput::Paged {
+            child,
+            child_stdin,
+            stderr_buffer: vec![],
+        })
+    }
+
+    fn new_threshold_paged(
+        pager_cmd: PagerCommand,
+        threshold: usize,
+        guard_pager: bool,
+    ) -> UiOutput {
+        UiOutput::ThresholdPaged {
+            pager: ThresholdPager(Arc::new(Mutex::new(ThresholdPagerState {
+                pager_cmd,
+                threshold,
+                guard_pager,
+                buffer: vec![],
+                lines: 0,
+                pager: None,
+                spawn_error: None,
+            }))),
+            stderr_buffer: vec![],
+        }
+    }
+
+    fn new_file_paged(pager_cmd: PagerCommand) -> io::Result<UiOutput> {
+        let file = tempfile::Builder::new().prefix("jj-pager-").tempfile()?;
+        Ok(UiOutput::FilePaged {
+            pager_cmd,
+            file,
+            stderr_buffer: vec![],
+        })
+    }
+}
+
+/// The line that ends the input of `Ui::prompt_multiline()` by default.
+pub const DEFAULT_PROMPT_TERMINATOR: &str = ".";
+
+/// Reads lines from `input` until one that only contains `terminator`, apart
+/// from its line ending, or until the end of the input. Returns the lines
+/// before the terminator.
+fn read_until_terminator(input: &mut impl BufRead, terminator: &str) -> io::Result<String> {
+    let mut text = String::new();
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        let content = line.strip_suffix('\n').unwrap_or(&line);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        if content == terminator {
+            break;
+        }
+        text.push_str(&line);
+    }
+    Ok(text)
+}
+
+/// Returns the error message for a pager that couldn't be spawned, and a hint
+/// suited to why.
+fn pager_spawn_error(pager_cmd: impl fmt::Display, err: &io::Error) -> (String, Option<String>) {
+    let message = format!("Failed to spawn pager '{pager_cmd}': {err}\n");
+    let hint = match err.kind() {
+        io::ErrorKind::NotFound => Some(format!(
+            "Is '{pager_cmd}' installed and on PATH? The pager can be changed with ui.pager.\n"
+        )),
+        io::ErrorKind::PermissionDenied => {
+            Some(format!("Check that '{pager_cmd}' is executable.\n"))
+        }
+        _ => None,
+    };
+    (message, hint)
+}
+
+/// Closes the pager's stdin and waits for the pager if the process is
+/// interrupted. Created by `Ui::install_pager_signal_guard()`.
+///
+/// Dropping the guard disarms it, since the `Ui` still owns the pager's stdin
+/// and the `Child`, and closes and waits for them itself.
+struct PagerGuard {
+    /// Set when the guard is dropped, so only the signal handler runs the
+    /// cleanup
+    disarmed: Arc<AtomicBool>,
+    _guard: CleanupGuard,
+}
+
+impl PagerGuard {
+    #[cfg(unix)]
+    fn new(child: &Child, child_stdin: &ChildStdin) -> Option<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let disarmed = Arc::new(AtomicBool::new(false));
+        let pid = child.id() as libc::pid_t;
+        let stdin_fd = child_stdin.as_raw_fd();
+        let guard = CleanupGuard::new({
+            let disarmed = disarmed.clone();
+            move || {
+                if disarmed.load(Ordering::SeqCst) {
+                    return;
+                }
+                // Safety: The `Ui` still owns the pager's stdin and hasn't
+                // waited for the pager, and the process exits once the
+                // cleanup guards have run, so the `Ui` never uses them again.
+                unsafe {
+                    libc::close(stdin_fd);
+                    libc::waitpid(pid, std::ptr::null_mut(), 0);
+                }
+            }
+        });
+        Some(PagerGuard {
+            disarmed,
+            _guard: guard,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new(_child: &Child, _child_stdin: &ChildStdin) -> Option<Self> {
+        None
+    }
+}
+
+impl Drop for PagerGuard {
+    fn drop(&mut self) {
+        // Runs before `_guard` is dropped, so its callback returns early
+        self.disarmed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The most output `ThresholdPager` holds back, however few lines it has, so
+/// the memory it uses stays bounded.
+const PAGER_THRESHOLD_MAX_BYTES: usize = 64 * 1024;
+
+/// The stdout of `UiOutput::ThresholdPaged`, which can be written through any
+/// of its clones. Output is held back until it has more lines than the
+/// threshold. Then the pager is started, and everything is piped to it from
+/// then on. If the pager can't be started, the output goes to stdout instead.
+#[derive(Clone)]
+struct ThresholdPager(Arc<Mutex<ThresholdPagerState>>);
+
+struct ThresholdPagerState {
+    pager_cmd: PagerCommand,
+    threshold: usize,
+    /// Whether the pager gets a `PagerGuard` when it's started
+    guard_pager: bool,
+    buffer: Vec<u8>,
+    /// The number of lines in `buffer`
+    lines: usize,
+    pager: Option<StartedPager>,
+    spawn_error: Option<io::Error>,
+}
+
+struct StartedPager {
+    child: Child,
+    child_stdin: ChildStdin,
+    guard: Option<PagerGuard>,
+}
+
+/// A pager that `Ui::finalize_writes()` has to wait for before returning.
+enum PagerToWaitFor {
+    /// A pager that reads the output from its stdin, which has been closed
+    Child {
+        child: Child,
+        stderr_buffer: Vec<u8>,
+    },
+    /// A pager yet to be started on the file the output was written to
+    File {
+        pager_cmd: PagerCommand,
+        file: NamedTempFile,
+        stderr_buffer: Vec<u8>,
+    },
+}
+
+/// What `Ui::finalize_writes()` has to finish for a `ThresholdPager`.
+enum ThresholdPagerEnd {
+    Paged(StartedPager),
+    /// The threshold wasn't reached, or the pager couldn't be started, in
+    /// which case the error is kept with the pager command
+    Unpaged {
+        output: Vec<u8>,
+        spawn_error: Option<(PagerCommand, io::Error)>,
+    },
+}
+
+impl ThresholdPager {
+    fn take_end(&self) -> ThresholdPagerEnd {
+        let state = &mut *self.0.lock().unwrap();
+        match state.pager.take() {
+            Some(pager) => ThresholdPagerEnd::Paged(pager),
+            None => ThresholdPagerEnd::Unpaged {
+                output: mem::take(&mut state.buffer),
+                spawn_error: state
+                    .spawn_error
+                    .take()
+                    .map(|err| (state.pager_cmd.clone(), err)),
+            },
+        }
+    }
+}
+
+impl ThresholdPagerState {
+    /// Starts the pager and writes the output held back so far to it.
+    fn start_pager(&mut self) -> io::Result<()> {
+        let buffer = mem::take(&mut self.buffer);
+        match self.pager_cmd.to_command().stdin(Stdio::piped()).spawn() {
             Ok(mut child) => {
-                let child_stdin = child.stdin.take().unwrap();
-                UiOutput::Paged { child, child_stdin }
+                let mut child_stdin = child.stdin.take().unwrap();
+                let guard = if self.guard_pager {
+                    PagerGuard::new(&child, &child_stdin)
+                } else {
+                    None
+                };
+                let result = child_stdin.write_all(&buffer);
+                self.pager = Some(StartedPager {
+                    child,
+                    child_stdin,
+                    guard,
+                });
+                result
             }
-            Err(e) => {
-                io::stderr()
-                    .write_fmt(format_args!("Failed to spawn pager: {}", e))
-                    .ok();
-                UiOutput::new_terminal()
+            Err(err) => {
+                self.spawn_error = Some(err);
+                io::stdout().write_all(&buffer)
             }
         }
     }
 }
 
+impl Write for ThresholdPager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = &mut *self.0.lock().unwrap();
+        if let Some(pager) = &mut state.pager {
+            return pager.child_stdin.write(buf);
+        }
+        if state.spawn_error.is_some() {
+            return io::stdout().write(buf);
+        }
+        state.buffer.extend_from_slice(buf);
+        state.lines += buf.iter().filter(|b| **b == b'\n').count();
+        if state.lines > state.threshold || state.buffer.len() > PAGER_THRESHOLD_MAX_BYTES {
+            state.start_pager()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let state = &mut *self.0.lock().unwrap();
+        match &mut state.pager {
+            Some(pager) => pager.child_stdin.flush(),
+            None if state.spawn_error.is_some() => io::stdout().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Opens another handle to the pager's stdin, which can outlive the `Ui`'s.
+#[cfg(unix)]
+fn duplicate_pager_stdin(child_stdin: &ChildStdin) -> Option<File> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    // Safety: `dup()` returns a new file descriptor, which the `File` takes
+    // ownership of.
+    let fd = unsafe { libc::dup(child_stdin.as_raw_fd()) };
+    if fd < 0 {
+        None
+    } else {
+        Some(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+#[cfg(not(unix))]
+fn duplicate_pager_stdin(_child_stdin: &ChildStdin) -> Option<File> {
+    None
+}
+
 pub struct OutputGuard {
     text: String,
-    output: Stdout,
+    output: Box<dyn Write + Send>,
 }
 
 impl Drop for OutputGuard {
@@ -370,3 +1589,554 @@ impl Drop for OutputGuard {
         _ = self.output.write_all(self.text.as_bytes());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn test_buffered_output() {
+        let (mut ui, buffers) = Ui::for_buffers(UserSettings::default());
+        writeln!(ui, "out {}", 1).unwrap();
+        ui.write("out 2\n").unwrap();
+        ui.stdout_formatter().write_str("out 3\n").unwrap();
+        ui.write_stderr("err 1\n").unwrap();
+        ui.write_hint("err 2\n").unwrap();
+        ui.request_pager();
+        ui.write("out 4\n").unwrap();
+        ui.finalize_writes();
+        assert_eq!(buffers.stdout_bytes(), b"out 1\nout 2\nout 3\nout 4\n");
+        assert_eq!(buffers.stderr_bytes(), b"err 1\nerr 2\n");
+        assert!(!ui.stdout_is_tty());
+        ui.reset_color(ColorChoice::Auto);
+        assert!(!ui.color());
+    }
+
+    #[test]
+    fn test_captured_output() {
+        let (mut ui, capture) = Ui::with_captured_output(UserSettings::default());
+        ui.write("out\n").unwrap();
+        ui.write_stderr("err\n").unwrap();
+        ui.finalize_writes();
+        assert_eq!(capture.stdout_bytes(), b"out\n");
+        assert_eq!(capture.stderr_bytes(), b"err\n");
+    }
+
+    #[test]
+    fn test_for_test() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.write("out\n").unwrap();
+        ui.stdout_formatter().write_str("formatted\n").unwrap();
+        ui.write_warn("warning\n").unwrap();
+        // The formatter's output isn't there until the `Ui` is flushed
+        assert_eq!(ui.test_stdout(), b"out\n");
+        ui.flush().unwrap();
+        assert_eq!(ui.test_stdout(), b"out\nformatted\n");
+        assert_eq!(ui.test_stderr(), b"warning\n");
+        assert!(!ui.stdout_is_tty());
+    }
+
+    #[test]
+    fn test_write_counted() {
+        let (mut ui, buffers) = Ui::for_buffers(UserSettings::default());
+        assert_eq!(ui.write_counted("out\n").unwrap(), 4);
+        assert_eq!(ui.write_counted("").unwrap(), 0);
+        assert_eq!(ui.write_stderr_counted("err \u{2026}\n").unwrap(), 8);
+        assert_eq!(buffers.stdout_bytes(), b"out\n");
+        assert_eq!(buffers.stderr_bytes(), "err \u{2026}\n".as_bytes());
+    }
+
+    #[test]
+    fn test_message_log() {
+        let (mut ui, buffers) = Ui::for_buffers(UserSettings::default());
+        ui.write("before\n").unwrap();
+        assert_eq!(ui.take_messages(), vec![]);
+        ui.enable_message_log();
+        ui.write("out 1\n").unwrap();
+        writeln!(ui, "out {}", 2).unwrap();
+        ui.write_hint("hint\n").unwrap();
+        ui.write_warn("warning\n").unwrap();
+        ui.write_error("error\n").unwrap();
+        assert_eq!(
+            ui.take_messages(),
+            vec![
+                UiMessage::Output("out 1\n".to_string()),
+                UiMessage::Output("out 2\n".to_string()),
+                UiMessage::Hint("hint\n".to_string()),
+                UiMessage::Warning("warning\n".to_string()),
+                UiMessage::Error("error\n".to_string()),
+            ]
+        );
+        assert_eq!(ui.take_messages(), vec![]);
+        // The messages are still written
+        assert_eq!(buffers.stdout_bytes(), b"before\nout 1\nout 2\n");
+        assert_eq!(buffers.stderr_bytes(), b"hint\nwarning\nerror\n");
+    }
+
+    #[test]
+    fn test_message_builder() {
+        let (mut ui, buffers) = Ui::for_buffers(UserSettings::default());
+        ui.enable_message_log();
+        ui.message()
+            .error("Error: failed\n")
+            .hint("Hint: try this\n")
+            .warn("Warning: or that\n")
+            .flush()
+            .unwrap();
+        ui.write_hint("Hint: again\n").unwrap();
+        assert_eq!(
+            ui.take_messages(),
+            vec![
+                UiMessage::Error("Error: failed\n".to_string()),
+                UiMessage::Hint("Hint: try this\n".to_string()),
+                UiMessage::Warning("Warning: or that\n".to_string()),
+                UiMessage::Hint("Hint: again\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            buffers.stderr_bytes(),
+            b"Error: failed\nHint: try this\nWarning: or that\nHint: again\n"
+        );
+    }
+
+    #[test]
+    fn test_log_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log_path = temp_dir.path().join("jj.log");
+        std::fs::write(&log_path, "existing\n").unwrap();
+        let (mut ui, buffers) = Ui::for_buffers(UserSettings::default());
+        ui.write_hint("before\n").unwrap();
+        ui.set_log_file(&log_path).unwrap();
+        ui.write("out\n").unwrap();
+        ui.write_hint("hint\n").unwrap();
+        ui.write_warn("warning").unwrap();
+        ui.write_error("error\n").unwrap();
+        ui.finalize_writes();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 4, "{log}");
+        assert_eq!(lines[0], "existing");
+        assert!(lines[1].starts_with('[') && lines[1].ends_with("] [hint] hint"));
+        assert!(lines[2].ends_with("] [warn] warning"));
+        assert!(lines[3].ends_with("] [error] error"));
+        // The messages are still written to stderr
+        assert_eq!(buffers.stderr_bytes(), b"before\nhint\nwarningerror\n");
+    }
+
+    #[test]
+    fn test_terminal_size_from() {
+        assert_eq!(terminal_size_from(None, None, None), None);
+        assert_eq!(
+            terminal_size_from(None, None, Some((80, 24))),
+            Some((80, 24))
+        );
+        // The environment variables take precedence
+        assert_eq!(
+            terminal_size_from(Some("123"), Some(" 45\n"), Some((80, 24))),
+            Some((123, 45))
+        );
+        assert_eq!(
+            terminal_size_from(Some("123"), None, Some((80, 24))),
+            Some((123, 24))
+        );
+        // Without a terminal, both must be set
+        assert_eq!(
+            terminal_size_from(Some("123"), Some("45"), None),
+            Some((123, 45))
+        );
+        assert_eq!(terminal_size_from(Some("123"), None, None), None);
+        // Values that aren't valid sizes are ignored
+        assert_eq!(
+            terminal_size_from(Some("wide"), Some("100000"), Some((80, 24))),
+            Some((80, 24))
+        );
+    }
+
+    #[test]
+    fn test_read_until_terminator() {
+        let read = |input: &str, terminator: &str| {
+            read_until_terminator(&mut input.as_bytes(), terminator).unwrap()
+        };
+        assert_eq!(read("a\nb\n.\nc\n", "."), "a\nb\n");
+        assert_eq!(read("a\r\n.\r\n", "."), "a\r\n");
+        // The terminator must be on its own line
+        assert_eq!(read("a.\n .\n.", "."), "a.\n .\n");
+        assert_eq!(read("a\nEOF\nb\n", "EOF"), "a\n");
+        // The end of the input also ends the text
+        assert_eq!(read("a\nb", "."), "a\nb");
+        assert_eq!(read("", "."), "");
+    }
+
+    #[test]
+    fn test_pager_mode_setting() {
+        assert_eq!(
+            pager_mode_setting(&UserSettings::default()),
+            PagerMode::Pipe
+        );
+        let settings = UserSettings::default()
+            .with_toml_strings(&["ui.pager-mode=\"file\"".to_string()])
+            .unwrap();
+        assert_eq!(pager_mode_setting(&settings), PagerMode::File);
+        assert!("stream".parse::<PagerMode>().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_file_pager_removes_temp_file() {
+        let (mut ui, _buffers) = Ui::for_buffers(UserSettings::default());
+        // `false` exits right away, without reading the file
+        ui.output = UiOutput::new_file_paged("false".into()).unwrap();
+        let path = match &ui.output {
+            UiOutput::FilePaged { file, .. } => file.path().to_owned(),
+            _ => unreachable!(),
+        };
+        ui.write("out 1\n").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"out 1\n");
+        ui.finalize_writes();
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_finalize_writes_async() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (mut ui, _buffers) = Ui::for_buffers(UserSettings::default());
+        ui.output = UiOutput::new_file_paged("false".into()).unwrap();
+        let path = match &ui.output {
+            UiOutput::FilePaged { file, .. } => file.path().to_owned(),
+            _ => unreachable!(),
+        };
+        ui.write("out 1\n").unwrap();
+        runtime.block_on(ui.finalize_writes_async());
+        assert!(!path.exists());
+
+        ui.output = UiOutput::new_paged(&"true".into()).unwrap();
+        ui.write("out 2\n").ok();
+        runtime.block_on(ui.finalize_writes_async());
+        assert!(matches!(ui.output, UiOutput::Terminal { .. }));
+    }
+
+    #[test]
+    fn test_pager_spawn_error() {
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        let (message, hint) = pager_spawn_error("lss", &not_found);
+        assert_eq!(
+            message,
+            format!("Failed to spawn pager 'lss': {not_found}\n")
+        );
+        assert_eq!(
+            hint.as_deref(),
+            Some("Is 'lss' installed and on PATH? The pager can be changed with ui.pager.\n")
+        );
+        let (_, hint) =
+            pager_spawn_error("less", &io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(hint.as_deref(), Some("Check that 'less' is executable.\n"));
+        let (_, hint) = pager_spawn_error("less", &io::Error::from(io::ErrorKind::Other));
+        assert_eq!(hint, None);
+    }
+
+    #[test]
+    fn test_reset_color_resets_terminal() {
+        let settings = UserSettings::default()
+            .with_toml_strings(&["ui.color=\"always\"".to_string()])
+            .unwrap();
+        let (mut ui, buffers) = Ui::for_buffers(settings.clone());
+        let mut formatter = ui.stdout_formatter();
+        formatter.add_label("error").unwrap();
+        formatter.write_str("text").unwrap();
+        drop(formatter);
+        assert_eq!(buffers.stdout_bytes(), b"\x1b[31mtext");
+        ui.enable_message_log();
+        ui.reset_color(ColorChoice::Never);
+        assert!(!ui.color());
+        assert_eq!(buffers.stdout_bytes(), b"\x1b[31mtext\x1b[0m");
+        // The reset isn't logged as output
+        assert_eq!(ui.take_messages(), vec![]);
+
+        // Nothing is written if no colored output was written
+        let (mut ui, buffers) = Ui::for_buffers(settings);
+        ui.reset_color(ColorChoice::Never);
+        assert_eq!(buffers.stdout_bytes(), b"");
+    }
+
+    #[test]
+    fn test_prompt_password_to_without_terminal() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        let mut out = vec![];
+        let err = ui.prompt_password_to("Password", &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(out, b"");
+    }
+
+    #[test]
+    fn test_pager_signal_guard_without_pager() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.install_pager_signal_guard();
+        assert!(ui.pager_guard.is_none());
+        ui.finalize_writes();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pager_signal_guard() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.install_pager_signal_guard();
+        // A pager started after the guard was installed is guarded too
+        ui.output = UiOutput::new_paged(&"cat".into()).unwrap();
+        ui.update_pager_guard();
+        assert!(ui.pager_guard.is_some());
+        // The `Ui` waits for the pager itself, so the guard is disarmed
+        ui.finalize_writes();
+        assert!(ui.pager_guard.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pager_guard_drop_keeps_stdin_open() {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut child_stdin = child.stdin.take().unwrap();
+        let guard = PagerGuard::new(&child, &child_stdin);
+        assert!(guard.is_some());
+        // Dropping the guard must neither close the pager's stdin nor reap the
+        // pager, since the `Ui` still owns both
+        drop(guard);
+        child_stdin.write_all(b"still open\n").unwrap();
+        drop(child_stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_pager_threshold() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.output = UiOutput::new_threshold_paged("jj-test-no-such-pager".into(), 2, false);
+        let held_back = |ui: &Ui| match &ui.output {
+            UiOutput::ThresholdPaged { pager, .. } => {
+                let state = pager.0.lock().unwrap();
+                assert!(state.pager.is_none());
+                assert!(state.spawn_error.is_none());
+                state.buffer.clone()
+            }
+            _ => panic!("the output isn't waiting for the pager threshold"),
+        };
+        ui.write("a\n").unwrap();
+        ui.stdout_formatter().write_str("b\n").unwrap();
+        ui.write_stderr("warning\n").unwrap();
+        // The threshold isn't exceeded yet
+        assert_eq!(held_back(&ui), b"a\nb\n");
+        assert!(matches!(
+            &ui.output,
+            UiOutput::ThresholdPaged { stderr_buffer, .. } if stderr_buffer == b"warning\n"
+        ));
+        // A partial line doesn't count
+        ui.write("c").unwrap();
+        assert_eq!(held_back(&ui), b"a\nb\nc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pager_threshold_exceeded() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.install_pager_signal_guard();
+        ui.output = UiOutput::new_threshold_paged("cat".into(), 2, true);
+        ui.write("\n\n\n").unwrap();
+        match &ui.output {
+            UiOutput::ThresholdPaged { pager, .. } => {
+                let state = pager.0.lock().unwrap();
+                let started = state.pager.as_ref().unwrap();
+                assert!(started.guard.is_some());
+                assert!(state.buffer.is_empty());
+            }
+            _ => panic!("the output isn't waiting for the pager threshold"),
+        }
+        ui.write("\n").unwrap();
+        ui.finalize_writes();
+        assert!(matches!(ui.output, UiOutput::Terminal { .. }));
+    }
+
+    #[test]
+    fn test_pager_setting() {
+        let pager_for_config = |toml: &str| {
+            let settings = UserSettings::default()
+                .with_toml_strings(&[toml.to_string()])
+                .unwrap();
+            pager_setting(&settings)
+        };
+        let pager_for_string = |pager: &str| pager_for_config(&format!("ui.pager = {pager:?}"));
+        let command = |words: &[&str]| PagerCommand {
+            command: words.iter().map(|word| word.to_string()).collect(),
+            env: HashMap::new(),
+        };
+        assert_eq!(pager_for_config(""), Ok(Some(command(&["less"]))));
+        assert_eq!(
+            pager_for_string("less -FRX"),
+            Ok(Some(command(&["less", "-FRX"])))
+        );
+        assert_eq!(pager_for_string(""), Ok(None));
+        assert_eq!(pager_for_string(" "), Ok(None));
+        assert_eq!(pager_for_string("cat"), Ok(None));
+        assert_eq!(pager_for_string(":"), Ok(None));
+        // `cat` with arguments still pages
+        assert_eq!(
+            pager_for_string("cat -v"),
+            Ok(Some(command(&["cat", "-v"])))
+        );
+
+        // The arguments of a list aren't split
+        assert_eq!(
+            pager_for_config(r#"ui.pager = ["my pager", "-F", "--prompt=a b"]"#),
+            Ok(Some(command(&["my pager", "-F", "--prompt=a b"])))
+        );
+        assert_eq!(pager_for_config("ui.pager = []"), Ok(None));
+        assert_eq!(
+            pager_for_config(r#"ui.pager = { command = ["less", "-R"], env = { LESS = "FRX" } }"#),
+            Ok(Some(PagerCommand {
+                env: HashMap::from([("LESS".to_string(), "FRX".to_string())]),
+                ..command(&["less", "-R"])
+            }))
+        );
+        assert_eq!(
+            pager_for_config(r#"ui.pager = { command = ["less"] }"#),
+            Ok(Some(command(&["less"])))
+        );
+
+        // Other shapes are errors
+        for toml in [
+            "ui.pager = 1",
+            r#"ui.pager = ["less", 1]"#,
+            r#"ui.pager = { env = { LESS = "FRX" } }"#,
+            r#"ui.pager = { command = ["less"], env = ["LESS"] }"#,
+            r#"ui.pager = { command = ["less"], env = { LESS = 1 } }"#,
+            r#"ui.pager = { command = ["less"], args = ["-R"] }"#,
+        ] {
+            let err = pager_for_config(toml).unwrap_err();
+            assert!(err.starts_with("ui.pager must be"), "{toml}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_pager_command() {
+        let pager_cmd = PagerCommand::from("less -FRX  --mouse");
+        assert_eq!(pager_cmd.to_string(), "less -FRX --mouse");
+        let command = pager_cmd.to_command();
+        assert_eq!(command.get_program(), "less");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-FRX", "--mouse"]);
+        assert_eq!(command.get_envs().count(), 0);
+        let command = PagerCommand::from("less").to_command();
+        assert_eq!(command.get_program(), "less");
+        assert_eq!(command.get_args().count(), 0);
+
+        let pager_cmd = PagerCommand {
+            env: HashMap::from([("LESS".to_string(), "FRX".to_string())]),
+            ..PagerCommand::from("less")
+        };
+        let command = pager_cmd.to_command();
+        assert_eq!(
+            command.get_envs().collect::<Vec<_>>(),
+            [(OsStr::new("LESS"), Some(OsStr::new("FRX")))]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pager_with_arguments() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A fake pager that records its arguments
+        let temp_dir = tempfile::tempdir().unwrap();
+        let args_path = temp_dir.path().join("args");
+        let pager_path = temp_dir.path().join("fake-pager");
+        std::fs::write(
+            &pager_path,
+            format!(
+                "#!/bin/sh\necho \"$@\" > '{}'\ncat > /dev/null\n",
+                args_path.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&pager_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.output =
+            UiOutput::new_paged(&format!("{} -F --raw", pager_path.display()).as_str().into())
+                .unwrap();
+        ui.write("paged\n").unwrap();
+        ui.finalize_writes();
+        assert_eq!(std::fs::read_to_string(&args_path).unwrap(), "-F --raw\n");
+    }
+
+    #[test]
+    fn test_pager_alive_without_pager() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        assert!(ui.pager_alive());
+        ui.output = UiOutput::new_threshold_paged("true".into(), 10, false);
+        assert!(ui.pager_alive());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pager_alive() {
+        let mut ui = Ui::for_test(UserSettings::default());
+        ui.output = UiOutput::new_paged(&"true".into()).unwrap();
+        // The pager exits right away without reading its input
+        let start = std::time::Instant::now();
+        while ui.pager_alive() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        ui.finalize_writes();
+
+        ui.output = UiOutput::new_paged(&"cat".into()).unwrap();
+        assert!(ui.pager_alive());
+        ui.finalize_writes();
+    }
+
+    #[test]
+    fn test_label_is_styled() {
+        let ui_with_config = |toml: &str| {
+            let settings = UserSettings::default()
+                .with_toml_strings(&[toml.to_string()])
+                .unwrap();
+            Ui::for_test(settings)
+        };
+        let ui = ui_with_config("ui.color=\"always\"");
+        assert!(ui.label_is_styled("error"));
+        assert!(ui.label_is_styled("diff added"));
+        assert!(!ui.label_is_styled("unknown"));
+
+        // The colors settings are taken into account
+        let ui =
+            ui_with_config("ui.color=\"always\"\ncolors.unknown=\"green\"\ncolors.error=\"none\"");
+        assert!(ui.label_is_styled("unknown"));
+        assert!(!ui.label_is_styled("error"));
+
+        // Nothing is styled without color
+        let ui = ui_with_config("ui.color=\"never\"");
+        assert!(!ui.label_is_styled("error"));
+    }
+
+    #[test]
+    fn test_color_choice() {
+        let ui = Ui::for_test(UserSettings::default());
+        assert_eq!(ui.color_choice(), ColorChoice::Auto);
+        assert!(!ui.color());
+
+        let settings = UserSettings::default()
+            .with_toml_strings(&["ui.color=\"always\"".to_string()])
+            .unwrap();
+        let mut ui = Ui::for_test(settings);
+        assert_eq!(ui.color_choice(), ColorChoice::Always);
+        assert!(ui.color());
+        ui.reset_color(ColorChoice::Never);
+        assert_eq!(ui.color_choice(), ColorChoice::Never);
+        assert!(!ui.color());
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Parser;
+use itertools::Itertools;
+
+/// A fake 3-way merge tool, useful for testing
+#[derive(Parser, Debug)]
+#[clap()]
+struct Args {
+    /// Path to the base file
+    base: PathBuf,
+
+    /// Path to the left file
+    left: PathBuf,
+
+    /// Path to the right file
+    right: PathBuf,
+
+    /// Path to the output file
+    output: PathBuf,
+}
+
+fn main() {
+    let args: Args = Args::parse();
+    let merge_script_path = PathBuf::from(std::env::var_os("MERGE_TOOL_SCRIPT").unwrap());
+    let merge_script = String::from_utf8(std::fs::read(merge_script_path).unwrap()).unwrap();
+    for instruction in merge_script.split('\0') {
+        let (command, payload) = instruction.split_once('\n').unwrap_or((instruction, ""));
+        let parts = command.split(' ').collect_vec();
+        match parts.as_slice() {
+            [""] => {}
+            ["fail"] => exit(1),
+            ["expect", file] => {
+                let path = match *file {
+                    "base" => &args.base,
+                    "left" => &args.left,
+                    "right" => &args.right,
+                    "output" => &args.output,
+                    _ => {
+                        eprintln!("fake-merge-tool: unexpected file: {}", file);
+                        exit(1)
+                    }
+                };
+                let actual = String::from_utf8(std::fs::read(path).unwrap()).unwrap();
+                if actual != payload {
+                    eprintln!(
+                        "fake-merge-tool: unexpected {}. EXPECTED: {:?} ACTUAL: {:?}",
+                        file, payload, actual
+                    );
+                    exit(1)
+                }
+            }
+            ["write"] => {
+                std::fs::write(&args.output, payload).unwrap();
+            }
+            _ => {
+                eprintln!("fake-merge-tool: unexpected command: {}", command);
+                exit(1)
+            }
+        }
+    }
+}
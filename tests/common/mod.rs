@@ -162,6 +162,30 @@ impl TestEnvironment {
         edit_script
     }
 
+    /// Sets up the fake merge tool as `merge-tools.fake` to read a merge
+    /// script from the returned path
+    pub fn set_up_fake_merge_tool(&mut self) -> PathBuf {
+        let merge_tool_path = assert_cmd::cargo::cargo_bin("fake-merge-tool");
+        assert!(merge_tool_path.is_file());
+        // Simplified TOML escaping, hoping that there are no '"' or control characters
+        // in it
+        let escaped_merge_tool_path = merge_tool_path.to_str().unwrap().replace('\\', r"\\");
+        self.add_config(
+            format!(
+                r###"
+        [merge-tools.fake]
+        program = "{}"
+        merge-args = ["$base", "$left", "$right", "$output"]
+        "###,
+                escaped_merge_tool_path
+            )
+            .as_bytes(),
+        );
+        let merge_script = self.env_root().join("merge_script");
+        self.add_env_var("MERGE_TOOL_SCRIPT", merge_script.to_str().unwrap());
+        merge_script
+    }
+
     pub fn normalize_output(&self, text: String) -> String {
         let text = text.replace("jj.exe", "jj");
         let regex = Regex::new(&format!(
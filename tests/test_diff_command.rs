@@ -172,6 +172,161 @@ fn test_diff_relative_paths() {
     "###);
 }
 
+#[test]
+fn test_diff_group_by_dir() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+    test_env.add_config(br#"diff.group-by-dir = true"#);
+
+    std::fs::create_dir(repo_path.join("a")).unwrap();
+    std::fs::create_dir(repo_path.join("b")).unwrap();
+    std::fs::write(repo_path.join("a").join("x"), "foo\n").unwrap();
+    std::fs::write(repo_path.join("b").join("z"), "bar\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new"]);
+    std::fs::write(repo_path.join("a").join("x"), "foo\nbaz\n").unwrap();
+    std::fs::write(repo_path.join("a").join("y"), "1\n").unwrap();
+    std::fs::remove_file(repo_path.join("b").join("z")).unwrap();
+    std::fs::write(repo_path.join("top"), "t\n").unwrap();
+    // Sorts before the directories, but is still in the same group as `top`
+    std::fs::write(repo_path.join("0top"), "0\n").unwrap();
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Directory ./: 2 files changed, 2 insertions(+), 0 deletions(-)
+    Added regular file 0top:
+            1: 0
+    Added regular file top:
+            1: t
+    Directory a/: 2 files changed, 2 insertions(+), 0 deletions(-)
+    Modified regular file a/x:
+       1    1: foo
+            2: baz
+    Added regular file a/y:
+            1: 1
+    Directory b/: 1 file changed, 0 insertions(+), 1 deletion(-)
+    Removed regular file b/z:
+       1     : bar
+    Total: 5 files changed, 4 insertions(+), 1 deletion(-)
+    "###);
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff", "-s"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Directory ./: 2 files changed, 2 insertions(+), 0 deletions(-)
+    A 0top
+    A top
+    Directory a/: 2 files changed, 2 insertions(+), 0 deletions(-)
+    M a/x
+    A a/y
+    Directory b/: 1 file changed, 0 insertions(+), 1 deletion(-)
+    R b/z
+    Total: 5 files changed, 4 insertions(+), 1 deletion(-)
+    "###);
+}
+
+#[test]
+fn test_diff_conflict_marker_style() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    std::fs::write(repo_path.join("file"), "a\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new"]);
+    std::fs::write(repo_path.join("file"), "b\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["branch", "create", "left"]);
+    test_env.jj_cmd_success(&repo_path, &["new", "@-"]);
+    std::fs::write(repo_path.join("file"), "c\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new", "left", "@"]);
+
+    // The conflict in the diff is written with the configured markers
+    test_env.add_config(br#"ui.conflict-marker-style = "git""#);
+    let stdout = test_env.jj_cmd_success(
+        &repo_path,
+        &["diff", "--git", "--from", "left", "--to", "@"],
+    );
+    assert!(stdout.contains("+<<<<<<<"), "{}", stdout);
+    assert!(stdout.contains("+>>>>>>>"), "{}", stdout);
+    assert!(!stdout.contains("%%%%%%%"), "{}", stdout);
+}
+
+#[test]
+fn test_diff_options_from_config() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    std::fs::write(repo_path.join("file1"), "a\nb\nc\nd\ne\nf\ng\nh\ni\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new"]);
+    std::fs::write(repo_path.join("file1"), "a\n b\nc\nd\nE\nf\ng\nh\ni\n").unwrap();
+
+    test_env.add_config(br#"diff.context-lines = 1"#);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Modified regular file file1:
+       1    1: a
+       2    2:  b
+       3    3: c
+        ...
+       5    5: eE
+       6    6: f
+        ...
+    "###);
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff", "--git"]);
+    insta::assert_snapshot!(stdout, @r###"
+    diff --git a/file1 b/file1
+    index b236ae5a2d...9dc2af5bcf 100644
+    --- a/file1
+    +++ b/file1
+    @@ -1,6 +1,6 @@
+     a
+    -b
+    + b
+     c
+     d
+    -e
+    +E
+     f
+    "###);
+
+    test_env.add_config(br#"diff.ignore-whitespace = true"#);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Modified regular file file1:
+        ...
+       4    4: d
+       5    5: eE
+       6    6: f
+        ...
+    "###);
+}
+
+#[test]
+fn test_diff_tab_width() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    std::fs::write(repo_path.join("file1"), "\tx\nab\ty\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new"]);
+    std::fs::write(repo_path.join("file1"), "\tx\nab\tY\n").unwrap();
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Modified regular file file1:
+       1    1:         x
+       2    2: ab      yY
+    "###);
+
+    test_env.add_config(br#"ui.tab-width = 4"#);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Modified regular file file1:
+       1    1:     x
+       2    2: ab  yY
+    "###);
+}
+
 #[test]
 fn test_diff_missing_newline() {
     let test_env = TestEnvironment::default();
@@ -219,6 +374,23 @@ fn test_diff_missing_newline() {
     "###);
 }
 
+#[test]
+fn test_color_words_diff_bom() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    std::fs::write(repo_path.join("file1"), "a\nb\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["new"]);
+    std::fs::write(repo_path.join("file1"), "\u{feff}a\nb\n").unwrap();
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Modified regular file file1:
+        (byte order mark added)
+    "###);
+}
+
 #[test]
 fn test_color_words_diff_missing_newline() {
     let test_env = TestEnvironment::default();
@@ -16,7 +16,7 @@ use std::path::Path;
 
 use git2::Oid;
 
-use crate::common::{get_stderr_string, TestEnvironment};
+use crate::common::{get_stderr_string, get_stdout_string, TestEnvironment};
 
 pub mod common;
 
@@ -188,15 +188,16 @@ fn test_git_colocated_conflicting_git_refs() {
     let assert = test_env
         .jj_cmd(&workspace_root, &["branch", "create", "main/sub"])
         .assert()
-        .success()
-        .stdout("");
-    insta::assert_snapshot!(get_stderr_string(&assert), @r###"
-    Failed to export some branches:
-      main/sub
+        .success();
+    insta::assert_snapshot!(get_stdout_string(&assert), @r###"
     Hint: Git doesn't allow a branch name that looks like a parent directory of
     another (e.g. `foo` and `foo/bar`). Try to rename the branches that failed to
     export or their "parent" branches.
     "###);
+    insta::assert_snapshot!(get_stderr_string(&assert), @r###"
+    Failed to export some branches:
+      main/sub
+    "###);
 }
 
 fn get_log_output(test_env: &TestEnvironment, workspace_root: &Path) -> String {
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::common::{get_stderr_string, TestEnvironment};
+use crate::common::{get_stderr_string, get_stdout_string, TestEnvironment};
 
 pub mod common;
 
@@ -27,13 +27,14 @@ fn test_git_export_conflicting_git_refs() {
     let assert = test_env
         .jj_cmd(&repo_path, &["git", "export"])
         .assert()
-        .success()
-        .stdout("");
-    insta::assert_snapshot!(get_stderr_string(&assert), @r###"
-    Failed to export some branches:
-      main/sub
+        .success();
+    insta::assert_snapshot!(get_stdout_string(&assert), @r###"
     Hint: Git doesn't allow a branch name that looks like a parent directory of
     another (e.g. `foo` and `foo/bar`). Try to rename the branches that failed to
     export or their "parent" branches.
     "###);
+    insta::assert_snapshot!(get_stderr_string(&assert), @r###"
+    Failed to export some branches:
+      main/sub
+    "###);
 }
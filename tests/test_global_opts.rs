@@ -204,6 +204,20 @@ fn test_invalid_config() {
     "###);
 }
 
+#[test]
+fn test_invalid_config_value() {
+    // Test that an invalid value of a setting is reported rather than ignored
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    test_env.add_config(br#"merge.granularity = "character""#);
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["status"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Config error: merge.granularity: must be one of line or word
+    "###);
+}
+
 #[test]
 fn test_no_user_configured() {
     // Test that the user is reminded if they haven't configured their name or email
@@ -252,7 +266,7 @@ fn test_help() {
       -R, --repository <REPOSITORY>      Path to repository to operate on
           --no-commit-working-copy       Don't commit the working copy
           --at-operation <AT_OPERATION>  Operation to load the repo at [default: @] [aliases: at-op]
-          --color <WHEN>                 When to colorize output (always, never, auto)
+          --color <WHEN>                 When to colorize output [possible values: always, never, auto]
           --no-pager                     Disable the pager
           --config-toml <TOML>           Additional configuration options
       -v, --verbose                      Enable verbose logging
@@ -161,6 +161,27 @@ fn test_init_git_internal_but_could_be_colocated() {
     let workspace_root = test_env.env_root().join("repo");
     init_git_repo(&workspace_root);
 
+    let assert = test_env
+        .jj_cmd(&workspace_root, &["init", "--git"])
+        .assert()
+        .success();
+    // The hint goes to stdout, since it isn't a terminal
+    insta::assert_snapshot!(get_stdout_string(&assert), @r###"
+    Initialized repo in "."
+    Hint: To create a repo backed by the existing Git repo, run `jj init --git-repo=.` instead.
+    "###);
+    insta::assert_snapshot!(get_stderr_string(&assert), @r###"
+    Empty repo created.
+    "###);
+}
+
+#[test]
+fn test_init_git_hints_to_stderr() {
+    let test_env = TestEnvironment::default();
+    test_env.add_config(b"ui.hints-to-stderr = true");
+    let workspace_root = test_env.env_root().join("repo");
+    init_git_repo(&workspace_root);
+
     let assert = test_env
         .jj_cmd(&workspace_root, &["init", "--git"])
         .assert()
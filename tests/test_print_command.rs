@@ -69,3 +69,51 @@ fn test_print() {
     >>>>>>>
     "###);
 }
+
+#[test]
+fn test_print_conflict_labels() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    std::fs::write(repo_path.join("file1"), "a\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["describe", "-m", "base"]);
+    test_env.jj_cmd_success(&repo_path, &["new", "-m", "left"]);
+    std::fs::write(repo_path.join("file1"), "b\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["branch", "create", "left"]);
+    test_env.jj_cmd_success(&repo_path, &["new", "@-", "-m", "right"]);
+    std::fs::write(repo_path.join("file1"), "c\n").unwrap();
+    test_env.jj_cmd_success(&repo_path, &["branch", "create", "right"]);
+    test_env.jj_cmd_success(&repo_path, &["new", "left", "right"]);
+
+    // The sides of the conflict in the merge commit are labeled with the parents
+    // and their common ancestor. Change ids are random, so only the
+    // descriptions are checked.
+    let stdout = test_env.jj_cmd_success(&repo_path, &["print", "file1"]);
+    let marker_lines = stdout
+        .lines()
+        .filter(|line| line.starts_with("%%%%%%%") || line.starts_with("+++++++"))
+        .collect::<Vec<_>>();
+    assert_eq!(marker_lines.len(), 2, "{}", stdout);
+    assert!(
+        marker_lines[0].starts_with("%%%%%%% diff from "),
+        "{}",
+        stdout
+    );
+    assert!(marker_lines[0].contains(" base to "), "{}", stdout);
+    let mut side_labels = [marker_lines[0], marker_lines[1]]
+        .iter()
+        .map(|line| line.rsplit(' ').next().unwrap())
+        .collect::<Vec<_>>();
+    side_labels.sort();
+    assert_eq!(side_labels, vec!["left", "right"], "{}", stdout);
+
+    // The conflict checked out in the working copy, and the conflict in a diff
+    // against the merge commit, are labeled the same way
+    let wc_content = std::fs::read_to_string(repo_path.join("file1")).unwrap();
+    assert_eq!(wc_content, stdout);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["diff", "--git", "--from", "left"]);
+    for marker_line in &marker_lines {
+        assert!(stdout.contains(&format!("+{marker_line}\n")), "{}", stdout);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use crate::common::TestEnvironment;
+
+pub mod common;
+
+/// Creates a working-copy commit where `file` conflicts between "a" (first
+/// side), "b" (base), and "c" (last side).
+fn create_conflict(test_env: &TestEnvironment, repo_path: &Path, file: &str) -> String {
+    std::fs::write(repo_path.join(file), "a\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["new"]);
+    std::fs::write(repo_path.join(file), "b\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["new"]);
+    std::fs::write(repo_path.join(file), "c\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["rebase", "-r", "@", "-d", "@--"])
+}
+
+#[test]
+fn test_reuse_resolution_across_paths() {
+    let test_env = TestEnvironment::default();
+    test_env.add_config(br#"merge.reuse-resolutions = true"#);
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    let stdout = create_conflict(&test_env, &repo_path, "file1");
+    assert!(!stdout.contains("Reused"), "{}", stdout);
+    std::fs::write(repo_path.join("file1"), "resolved\n").unwrap();
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolutions", "list"]);
+    assert!(
+        stdout.starts_with("Recorded 1 conflict resolutions\n"),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.lines().count(), 2, "{}", stdout);
+    assert!(stdout.ends_with(": resolved\n"), "{}", stdout);
+
+    // The same conflict in another file is resolved the same way
+    test_env.jj_cmd_success(&repo_path, &["checkout", "root"]);
+    let stdout = create_conflict(&test_env, &repo_path, "file2");
+    assert!(
+        stdout.contains("Reused recorded conflict resolution in file2\n"),
+        "{}",
+        stdout
+    );
+    let stdout = test_env.jj_cmd_success(&repo_path, &["print", "file2"]);
+    insta::assert_snapshot!(stdout, @r###"
+    resolved
+    "###);
+
+    test_env.jj_cmd_success(&repo_path, &["resolutions", "forget", "--all"]);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolutions", "list"]);
+    insta::assert_snapshot!(stdout, @"");
+}
+
+#[test]
+fn test_resolutions_not_recorded_by_default() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    create_conflict(&test_env, &repo_path, "file1");
+    std::fs::write(repo_path.join("file1"), "resolved\n").unwrap();
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolutions", "list"]);
+    insta::assert_snapshot!(stdout, @"");
+
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["resolutions", "forget", "0123"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: No such resolution: 0123
+    "###);
+}
@@ -0,0 +1,182 @@
+// Copyright 2022 The Jujutsu Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use crate::common::TestEnvironment;
+
+pub mod common;
+
+/// Creates a working-copy commit where "file1" conflicts between "a" (first
+/// side), "b" (base), and "c" (last side), and "file2" was modified on one side
+/// and deleted on the other.
+fn create_conflicts(test_env: &TestEnvironment, repo_path: &Path) {
+    std::fs::write(repo_path.join("file1"), "a\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["new"]);
+    std::fs::write(repo_path.join("file1"), "b\n").unwrap();
+    std::fs::write(repo_path.join("file2"), "x\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["new"]);
+    std::fs::write(repo_path.join("file1"), "c\n").unwrap();
+    std::fs::write(repo_path.join("file2"), "y\n").unwrap();
+    test_env.jj_cmd_success(repo_path, &["rebase", "-r", "@", "-d", "@--"]);
+}
+
+#[test]
+fn test_resolve_builtin_tools() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+    create_conflicts(&test_env, &repo_path);
+
+    for (tool, expected) in [(":ours", "a\n"), (":theirs", "c\n"), (":union", "a\nc\n")] {
+        let stdout = test_env.jj_cmd_success(&repo_path, &["resolve", "--tool", tool, "file1"]);
+        assert!(!stdout.contains("Could not resolve"), "{}", stdout);
+        assert_eq!(
+            std::fs::read_to_string(repo_path.join("file1")).unwrap(),
+            expected,
+            "{}",
+            tool
+        );
+        test_env.jj_cmd_success(&repo_path, &["undo"]);
+    }
+}
+
+#[test]
+fn test_resolve_unresolvable() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+    create_conflicts(&test_env, &repo_path);
+
+    // The modify/delete conflict is left as it is
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolve", "--tool", ":ours"]);
+    assert!(
+        stdout.contains("Could not resolve these conflicts with :ours:\n  file2\n"),
+        "{}",
+        stdout
+    );
+    let stdout = test_env.jj_cmd_success(&repo_path, &["print", "file1"]);
+    insta::assert_snapshot!(stdout, @r###"
+    a
+    "###);
+
+    // Nothing left that matches the paths
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["resolve", "--tool", ":ours", "file1"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: No conflicts found
+    "###);
+}
+
+#[test]
+fn test_conflict_stats() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+    create_conflicts(&test_env, &repo_path);
+
+    let stdout = test_env.jj_cmd_success(&repo_path, &["status"]);
+    assert!(
+        stdout.contains(
+            "There are unresolved conflicts at these paths:\nfile1: 1 conflict\nfile2: 1 conflict \
+             (involves a deletion)\n"
+        ),
+        "{}",
+        stdout
+    );
+
+    let stdout = test_env.jj_cmd_success(
+        &repo_path,
+        &[
+            "log",
+            "--no-graph",
+            "-r",
+            "@ | @-",
+            "-T",
+            r#"conflict_count "\n""#,
+        ],
+    );
+    insta::assert_snapshot!(stdout, @r###"
+    2
+    0
+    "###);
+}
+
+#[test]
+fn test_resolve_unknown_tool() {
+    let test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["resolve", "--tool", ":mine"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: Unknown merge tool: :mine
+    Hint: The built-in merge tools are :ours, :theirs, and :union
+    "###);
+
+    let stderr = test_env.jj_cmd_failure(&repo_path, &["resolve"]);
+    insta::assert_snapshot!(stderr, @r###"
+    Error: Failed to resolve conflicts: No merge tool configured; set ui.merge-editor or pass --tool
+    "###);
+}
+
+#[test]
+fn test_resolve_external_tool() {
+    let mut test_env = TestEnvironment::default();
+    test_env.jj_cmd_success(test_env.env_root(), &["init", "repo", "--git"]);
+    let repo_path = test_env.env_root().join("repo");
+    create_conflicts(&test_env, &repo_path);
+    let merge_script = test_env.set_up_fake_merge_tool();
+
+    // The tool gets the sides of the conflict, and what it writes to the output
+    // file is the resolution
+    std::fs::write(
+        &merge_script,
+        "expect base\nb\n\0expect left\na\n\0expect right\nc\n\0write\nresolved\n",
+    )
+    .unwrap();
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolve", "--tool", "fake", "file1"]);
+    assert!(!stdout.contains("Could not resolve"), "{}", stdout);
+    assert_eq!(
+        std::fs::read_to_string(repo_path.join("file1")).unwrap(),
+        "resolved\n"
+    );
+    test_env.jj_cmd_success(&repo_path, &["undo"]);
+
+    // A non-zero exit code leaves the file conflicted, even if the tool wrote
+    // to the output file
+    std::fs::write(&merge_script, "write\nresolved\n\0fail").unwrap();
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolve", "--tool", "fake", "file1"]);
+    insta::assert_snapshot!(stdout, @r###"
+    Could not resolve these conflicts with fake:
+      file1
+    "###);
+    let stdout = test_env.jj_cmd_success(&repo_path, &["print", "file1"]);
+    assert!(stdout.starts_with("<<<<<<<"), "{}", stdout);
+
+    // Without --tool, the tools in ui.merge-editor are tried in order, skipping
+    // those that aren't installed
+    test_env.add_config(
+        br#"ui.merge-editor = ["nonexistent-merge-tool", "fake"]
+        [merge-tools.nonexistent-merge-tool]
+        merge-args = ["$output"]
+        "#,
+    );
+    std::fs::write(&merge_script, "write\nresolved\n").unwrap();
+    let stdout = test_env.jj_cmd_success(&repo_path, &["resolve", "file1"]);
+    assert!(!stdout.contains("Could not resolve"), "{}", stdout);
+    assert_eq!(
+        std::fs::read_to_string(repo_path.join("file1")).unwrap(),
+        "resolved\n"
+    );
+}